@@ -0,0 +1,354 @@
+use std::fs;
+use std::io::{self, PipeReader, Read};
+use std::path::{Path, PathBuf};
+use std::thread::{self, JoinHandle};
+
+use filetime::FileTime;
+use tar::{Archive, Builder, EntryType, HeaderMode};
+use walkdir::WalkDir;
+
+use crate::hash::normalize;
+use crate::types::{CryptoError, Result};
+
+// A directory is encrypted as a tar stream (ustar with GNU extensions for
+// long names), so decrypting it with `decrypt` gives a plain .tar file.
+// Paths are relative to the encrypted directory. Modes and mtimes are
+// restored on extraction; owners are stored but not restored.
+
+/// Reads a tar stream of a directory while a thread writes it
+///
+/// Memory use is bounded by the pipe, whatever the size of the directory.
+pub struct Packer {
+    pipe: PipeReader,
+    worker: Option<JoinHandle<io::Result<()>>>,
+}
+
+/// Type of an archive entry
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum EntryKind {
+    File,
+    Directory,
+    Symlink(PathBuf),
+    Other,
+}
+
+/// One entry of an archive, as shown by `list`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ArchiveEntry {
+    pub path: PathBuf,
+    pub kind: EntryKind,
+    pub mode: u32,
+    pub size: u64,
+    /// Seconds since the Unix epoch
+    pub mtime: u64,
+}
+
+impl ArchiveEntry {
+    /// `ls -l` style type and permissions, e.g. `drwxr-xr-x`
+    pub fn mode_string(&self) -> String {
+        let mut mode = String::with_capacity(10);
+        mode.push(match self.kind {
+            EntryKind::File => '-',
+            EntryKind::Directory => 'd',
+            EntryKind::Symlink(_) => 'l',
+            EntryKind::Other => '?',
+        });
+        for shift in [6, 3, 0] {
+            let bits = self.mode >> shift;
+            mode.push(if bits & 4 != 0 { 'r' } else { '-' });
+            mode.push(if bits & 2 != 0 { 'w' } else { '-' });
+            mode.push(if bits & 1 != 0 { 'x' } else { '-' });
+        }
+        mode
+    }
+}
+
+/// Start packing `dir` into a tar stream
+pub fn pack(dir: &Path) -> Result<Packer> {
+    if !dir.is_dir() {
+        return Err(CryptoError::ArchiveError(format!("{} is not a directory", dir.display())));
+    }
+    let (pipe, writer) = io::pipe()?;
+    let dir = dir.to_path_buf();
+    let worker = thread::spawn(move || {
+        let mut builder = Builder::new(writer);
+        builder.mode(HeaderMode::Complete);
+        builder.follow_symlinks(false);
+        for entry in WalkDir::new(&dir).min_depth(1).sort_by_file_name() {
+            let entry = entry?;
+            let name = entry.path().strip_prefix(&dir).expect("walkdir yields paths below its root");
+            builder.append_path_with_name(entry.path(), name)?;
+        }
+        builder.into_inner().map(drop)
+    });
+    Ok(Packer { pipe, worker: Some(worker) })
+}
+
+impl Read for Packer {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.pipe.read(buf)?;
+        if n == 0 && !buf.is_empty() {
+            // The writer has finished or failed; surface its error
+            if let Some(worker) = self.worker.take() {
+                worker.join().map_err(|_| io::Error::other("archive thread panicked"))??;
+            }
+        }
+        Ok(n)
+    }
+}
+
+/// Read past the end-of-archive marker so the writer never sees a closed pipe
+fn drain<R: Read>(archive: Archive<R>) -> io::Result<()> {
+    io::copy(&mut archive.into_inner(), &mut io::sink()).map(drop)
+}
+
+/// List the entries of a tar stream
+pub fn list<R: Read>(reader: R) -> Result<Vec<ArchiveEntry>> {
+    let mut archive = Archive::new(reader);
+    let mut entries = Vec::new();
+    for entry in archive.entries()? {
+        let entry = entry?;
+        let header = entry.header();
+        let kind = match header.entry_type() {
+            EntryType::Regular | EntryType::Continuous => EntryKind::File,
+            EntryType::Directory => EntryKind::Directory,
+            EntryType::Symlink => EntryKind::Symlink(entry.link_name()?.unwrap_or_default().into_owned()),
+            _ => EntryKind::Other,
+        };
+        entries.push(ArchiveEntry {
+            path: entry.path()?.into_owned(),
+            kind,
+            mode: header.mode()?,
+            size: header.size()?,
+            mtime: header.mtime()?,
+        });
+    }
+    drain(archive)?;
+    Ok(entries)
+}
+
+/// Entries extracted into a staging directory next to their destination
+///
+/// Nothing is written to the destination until [`Extraction::commit`], so the
+/// caller can check the archive, e.g. its signature, first. Dropping an
+/// uncommitted extraction removes the staging directory.
+#[derive(Debug)]
+pub struct Extraction {
+    staging: PathBuf,
+    dest: PathBuf,
+    /// Directory entries with the permissions and mtimes to restore
+    directories: Vec<(PathBuf, fs::Permissions, u64)>,
+    count: usize,
+}
+
+impl Extraction {
+    /// Move the entries into the destination and return their number
+    ///
+    /// Existing files are replaced and existing directories merged, as
+    /// extracting in place would.
+    pub fn commit(self) -> Result<usize> {
+        match fs::symlink_metadata(&self.dest) {
+            Err(e) if e.kind() == io::ErrorKind::NotFound => fs::rename(&self.staging, &self.dest)?,
+            _ => {
+                for entry in fs::read_dir(&self.staging)? {
+                    let entry = entry?;
+                    move_entry(&entry.path(), &self.dest.join(entry.file_name()))?;
+                }
+            }
+        }
+
+        // Moving entries updated their directories' mtimes, so restore those last
+        for (path, permissions, mtime) in self.directories.iter().rev() {
+            let path = self.dest.join(path);
+            fs::set_permissions(&path, permissions.clone())?;
+            filetime::set_file_mtime(path, FileTime::from_unix_time(*mtime as i64, 0))?;
+        }
+        Ok(self.count)
+    }
+}
+
+impl Drop for Extraction {
+    fn drop(&mut self) {
+        // Gone or empty after a commit
+        let _ = fs::remove_dir_all(&self.staging);
+    }
+}
+
+/// Move `from` to `to`, merging it into an existing directory
+fn move_entry(from: &Path, to: &Path) -> io::Result<()> {
+    let staged_dir = fs::symlink_metadata(from)?.is_dir();
+    match fs::symlink_metadata(to) {
+        Ok(existing) if existing.is_dir() => {
+            if !staged_dir {
+                return Err(io::Error::new(io::ErrorKind::AlreadyExists, format!("{} is a directory", to.display())));
+            }
+            for entry in fs::read_dir(from)? {
+                let entry = entry?;
+                move_entry(&entry.path(), &to.join(entry.file_name()))?;
+            }
+            fs::remove_dir(from)
+        }
+        // A directory cannot be renamed over a file
+        Ok(_) if staged_dir => fs::remove_file(to).and_then(|_| fs::rename(from, to)),
+        _ => fs::rename(from, to),
+    }
+}
+
+/// A new directory next to `dest` to extract into
+fn create_staging(dest: &Path) -> Result<PathBuf> {
+    let dest = match fs::canonicalize(dest) {
+        Ok(dest) => dest,
+        Err(_) => {
+            let parent = dest.parent().filter(|parent| !parent.as_os_str().is_empty()).unwrap_or(Path::new("."));
+            fs::create_dir_all(parent)?;
+            fs::canonicalize(parent)?.join(dest.file_name().unwrap_or(dest.as_os_str()))
+        }
+    };
+    let (Some(parent), Some(name)) = (dest.parent(), dest.file_name()) else {
+        return Err(CryptoError::ArchiveError(format!("cannot extract into {}", dest.display())));
+    };
+    let staging = parent.join(format!(".{}.{:08x}.tmp", name.to_string_lossy(), rand::random::<u32>()));
+    fs::create_dir(&staging)?;
+    Ok(staging)
+}
+
+/// Extract a tar stream for `dest`, or only the entries at or below `selected`
+///
+/// The entries are staged, see [`Extraction`]. Entries that would land outside
+/// `dest` are skipped. Fails if a selected path is not in the archive.
+pub fn unpack<R: Read>(reader: R, dest: &Path, selected: &[PathBuf]) -> Result<Extraction> {
+    let staging = create_staging(dest)?;
+    let mut extraction = Extraction { staging, dest: dest.to_path_buf(), directories: Vec::new(), count: 0 };
+    let mut archive = Archive::new(reader);
+    archive.set_preserve_permissions(true);
+    archive.set_preserve_mtime(true);
+    archive.set_overwrite(true);
+
+    let selected: Vec<PathBuf> = selected.iter().map(|path| normalize(path)).collect();
+    let mut matched = vec![false; selected.len()];
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        let path = normalize(&entry.path()?);
+        if !selected.is_empty() {
+            let mut wanted = false;
+            for (prefix, matched) in selected.iter().zip(&mut matched) {
+                if path.starts_with(prefix) {
+                    *matched = true;
+                    wanted = true;
+                }
+            }
+            if !wanted {
+                continue;
+            }
+        }
+        if entry.unpack_in(&extraction.staging)? {
+            extraction.count += 1;
+            if entry.header().entry_type().is_dir() {
+                let permissions = fs::metadata(extraction.staging.join(&path))?.permissions();
+                extraction.directories.push((path, permissions, entry.header().mtime()?));
+            }
+        }
+    }
+    drain(archive)?;
+
+    let missing: Vec<String> = selected
+        .iter()
+        .zip(&matched)
+        .filter(|(_, matched)| !**matched)
+        .map(|(path, _)| path.display().to_string())
+        .collect();
+    if !missing.is_empty() {
+        return Err(CryptoError::ArchiveError(format!("not in the archive: {}", missing.join(", "))));
+    }
+    Ok(extraction)
+}
+
+/// Format seconds since the Unix epoch as `YYYY-MM-DD HH:MM` in UTC
+pub fn format_mtime(secs: u64) -> String {
+    // Days to a civil date, after Howard Hinnant's `civil_from_days`
+    let days = (secs / 86_400) as i64 + 719_468;
+    let minutes = secs % 86_400 / 60;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days.rem_euclid(146_097);
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+    let month = if shifted_month < 10 { shifted_month + 3 } else { shifted_month - 9 };
+    let year = era * 400 + year_of_era + i64::from(month <= 2);
+    format!("{:04}-{:02}-{:02} {:02}:{:02}", year, month, day, minutes / 60, minutes % 60)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_mtime() {
+        assert_eq!(format_mtime(0), "1970-01-01 00:00");
+        assert_eq!(format_mtime(951_782_400), "2000-02-29 00:00");
+        assert_eq!(format_mtime(1_700_000_000), "2023-11-14 22:13");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_pack_list_and_unpack() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let root = std::env::temp_dir().join(format!("crypto-tool-archive-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        let source = root.join("source");
+        fs::create_dir_all(source.join("sub/deeper")).unwrap();
+        fs::write(source.join("run.sh"), "#!/bin/sh\n").unwrap();
+        fs::write(source.join("sub/notes.txt"), "notes").unwrap();
+        fs::write(source.join("sub/deeper/data"), vec![7u8; 100_000]).unwrap();
+        fs::set_permissions(source.join("run.sh"), fs::Permissions::from_mode(0o751)).unwrap();
+        let mtime = FileTime::from_unix_time(1_600_000_000, 0);
+        filetime::set_file_mtime(source.join("sub/notes.txt"), mtime).unwrap();
+        filetime::set_file_mtime(source.join("sub"), mtime).unwrap();
+
+        let mut tar = Vec::new();
+        pack(&source).unwrap().read_to_end(&mut tar).unwrap();
+        let entries = list(&tar[..]).unwrap();
+        let paths: Vec<_> = entries.iter().map(|entry| entry.path.to_str().unwrap()).collect();
+        assert_eq!(paths, ["run.sh", "sub", "sub/deeper", "sub/deeper/data", "sub/notes.txt"]);
+        assert_eq!(entries[0].mode_string(), "-rwxr-x--x");
+        assert_eq!(entries[1].kind, EntryKind::Directory);
+        assert_eq!(entries[3].size, 100_000);
+
+        // Nothing lands before the commit
+        let everything = root.join("everything");
+        drop(unpack(&tar[..], &everything, &[]).unwrap());
+        assert!(!everything.exists());
+        assert_eq!(fs::read_dir(&root).unwrap().count(), 1);
+
+        assert_eq!(unpack(&tar[..], &everything, &[]).unwrap().commit().unwrap(), 5);
+        let mode = fs::metadata(everything.join("run.sh")).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o751);
+        for path in ["sub", "sub/notes.txt"] {
+            let extracted = FileTime::from_last_modification_time(&fs::metadata(everything.join(path)).unwrap());
+            assert_eq!(extracted, mtime, "{}", path);
+        }
+
+        let selected = root.join("selected");
+        assert_eq!(unpack(&tar[..], &selected, &[PathBuf::from("./sub/deeper")]).unwrap().commit().unwrap(), 2);
+        assert_eq!(fs::read(selected.join("sub/deeper/data")).unwrap().len(), 100_000);
+        assert!(!selected.join("run.sh").exists());
+
+        // Extracting again merges into the existing directories
+        fs::write(selected.join("sub/deeper/data"), "old").unwrap();
+        fs::write(selected.join("sub/kept"), "kept").unwrap();
+        assert_eq!(unpack(&tar[..], &selected, &[PathBuf::from("sub")]).unwrap().commit().unwrap(), 4);
+        assert_eq!(fs::read(selected.join("sub/deeper/data")).unwrap().len(), 100_000);
+        assert_eq!(fs::read_to_string(selected.join("sub/kept")).unwrap(), "kept");
+        let extracted = FileTime::from_last_modification_time(&fs::metadata(selected.join("sub")).unwrap());
+        assert_eq!(extracted, mtime);
+        assert!(matches!(
+            unpack(&tar[..], &selected, &[PathBuf::from("nope")]),
+            Err(CryptoError::ArchiveError(_))
+        ));
+
+        assert!(pack(&source.join("run.sh")).is_err());
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
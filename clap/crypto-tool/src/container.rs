@@ -0,0 +1,285 @@
+use std::io::{self, Read};
+
+use aes_gcm::Aes256Gcm;
+use aes_gcm::aead::{Aead, KeyInit, Payload};
+use chacha20poly1305::ChaCha20Poly1305;
+use rand::RngCore;
+use rand::rngs::OsRng;
+
+use crate::kdf::Kdf;
+use crate::secret::Secret;
+use crate::stream;
+use crate::types::{CryptoError, Result};
+
+// Container layout, all integers big-endian:
+//
+//   magic "CRTL" | version u8 | flags u8 | cipher u8 | kdf id u8 | kdf params len u8
+//   | kdf params | salt len u8 | salt | nonce len u8 | nonce | ciphertext with auth tag
+//
+// Streams (version 4) store a nonce prefix instead of the nonce, followed by
+// the chunk size as u32 and the chunks; see `stream.rs`. Versions 1 and 2 are
+// the same without the flags byte.
+//
+// Everything before the ciphertext is authenticated as associated data, so
+// changing the header is detected just like changing the ciphertext.
+
+pub const MAGIC: &[u8; 4] = b"CRTL";
+/// Version of a single-shot container
+pub const VERSION: u8 = 3;
+/// Version of a chunked stream, see [`stream`]
+pub const STREAM_VERSION: u8 = 4;
+/// Versions before the flags byte, still read
+const VERSION_WITHOUT_FLAGS: u8 = 1;
+const STREAM_VERSION_WITHOUT_FLAGS: u8 = 2;
+
+/// The plaintext carries a signature trailer, see [`crate::sign`]
+const FLAG_SIGNED: u8 = 0x01;
+
+pub(crate) const KEY_LEN: usize = 32;
+pub(crate) const SALT_LEN: usize = 16;
+const NONCE_LEN: usize = 12;
+
+/// Authenticated cipher used for the payload
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Cipher {
+    Aes256Gcm,
+    ChaCha20Poly1305,
+}
+
+impl Cipher {
+    fn id(self) -> u8 {
+        match self {
+            Cipher::Aes256Gcm => 1,
+            Cipher::ChaCha20Poly1305 => 2,
+        }
+    }
+
+    fn from_id(id: u8) -> Result<Self> {
+        match id {
+            1 => Ok(Cipher::Aes256Gcm),
+            2 => Ok(Cipher::ChaCha20Poly1305),
+            _ => Err(CryptoError::InvalidFormat(format!("unknown cipher id {}", id))),
+        }
+    }
+
+    pub(crate) fn encrypt(self, key: &[u8], nonce: &[u8], payload: Payload) -> Result<Vec<u8>> {
+        let result = match self {
+            Cipher::Aes256Gcm => Aes256Gcm::new_from_slice(key)
+                .map_err(|_| CryptoError::InvalidKeyLength)?
+                .encrypt(nonce.into(), payload),
+            Cipher::ChaCha20Poly1305 => ChaCha20Poly1305::new_from_slice(key)
+                .map_err(|_| CryptoError::InvalidKeyLength)?
+                .encrypt(nonce.into(), payload),
+        };
+        result.map_err(|e| CryptoError::CryptoError(e.to_string()))
+    }
+
+    pub(crate) fn decrypt(self, key: &[u8], nonce: &[u8], payload: Payload) -> Result<Vec<u8>> {
+        let result = match self {
+            Cipher::Aes256Gcm => Aes256Gcm::new_from_slice(key)
+                .map_err(|_| CryptoError::InvalidKeyLength)?
+                .decrypt(nonce.into(), payload),
+            Cipher::ChaCha20Poly1305 => ChaCha20Poly1305::new_from_slice(key)
+                .map_err(|_| CryptoError::InvalidKeyLength)?
+                .decrypt(nonce.into(), payload),
+        };
+        result.map_err(|_| CryptoError::AuthenticationFailed)
+    }
+}
+
+/// Parsed container header
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Header {
+    pub version: u8,
+    /// Whether the plaintext was signed before encryption
+    pub signed: bool,
+    pub cipher: Cipher,
+    pub kdf: Kdf,
+    pub salt: Vec<u8>,
+    /// The nonce, or the nonce prefix of a stream
+    pub nonce: Vec<u8>,
+    /// Plaintext bytes per chunk, for streams only
+    pub chunk_size: Option<u32>,
+}
+
+impl Header {
+    pub(crate) fn encode(&self) -> Vec<u8> {
+        let params = self.kdf.encode_params();
+        let mut header = Vec::with_capacity(16 + params.len() + self.salt.len() + self.nonce.len());
+        header.extend_from_slice(MAGIC);
+        header.push(self.version);
+        if has_flags(self.version) {
+            header.push(if self.signed { FLAG_SIGNED } else { 0 });
+        }
+        header.push(self.cipher.id());
+        header.push(self.kdf.id());
+        header.push(params.len() as u8);
+        header.extend_from_slice(&params);
+        header.push(self.salt.len() as u8);
+        header.extend_from_slice(&self.salt);
+        header.push(self.nonce.len() as u8);
+        header.extend_from_slice(&self.nonce);
+        if let Some(chunk_size) = self.chunk_size {
+            header.extend_from_slice(&chunk_size.to_be_bytes());
+        }
+        header
+    }
+
+    /// Parse the header at the start of `data` and return it with its length
+    pub fn parse(data: &[u8]) -> Result<(Header, usize)> {
+        let mut reader = data;
+        let header = Self::read(&mut reader)?;
+        Ok((header, data.len() - reader.len()))
+    }
+
+    /// Read a header from the start of a stream
+    pub fn read<R: Read + ?Sized>(reader: &mut R) -> Result<Header> {
+        let mut magic = [0u8; 4];
+        read_header_bytes(reader, &mut magic)?;
+        if !is_container(&magic) {
+            return Err(CryptoError::InvalidFormat(
+                "missing crypto-tool header; files from older versions need --legacy".to_string(),
+            ));
+        }
+
+        let version = read_byte(reader)?;
+        if ![VERSION, STREAM_VERSION, VERSION_WITHOUT_FLAGS, STREAM_VERSION_WITHOUT_FLAGS].contains(&version) {
+            return Err(CryptoError::UnsupportedVersion(version));
+        }
+        let flags = if has_flags(version) { read_byte(reader)? } else { 0 };
+        if flags & !FLAG_SIGNED != 0 {
+            return Err(CryptoError::InvalidFormat(format!("unknown flags {:#04x}", flags)));
+        }
+        let cipher = Cipher::from_id(read_byte(reader)?)?;
+        let kdf_id = read_byte(reader)?;
+        let kdf = Kdf::decode(kdf_id, &read_field(reader)?)?;
+        let salt = read_field(reader)?;
+        let nonce = read_field(reader)?;
+
+        let expected_nonce = if is_stream(version) { stream::NONCE_PREFIX_LEN } else { NONCE_LEN };
+        if nonce.len() != expected_nonce {
+            return Err(CryptoError::InvalidFormat(format!("bad nonce length {}", nonce.len())));
+        }
+
+        let chunk_size = if is_stream(version) {
+            let mut size = [0u8; 4];
+            read_header_bytes(reader, &mut size)?;
+            let size = u32::from_be_bytes(size);
+            if size == 0 || size > stream::MAX_CHUNK_SIZE {
+                return Err(CryptoError::InvalidFormat(format!("bad chunk size {}", size)));
+            }
+            Some(size)
+        } else {
+            None
+        };
+
+        Ok(Header { version, signed: flags & FLAG_SIGNED != 0, cipher, kdf, salt, nonce, chunk_size })
+    }
+}
+
+fn has_flags(version: u8) -> bool {
+    version == VERSION || version == STREAM_VERSION
+}
+
+fn is_stream(version: u8) -> bool {
+    version == STREAM_VERSION || version == STREAM_VERSION_WITHOUT_FLAGS
+}
+
+fn read_header_bytes<R: Read + ?Sized>(reader: &mut R, buf: &mut [u8]) -> Result<()> {
+    reader.read_exact(buf).map_err(|e| match e.kind() {
+        io::ErrorKind::UnexpectedEof => CryptoError::InvalidFormat("truncated header".to_string()),
+        _ => CryptoError::IoError(e),
+    })
+}
+
+fn read_byte<R: Read + ?Sized>(reader: &mut R) -> Result<u8> {
+    let mut byte = [0u8; 1];
+    read_header_bytes(reader, &mut byte)?;
+    Ok(byte[0])
+}
+
+/// A field prefixed with its one-byte length
+fn read_field<R: Read + ?Sized>(reader: &mut R) -> Result<Vec<u8>> {
+    let mut field = vec![0u8; read_byte(reader)? as usize];
+    read_header_bytes(reader, &mut field)?;
+    Ok(field)
+}
+
+/// Check whether `data` starts with the container magic
+pub fn is_container(data: &[u8]) -> bool {
+    data.starts_with(MAGIC)
+}
+
+/// Encrypt `data` into a new container with a fresh salt and nonce
+pub fn seal(data: &[u8], secret: &Secret, cipher: Cipher, kdf: Kdf) -> Result<Vec<u8>> {
+    kdf.validate()?;
+
+    let mut salt = vec![0u8; SALT_LEN];
+    let mut nonce = vec![0u8; NONCE_LEN];
+    OsRng.fill_bytes(&mut salt);
+    OsRng.fill_bytes(&mut nonce);
+
+    let header = Header { version: VERSION, signed: false, cipher, kdf, salt, nonce, chunk_size: None };
+    let mut output = header.encode();
+    let key = secret.derive_key(kdf, &header.salt, KEY_LEN)?;
+    let ciphertext = cipher.encrypt(&key, &header.nonce, Payload { msg: data, aad: &output })?;
+
+    output.extend_from_slice(&ciphertext);
+    Ok(output)
+}
+
+/// Decrypt a container or stream, failing with `AuthenticationFailed` if it was modified
+pub fn open(data: &[u8], secret: &Secret) -> Result<Vec<u8>> {
+    if data.get(MAGIC.len()).is_some_and(|&version| is_stream(version)) {
+        let mut output = Vec::new();
+        stream::decrypt_stream(data, &mut output, secret, &mut |_| {})?;
+        return Ok(output);
+    }
+
+    let (header, header_len) = Header::parse(data)?;
+    let (aad, ciphertext) = data.split_at(header_len);
+    let key = secret.derive_key(header.kdf, &header.salt, KEY_LEN)?;
+
+    header.cipher.decrypt(&key, &header.nonce, Payload { msg: ciphertext, aad })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FAST: Kdf = Kdf::Pbkdf2Sha256 { iterations: 1_000 };
+
+    #[test]
+    fn test_round_trip_both_ciphers() {
+        for cipher in [Cipher::Aes256Gcm, Cipher::ChaCha20Poly1305] {
+            let sealed = seal(b"attack at dawn", &Secret::from_password("secret"), cipher, FAST).unwrap();
+            let (header, _) = Header::parse(&sealed).unwrap();
+            assert_eq!(header.cipher, cipher);
+            assert_eq!(header.kdf, FAST);
+            assert_eq!(open(&sealed, &Secret::from_password("secret")).unwrap(), b"attack at dawn");
+        }
+    }
+
+    #[test]
+    fn test_tampering_is_detected() {
+        let sealed = seal(b"attack at dawn", &Secret::from_password("secret"), Cipher::Aes256Gcm, FAST).unwrap();
+
+        assert!(matches!(open(&sealed, &Secret::from_password("wrong")), Err(CryptoError::AuthenticationFailed)));
+
+        let mut modified = sealed.clone();
+        *modified.last_mut().unwrap() ^= 1;
+        assert!(matches!(open(&modified, &Secret::from_password("secret")), Err(CryptoError::AuthenticationFailed)));
+
+        // The salt is part of the authenticated header
+        let mut modified = sealed.clone();
+        modified[14] ^= 1;
+        assert!(matches!(open(&modified, &Secret::from_password("secret")), Err(CryptoError::AuthenticationFailed)));
+
+        let mut modified = sealed;
+        modified[4] = 9;
+        assert!(matches!(open(&modified, &Secret::from_password("secret")), Err(CryptoError::UnsupportedVersion(9))));
+
+        assert!(matches!(open(b"no header", &Secret::from_password("secret")), Err(CryptoError::InvalidFormat(_))));
+        assert!(matches!(open(b"CRTL\x01\x01", &Secret::from_password("secret")), Err(CryptoError::InvalidFormat(_))));
+    }
+}
@@ -1,71 +1,89 @@
+use crate::container::{self, Cipher};
+use crate::hybrid;
+use crate::kdf::Kdf;
+use crate::secret::Secret;
 use crate::types::{CryptoMethod, CryptoError, Result};
 
-// Crypto dependencies (add these to Cargo.toml)
 use aes::Aes256;
 use aes::cipher::{
-    BlockEncrypt, BlockDecrypt, KeyInit,
+    BlockDecrypt, KeyInit,
     generic_array::GenericArray,
 };
 use des::Des;
-use rsa::{RsaPrivateKey, RsaPublicKey, Pkcs1v15Encrypt};
-use rand::rngs::OsRng;
+use rsa::{RsaPrivateKey, RsaPublicKey};
 use sha2::{Sha256, Digest};
+use zeroize::Zeroizing;
 
-pub fn encrypt_data(data: &[u8], method: &CryptoMethod, password: &str) -> Result<Vec<u8>> {
+/// Encrypt data; `kdf` derives the key for the AES and ChaCha20 container
+pub fn encrypt_data(data: &[u8], method: &CryptoMethod, secret: &Secret, kdf: Kdf) -> Result<Vec<u8>> {
     match method {
-        CryptoMethod::Aes => aes_encrypt(data, password),
-        CryptoMethod::Des => des_encrypt(data, password),
-        CryptoMethod::Rsa => rsa_encrypt(data, password),
+        CryptoMethod::Aes => container::seal(data, secret, Cipher::Aes256Gcm, kdf),
+        CryptoMethod::Chacha20 => container::seal(data, secret, Cipher::ChaCha20Poly1305, kdf),
+        CryptoMethod::Des => Err(des_is_legacy()),
+        CryptoMethod::Rsa => Err(rsa_needs_keys()),
     }
 }
 
-pub fn decrypt_data(data: &[u8], method: &CryptoMethod, password: &str) -> Result<Vec<u8>> {
+/// Decrypt data; AES and ChaCha20 read the cipher from the container header
+pub fn decrypt_data(data: &[u8], method: &CryptoMethod, secret: &Secret) -> Result<Vec<u8>> {
     match method {
-        CryptoMethod::Aes => aes_decrypt(data, password),
-        CryptoMethod::Des => des_decrypt(data, password),
-        CryptoMethod::Rsa => rsa_decrypt(data, password),
+        CryptoMethod::Aes | CryptoMethod::Chacha20 => container::open(data, secret),
+        CryptoMethod::Des => Err(des_is_legacy()),
+        CryptoMethod::Rsa => Err(rsa_needs_keys()),
     }
 }
 
-fn derive_key(password: &str, key_size: usize) -> Vec<u8>{
+/// Encrypt data for RSA recipients: RSA-OAEP wraps a random AES-256-GCM key
+///
+/// `signed` marks the data as carrying a signature trailer.
+pub fn encrypt_for_recipients(data: &[u8], recipients: &[RsaPublicKey], signed: bool) -> Result<Vec<u8>> {
+    hybrid::seal(data, recipients, signed)
+}
+
+/// Decrypt RSA hybrid output with the private key of one of its recipients
+pub fn decrypt_with_private_key(data: &[u8], key: &RsaPrivateKey) -> Result<Vec<u8>> {
+    hybrid::open(data, key)
+}
+
+fn rsa_needs_keys() -> CryptoError {
+    CryptoError::KeyError("RSA uses key files instead of a password".to_string())
+}
+
+fn des_is_legacy() -> CryptoError {
+    CryptoError::CryptoError("DES only decrypts files of older versions, with --legacy".to_string())
+}
+
+/// Decrypt headerless AES-256-ECB or DES-ECB output from older versions
+///
+/// The old formats are not authenticated, so a wrong password or modified
+/// data cannot be detected reliably.
+pub fn decrypt_legacy(data: &[u8], method: &CryptoMethod, secret: &Secret) -> Result<Vec<u8>> {
+    match method {
+        CryptoMethod::Aes => legacy_aes_decrypt(data, secret.password()?),
+        CryptoMethod::Des => des_decrypt(data, secret.password()?),
+        _ => Err(CryptoError::CryptoError(format!("--legacy only applies to the aes and des methods, not {:?}", method))),
+    }
+}
+
+/// Unsalted SHA-256 key of the legacy AES and the DES formats; use a [`Kdf`] for anything new
+fn derive_key(password: &str, key_size: usize) -> Zeroizing<Vec<u8>> {
     let mut hasher = Sha256::new();
     hasher.update(password.as_bytes());
     let result = hasher.finalize();
 
     // Truncate or pad to desired key size
-    let mut key = vec![0u8; key_size];
+    let mut key = Zeroizing::new(vec![0u8; key_size]);
     let copy_len = std::cmp::min(result.len(), key_size);
     key[..copy_len].copy_from_slice(&result[..copy_len]);
     key
 }
 
-fn aes_encrypt(data: &[u8], password: &str) -> Result<Vec<u8>> {
-    let key = derive_key(password, 32); // AES-256 key size
-    let key_array = GenericArray::from_slice(&key);
-    let cipher = Aes256::new(key_array);
-
-    // Pad data to 16-byte block size
-    let mut padded_data = data.to_vec();
-    let padding_needed = 16 - (data.len() % 16);
-    padded_data.extend(vec![padding_needed as u8; padding_needed]);
-
-
-    let mut encrypted = Vec::new();
-    for chunk in padded_data.chunks(16) {
-        let mut block = GenericArray::clone_from_slice(chunk);
-        cipher.encrypt_block(&mut block);
-        encrypted.extend_from_slice(&block);
-    }
-
-    Ok(encrypted)
-}
-
-fn aes_decrypt(data: &[u8], password: &str) -> Result<Vec<u8>> {
+fn legacy_aes_decrypt(data: &[u8], password: &str) -> Result<Vec<u8>> {
     let key = derive_key(password, 32); // AES-256
     let key_array = GenericArray::from_slice(&key);
     let cipher = Aes256::new(key_array);
 
-    if data.len() % 16 != 0 {
+    if !data.len().is_multiple_of(16) {
         return Err(CryptoError::InvalidDataLength);
     }
 
@@ -87,33 +105,13 @@ fn aes_decrypt(data: &[u8], password: &str) -> Result<Vec<u8>> {
     Ok(decrypted)
 }
 
-// DES encryption/decryption
-fn des_encrypt(data: &[u8], password: &str) -> Result<Vec<u8>> {
-    let key = derive_key(password, 8); // DES key is 8 bytes
-    let key_array = GenericArray::from_slice(&key);
-    let cipher = Des::new(key_array);
-
-    // Pad data to 8-byte blocks
-    let mut padded_data = data.to_vec();
-    let padding_needed = 8 - (data.len() % 8);
-    padded_data.extend(vec![padding_needed as u8; padding_needed]);
-
-    let mut encrypted = Vec::new();
-    for chunk in padded_data.chunks(8) {
-        let mut block = GenericArray::clone_from_slice(chunk);
-        cipher.encrypt_block(&mut block);
-        encrypted.extend_from_slice(&block);
-    }
-
-    Ok(encrypted)
-}
-
+// DES decryption of the legacy format
 fn des_decrypt(data: &[u8], password: &str) -> Result<Vec<u8>> {
     let key = derive_key(password, 8); // DES key is 8 bytes
     let key_array = GenericArray::from_slice(&key);
     let cipher = Des::new(key_array);
 
-    if data.len() % 8 != 0 {
+    if !data.len().is_multiple_of(8) {
         return Err(CryptoError::InvalidDataLength);
     }
 
@@ -135,33 +133,37 @@ fn des_decrypt(data: &[u8], password: &str) -> Result<Vec<u8>> {
     Ok(decrypted)
 }
 
-// RSA encryption/decryption
-fn rsa_encrypt(data: &[u8], _password: &str) -> Result<Vec<u8>> {
-    // For RSA, we'll generate a key pair (in a real implementation, you'd save/load keys)
-    let mut rng = OsRng;
-    let bits = 2048;
-    let private_key = RsaPrivateKey::new(&mut rng, bits)
-        .map_err(|e| CryptoError::CryptoError(e.to_string()))?;
-    let public_key = RsaPublicKey::from(&private_key);
-
-    // RSA can only encrypt small amounts of data
-    // For larger data, you'd typically use hybrid encryption
-    if data.len() > 245 { // Conservative limit for 2048-bit RSA with PKCS1v15 padding
-        return Err(CryptoError::CryptoError(
-            "Data too large for RSA encryption. Use AES for large files.".to_string()
-        ));
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use aes::cipher::BlockEncrypt;
+
+    #[test]
+    fn test_legacy_aes_still_decrypts() {
+        // Headerless AES-256-ECB with PKCS#7 padding, as written by older versions
+        let key = derive_key("mypass", 32);
+        let cipher = Aes256::new(GenericArray::from_slice(&key));
+        let mut legacy = b"old file".to_vec();
+        legacy.extend([8u8; 8]);
+        cipher.encrypt_block(GenericArray::from_mut_slice(&mut legacy));
+
+        assert_eq!(decrypt_legacy(&legacy, &CryptoMethod::Aes, &Secret::from_password("mypass")).unwrap(), b"old file");
+        assert!(matches!(decrypt_data(&legacy, &CryptoMethod::Aes, &Secret::from_password("mypass")), Err(CryptoError::InvalidFormat(_))));
+        assert!(decrypt_legacy(&legacy, &CryptoMethod::Chacha20, &Secret::from_password("mypass")).is_err());
     }
 
-    let encrypted = public_key.encrypt(&mut rng, Pkcs1v15Encrypt, data)
-        .map_err(|e| CryptoError::CryptoError(e.to_string()))?;
-
-    Ok(encrypted)
-}
-
-fn rsa_decrypt(data: &[u8], _password: &str) -> Result<Vec<u8>> {
-    // This is a simplified example - in practice, you'd load the private key
-    // For this demo, RSA decrypt won't work without the original private key
-    Err(CryptoError::CryptoError(
-        "RSA decryption requires the original private key. This is a demo limitation.".to_string()
-    ))
+    #[test]
+    fn test_des_is_decrypt_only() {
+        // Headerless DES-ECB with PKCS#7 padding and the same unsalted key
+        let key = derive_key("mypass", 8);
+        let cipher = Des::new(GenericArray::from_slice(&key));
+        let mut legacy = b"old".to_vec();
+        legacy.extend([5u8; 5]);
+        cipher.encrypt_block(GenericArray::from_mut_slice(&mut legacy));
+
+        let secret = Secret::from_password("mypass");
+        assert_eq!(decrypt_legacy(&legacy, &CryptoMethod::Des, &secret).unwrap(), b"old");
+        assert!(decrypt_data(&legacy, &CryptoMethod::Des, &secret).is_err());
+        assert!(encrypt_data(b"new", &CryptoMethod::Des, &secret, Kdf::default()).is_err());
+    }
 }
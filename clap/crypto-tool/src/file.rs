@@ -1,6 +1,6 @@
-use std::fs;
-use std::io::{self, Write};
-use std::path::Path;
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
 
 use crate::types::{CryptoError, Result};
 
@@ -15,6 +15,140 @@ pub fn read_input_file(path: &str) -> Result<Vec<u8>> {
     fs::read(path).map_err(CryptoError::from)
 }
 
+/// Read a whole file, or stdin when `path` is `None` or `-`
+pub fn read_input(path: Option<&str>) -> Result<Vec<u8>> {
+    match path {
+        Some(path) if path != "-" => read_input_file(path),
+        _ => {
+            let mut data = Vec::new();
+            io::stdin().lock().read_to_end(&mut data)?;
+            Ok(data)
+        }
+    }
+}
+
+/// Open a file or stdin for streaming, with the file size when known
+pub fn open_input(path: Option<&str>) -> Result<(Box<dyn Read>, Option<u64>)> {
+    match path {
+        Some(path) if path != "-" => {
+            let file = File::open(path).map_err(|e| {
+                CryptoError::IoError(io::Error::new(e.kind(), format!("Input file '{}': {}", path, e)))
+            })?;
+            let len = file.metadata()?.len();
+            Ok((Box::new(BufReader::new(file)), Some(len)))
+        }
+        _ => Ok((Box::new(io::stdin().lock()), None)),
+    }
+}
+
+/// Fail if `input` and `output` name the same existing file, which writing would destroy
+pub fn check_distinct(input: Option<&str>, output: Option<&String>) -> Result<()> {
+    if let (Some(input), Some(output)) = (input, output)
+        && input != "-"
+        && let (Ok(input), Ok(output)) = (fs::canonicalize(input), fs::canonicalize(output))
+        && input == output
+    {
+        return Err(CryptoError::IoError(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("Input and output are the same file '{}'", output.display()),
+        )));
+    }
+    Ok(())
+}
+
+/// Streaming output to stdout or a file
+///
+/// A regular file is written under a temporary name in its directory and
+/// renamed over the target by [`Output::finish`], so a failed run leaves an
+/// existing file untouched. Dropping an unfinished output removes only the
+/// temporary file.
+pub struct Output {
+    writer: OutputWriter,
+    /// The temporary file and the path it replaces
+    pending: Option<(PathBuf, PathBuf)>,
+}
+
+enum OutputWriter {
+    Stdout(io::StdoutLock<'static>),
+    File(BufWriter<File>),
+}
+
+impl Output {
+    /// Flush the output and move a file into place
+    pub fn finish(mut self) -> Result<()> {
+        match &mut self.writer {
+            OutputWriter::Stdout(out) => out.flush()?,
+            OutputWriter::File(writer) => writer.flush()?,
+        }
+        if let Some((temp, path)) = self.pending.take() {
+            let renamed = match &self.writer {
+                OutputWriter::File(writer) => writer.get_ref().sync_all().and_then(|_| fs::rename(&temp, &path)),
+                OutputWriter::Stdout(_) => Ok(()),
+            };
+            if let Err(e) = renamed {
+                let _ = fs::remove_file(&temp);
+                return Err(e.into());
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Write for Output {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match &mut self.writer {
+            OutputWriter::Stdout(out) => out.write(buf),
+            OutputWriter::File(writer) => writer.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match &mut self.writer {
+            OutputWriter::Stdout(out) => out.flush(),
+            OutputWriter::File(writer) => writer.flush(),
+        }
+    }
+}
+
+impl Drop for Output {
+    fn drop(&mut self) {
+        if let Some((temp, _)) = &self.pending {
+            let _ = fs::remove_file(temp);
+        }
+    }
+}
+
+/// Open a file or stdout for streaming output; see [`Output`]
+pub fn create_output(path: Option<&String>) -> Result<Output> {
+    let Some(path) = path else {
+        return Ok(Output { writer: OutputWriter::Stdout(io::stdout().lock()), pending: None });
+    };
+    let path = Path::new(path);
+    let existing = fs::metadata(path).ok();
+    if let Some(metadata) = &existing
+        && !metadata.is_file()
+    {
+        // Devices and pipes such as /dev/null are written in place
+        let file = fs::OpenOptions::new().write(true).open(path)?;
+        return Ok(Output { writer: OutputWriter::File(BufWriter::new(file)), pending: None });
+    }
+
+    let name = path.file_name().ok_or_else(|| {
+        CryptoError::IoError(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("Output '{}' is not a file name", path.display()),
+        ))
+    })?;
+    let dir = path.parent().filter(|dir| !dir.as_os_str().is_empty()).unwrap_or(Path::new("."));
+    let temp = dir.join(format!(".{}.{:08x}.tmp", name.to_string_lossy(), rand::random::<u32>()));
+    let file = fs::OpenOptions::new().write(true).create_new(true).open(&temp)?;
+    let output = Output { writer: OutputWriter::File(BufWriter::new(file)), pending: Some((temp, path.to_path_buf())) };
+    if let (Some(metadata), OutputWriter::File(writer)) = (&existing, &output.writer) {
+        writer.get_ref().set_permissions(metadata.permissions())?;
+    }
+    Ok(output)
+}
+
 pub fn write_output(data: &[u8], output_path: Option<&String>) -> Result<()> {
     match output_path {
         Some(path) => {
@@ -27,4 +161,80 @@ pub fn write_output(data: &[u8], output_path: Option<&String>) -> Result<()> {
         }
     }
     Ok(())
-}
\ No newline at end of file
+}
+
+/// Write a private key readable only by the owner where the platform supports it
+pub fn write_private_key(path: &str, pem: &str) -> Result<()> {
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+
+    let mut file = options.open(path)?;
+    // The mode above only applies to a new file; tighten an existing one before the key lands in it
+    #[cfg(unix)]
+    file.set_permissions(std::os::unix::fs::PermissionsExt::from_mode(0o600))?;
+    file.write_all(pem.as_bytes())?;
+    println!("Private key written to: {}", path);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn dir_names(dir: &Path) -> Vec<String> {
+        let mut names: Vec<_> = fs::read_dir(dir).unwrap().map(|e| e.unwrap().file_name().into_string().unwrap()).collect();
+        names.sort();
+        names
+    }
+
+    #[test]
+    fn test_output_replaces_file_only_when_finished() {
+        let dir = std::env::temp_dir().join(format!("crypto-tool-output-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("data.enc");
+        let path_string = path.to_str().unwrap().to_string();
+        fs::write(&path, "original").unwrap();
+
+        // A run that fails part way, such as a decryption with the wrong password
+        let mut output = create_output(Some(&path_string)).unwrap();
+        output.write_all(b"partial").unwrap();
+        drop(output);
+        assert_eq!(fs::read_to_string(&path).unwrap(), "original");
+        assert_eq!(dir_names(&dir), ["data.enc"]);
+
+        let mut output = create_output(Some(&path_string)).unwrap();
+        output.write_all(b"replaced").unwrap();
+        output.finish().unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "replaced");
+        assert_eq!(dir_names(&dir), ["data.enc"]);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_check_distinct() {
+        let dir = std::env::temp_dir().join(format!("crypto-tool-distinct-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let file = dir.join("f");
+        fs::write(&file, "data").unwrap();
+        let input = file.to_str().unwrap();
+        let other = dir.join("g").to_str().unwrap().to_string();
+
+        assert!(check_distinct(Some(input), Some(&input.to_string())).is_err());
+        let respelled = dir.join(".").join("f").to_str().unwrap().to_string();
+        assert!(check_distinct(Some(input), Some(&respelled)).is_err());
+
+        assert!(check_distinct(Some(input), Some(&other)).is_ok());
+        fs::write(&other, "other").unwrap();
+        assert!(check_distinct(Some(input), Some(&other)).is_ok());
+        assert!(check_distinct(Some("-"), Some(&input.to_string())).is_ok());
+        assert!(check_distinct(None, Some(&input.to_string())).is_ok());
+        assert!(check_distinct(Some(input), None).is_ok());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
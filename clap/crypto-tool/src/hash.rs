@@ -0,0 +1,351 @@
+use std::collections::HashSet;
+use std::fs::{self, File};
+use std::io::{self, BufReader, Read, Write};
+use std::path::{Component, Path, PathBuf};
+
+use rayon::prelude::*;
+use sha2::{Digest, Sha256, Sha512};
+use sha3::{Sha3_256, Sha3_512};
+use walkdir::WalkDir;
+
+use crate::types::{CryptoError, Result};
+
+// Manifests use the coreutils `sha256sum` format, one file per line:
+//
+//   hex digest | two spaces (or space and '*') | path
+//
+// A path containing a backslash, CR or LF is escaped and the line starts
+// with a backslash, as GNU coreutils does. The format does not name the
+// algorithm, so checking needs the same `--algorithm` that wrote it.
+
+/// Digest algorithm for `hash`
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HashAlgorithm {
+    #[value(name = "sha256")]
+    Sha256,
+    #[value(name = "sha512")]
+    Sha512,
+    #[value(name = "sha3-256")]
+    Sha3_256,
+    #[value(name = "sha3-512")]
+    Sha3_512,
+    #[value(name = "blake3")]
+    Blake3,
+}
+
+impl HashAlgorithm {
+    /// Digest length in bytes
+    pub fn digest_len(self) -> usize {
+        match self {
+            HashAlgorithm::Sha256 | HashAlgorithm::Sha3_256 | HashAlgorithm::Blake3 => 32,
+            HashAlgorithm::Sha512 | HashAlgorithm::Sha3_512 => 64,
+        }
+    }
+}
+
+/// One line of a manifest
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Entry {
+    /// Lowercase hex digest
+    pub digest: String,
+    pub path: String,
+}
+
+/// Outcome of checking a manifest against the file system
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct CheckReport {
+    pub ok: Vec<String>,
+    pub modified: Vec<String>,
+    pub missing: Vec<String>,
+    /// Files below the manifest's directory that it does not list
+    pub extra: Vec<String>,
+}
+
+impl CheckReport {
+    pub fn is_clean(&self) -> bool {
+        self.modified.is_empty() && self.missing.is_empty() && self.extra.is_empty()
+    }
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn digest_with<D: Digest + Write, R: Read>(mut hasher: D, mut reader: R) -> io::Result<Vec<u8>> {
+    io::copy(&mut reader, &mut hasher)?;
+    Ok(hasher.finalize().to_vec())
+}
+
+/// Hex digest of everything in `reader`
+pub fn hash_reader<R: Read>(algorithm: HashAlgorithm, mut reader: R) -> io::Result<String> {
+    let digest = match algorithm {
+        HashAlgorithm::Sha256 => digest_with(Sha256::new(), reader)?,
+        HashAlgorithm::Sha512 => digest_with(Sha512::new(), reader)?,
+        HashAlgorithm::Sha3_256 => digest_with(Sha3_256::new(), reader)?,
+        HashAlgorithm::Sha3_512 => digest_with(Sha3_512::new(), reader)?,
+        HashAlgorithm::Blake3 => {
+            let mut hasher = blake3::Hasher::new();
+            io::copy(&mut reader, &mut hasher)?;
+            hasher.finalize().as_bytes().to_vec()
+        }
+    };
+    Ok(to_hex(&digest))
+}
+
+fn hash_file(algorithm: HashAlgorithm, path: &Path) -> io::Result<String> {
+    hash_reader(algorithm, BufReader::new(File::open(path)?))
+}
+
+fn path_str(path: &Path) -> Result<String> {
+    path.to_str()
+        .map(str::to_string)
+        .ok_or_else(|| CryptoError::InvalidFormat(format!("path is not valid UTF-8: {}", path.display())))
+}
+
+fn with_path(path: &Path) -> impl Fn(io::Error) -> CryptoError + '_ {
+    move |e| CryptoError::IoError(io::Error::new(e.kind(), format!("{}: {}", path.display(), e)))
+}
+
+/// Expand directories into the regular files below them
+///
+/// Files come out in the order given, with each directory sorted by name.
+/// Symbolic links inside directories are not followed.
+pub fn collect_files(paths: &[PathBuf]) -> Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    for path in paths {
+        for entry in WalkDir::new(path).sort_by_file_name() {
+            let entry = entry.map_err(|e| {
+                let path = e.path().unwrap_or(path).to_path_buf();
+                with_path(&path)(e.into())
+            })?;
+            if entry.file_type().is_file() {
+                files.push(entry.into_path());
+            }
+        }
+    }
+    Ok(files)
+}
+
+/// Hash files in parallel, keeping their order
+pub fn hash_files(algorithm: HashAlgorithm, files: &[PathBuf]) -> Result<Vec<Entry>> {
+    files
+        .par_iter()
+        .map(|path| {
+            let digest = hash_file(algorithm, path).map_err(with_path(path))?;
+            Ok(Entry { digest, path: path_str(path)? })
+        })
+        .collect()
+}
+
+/// Format one manifest line, without the newline
+pub fn format_line(entry: &Entry) -> String {
+    if entry.path.contains(['\\', '\n', '\r']) {
+        let path = entry.path.replace('\\', "\\\\").replace('\n', "\\n").replace('\r', "\\r");
+        format!("\\{}  {}", entry.digest, path)
+    } else {
+        format!("{}  {}", entry.digest, entry.path)
+    }
+}
+
+fn unescape(path: &str) -> Option<String> {
+    let mut unescaped = String::with_capacity(path.len());
+    let mut chars = path.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            unescaped.push(c);
+            continue;
+        }
+        match chars.next()? {
+            '\\' => unescaped.push('\\'),
+            'n' => unescaped.push('\n'),
+            'r' => unescaped.push('\r'),
+            _ => return None,
+        }
+    }
+    Some(unescaped)
+}
+
+/// Parse a manifest written by `hash` or `sha256sum` and friends
+///
+/// Blank lines are skipped. Digests must have the length of `algorithm`.
+pub fn parse_manifest(text: &str, algorithm: HashAlgorithm) -> Result<Vec<Entry>> {
+    let hex_len = 2 * algorithm.digest_len();
+    let mut entries = Vec::new();
+    for (number, line) in text.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let invalid = |reason: &str| CryptoError::InvalidFormat(format!("manifest line {}: {}", number + 1, reason));
+
+        let (escaped, line) = match line.strip_prefix('\\') {
+            Some(line) => (true, line),
+            None => (false, line),
+        };
+        let (digest, rest) = line.split_once(' ').ok_or_else(|| invalid("expected '<digest>  <path>'"))?;
+        if digest.len() != hex_len || !digest.bytes().all(|b| b.is_ascii_hexdigit()) {
+            return Err(invalid(&format!("expected a {}-character hex digest", hex_len)));
+        }
+        // A second space marks text mode and '*' binary mode; both hash the same bytes
+        let path = rest.strip_prefix([' ', '*']).ok_or_else(|| invalid("expected two spaces before the path"))?;
+        let path = if escaped { unescape(path).ok_or_else(|| invalid("bad escape in path"))? } else { path.to_string() };
+        if path.is_empty() {
+            return Err(invalid("empty path"));
+        }
+        entries.push(Entry { digest: digest.to_ascii_lowercase(), path });
+    }
+    Ok(entries)
+}
+
+/// Drop `.` components so `./a` and `a` compare equal
+pub(crate) fn normalize(path: &Path) -> PathBuf {
+    path.components().filter(|c| *c != Component::CurDir).collect()
+}
+
+/// Deepest directory that contains every entry
+fn common_dir(entries: &[Entry]) -> PathBuf {
+    let mut parents = entries.iter().map(|entry| normalize(Path::new(&entry.path)).parent().map(Path::to_path_buf));
+    let Some(Some(mut common)) = parents.next() else {
+        return PathBuf::new();
+    };
+    for parent in parents {
+        let parent = parent.unwrap_or_default();
+        while !parent.starts_with(&common) {
+            if !common.pop() {
+                break;
+            }
+        }
+    }
+    common
+}
+
+/// Verify `entries` with paths relative to `root`
+///
+/// Files that cannot be found are reported as missing. Every other file
+/// below the deepest directory holding all entries is reported as extra,
+/// except for the paths in `ignore` such as the manifest itself.
+pub fn check(entries: &[Entry], algorithm: HashAlgorithm, root: &Path, ignore: &[PathBuf]) -> Result<CheckReport> {
+    let results = entries
+        .par_iter()
+        .map(|entry| {
+            let path = root.join(&entry.path);
+            match hash_file(algorithm, &path) {
+                Ok(digest) => Ok(Some(digest == entry.digest)),
+                Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+                Err(e) => Err(with_path(&path)(e)),
+            }
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    let mut report = CheckReport::default();
+    for (entry, result) in entries.iter().zip(results) {
+        let list = match result {
+            Some(true) => &mut report.ok,
+            Some(false) => &mut report.modified,
+            None => &mut report.missing,
+        };
+        list.push(entry.path.clone());
+    }
+
+    if entries.is_empty() {
+        return Ok(report);
+    }
+    let common = common_dir(entries);
+    let scan_dir = root.join(&common);
+    if !scan_dir.is_dir() {
+        return Ok(report);
+    }
+    let listed: HashSet<PathBuf> = entries.iter().map(|entry| normalize(Path::new(&entry.path))).collect();
+    let ignore: HashSet<PathBuf> = ignore.iter().filter_map(|path| fs::canonicalize(path).ok()).collect();
+    for file in collect_files(std::slice::from_ref(&scan_dir))? {
+        let relative = common.join(file.strip_prefix(&scan_dir).unwrap_or(&file));
+        if listed.contains(&normalize(&relative)) {
+            continue;
+        }
+        if fs::canonicalize(&file).is_ok_and(|file| ignore.contains(&file)) {
+            continue;
+        }
+        report.extra.push(path_str(&relative)?);
+    }
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_known_digests() {
+        let cases = [
+            (HashAlgorithm::Sha256, "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"),
+            (
+                HashAlgorithm::Sha512,
+                "ddaf35a193617abacc417349ae20413112e6fa4e89a97ea20a9eeee64b55d39a\
+                 2192992a274fc1a836ba3c23a3feebbd454d4423643ce80e2a9ac94fa54ca49f",
+            ),
+            (HashAlgorithm::Sha3_256, "3a985da74fe225b2045c172d6bd390bd855f086e3e9d525b46bfe24511431532"),
+            (
+                HashAlgorithm::Sha3_512,
+                "b751850b1a57168a5693cd924b6b096e08f621827444f70d884f5d0240d2712e\
+                 10e116e9192af3c91a7ec57647e3934057340b4cf408d5a56592f8274eec53f0",
+            ),
+            (HashAlgorithm::Blake3, "6437b3ac38465133ffb63b75273a8db548c558465d79db03fd359c6cd5bd9d85"),
+        ];
+        for (algorithm, expected) in cases {
+            assert_eq!(hash_reader(algorithm, &b"abc"[..]).unwrap(), expected, "{:?}", algorithm);
+        }
+    }
+
+    #[test]
+    fn test_manifest_lines() {
+        let digest = "ab".repeat(32);
+        let entries = vec![
+            Entry { digest: digest.clone(), path: "dir/plain name.txt".to_string() },
+            Entry { digest: digest.clone(), path: "odd\\name\nwith newline".to_string() },
+        ];
+        let text: String = entries.iter().map(|entry| format_line(entry) + "\n").collect();
+        assert!(text.starts_with(&format!("{}  dir/plain name.txt\n\\{}  odd\\\\name\\n", digest, digest)));
+        assert_eq!(parse_manifest(&text, HashAlgorithm::Sha256).unwrap(), entries);
+
+        // Binary mode markers and uppercase digests from other tools
+        let binary = format!("{} *file.bin\n\n", digest.to_uppercase());
+        assert_eq!(parse_manifest(&binary, HashAlgorithm::Blake3).unwrap()[0].digest, digest);
+
+        assert!(parse_manifest(&text, HashAlgorithm::Sha512).is_err());
+        assert!(parse_manifest(&format!("{} file", digest), HashAlgorithm::Sha256).is_err());
+        assert!(parse_manifest("not a manifest", HashAlgorithm::Sha256).is_err());
+    }
+
+    #[test]
+    fn test_check_reports_changes() {
+        let root = std::env::temp_dir().join(format!("crypto-tool-hash-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(root.join("tree/sub")).unwrap();
+        for (name, contents) in [("tree/a", "a"), ("tree/b", "b"), ("tree/sub/c", "c")] {
+            fs::write(root.join(name), contents).unwrap();
+        }
+
+        let files = collect_files(&[root.join("tree")]).unwrap();
+        let names: Vec<_> = files.iter().map(|file| file.strip_prefix(&root).unwrap()).collect();
+        assert_eq!(names, [Path::new("tree/a"), Path::new("tree/b"), Path::new("tree/sub/c")]);
+
+        let mut entries = hash_files(HashAlgorithm::Sha256, &files).unwrap();
+        for entry in &mut entries {
+            entry.path = entry.path.strip_prefix(root.to_str().unwrap()).unwrap()[1..].to_string();
+        }
+        let clean = check(&entries, HashAlgorithm::Sha256, &root, &[]).unwrap();
+        assert!(clean.is_clean());
+        assert_eq!(clean.ok.len(), 3);
+
+        fs::write(root.join("tree/a"), "changed").unwrap();
+        fs::remove_file(root.join("tree/b")).unwrap();
+        fs::write(root.join("tree/sub/new"), "new").unwrap();
+        fs::write(root.join("tree/MANIFEST"), "ignored").unwrap();
+        let report = check(&entries, HashAlgorithm::Sha256, &root, &[root.join("tree/MANIFEST")]).unwrap();
+        assert_eq!(report.ok, ["tree/sub/c"]);
+        assert_eq!(report.modified, ["tree/a"]);
+        assert_eq!(report.missing, ["tree/b"]);
+        assert_eq!(report.extra, ["tree/sub/new"]);
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
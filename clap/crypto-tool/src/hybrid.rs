@@ -0,0 +1,183 @@
+use aes_gcm::Aes256Gcm;
+use aes_gcm::aead::{Aead, KeyInit, Payload};
+use rand::RngCore;
+use rand::rngs::OsRng;
+use rsa::{Oaep, RsaPrivateKey, RsaPublicKey};
+use sha2::Sha256;
+use zeroize::Zeroizing;
+
+use crate::keys;
+use crate::types::{CryptoError, Result};
+
+// Hybrid layout, all integers big-endian:
+//
+//   magic "CRTR" | version u8 | flags u8 | recipient count u16
+//   | per recipient: key fingerprint [32] | wrapped key len u16 | wrapped key
+//   | nonce [12] | AES-256-GCM ciphertext with auth tag
+//
+// The data key is random and wrapped for each recipient with RSA-OAEP
+// (SHA-256). Everything before the ciphertext is associated data. Version 1
+// is the same without the flags byte.
+
+pub const MAGIC: &[u8; 4] = b"CRTR";
+pub const VERSION: u8 = 2;
+const VERSION_WITHOUT_FLAGS: u8 = 1;
+
+/// The plaintext carries a signature trailer, see [`crate::sign`]
+const FLAG_SIGNED: u8 = 0x01;
+
+const DATA_KEY_LEN: usize = 32;
+const NONCE_LEN: usize = 12;
+
+/// A data key wrapped for one recipient
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Recipient {
+    pub fingerprint: [u8; 32],
+    pub wrapped_key: Vec<u8>,
+}
+
+/// Check whether `data` starts with the hybrid magic
+pub fn is_hybrid(data: &[u8]) -> bool {
+    data.starts_with(MAGIC)
+}
+
+/// Encrypt `data` so that any of `recipients` can decrypt it
+///
+/// `signed` marks the data as carrying a signature trailer.
+pub fn seal(data: &[u8], recipients: &[RsaPublicKey], signed: bool) -> Result<Vec<u8>> {
+    if recipients.is_empty() {
+        return Err(CryptoError::KeyError("RSA encryption needs at least one recipient".to_string()));
+    }
+    let count = u16::try_from(recipients.len())
+        .map_err(|_| CryptoError::KeyError("Too many recipients".to_string()))?;
+
+    let mut data_key = Zeroizing::new([0u8; DATA_KEY_LEN]);
+    let mut nonce = [0u8; NONCE_LEN];
+    OsRng.fill_bytes(data_key.as_mut());
+    OsRng.fill_bytes(&mut nonce);
+
+    let mut output = Vec::new();
+    output.extend_from_slice(MAGIC);
+    output.push(VERSION);
+    output.push(if signed { FLAG_SIGNED } else { 0 });
+    output.extend_from_slice(&count.to_be_bytes());
+    for recipient in recipients {
+        let wrapped_key = recipient
+            .encrypt(&mut OsRng, Oaep::new::<Sha256>(), data_key.as_ref())
+            .map_err(|e| CryptoError::CryptoError(e.to_string()))?;
+        output.extend_from_slice(&keys::fingerprint(recipient)?);
+        output.extend_from_slice(&(wrapped_key.len() as u16).to_be_bytes());
+        output.extend_from_slice(&wrapped_key);
+    }
+    output.extend_from_slice(&nonce);
+
+    let ciphertext = Aes256Gcm::new(data_key.as_ref().into())
+        .encrypt(&nonce.into(), Payload { msg: data, aad: &output })
+        .map_err(|e| CryptoError::CryptoError(e.to_string()))?;
+    output.extend_from_slice(&ciphertext);
+    Ok(output)
+}
+
+fn take<'a>(data: &'a [u8], pos: &mut usize, len: usize) -> Result<&'a [u8]> {
+    let bytes = data
+        .get(*pos..*pos + len)
+        .ok_or_else(|| CryptoError::InvalidFormat("truncated recipient header".to_string()))?;
+    *pos += len;
+    Ok(bytes)
+}
+
+/// Read the version and flags; returns whether the data is signed and the position after them
+fn parse_flags(data: &[u8]) -> Result<(bool, usize)> {
+    if !is_hybrid(data) {
+        return Err(CryptoError::InvalidFormat("missing RSA recipient header".to_string()));
+    }
+    let mut pos = MAGIC.len();
+    let flags = match take(data, &mut pos, 1)?[0] {
+        VERSION => take(data, &mut pos, 1)?[0],
+        VERSION_WITHOUT_FLAGS => 0,
+        version => return Err(CryptoError::UnsupportedVersion(version)),
+    };
+    if flags & !FLAG_SIGNED != 0 {
+        return Err(CryptoError::InvalidFormat(format!("unknown flags {:#04x}", flags)));
+    }
+    Ok((flags & FLAG_SIGNED != 0, pos))
+}
+
+/// Whether the header marks the plaintext as signed
+pub fn is_signed(data: &[u8]) -> Result<bool> {
+    parse_flags(data).map(|(signed, _)| signed)
+}
+
+/// Parse the header and return the recipients and the header length
+pub fn parse_recipients(data: &[u8]) -> Result<(Vec<Recipient>, usize)> {
+    let (_, mut pos) = parse_flags(data)?;
+    let count = u16::from_be_bytes(take(data, &mut pos, 2)?.try_into().unwrap());
+
+    let mut recipients = Vec::with_capacity(count as usize);
+    for _ in 0..count {
+        let fingerprint = take(data, &mut pos, 32)?.try_into().unwrap();
+        let len = u16::from_be_bytes(take(data, &mut pos, 2)?.try_into().unwrap()) as usize;
+        let wrapped_key = take(data, &mut pos, len)?.to_vec();
+        recipients.push(Recipient { fingerprint, wrapped_key });
+    }
+    take(data, &mut pos, NONCE_LEN)?;
+
+    Ok((recipients, pos))
+}
+
+/// Decrypt with the private key of one of the recipients
+pub fn open(data: &[u8], key: &RsaPrivateKey) -> Result<Vec<u8>> {
+    let (recipients, header_len) = parse_recipients(data)?;
+    let fingerprint = keys::fingerprint(&RsaPublicKey::from(key))?;
+
+    let recipient = recipients
+        .iter()
+        .find(|r| r.fingerprint == fingerprint)
+        .ok_or_else(|| CryptoError::KeyError("The file was not encrypted for this key".to_string()))?;
+    let data_key = Zeroizing::new(
+        key.decrypt(Oaep::new::<Sha256>(), &recipient.wrapped_key)
+            .map_err(|_| CryptoError::AuthenticationFailed)?,
+    );
+    if data_key.len() != DATA_KEY_LEN {
+        return Err(CryptoError::AuthenticationFailed);
+    }
+
+    let (aad, ciphertext) = data.split_at(header_len);
+    let nonce = &aad[header_len - NONCE_LEN..];
+    Aes256Gcm::new(data_key.as_slice().into())
+        .decrypt(nonce.into(), Payload { msg: ciphertext, aad })
+        .map_err(|_| CryptoError::AuthenticationFailed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_multiple_recipients() {
+        let alice = RsaPrivateKey::new(&mut OsRng, 1024).unwrap();
+        let bob = RsaPrivateKey::new(&mut OsRng, 1024).unwrap();
+        let eve = RsaPrivateKey::new(&mut OsRng, 1024).unwrap();
+        let data = vec![7u8; 10_000];
+
+        let sealed = seal(&data, &[RsaPublicKey::from(&alice), RsaPublicKey::from(&bob)], true).unwrap();
+        assert_eq!(parse_recipients(&sealed).unwrap().0.len(), 2);
+        assert!(is_signed(&sealed).unwrap());
+        assert_eq!(open(&sealed, &alice).unwrap(), data);
+        assert_eq!(open(&sealed, &bob).unwrap(), data);
+        assert!(matches!(open(&sealed, &eve), Err(CryptoError::KeyError(_))));
+
+        let mut modified = sealed.clone();
+        *modified.last_mut().unwrap() ^= 1;
+        assert!(matches!(open(&modified, &alice), Err(CryptoError::AuthenticationFailed)));
+
+        // The flag is authenticated like the rest of the header
+        let mut modified = sealed.clone();
+        modified[MAGIC.len() + 1] = 0;
+        assert!(!is_signed(&modified).unwrap());
+        assert!(matches!(open(&modified, &alice), Err(CryptoError::AuthenticationFailed)));
+
+        assert!(seal(&data, &[], false).is_err());
+        assert!(open(&sealed[..40], &alice).is_err());
+    }
+}
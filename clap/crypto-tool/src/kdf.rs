@@ -0,0 +1,336 @@
+use std::time::{Duration, Instant};
+
+use argon2::{Algorithm, Argon2, Version};
+use sha2::Sha256;
+use zeroize::Zeroizing;
+
+use crate::types::{CryptoError, Result};
+
+/// Upper bound on the memory a KDF may use, so a crafted header cannot exhaust it
+pub const MAX_MEMORY_KIB: u32 = 4 * 1024 * 1024;
+
+/// Upper bounds on the time costs, so a crafted header cannot keep decryption busy for hours
+pub const MAX_ARGON2_ITERATIONS: u32 = 256;
+pub const MAX_SCRYPT_P: u32 = 64;
+pub const MAX_PBKDF2_ITERATIONS: u32 = 100_000_000;
+
+/// Upper bound on memory times time cost in KiB (32 GiB), since the largest
+/// memory and time costs together would still take hours
+pub const MAX_WORK_KIB: u64 = 32 * 1024 * 1024;
+
+/// Password-based key derivation function and its parameters
+///
+/// The parameters are stored in the container header, so files keep
+/// decrypting after the defaults change.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Kdf {
+    /// Argon2id with memory in KiB, passes over memory and lanes
+    Argon2id { memory_kib: u32, iterations: u32, parallelism: u32 },
+    /// scrypt with N = 2^log_n, block size r and parallelization p
+    Scrypt { log_n: u8, r: u32, p: u32 },
+    Pbkdf2Sha256 { iterations: u32 },
+    /// No derivation: the secret is a key from a key file, used as is
+    RawKey,
+}
+
+impl Default for Kdf {
+    /// Argon2id with 64 MiB and 3 passes, the second recommendation of RFC 9106
+    fn default() -> Self {
+        Kdf::Argon2id { memory_kib: 64 * 1024, iterations: 3, parallelism: 1 }
+    }
+}
+
+/// KDF selected on the command line
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum KdfKind {
+    #[value(name = "argon2id")]
+    Argon2id,
+    #[value(name = "scrypt")]
+    Scrypt,
+    #[value(name = "pbkdf2")]
+    Pbkdf2,
+}
+
+impl KdfKind {
+    /// Default parameters for this KDF
+    pub fn defaults(self) -> Kdf {
+        match self {
+            KdfKind::Argon2id => Kdf::default(),
+            // 2^17 * 128 * 8 bytes = 128 MiB
+            KdfKind::Scrypt => Kdf::Scrypt { log_n: 17, r: 8, p: 1 },
+            KdfKind::Pbkdf2 => Kdf::Pbkdf2Sha256 { iterations: 600_000 },
+        }
+    }
+}
+
+impl Kdf {
+    /// Apply CLI cost settings: memory in MiB and a time cost
+    ///
+    /// The time cost is the number of passes for Argon2id, the
+    /// parallelization factor p for scrypt and the iteration count for
+    /// PBKDF2. scrypt rounds memory down to a power of two.
+    pub fn with_costs(self, memory_mib: Option<u32>, time_cost: Option<u32>) -> Result<Kdf> {
+        let kdf = match (self, memory_mib) {
+            (Kdf::Argon2id { iterations, parallelism, .. }, Some(mib)) => {
+                Kdf::Argon2id { memory_kib: mib.saturating_mul(1024), iterations, parallelism }
+            }
+            (Kdf::Scrypt { r, p, .. }, Some(mib)) => {
+                // memory = 128 * r * N bytes
+                let blocks = (u64::from(mib) << 20) / (128 * u64::from(r.max(1)));
+                Kdf::Scrypt { log_n: blocks.max(2).ilog2() as u8, r, p }
+            }
+            (Kdf::Pbkdf2Sha256 { .. }, Some(_)) => {
+                return Err(CryptoError::CryptoError("PBKDF2 has no memory cost".to_string()));
+            }
+            (Kdf::RawKey, Some(_)) => {
+                return Err(CryptoError::CryptoError("A raw key has no memory cost".to_string()));
+            }
+            (kdf, None) => kdf,
+        };
+        let kdf = match time_cost {
+            Some(cost) => kdf.with_time_cost(cost),
+            None => kdf,
+        };
+        kdf.validate()?;
+        Ok(kdf)
+    }
+
+    fn with_time_cost(self, cost: u32) -> Kdf {
+        match self {
+            Kdf::Argon2id { memory_kib, parallelism, .. } => Kdf::Argon2id { memory_kib, iterations: cost, parallelism },
+            Kdf::Scrypt { log_n, r, .. } => Kdf::Scrypt { log_n, r, p: cost },
+            Kdf::Pbkdf2Sha256 { .. } => Kdf::Pbkdf2Sha256 { iterations: cost },
+            Kdf::RawKey => Kdf::RawKey,
+        }
+    }
+
+    /// Largest time cost [`Kdf::validate`] accepts, lower the more memory each pass fills
+    fn max_time_cost(self) -> u32 {
+        let limit = match self {
+            Kdf::Argon2id { .. } => MAX_ARGON2_ITERATIONS,
+            Kdf::Scrypt { .. } => MAX_SCRYPT_P,
+            Kdf::Pbkdf2Sha256 { .. } => MAX_PBKDF2_ITERATIONS,
+            Kdf::RawKey => u32::MAX,
+        };
+        match self.memory_kib() {
+            0 => limit,
+            memory => limit.min(u32::try_from(MAX_WORK_KIB / memory).unwrap_or(u32::MAX)),
+        }
+    }
+
+    /// Memory the KDF needs, in KiB
+    pub fn memory_kib(self) -> u64 {
+        match self {
+            Kdf::Argon2id { memory_kib, .. } => u64::from(memory_kib),
+            Kdf::Scrypt { log_n, r, .. } => (128 * u64::from(r)).checked_shl(u32::from(log_n)).unwrap_or(u64::MAX) / 1024,
+            Kdf::Pbkdf2Sha256 { .. } | Kdf::RawKey => 0,
+        }
+    }
+
+    pub(crate) fn id(self) -> u8 {
+        match self {
+            Kdf::Pbkdf2Sha256 { .. } => 1,
+            Kdf::Argon2id { .. } => 2,
+            Kdf::Scrypt { .. } => 3,
+            Kdf::RawKey => 4,
+        }
+    }
+
+    pub(crate) fn encode_params(self) -> Vec<u8> {
+        let mut params = Vec::new();
+        match self {
+            Kdf::Pbkdf2Sha256 { iterations } => params.extend_from_slice(&iterations.to_be_bytes()),
+            Kdf::Argon2id { memory_kib, iterations, parallelism } => {
+                params.extend_from_slice(&memory_kib.to_be_bytes());
+                params.extend_from_slice(&iterations.to_be_bytes());
+                params.extend_from_slice(&parallelism.to_be_bytes());
+            }
+            Kdf::Scrypt { log_n, r, p } => {
+                params.push(log_n);
+                params.extend_from_slice(&r.to_be_bytes());
+                params.extend_from_slice(&p.to_be_bytes());
+            }
+            Kdf::RawKey => {}
+        }
+        params
+    }
+
+    pub(crate) fn decode(id: u8, params: &[u8]) -> Result<Kdf> {
+        let word = |i: usize| u32::from_be_bytes([params[i], params[i + 1], params[i + 2], params[i + 3]]);
+        let kdf = match (id, params.len()) {
+            (1, 4) => Kdf::Pbkdf2Sha256 { iterations: word(0) },
+            (2, 12) => Kdf::Argon2id { memory_kib: word(0), iterations: word(4), parallelism: word(8) },
+            (3, 9) => Kdf::Scrypt { log_n: params[0], r: word(1), p: word(5) },
+            (4, 0) => Kdf::RawKey,
+            (1..=4, _) => return Err(CryptoError::InvalidFormat(format!("bad parameters for KDF id {}", id))),
+            _ => return Err(CryptoError::InvalidFormat(format!("unknown KDF id {}", id))),
+        };
+        kdf.validate()?;
+        Ok(kdf)
+    }
+
+    /// Check the parameters, including the memory and time cost limits
+    pub fn validate(self) -> Result<()> {
+        let invalid = |e: String| Err(CryptoError::InvalidFormat(e));
+        if self.memory_kib() > u64::from(MAX_MEMORY_KIB) {
+            return invalid(format!("KDF needs {} MiB, more than the limit of 4 GiB", self.memory_kib() / 1024));
+        }
+        let too_slow = match self {
+            Kdf::Argon2id { iterations, .. } | Kdf::Pbkdf2Sha256 { iterations } => iterations > self.max_time_cost(),
+            Kdf::Scrypt { p, .. } => p > self.max_time_cost(),
+            Kdf::RawKey => false,
+        };
+        if too_slow {
+            return invalid(format!("KDF time cost is above the limit of {}", self.max_time_cost()));
+        }
+        match self {
+            Kdf::Pbkdf2Sha256 { iterations: 0 } => invalid("PBKDF2 needs at least one iteration".to_string()),
+            Kdf::Pbkdf2Sha256 { .. } | Kdf::RawKey => Ok(()),
+            Kdf::Argon2id { memory_kib, iterations, parallelism } => {
+                argon2::Params::new(memory_kib, iterations, parallelism, Some(32))
+                    .map(|_| ())
+                    .or_else(|e| invalid(format!("Argon2id: {}", e)))
+            }
+            Kdf::Scrypt { log_n, r, p } => scrypt::Params::new(log_n, r, p, 32)
+                .map(|_| ())
+                .or_else(|e| invalid(format!("scrypt: {}", e))),
+        }
+    }
+
+    /// Derive a key of `len` bytes from a password and salt
+    ///
+    /// [`Kdf::RawKey`] returns the "password" itself, which must be `len` bytes.
+    pub fn derive_key(self, password: &[u8], salt: &[u8], len: usize) -> Result<Zeroizing<Vec<u8>>> {
+        let mut key = Zeroizing::new(vec![0u8; len]);
+        let failed = |e: String| CryptoError::CryptoError(format!("Key derivation failed: {}", e));
+        match self {
+            Kdf::Pbkdf2Sha256 { iterations } => {
+                pbkdf2::pbkdf2_hmac::<Sha256>(password, salt, iterations, &mut key);
+            }
+            Kdf::Argon2id { memory_kib, iterations, parallelism } => {
+                let params = argon2::Params::new(memory_kib, iterations, parallelism, Some(len))
+                    .map_err(|e| failed(e.to_string()))?;
+                Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+                    .hash_password_into(password, salt, &mut key)
+                    .map_err(|e| failed(e.to_string()))?;
+            }
+            Kdf::Scrypt { log_n, r, p } => {
+                let params = scrypt::Params::new(log_n, r, p, len).map_err(|e| failed(e.to_string()))?;
+                scrypt::scrypt(password, salt, &params, &mut key).map_err(|e| failed(e.to_string()))?;
+            }
+            Kdf::RawKey if password.len() == len => key.copy_from_slice(password),
+            Kdf::RawKey => return Err(CryptoError::InvalidKeyLength),
+        }
+        Ok(key)
+    }
+}
+
+/// Result of [`calibrate`]
+#[derive(Clone, Copy, Debug)]
+pub struct Calibration {
+    pub kdf: Kdf,
+    pub elapsed: Duration,
+}
+
+fn time_derivation(kdf: Kdf) -> Result<Duration> {
+    let start = Instant::now();
+    kdf.derive_key(b"calibration password", &[0u8; 16], 32)?;
+    Ok(start.elapsed())
+}
+
+/// Find the time cost that makes `base` take about `target` to unlock
+///
+/// The memory cost stays as given. Time grows linearly with the time cost,
+/// so one measurement at a low cost gives the estimate, and a second run
+/// at the estimate reports the actual time on this machine. The cost is
+/// capped at the limit [`Kdf::validate`] enforces.
+pub fn calibrate(base: Kdf, target: Duration) -> Result<Calibration> {
+    let probe_cost = match base {
+        Kdf::Pbkdf2Sha256 { .. } => 10_000,
+        _ => 1,
+    };
+    let probe = base.with_time_cost(probe_cost);
+    let probe_time = time_derivation(probe)?.max(Duration::from_micros(1));
+
+    let scale = target.as_secs_f64() / probe_time.as_secs_f64();
+    let cost = (f64::from(probe_cost) * scale).round().clamp(1.0, f64::from(base.max_time_cost())) as u32;
+    let kdf = base.with_time_cost(cost);
+    kdf.validate()?;
+
+    let elapsed = if kdf == probe { probe_time } else { time_derivation(kdf)? };
+    Ok(Calibration { kdf, elapsed })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_params_round_trip_and_limits() {
+        let kdfs = [
+            Kdf::Argon2id { memory_kib: 1024, iterations: 1, parallelism: 1 },
+            Kdf::Scrypt { log_n: 4, r: 8, p: 1 },
+            Kdf::Pbkdf2Sha256 { iterations: 100 },
+        ];
+        for kdf in kdfs {
+            assert_eq!(Kdf::decode(kdf.id(), &kdf.encode_params()).unwrap(), kdf);
+            let key = kdf.derive_key(b"password", b"saltsaltsalt", 32).unwrap();
+            assert_eq!(key, kdf.derive_key(b"password", b"saltsaltsalt", 32).unwrap());
+            assert_ne!(key, kdf.derive_key(b"passw0rd", b"saltsaltsalt", 32).unwrap());
+        }
+
+        let huge = Kdf::Argon2id { memory_kib: u32::MAX, iterations: 1, parallelism: 1 };
+        assert!(Kdf::decode(huge.id(), &huge.encode_params()).is_err());
+        let huge = Kdf::Scrypt { log_n: 40, r: 8, p: 1 };
+        assert!(Kdf::decode(huge.id(), &huge.encode_params()).is_err());
+
+        // Time costs over the limits, which would otherwise run for hours
+        let slow = [
+            Kdf::Argon2id { memory_kib: 1024, iterations: MAX_ARGON2_ITERATIONS + 1, parallelism: 1 },
+            Kdf::Scrypt { log_n: 4, r: 8, p: MAX_SCRYPT_P + 1 },
+            Kdf::Pbkdf2Sha256 { iterations: u32::MAX },
+        ];
+        for kdf in slow {
+            assert!(Kdf::decode(kdf.id(), &kdf.encode_params()).is_err(), "{:?}", kdf);
+            assert!(Kdf::decode(kdf.id(), &kdf.with_time_cost(kdf.max_time_cost()).encode_params()).is_ok());
+        }
+        assert!(Kdf::decode(2, &[0; 4]).is_err());
+
+        // Each cost within its limit, but together far too slow
+        let slow = [
+            Kdf::Argon2id { memory_kib: MAX_MEMORY_KIB, iterations: MAX_ARGON2_ITERATIONS, parallelism: 1 },
+            Kdf::Scrypt { log_n: 22, r: 8, p: MAX_SCRYPT_P },
+        ];
+        for kdf in slow {
+            assert!(Kdf::decode(kdf.id(), &kdf.encode_params()).is_err(), "{:?}", kdf);
+            assert!(kdf.with_time_cost(8).validate().is_ok(), "{:?}", kdf);
+            assert!(kdf.with_time_cost(9).validate().is_err(), "{:?}", kdf);
+        }
+
+        assert_eq!(Kdf::decode(Kdf::RawKey.id(), &[]).unwrap(), Kdf::RawKey);
+        assert_eq!(*Kdf::RawKey.derive_key(&[9; 32], b"ignored", 32).unwrap(), [9; 32]);
+        assert!(matches!(Kdf::RawKey.derive_key(b"password", b"salt", 32), Err(CryptoError::InvalidKeyLength)));
+    }
+
+    #[test]
+    fn test_cli_costs() {
+        let kdf = KdfKind::Argon2id.defaults().with_costs(Some(32), Some(2)).unwrap();
+        assert_eq!(kdf, Kdf::Argon2id { memory_kib: 32 * 1024, iterations: 2, parallelism: 1 });
+
+        let kdf = KdfKind::Scrypt.defaults().with_costs(Some(16), None).unwrap();
+        assert_eq!(kdf, Kdf::Scrypt { log_n: 14, r: 8, p: 1 });
+        assert_eq!(kdf.memory_kib(), 16 * 1024);
+
+        assert!(KdfKind::Pbkdf2.defaults().with_costs(Some(16), None).is_err());
+        assert!(KdfKind::Pbkdf2.defaults().with_costs(None, Some(0)).is_err());
+        assert!(KdfKind::Argon2id.defaults().with_costs(None, Some(MAX_ARGON2_ITERATIONS + 1)).is_err());
+    }
+
+    #[test]
+    fn test_calibrate_scales_time_cost() {
+        let base = Kdf::Pbkdf2Sha256 { iterations: 1 };
+        let calibration = calibrate(base, Duration::from_millis(20)).unwrap();
+        let Kdf::Pbkdf2Sha256 { iterations } = calibration.kdf else { panic!("KDF changed") };
+        assert!(iterations > 1);
+    }
+}
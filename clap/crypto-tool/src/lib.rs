@@ -1,5 +1,14 @@
+pub mod archive;
+pub mod container;
 pub mod encrypt;
 pub mod file;
+pub mod hash;
+pub mod hybrid;
+pub mod kdf;
+pub mod keys;
+pub mod secret;
+pub mod sign;
+pub mod stream;
 pub mod types;
 
 // pub use encrypt::encrypt_data;
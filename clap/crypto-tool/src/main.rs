@@ -1,9 +1,27 @@
-use clap::{Parser, Subcommand};
+use std::fs;
+use std::io::{ErrorKind, PipeReader, Read, Write};
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::Duration;
+
+use clap::{Parser, Subcommand, ValueEnum};
 use base64::{Engine as _, engine::general_purpose};
+use indicatif::{ProgressBar, ProgressStyle};
 
-use crypto_tool::file::{read_input_file, write_output};
-use crypto_tool::types::{CryptoMethod, Result};
-use crypto_tool::encrypt::{encrypt_data, decrypt_data};
+use crypto_tool::archive::{self, EntryKind};
+use crypto_tool::container::{Cipher, Header};
+use crypto_tool::hybrid;
+use crypto_tool::file::{check_distinct, create_output, open_input, read_input, write_output, write_private_key};
+use crypto_tool::stream::{DEFAULT_CHUNK_SIZE, decrypt_stream_body, encrypt_stream};
+use crypto_tool::hash::{self, HashAlgorithm};
+use crypto_tool::kdf::{Kdf, KdfKind, calibrate};
+use crypto_tool::keys::{self, Key, KeyAlgorithm, PrivateKey, PublicKey};
+use crypto_tool::secret::{KeyPasswordArgs, Secret, SecretArgs, prompt_password};
+use crypto_tool::sign::{self, DataDigest, Signature, SigningReader, VerifyingWriter};
+use crypto_tool::types::{CryptoMethod, CryptoError, Result};
+use crypto_tool::encrypt::{
+    encrypt_data, decrypt_data, decrypt_legacy, encrypt_for_recipients, decrypt_with_private_key,
+};
 
 #[derive(Parser, Debug)]
 #[command(name = "crypto-tool")]
@@ -18,32 +36,54 @@ struct Args {
 enum Commands {
      #[command(about = "Encrypt a file")]
     Encrypt {
-        /// Input file path
-        #[arg(short = 'i', long = "input", value_name = "FILE")]
-        input: String,
+        /// Input file or directory path (optional, defaults to stdin)
+        #[arg(short = 'i', long = "input", value_name = "PATH")]
+        input: Option<String>,
 
         /// Output file path (optional, defaults to stdout)
         #[arg(short = 'o', long = "output", value_name = "FILE")]
         output: Option<String>,
 
-        /// Encryption method
+        /// Encryption method; des only decrypts files of older versions
         #[arg(long = "method", value_enum, default_value = "aes")]
         method: CryptoMethod,
 
-        /// Password for encryption
-        #[arg(long = "password")]
-        password: String,
+        #[command(flatten)]
+        secret: SecretArgs,
+
+        /// Public key file of an RSA recipient (repeat for several recipients)
+        #[arg(short = 'r', long = "recipient", value_name = "KEY")]
+        recipients: Vec<String>,
+
+        /// Sign the data with this private key before encrypting it
+        #[arg(long = "sign-key", value_name = "KEY")]
+        sign_key: Option<String>,
+
+        #[command(flatten)]
+        sign_key_password: KeyPasswordArgs,
 
         /// Enable Base64 encoding
         #[arg(long = "base64")]
         base64: bool,
+
+        /// Key derivation function for the aes and chacha20 methods
+        #[arg(long = "kdf", value_enum, default_value = "argon2id")]
+        kdf: KdfKind,
+
+        /// KDF memory cost in MiB (Argon2id, scrypt)
+        #[arg(long = "kdf-memory", value_name = "MIB")]
+        kdf_memory: Option<u32>,
+
+        /// KDF time cost: Argon2id passes, scrypt p or PBKDF2 iterations
+        #[arg(long = "kdf-time", value_name = "COST")]
+        kdf_time: Option<u32>,
     },
 
     #[command(about = "Decrypt a file")]
     Decrypt {
-        /// Input file path
+        /// Input file path (optional, defaults to stdin)
         #[arg(short = 'i', long = "input", value_name = "FILE")]
-        input: String,
+        input: Option<String>,
 
         /// Output file path (optional, defaults to stdout)
         #[arg(short = 'o', long = "output", value_name = "FILE")]
@@ -53,34 +93,238 @@ enum Commands {
         #[arg(long = "method", value_enum, default_value = "aes")]
         method: CryptoMethod,
 
-        /// Password for decryption
-        #[arg(long = "password")]
-        password: String,
+        #[command(flatten)]
+        secret: SecretArgs,
+
+        /// Private key file for the rsa method; the password options then unlock this key
+        #[arg(long = "key", value_name = "KEY")]
+        key: Option<String>,
+
+        /// Require a valid signature from this public key after decrypting
+        #[arg(long = "verify-key", value_name = "KEY")]
+        verify_key: Option<String>,
 
         /// Input is Base64 encoded
         #[arg(long = "base64")]
         base64: bool,
-    }
+
+        /// Read the unauthenticated AES-ECB or DES format of older versions
+        #[arg(long = "legacy")]
+        legacy: bool,
+    },
+
+    #[command(about = "Calibrate the KDF time cost to a target unlock time")]
+    BenchKdf {
+        /// Key derivation function to calibrate
+        #[arg(long = "kdf", value_enum, default_value = "argon2id")]
+        kdf: KdfKind,
+
+        /// KDF memory cost in MiB (Argon2id, scrypt)
+        #[arg(long = "kdf-memory", value_name = "MIB")]
+        kdf_memory: Option<u32>,
+
+        /// Target unlock time in milliseconds
+        #[arg(long = "target-ms", default_value_t = 1000)]
+        target_ms: u64,
+    },
+
+    #[command(about = "Generate an RSA or Ed25519 private key")]
+    Keygen {
+        /// Private key output file (PKCS#8 PEM)
+        #[arg(short = 'o', long = "output", value_name = "FILE")]
+        output: String,
+
+        /// Also write the public key to this file
+        #[arg(long = "public-output", value_name = "FILE")]
+        public_output: Option<String>,
+
+        /// Key algorithm: rsa for encryption and signing, ed25519 for signing
+        #[arg(long = "algorithm", value_enum, default_value = "rsa")]
+        algorithm: KeyAlgorithm,
+
+        /// RSA key size in bits
+        #[arg(long = "bits", default_value_t = 3072)]
+        bits: usize,
+
+        #[command(flatten)]
+        secret: SecretArgs,
+    },
+
+    #[command(about = "Write the public key of a private key")]
+    ExportPublic {
+        /// Private key file
+        #[arg(short = 'i', long = "input", value_name = "FILE")]
+        input: String,
+
+        /// Output file path (optional, defaults to stdout)
+        #[arg(short = 'o', long = "output", value_name = "FILE")]
+        output: Option<String>,
+
+        #[command(flatten)]
+        secret: SecretArgs,
+    },
+
+    #[command(about = "Convert a PKCS#1 or PKCS#8 key to the format of this tool")]
+    Import {
+        /// Key file to import (PEM)
+        #[arg(short = 'i', long = "input", value_name = "FILE")]
+        input: String,
+
+        /// Output file: PKCS#8 for private keys, SubjectPublicKeyInfo for public keys
+        #[arg(short = 'o', long = "output", value_name = "FILE")]
+        output: String,
+
+        #[command(flatten)]
+        secret: SecretArgs,
+
+        #[command(flatten)]
+        key_password: KeyPasswordArgs,
+    },
+
+    #[command(about = "Write a detached signature of a file")]
+    Sign {
+        /// File to sign
+        #[arg(short = 'i', long = "input", value_name = "FILE")]
+        input: String,
+
+        /// Signature output file (defaults to <input>.sig)
+        #[arg(short = 'o', long = "output", value_name = "FILE")]
+        output: Option<String>,
+
+        /// Private key: Ed25519, or RSA for RSA-PSS
+        #[arg(long = "key", value_name = "KEY")]
+        key: String,
+
+        #[command(flatten)]
+        secret: SecretArgs,
+    },
+
+    #[command(about = "Check a detached signature of a file")]
+    Verify {
+        /// Signed file
+        #[arg(short = 'i', long = "input", value_name = "FILE")]
+        input: String,
+
+        /// Signature file (defaults to <input>.sig)
+        #[arg(short = 's', long = "signature", value_name = "FILE")]
+        signature: Option<String>,
+
+        /// Public key of the signer
+        #[arg(long = "key", value_name = "KEY")]
+        key: String,
+    },
+
+    #[command(about = "Hash files and directories, or check a checksum manifest")]
+    Hash {
+        /// Files or directories to hash (defaults to stdin)
+        #[arg(value_name = "PATH", conflicts_with = "check")]
+        paths: Vec<String>,
+
+        /// Digest algorithm
+        #[arg(short = 'a', long = "algorithm", value_enum, default_value = "sha256")]
+        algorithm: HashAlgorithm,
+
+        /// Manifest output file (optional, defaults to stdout)
+        #[arg(short = 'o', long = "output", value_name = "FILE", conflicts_with = "check")]
+        output: Option<String>,
+
+        /// Verify the files listed in a manifest
+        #[arg(short = 'c', long = "check", value_name = "MANIFEST")]
+        check: Option<String>,
+
+        /// Directory the manifest paths are relative to (defaults to the current directory)
+        #[arg(long = "root", value_name = "DIR", requires = "check")]
+        root: Option<String>,
+
+        /// Only report files that failed the check
+        #[arg(short = 'q', long = "quiet", requires = "check")]
+        quiet: bool,
+    },
+
+    #[command(about = "Extract all or part of an encrypted directory archive")]
+    Extract {
+        /// Encrypted archive (optional, defaults to stdin)
+        #[arg(short = 'i', long = "input", value_name = "FILE")]
+        input: Option<String>,
+
+        /// Directory to extract into
+        #[arg(short = 'o', long = "output", value_name = "DIR", default_value = ".")]
+        output: String,
+
+        /// Paths inside the archive to extract (defaults to everything)
+        #[arg(value_name = "PATH")]
+        paths: Vec<String>,
+
+        #[command(flatten)]
+        secret: SecretArgs,
+
+        /// Require a valid signature from this public key
+        #[arg(long = "verify-key", value_name = "KEY")]
+        verify_key: Option<String>,
+    },
+
+    #[command(about = "List the contents of an encrypted directory archive")]
+    List {
+        /// Encrypted archive (optional, defaults to stdin)
+        #[arg(short = 'i', long = "input", value_name = "FILE")]
+        input: Option<String>,
+
+        #[command(flatten)]
+        secret: SecretArgs,
+    },
 }
 
 fn main() {
     let args = Args::parse();
 
    let result = match args.command {
-        Commands::Encrypt { input, output, method, password, base64 } => handle_encrypt(
-            &input,
-            output.as_ref(),
-            &method,
-            &password,
-            base64,
-        ),
-         Commands::Decrypt { input, output, method, password, base64 } => handle_decrypt(
-            &input,
-            output.as_ref(),
-            &method,
-            &password,
-            base64,
-        ),
+        Commands::Encrypt {
+            input, output, method, secret, recipients, sign_key, sign_key_password, base64, kdf, kdf_memory, kdf_time,
+        } => {
+            kdf.defaults().with_costs(kdf_memory, kdf_time).and_then(|kdf| handle_encrypt(
+                input.as_deref(),
+                output.as_ref(),
+                &method,
+                secret,
+                &recipients,
+                sign_key.as_deref(),
+                sign_key_password,
+                base64,
+                kdf,
+            ))
+        }
+         Commands::Decrypt { input, output, method, secret, key, verify_key, base64, legacy } => verify_key
+            .map(|path| keys::load_public(Path::new(&path), None))
+            .transpose()
+            .and_then(|verify_key| handle_decrypt(
+                input.as_deref(),
+                output.as_ref(),
+                &method,
+                secret,
+                key.as_deref(),
+                verify_key.as_ref(),
+                base64,
+                legacy,
+            )),
+        Commands::BenchKdf { kdf, kdf_memory, target_ms } => {
+            handle_bench_kdf(kdf, kdf_memory, Duration::from_millis(target_ms))
+        }
+        Commands::Keygen { output, public_output, algorithm, bits, secret } => {
+            handle_keygen(&output, public_output.as_deref(), algorithm, bits, secret)
+        }
+        Commands::ExportPublic { input, output, secret } => handle_export_public(&input, output.as_ref(), secret),
+        Commands::Import { input, output, secret, key_password } => handle_import(&input, &output, secret, key_password),
+        Commands::Sign { input, output, key, secret } => handle_sign(&input, output.as_deref(), &key, secret),
+        Commands::Verify { input, signature, key } => handle_verify(&input, signature.as_deref(), &key),
+        Commands::Hash { algorithm, check: Some(manifest), root, quiet, .. } => {
+            handle_hash_check(&manifest, algorithm, root.as_deref(), quiet)
+        }
+        Commands::Hash { paths, algorithm, output, check: None, .. } => handle_hash(&paths, algorithm, output.as_ref()),
+        Commands::Extract { input, output, paths, secret, verify_key } => verify_key
+            .map(|path| keys::load_public(Path::new(&path), None))
+            .transpose()
+            .and_then(|verify_key| handle_extract(input.as_deref(), &output, &paths, secret, verify_key.as_ref())),
+        Commands::List { input, secret } => handle_list(input.as_deref(), secret),
     };
 
     if let Err(e) = result {
@@ -89,56 +333,494 @@ fn main() {
     }
 }
 
+#[allow(clippy::too_many_arguments)]
 fn handle_encrypt(
-    input: &str,
+    input: Option<&str>,
     output: Option<&String>,
     method: &CryptoMethod,
-    password: &str,
+    secret: SecretArgs,
+    recipients: &[String],
+    sign_key: Option<&str>,
+    sign_key_password: KeyPasswordArgs,
     use_base64: bool,
+    kdf: Kdf,
 ) -> Result<()> {
-    println!("Encrypting file '{}' using {:?}...", input, method);
+    check_distinct(input, output)?;
+    let sign_key = load_sign_key(sign_key, sign_key_password)?;
+    let sign_key = sign_key.as_ref();
+    let secret = match method {
+        CryptoMethod::Rsa if secret.is_given() => {
+            return Err(CryptoError::KeyError("--method rsa encrypts for --recipient keys, not a password".to_string()));
+        }
+        CryptoMethod::Rsa => None,
+        CryptoMethod::Des => {
+            return Err(CryptoError::CryptoError("--method des is decrypt-only; encrypt with aes or chacha20".to_string()));
+        }
+        _ => Some(secret.resolve_or_prompt(true)?),
+    };
+    let kdf = secret.as_ref().map_or(kdf, |secret| secret.kdf(kdf));
+    eprintln!("Encrypting {} using {:?}...", describe_input(input), method);
 
-    let data = read_input_file(input)?;
-    let encrypted = encrypt_data(&data, method, password)?;
+    let cipher = match method {
+        CryptoMethod::Aes => Some(Cipher::Aes256Gcm),
+        CryptoMethod::Chacha20 => Some(Cipher::ChaCha20Poly1305),
+        _ => None,
+    };
+    if cipher.is_some() && !recipients.is_empty() {
+        return Err(CryptoError::KeyError("--recipient needs --method rsa".to_string()));
+    }
+    let directory = input.map(Path::new).filter(|path| path.is_dir());
+    if directory.is_some() && cipher.is_none() {
+        return Err(CryptoError::ArchiveError("directories can only be encrypted with aes or chacha20".to_string()));
+    }
 
-    let final_data = if use_base64 {
-        general_purpose::STANDARD.encode(&encrypted).into_bytes()
+    if let Some(cipher) = cipher {
+        // Stream with bounded memory
+        let secret = require_secret(secret.as_ref())?;
+        let (reader, len) = match directory {
+            Some(directory) => (Box::new(archive::pack(directory)?) as Box<dyn Read>, None),
+            None => open_input(input)?,
+        };
+        let bar = progress_bar(len);
+        let reader = bar.wrap_read(reader);
+        with_output(output, use_base64, |writer| match sign_key {
+            Some(key) => {
+                let reader = SigningReader::new(reader, key);
+                encrypt_stream(reader, writer, secret, cipher, kdf, DEFAULT_CHUNK_SIZE, true, &mut |_| {})
+            }
+            None => encrypt_stream(reader, writer, secret, cipher, kdf, DEFAULT_CHUNK_SIZE, false, &mut |_| {}),
+        })?;
+        bar.finish_and_clear();
     } else {
-        encrypted
-    };
+        let mut data = read_input(input)?;
+        if let Some(key) = sign_key {
+            let mut signed = Vec::new();
+            SigningReader::new(&data[..], key).read_to_end(&mut signed)?;
+            data = signed;
+        }
+
+        let encrypted = match method {
+            CryptoMethod::Rsa => {
+                let keys = recipients
+                    .iter()
+                    .map(|path| keys::load_rsa_public(Path::new(path), None))
+                    .collect::<Result<Vec<_>>>()?;
+                encrypt_for_recipients(&data, &keys, sign_key.is_some())?
+            }
+            _ if !recipients.is_empty() => {
+                return Err(CryptoError::KeyError("--recipient needs --method rsa".to_string()));
+            }
+            _ => encrypt_data(&data, method, require_secret(secret.as_ref())?, kdf)?,
+        };
 
-    write_output(&final_data, output)?;
+        let final_data = if use_base64 {
+            general_purpose::STANDARD.encode(&encrypted).into_bytes()
+        } else {
+            encrypted
+        };
+
+        write_output(&final_data, output)?;
+    }
 
     if output.is_some() {
-        println!("Encryption completed successfully!");
+        eprintln!("Encryption completed successfully!");
     }
 
     Ok(())
 }
 
+/// The key to sign with before encrypting, if any
+fn load_sign_key(path: Option<&str>, password: KeyPasswordArgs) -> Result<Option<PrivateKey>> {
+    match path {
+        Some(path) => Ok(Some(load_private_key(Path::new(path), password.resolve()?)?)),
+        None if password.is_given() => Err(CryptoError::KeyError("--key-password needs --sign-key".to_string())),
+        None => Ok(None),
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
 fn handle_decrypt(
-    input: &str,
+    input: Option<&str>,
     output: Option<&String>,
     method: &CryptoMethod,
-    password: &str,
+    secret: SecretArgs,
+    key: Option<&str>,
+    verify_key: Option<&PublicKey>,
     use_base64: bool,
+    legacy: bool,
 ) -> Result<()> {
-    println!("Decrypting file '{}' using {:?}...", input, method);
+    check_distinct(input, output)?;
+    // With --key the secret, if any, unlocks the private key
+    let secret = match key {
+        Some(_) => secret.resolve()?,
+        None => Some(secret.resolve_or_prompt(false)?),
+    };
+    eprintln!("Decrypting {} using {:?}...", describe_input(input), method);
 
-    let mut data = read_input_file(input)?;
+    let streamed = matches!(method, CryptoMethod::Aes | CryptoMethod::Chacha20) && !legacy && key.is_none();
+    if streamed {
+        let secret = require_secret(secret.as_ref())?;
+        let (reader, len) = open_input(input)?;
+        let bar = progress_bar(len);
+        let reader = bar.wrap_read(reader);
+        let decrypt_and_verify = |reader: &mut dyn Read, writer: &mut dyn Write| {
+            let header = Header::read(reader)?;
+            let mut writer = VerifyingWriter::new(writer, header.signed);
+            let total = decrypt_stream_body(&header, reader, &mut writer, secret, &mut |_| {})?;
+            check_signature(writer.finish()?.1, verify_key)?;
+            Ok(total)
+        };
+        let result = if use_base64 {
+            let mut reader = base64::read::DecoderReader::new(reader, &general_purpose::STANDARD);
+            with_output(output, false, |writer| decrypt_and_verify(&mut reader, writer))
+        } else {
+            let mut reader = reader;
+            with_output(output, false, |writer| decrypt_and_verify(&mut reader, writer))
+        };
+        bar.finish_and_clear();
+        result?;
+    } else {
+        let mut data = read_input(input)?;
+
+        if use_base64 {
+            let decoded = general_purpose::STANDARD.decode(&data)?;
+            data = decoded;
+        }
+
+        // Only the authenticated header says whether the plaintext is signed
+        let (decrypted, signed) = match (method, key) {
+            (CryptoMethod::Rsa, Some(key)) => {
+                let key = match load_private_key(Path::new(key), secret)? {
+                    PrivateKey::Rsa(key) => key,
+                    PrivateKey::Ed25519(_) => {
+                        return Err(CryptoError::KeyError("Ed25519 keys can sign but not decrypt".to_string()));
+                    }
+                };
+                (decrypt_with_private_key(&data, &key)?, hybrid::is_signed(&data)?)
+            }
+            (CryptoMethod::Rsa, None) => return Err(CryptoError::KeyError("--method rsa needs --key".to_string())),
+            (_, Some(_)) => return Err(CryptoError::KeyError("--key needs --method rsa".to_string())),
+            _ if legacy => (decrypt_legacy(&data, method, require_secret(secret.as_ref())?)?, false),
+            _ => {
+                let decrypted = decrypt_data(&data, method, require_secret(secret.as_ref())?)?;
+                (decrypted, Header::parse(&data)?.0.signed)
+            }
+        };
+
+        let mut writer = VerifyingWriter::new(Vec::new(), signed);
+        writer.write_all(&decrypted)?;
+        let (decrypted, signed) = writer.finish()?;
+        check_signature(signed, verify_key)?;
+
+        write_output(&decrypted, output)?;
+    }
+
+    if output.is_some() {
+        eprintln!("Decryption completed successfully!");
+    }
+
+    Ok(())
+}
 
+fn describe_input(input: Option<&str>) -> String {
+    match input {
+        Some(path) if Path::new(path).is_dir() => format!("directory '{}'", path),
+        Some(path) if path != "-" => format!("file '{}'", path),
+        _ => "stdin".to_string(),
+    }
+}
+
+/// Progress on stderr; hidden when stderr is not a terminal
+fn progress_bar(len: Option<u64>) -> ProgressBar {
+    match len {
+        Some(len) => ProgressBar::new(len).with_style(
+            ProgressStyle::with_template("{bar:40} {bytes}/{total_bytes} {bytes_per_sec} ETA {eta}")
+                .unwrap_or_else(|_| ProgressStyle::default_bar()),
+        ),
+        None => ProgressBar::new_spinner().with_style(
+            ProgressStyle::with_template("{spinner} {bytes} {bytes_per_sec}")
+                .unwrap_or_else(|_| ProgressStyle::default_spinner()),
+        ),
+    }
+}
+
+/// Run a streaming operation against the output, Base64-encoding it if asked
+///
+/// An output file is only replaced once the operation has succeeded.
+fn with_output<F>(output: Option<&String>, use_base64: bool, run: F) -> Result<()>
+where
+    F: FnOnce(&mut dyn Write) -> Result<u64>,
+{
+    let mut writer = create_output(output)?;
     if use_base64 {
-        let decoded = general_purpose::STANDARD.decode(&data)?;
-        data = decoded;
+        let mut encoder = base64::write::EncoderWriter::new(&mut writer, &general_purpose::STANDARD);
+        run(&mut encoder)?;
+        encoder.finish()?;
+    } else {
+        run(&mut writer)?;
+    }
+    writer.finish()?;
+
+    if let Some(path) = output {
+        eprintln!("Output written to: {}", path);
     }
+    Ok(())
+}
 
-    let decrypted = decrypt_data(&data, method, password)?;
+fn require_secret(secret: Option<&Secret>) -> Result<&Secret> {
+    secret.ok_or_else(|| CryptoError::CryptoError("A password or key file is required for this method".to_string()))
+}
 
-    write_output(&decrypted, output)?;
+/// The password for a key: the one given, or else asked for if the key is encrypted
+fn key_password(path: &Path, password: Option<Secret>) -> Result<Option<Secret>> {
+    match password {
+        Some(password) => Ok(Some(password)),
+        None if keys::is_encrypted(path)? => Ok(Some(prompt_password(&format!("Password for {}: ", path.display()), false)?)),
+        None => Ok(None),
+    }
+}
 
-    if output.is_some() {
-        println!("Decryption completed successfully!");
+/// Load a key, asking for its password if it is encrypted and none was given
+fn load_key(path: &Path, password: Option<Secret>) -> Result<Key> {
+    let password = key_password(path, password)?;
+    keys::load(path, password.as_ref().map(Secret::password).transpose()?)
+}
+
+/// Load a private key, asking for its password if it is encrypted and none was given
+fn load_private_key(path: &Path, password: Option<Secret>) -> Result<PrivateKey> {
+    let password = key_password(path, password)?;
+    keys::load_private(path, password.as_ref().map(Secret::password).transpose()?)
+}
+
+fn handle_keygen(
+    output: &str,
+    public_output: Option<&str>,
+    algorithm: KeyAlgorithm,
+    bits: usize,
+    secret: SecretArgs,
+) -> Result<()> {
+    let password = secret.resolve()?;
+    let key = match algorithm {
+        KeyAlgorithm::Rsa => {
+            println!("Generating a {}-bit RSA key...", bits);
+            PrivateKey::Rsa(keys::generate(bits)?)
+        }
+        KeyAlgorithm::Ed25519 => {
+            println!("Generating an Ed25519 key...");
+            PrivateKey::Ed25519(keys::generate_ed25519())
+        }
+    };
+    write_private_key(output, &key.to_pem(password.as_ref().map(Secret::password).transpose()?)?)?;
+
+    let public = key.public_key();
+    if let Some(path) = public_output {
+        write_output(public.to_pem()?.as_bytes(), Some(&path.to_string()))?;
+    }
+    println!("Fingerprint: {}", public.fingerprint_hex()?);
+    Ok(())
+}
+
+fn handle_export_public(input: &str, output: Option<&String>, secret: SecretArgs) -> Result<()> {
+    let public = load_key(Path::new(input), secret.resolve()?)?.public_key();
+    write_output(public.to_pem()?.as_bytes(), output)
+}
+
+fn handle_import(input: &str, output: &str, secret: SecretArgs, key_password: KeyPasswordArgs) -> Result<()> {
+    let key = load_key(Path::new(input), key_password.resolve()?)?;
+    // Never write an encrypted key out unencrypted; without --password, ask for one
+    let password = match secret.resolve()? {
+        Some(password) => Some(password),
+        None if keys::is_encrypted(Path::new(input))? => Some(prompt_password("Password for the imported key: ", true)?),
+        None => None,
+    };
+    let public = key.public_key();
+    match key {
+        Key::Private(key) => write_private_key(output, &key.to_pem(password.as_ref().map(Secret::password).transpose()?)?)?,
+        Key::Public(key) => write_output(key.to_pem()?.as_bytes(), Some(&output.to_string()))?,
+    }
+    println!("Fingerprint: {}", public.fingerprint_hex()?);
+    Ok(())
+}
+
+fn handle_sign(input: &str, output: Option<&str>, key: &str, secret: SecretArgs) -> Result<()> {
+    let key = load_private_key(Path::new(key), secret.resolve()?)?;
+    let (reader, _) = open_input(Some(input))?;
+    let signature = sign::sign(&key, reader)?;
+
+    let output = output.map_or_else(|| format!("{}.sig", input), str::to_string);
+    write_output(signature.encode().as_bytes(), Some(&output))?;
+    println!("Signed with {} key {}", signature.algorithm.name(), keys::format_fingerprint(&signature.fingerprint));
+    Ok(())
+}
+
+fn handle_verify(input: &str, signature: Option<&str>, key: &str) -> Result<()> {
+    let key = keys::load_public(Path::new(key), None)?;
+    let signature_path = signature.map_or_else(|| format!("{}.sig", input), str::to_string);
+    let signature = Signature::parse(&fs::read_to_string(&signature_path)?)?;
+
+    let (reader, _) = open_input(Some(input))?;
+    sign::verify(&key, reader, &signature)?;
+    println!("Good {} signature from {}", signature.algorithm.name(), keys::format_fingerprint(&signature.fingerprint));
+    Ok(())
+}
+
+/// Decrypt-then-verify: report or require the signature found in the plaintext
+fn check_signature(signed: Option<(DataDigest, Signature)>, verify_key: Option<&PublicKey>) -> Result<()> {
+    match (signed, verify_key) {
+        (Some((digest, signature)), Some(key)) => {
+            sign::verify_digest(key, &digest, &signature)?;
+            eprintln!("Good {} signature from {}", signature.algorithm.name(), keys::format_fingerprint(&signature.fingerprint));
+        }
+        (Some((_, signature)), None) => eprintln!(
+            "Signed by {}; pass --verify-key to check the signature",
+            keys::format_fingerprint(&signature.fingerprint)
+        ),
+        (None, Some(_)) => return Err(CryptoError::SignatureMissing),
+        (None, None) => {}
+    }
+    Ok(())
+}
+
+fn handle_hash(paths: &[String], algorithm: HashAlgorithm, output: Option<&String>) -> Result<()> {
+    let entries = if paths.is_empty() || paths == ["-"] {
+        let digest = hash::hash_reader(algorithm, std::io::stdin().lock())?;
+        vec![hash::Entry { digest, path: "-".to_string() }]
+    } else {
+        let paths: Vec<PathBuf> = paths.iter().map(PathBuf::from).collect();
+        hash::hash_files(algorithm, &hash::collect_files(&paths)?)?
+    };
+
+    let manifest: String = entries.iter().map(|entry| hash::format_line(entry) + "\n").collect();
+    write_output(manifest.as_bytes(), output)
+}
+
+fn handle_hash_check(manifest: &str, algorithm: HashAlgorithm, root: Option<&str>, quiet: bool) -> Result<()> {
+    let entries = hash::parse_manifest(&fs::read_to_string(manifest)?, algorithm)?;
+    let root = Path::new(root.unwrap_or("."));
+    let report = hash::check(&entries, algorithm, root, &[PathBuf::from(manifest)])?;
+
+    if !quiet {
+        for path in &report.ok {
+            println!("{}: OK", path);
+        }
+    }
+    for (paths, status) in [(&report.modified, "FAILED"), (&report.missing, "MISSING"), (&report.extra, "EXTRA")] {
+        for path in paths {
+            println!("{}: {}", path, status);
+        }
+    }
+
+    if !report.is_clean() {
+        return Err(CryptoError::ManifestMismatch {
+            modified: report.modified.len(),
+            missing: report.missing.len(),
+            extra: report.extra.len(),
+        });
     }
+    if !quiet {
+        eprintln!("All {} files match", report.ok.len());
+    }
+    Ok(())
+}
+
+fn handle_extract(
+    input: Option<&str>,
+    output: &str,
+    paths: &[String],
+    secret: SecretArgs,
+    verify_key: Option<&PublicKey>,
+) -> Result<()> {
+    let secret = secret.resolve_or_prompt(false)?;
+    let paths: Vec<PathBuf> = paths.iter().map(PathBuf::from).collect();
+    // The entries are staged until the signature is checked
+    let extraction =
+        with_decrypted(input, &secret, verify_key, |reader| archive::unpack(reader, Path::new(output), &paths))?;
+    let count = extraction.commit()?;
+    eprintln!("Extracted {} entries to {}", count, output);
+    Ok(())
+}
 
+fn handle_list(input: Option<&str>, secret: SecretArgs) -> Result<()> {
+    let secret = secret.resolve_or_prompt(false)?;
+    for entry in with_decrypted(input, &secret, None, archive::list)? {
+        let suffix = match &entry.kind {
+            EntryKind::Directory => "/".to_string(),
+            EntryKind::Symlink(target) => format!(" -> {}", target.display()),
+            _ => String::new(),
+        };
+        println!(
+            "{} {:>12} {} {}{}",
+            entry.mode_string(),
+            entry.size,
+            archive::format_mtime(entry.mtime),
+            entry.path.display(),
+            suffix
+        );
+    }
     Ok(())
-}
\ No newline at end of file
+}
+
+/// Decrypt a stream while `consume` reads the plaintext on another thread
+///
+/// Decryption errors take precedence, since a consumer reading a forged or
+/// truncated stream fails too. The signature is checked once all is read.
+fn with_decrypted<T, F>(input: Option<&str>, secret: &Secret, verify_key: Option<&PublicKey>, consume: F) -> Result<T>
+where
+    T: Send,
+    F: FnOnce(PipeReader) -> Result<T> + Send,
+{
+    let (reader, len) = open_input(input)?;
+    let bar = progress_bar(len);
+    let (pipe_reader, pipe_writer) = std::io::pipe()?;
+
+    let (decrypted, consumed) = thread::scope(|scope| {
+        let consumer = scope.spawn(move || consume(pipe_reader));
+        let decrypt = || -> Result<Option<(DataDigest, Signature)>> {
+            let mut reader = bar.wrap_read(reader);
+            let header = Header::read(&mut reader)?;
+            let mut writer = VerifyingWriter::new(pipe_writer, header.signed);
+            decrypt_stream_body(&header, reader, &mut writer, secret, &mut |_| {})?;
+            // Dropping the pipe tells the consumer that the plaintext is complete
+            Ok(writer.finish()?.1)
+        };
+        let decrypted = decrypt();
+        (decrypted, consumer.join())
+    });
+    bar.finish_and_clear();
+
+    let consumed = consumed.unwrap_or_else(|_| Err(CryptoError::ArchiveError("extraction thread panicked".to_string())));
+    match decrypted {
+        // The consumer stopped reading early and its error says why
+        Err(CryptoError::IoError(e)) if e.kind() == ErrorKind::BrokenPipe => consumed.and(Err(CryptoError::IoError(e))),
+        Err(e) => Err(e),
+        Ok(signed) => {
+            let value = consumed?;
+            check_signature(signed, verify_key)?;
+            Ok(value)
+        }
+    }
+}
+
+fn handle_bench_kdf(kind: KdfKind, memory_mib: Option<u32>, target: Duration) -> Result<()> {
+    let base = kind.defaults().with_costs(memory_mib, None)?;
+    println!("Calibrating {} for {} ms...", kind_name(kind), target.as_millis());
+
+    let calibration = calibrate(base, target)?;
+    let (memory, time_cost) = match calibration.kdf {
+        Kdf::Argon2id { iterations, .. } => (Some(calibration.kdf.memory_kib() / 1024), iterations),
+        Kdf::Scrypt { p, .. } => (Some(calibration.kdf.memory_kib() / 1024), p),
+        Kdf::Pbkdf2Sha256 { iterations } => (None, iterations),
+        Kdf::RawKey => unreachable!("KdfKind never selects a raw key"),
+    };
+
+    println!("{:?} took {} ms", calibration.kdf, calibration.elapsed.as_millis());
+    match memory {
+        Some(mib) => println!("Use: --kdf {} --kdf-memory {} --kdf-time {}", kind_name(kind), mib, time_cost),
+        None => println!("Use: --kdf {} --kdf-time {}", kind_name(kind), time_cost),
+    }
+    Ok(())
+}
+
+fn kind_name(kind: KdfKind) -> String {
+    kind.to_possible_value().map(|v| v.get_name().to_string()).unwrap_or_default()
+}
@@ -0,0 +1,247 @@
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use zeroize::Zeroizing;
+
+use crate::container::KEY_LEN;
+use crate::kdf::Kdf;
+use crate::types::{CryptoError, Result};
+
+// Passwords and keys are held in `Zeroizing` buffers, which overwrite their
+// memory when dropped. `--password` is still accepted for scripts, but the
+// copy in the process arguments stays visible to `ps` and shell history.
+
+/// A password, or a key from a key file, wiped from memory when dropped
+pub enum Secret {
+    Password(Zeroizing<String>),
+    Key(Zeroizing<Vec<u8>>),
+}
+
+/// Where the password or key comes from; at most one may be given
+#[derive(clap::Args, Debug, Default)]
+#[group(id = "secret", multiple = false)]
+pub struct SecretArgs {
+    /// Password on the command line; visible to other users, prefer the options below
+    #[arg(long = "password")]
+    pub password: Option<String>,
+
+    /// Read the password from an environment variable
+    #[arg(long = "password-env", value_name = "VAR")]
+    pub password_env: Option<String>,
+
+    /// Read the password from the first line of a file
+    #[arg(long = "password-file", value_name = "PATH")]
+    pub password_file: Option<PathBuf>,
+
+    /// Use the 32-byte key in a file instead of a password, skipping key derivation
+    #[arg(long = "keyfile", value_name = "PATH")]
+    pub keyfile: Option<PathBuf>,
+}
+
+/// Where the password of a private key being read comes from; at most one may be given
+#[derive(clap::Args, Debug, Default)]
+#[group(id = "key_password_source", multiple = false)]
+pub struct KeyPasswordArgs {
+    /// Private key password on the command line; visible to other users, prefer the options below
+    #[arg(long = "key-password")]
+    pub key_password: Option<String>,
+
+    /// Read the private key password from an environment variable
+    #[arg(long = "key-password-env", value_name = "VAR")]
+    pub key_password_env: Option<String>,
+
+    /// Read the private key password from the first line of a file
+    #[arg(long = "key-password-file", value_name = "PATH")]
+    pub key_password_file: Option<PathBuf>,
+}
+
+impl Secret {
+    pub fn from_password(password: impl Into<String>) -> Self {
+        Secret::Password(Zeroizing::new(password.into()))
+    }
+
+    /// The password, for uses that cannot take a key file
+    pub fn password(&self) -> Result<&str> {
+        match self {
+            Secret::Password(password) => Ok(password),
+            Secret::Key(_) => Err(CryptoError::KeyError("A key file cannot be used here; give a password".to_string())),
+        }
+    }
+
+    /// The KDF to encrypt with: `kdf` for a password, none for a key file
+    pub fn kdf(&self, kdf: Kdf) -> Kdf {
+        match self {
+            Secret::Password(_) => kdf,
+            Secret::Key(_) => Kdf::RawKey,
+        }
+    }
+
+    /// Derive the content key for a header that uses `kdf`
+    pub fn derive_key(&self, kdf: Kdf, salt: &[u8], len: usize) -> Result<Zeroizing<Vec<u8>>> {
+        match (self, kdf) {
+            (Secret::Key(key), Kdf::RawKey) => kdf.derive_key(key, salt, len),
+            (Secret::Key(_), _) => {
+                Err(CryptoError::KeyError("The data was encrypted with a password, not a key file".to_string()))
+            }
+            (Secret::Password(_), Kdf::RawKey) => {
+                Err(CryptoError::KeyError("The data was encrypted with a key file; use --keyfile".to_string()))
+            }
+            (Secret::Password(password), kdf) => kdf.derive_key(password.as_bytes(), salt, len),
+        }
+    }
+}
+
+impl SecretArgs {
+    /// Whether any of the options was given
+    pub fn is_given(&self) -> bool {
+        self.password.is_some() || self.password_env.is_some() || self.password_file.is_some() || self.keyfile.is_some()
+    }
+
+    /// The secret from the options, or `None` if none was given
+    pub fn resolve(self) -> Result<Option<Secret>> {
+        if let Some(secret) = resolve_password(self.password, self.password_env, self.password_file)? {
+            return Ok(Some(secret));
+        }
+        self.keyfile.map(|path| read_keyfile(&path)).transpose()
+    }
+
+    /// The secret from the options, or else asked for on the terminal
+    ///
+    /// With `confirm` the password is asked for twice, as when encrypting.
+    pub fn resolve_or_prompt(self, confirm: bool) -> Result<Secret> {
+        match self.resolve()? {
+            Some(secret) => Ok(secret),
+            None => prompt_password("Password: ", confirm),
+        }
+    }
+}
+
+impl KeyPasswordArgs {
+    /// Whether any of the options was given
+    pub fn is_given(&self) -> bool {
+        self.key_password.is_some() || self.key_password_env.is_some() || self.key_password_file.is_some()
+    }
+
+    /// The password from the options, or `None` if none was given
+    pub fn resolve(self) -> Result<Option<Secret>> {
+        resolve_password(self.key_password, self.key_password_env, self.key_password_file)
+    }
+}
+
+/// A password given directly, through an environment variable or in a file
+fn resolve_password(password: Option<String>, var: Option<String>, path: Option<PathBuf>) -> Result<Option<Secret>> {
+    if let Some(password) = password {
+        return Ok(Some(Secret::from_password(password)));
+    }
+    if let Some(var) = var {
+        let password = env::var(&var).map_err(|e| CryptoError::KeyError(format!("${}: {}", var, e)))?;
+        return Ok(Some(Secret::from_password(password)));
+    }
+    path.map(|path| read_password_file(&path)).transpose()
+}
+
+/// Ask for a password on the terminal without echoing it
+pub fn prompt_password(prompt: &str, confirm: bool) -> Result<Secret> {
+    let no_terminal = |e: std::io::Error| {
+        CryptoError::KeyError(format!(
+            "Cannot ask for a password ({}); use --password-env, --password-file or --keyfile",
+            e
+        ))
+    };
+    let password = Zeroizing::new(rpassword::prompt_password(prompt).map_err(no_terminal)?);
+    if password.is_empty() {
+        return Err(CryptoError::KeyError("Empty password".to_string()));
+    }
+    if confirm {
+        let repeated = Zeroizing::new(rpassword::prompt_password("Repeat password: ").map_err(no_terminal)?);
+        if *repeated != *password {
+            return Err(CryptoError::KeyError("The passwords do not match".to_string()));
+        }
+    }
+    Ok(Secret::Password(password))
+}
+
+/// Read a password from the first line of a file
+pub fn read_password_file(path: &Path) -> Result<Secret> {
+    let contents = Zeroizing::new(fs::read_to_string(path)?);
+    let line = contents.lines().next().unwrap_or_default();
+    if line.is_empty() {
+        return Err(CryptoError::KeyError(format!("{}: empty password", path.display())));
+    }
+    Ok(Secret::from_password(line))
+}
+
+/// Read a key file: 32 bytes, either raw or as 64 hex characters
+pub fn read_keyfile(path: &Path) -> Result<Secret> {
+    let contents = Zeroizing::new(fs::read(path)?);
+    parse_key(&contents)
+        .map(Secret::Key)
+        .ok_or_else(|| CryptoError::KeyError(format!("{}: a key file holds {} bytes, raw or in hex", path.display(), KEY_LEN)))
+}
+
+fn parse_key(contents: &[u8]) -> Option<Zeroizing<Vec<u8>>> {
+    if contents.len() == KEY_LEN {
+        return Some(Zeroizing::new(contents.to_vec()));
+    }
+    let hex = contents.trim_ascii();
+    if hex.len() != 2 * KEY_LEN {
+        return None;
+    }
+    let mut key = Zeroizing::new(vec![0u8; KEY_LEN]);
+    for (byte, pair) in key.iter_mut().zip(hex.chunks(2)) {
+        *byte = u8::from_str_radix(std::str::from_utf8(pair).ok()?, 16).ok()?;
+    }
+    Some(key)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_key_files() {
+        let raw = [0xabu8; KEY_LEN];
+        assert_eq!(*parse_key(&raw).unwrap(), raw);
+        assert_eq!(*parse_key(format!("{}\n", "ab".repeat(KEY_LEN)).as_bytes()).unwrap(), raw);
+        assert!(parse_key(b"short").is_none());
+        assert!(parse_key("zz".repeat(KEY_LEN).as_bytes()).is_none());
+    }
+
+    #[test]
+    fn test_secret_must_match_the_kdf() {
+        let fast = Kdf::Pbkdf2Sha256 { iterations: 1_000 };
+        let password = Secret::from_password("secret");
+        let key = Secret::Key(Zeroizing::new(vec![1; KEY_LEN]));
+
+        assert_eq!(password.kdf(fast), fast);
+        assert_eq!(key.kdf(fast), Kdf::RawKey);
+        assert!(password.derive_key(fast, b"salt", KEY_LEN).is_ok());
+        assert_eq!(*key.derive_key(Kdf::RawKey, b"salt", KEY_LEN).unwrap(), [1; KEY_LEN]);
+        assert!(matches!(password.derive_key(Kdf::RawKey, b"salt", KEY_LEN), Err(CryptoError::KeyError(_))));
+        assert!(matches!(key.derive_key(fast, b"salt", KEY_LEN), Err(CryptoError::KeyError(_))));
+        assert!(key.password().is_err());
+    }
+
+    #[test]
+    fn test_resolve_sources() {
+        let dir = std::env::temp_dir().join(format!("crypto-tool-secret-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("password"), "hunter2\nignored\n").unwrap();
+
+        let from_file = SecretArgs { password_file: Some(dir.join("password")), ..Default::default() };
+        assert_eq!(from_file.resolve().unwrap().unwrap().password().unwrap(), "hunter2");
+        let from_argv = SecretArgs { password: Some("argv".to_string()), ..Default::default() };
+        assert_eq!(from_argv.resolve().unwrap().unwrap().password().unwrap(), "argv");
+        let unset = SecretArgs { password_env: Some("CRYPTO_TOOL_TEST_UNSET_VARIABLE".to_string()), ..Default::default() };
+        assert!(unset.resolve().is_err());
+        assert!(SecretArgs::default().resolve().unwrap().is_none());
+
+        let key_password = KeyPasswordArgs { key_password_file: Some(dir.join("password")), ..Default::default() };
+        assert!(key_password.is_given());
+        assert_eq!(key_password.resolve().unwrap().unwrap().password().unwrap(), "hunter2");
+        assert!(KeyPasswordArgs::default().resolve().unwrap().is_none());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,391 @@
+use std::io::{self, Read, Write};
+
+use base64::{Engine as _, engine::general_purpose};
+use ed25519_dalek::{Signer, Verifier};
+use rand::rngs::OsRng;
+use rsa::Pss;
+use sha2::{Digest, Sha256, Sha512};
+
+use crate::keys::{self, PrivateKey, PublicKey};
+use crate::types::{CryptoError, Result};
+
+// Signatures cover a domain-separated message rather than the file itself,
+// so large files are hashed in a streaming pass:
+//
+//   "crypto-tool signature v1\0" | algorithm name | 0 | key fingerprint [32] | SHA-512(data)
+//
+// Binding the algorithm and fingerprint prevents a signature from being
+// reinterpreted under a different key or scheme.
+
+const DOMAIN: &[u8] = b"crypto-tool signature v1\0";
+const HEADER_LINE: &str = "crypto-tool signature v1";
+
+/// Ends the signature trailer of signed plaintext
+const TRAILER_MAGIC: &[u8; 4] = b"CTSG";
+/// Largest trailer accepted, which bounds the bytes held back while decrypting
+const MAX_TRAILER: usize = 8 * 1024;
+
+pub type DataDigest = [u8; 64];
+
+/// Signature scheme, chosen by the key type
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SignatureAlgorithm {
+    Ed25519,
+    RsaPssSha256,
+}
+
+impl SignatureAlgorithm {
+    pub fn name(self) -> &'static str {
+        match self {
+            SignatureAlgorithm::Ed25519 => "ed25519",
+            SignatureAlgorithm::RsaPssSha256 => "rsa-pss-sha256",
+        }
+    }
+
+    fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "ed25519" => Some(SignatureAlgorithm::Ed25519),
+            "rsa-pss-sha256" => Some(SignatureAlgorithm::RsaPssSha256),
+            _ => None,
+        }
+    }
+}
+
+/// A detached signature
+///
+/// Its text form is
+///
+/// ```text
+/// crypto-tool signature v1
+/// algorithm: ed25519
+/// key: SHA256:<hex fingerprint of the signing key>
+/// signature: <base64>
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Signature {
+    pub algorithm: SignatureAlgorithm,
+    pub fingerprint: [u8; 32],
+    pub bytes: Vec<u8>,
+}
+
+impl Signature {
+    pub fn encode(&self) -> String {
+        format!(
+            "{}\nalgorithm: {}\nkey: {}\nsignature: {}\n",
+            HEADER_LINE,
+            self.algorithm.name(),
+            keys::format_fingerprint(&self.fingerprint),
+            general_purpose::STANDARD.encode(&self.bytes)
+        )
+    }
+
+    pub fn parse(text: &str) -> Result<Signature> {
+        let malformed = |e: &str| CryptoError::MalformedSignature(e.to_string());
+        let mut lines = text.lines().map(str::trim).filter(|line| !line.is_empty());
+        if lines.next() != Some(HEADER_LINE) {
+            return Err(malformed("not a crypto-tool signature"));
+        }
+
+        let (mut algorithm, mut fingerprint, mut bytes) = (None, None, None);
+        for line in lines {
+            let (name, value) = line.split_once(':').ok_or_else(|| malformed("expected 'name: value' lines"))?;
+            let value = value.trim();
+            match name.trim() {
+                "algorithm" => {
+                    algorithm = Some(SignatureAlgorithm::from_name(value).ok_or_else(|| malformed("unknown algorithm"))?)
+                }
+                "key" => fingerprint = Some(keys::parse_fingerprint(value).ok_or_else(|| malformed("bad key fingerprint"))?),
+                "signature" => {
+                    bytes = Some(general_purpose::STANDARD.decode(value).map_err(|_| malformed("bad base64 signature"))?)
+                }
+                _ => return Err(malformed("unknown field")),
+            }
+        }
+
+        Ok(Signature {
+            algorithm: algorithm.ok_or_else(|| malformed("missing algorithm"))?,
+            fingerprint: fingerprint.ok_or_else(|| malformed("missing key"))?,
+            bytes: bytes.ok_or_else(|| malformed("missing signature"))?,
+        })
+    }
+}
+
+/// SHA-512 of everything `reader` yields
+pub fn digest_reader<R: Read>(mut reader: R) -> Result<DataDigest> {
+    let mut hasher = Sha512::new();
+    io::copy(&mut reader, &mut hasher)?;
+    Ok(hasher.finalize().into())
+}
+
+fn signed_message(algorithm: SignatureAlgorithm, fingerprint: &[u8; 32], digest: &DataDigest) -> Vec<u8> {
+    let mut message = Vec::with_capacity(DOMAIN.len() + 16 + 32 + 64);
+    message.extend_from_slice(DOMAIN);
+    message.extend_from_slice(algorithm.name().as_bytes());
+    message.push(0);
+    message.extend_from_slice(fingerprint);
+    message.extend_from_slice(digest);
+    message
+}
+
+/// Sign a data digest: Ed25519 keys use Ed25519, RSA keys RSA-PSS with SHA-256
+pub fn sign_digest(key: &PrivateKey, digest: &DataDigest) -> Result<Signature> {
+    let fingerprint = key.public_key().fingerprint()?;
+    let (algorithm, bytes) = match key {
+        PrivateKey::Ed25519(key) => {
+            let message = signed_message(SignatureAlgorithm::Ed25519, &fingerprint, digest);
+            (SignatureAlgorithm::Ed25519, key.sign(&message).to_bytes().to_vec())
+        }
+        PrivateKey::Rsa(key) => {
+            let message = signed_message(SignatureAlgorithm::RsaPssSha256, &fingerprint, digest);
+            let bytes = key
+                .sign_with_rng(&mut OsRng, Pss::new::<Sha256>(), &Sha256::digest(&message))
+                .map_err(|e| CryptoError::CryptoError(e.to_string()))?;
+            (SignatureAlgorithm::RsaPssSha256, bytes)
+        }
+    };
+    Ok(Signature { algorithm, fingerprint, bytes })
+}
+
+/// Verify a signature over a data digest
+pub fn verify_digest(key: &PublicKey, digest: &DataDigest, signature: &Signature) -> Result<()> {
+    let fingerprint = key.fingerprint()?;
+    if fingerprint != signature.fingerprint {
+        return Err(CryptoError::SignatureKeyMismatch {
+            expected: keys::format_fingerprint(&fingerprint),
+            found: keys::format_fingerprint(&signature.fingerprint),
+        });
+    }
+
+    let message = signed_message(signature.algorithm, &fingerprint, digest);
+    let valid = match (key, signature.algorithm) {
+        (PublicKey::Ed25519(key), SignatureAlgorithm::Ed25519) => ed25519_dalek::Signature::from_slice(&signature.bytes)
+            .is_ok_and(|sig| key.verify(&message, &sig).is_ok()),
+        (PublicKey::Rsa(key), SignatureAlgorithm::RsaPssSha256) => {
+            key.verify(Pss::new::<Sha256>(), &Sha256::digest(&message), &signature.bytes).is_ok()
+        }
+        _ => false,
+    };
+    if valid { Ok(()) } else { Err(CryptoError::SignatureInvalid) }
+}
+
+/// Sign everything `reader` yields
+pub fn sign<R: Read>(key: &PrivateKey, reader: R) -> Result<Signature> {
+    sign_digest(key, &digest_reader(reader)?)
+}
+
+/// Verify a detached signature over everything `reader` yields
+pub fn verify<R: Read>(key: &PublicKey, reader: R, signature: &Signature) -> Result<()> {
+    verify_digest(key, &digest_reader(reader)?, signature)
+}
+
+fn to_io_error(e: CryptoError) -> io::Error {
+    io::Error::other(e.to_string())
+}
+
+/// Yields signed plaintext for sign-then-encrypt
+///
+/// The output is the data from the inner reader and a trailer with the
+/// signature that is computed once the inner reader is exhausted:
+///
+///   data | signature text | text length u16 | "CTSG"
+///
+/// The trailer is not recognisable by itself, so the encrypted header has to
+/// mark the data as signed.
+pub struct SigningReader<'a, R> {
+    inner: R,
+    key: &'a PrivateKey,
+    hasher: Sha512,
+    /// Bytes waiting to be returned before or after the data
+    pending: Vec<u8>,
+    pos: usize,
+    body_done: bool,
+}
+
+impl<'a, R: Read> SigningReader<'a, R> {
+    pub fn new(inner: R, key: &'a PrivateKey) -> Self {
+        Self { inner, key, hasher: Sha512::new(), pending: Vec::new(), pos: 0, body_done: false }
+    }
+
+    fn trailer(&mut self) -> Result<Vec<u8>> {
+        let digest: DataDigest = std::mem::take(&mut self.hasher).finalize().into();
+        let text = sign_digest(self.key, &digest)?.encode().into_bytes();
+        let mut trailer = text;
+        let len = trailer.len() as u16;
+        trailer.extend_from_slice(&len.to_be_bytes());
+        trailer.extend_from_slice(TRAILER_MAGIC);
+        Ok(trailer)
+    }
+}
+
+impl<R: Read> Read for SigningReader<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            if self.pos < self.pending.len() {
+                let n = buf.len().min(self.pending.len() - self.pos);
+                buf[..n].copy_from_slice(&self.pending[self.pos..self.pos + n]);
+                self.pos += n;
+                return Ok(n);
+            }
+            if self.body_done {
+                return Ok(0);
+            }
+
+            let n = self.inner.read(buf)?;
+            if n > 0 {
+                self.hasher.update(&buf[..n]);
+                return Ok(n);
+            }
+            self.body_done = true;
+            self.pending = self.trailer().map_err(to_io_error)?;
+            self.pos = 0;
+        }
+    }
+}
+
+enum VerifyState {
+    Unsigned,
+    /// The last bytes may belong to the trailer and are held back
+    Signed { held: Vec<u8>, hasher: Box<Sha512> },
+}
+
+/// Splits signed plaintext back into data and signature for decrypt-then-verify
+///
+/// Whether the plaintext is signed comes from the authenticated header, never
+/// from the plaintext itself. Unsigned data passes through unchanged. Signed
+/// data is written without its trailer; at most [`MAX_TRAILER`] bytes are
+/// held back.
+pub struct VerifyingWriter<W> {
+    inner: W,
+    state: VerifyState,
+}
+
+impl<W: Write> VerifyingWriter<W> {
+    pub fn new(inner: W, signed: bool) -> Self {
+        let state = match signed {
+            true => VerifyState::Signed { held: Vec::new(), hasher: Box::default() },
+            false => VerifyState::Unsigned,
+        };
+        Self { inner, state }
+    }
+
+    fn write_signed(&mut self, data: &[u8]) -> io::Result<()> {
+        let VerifyState::Signed { held, hasher } = &mut self.state else { unreachable!() };
+        held.extend_from_slice(data);
+        if held.len() > MAX_TRAILER {
+            let excess = held.len() - MAX_TRAILER;
+            hasher.update(&held[..excess]);
+            self.inner.write_all(&held[..excess])?;
+            held.drain(..excess);
+        }
+        Ok(())
+    }
+
+    /// Finish writing; returns the digest and signature if the data was signed
+    pub fn finish(mut self) -> Result<(W, Option<(DataDigest, Signature)>)> {
+        let state = std::mem::replace(&mut self.state, VerifyState::Unsigned);
+        let signed = match state {
+            VerifyState::Unsigned => None,
+            VerifyState::Signed { held, mut hasher } => {
+                let malformed = || CryptoError::MalformedSignature("bad signature trailer".to_string());
+                let end = held.len().checked_sub(TRAILER_MAGIC.len() + 2).ok_or_else(malformed)?;
+                if &held[end + 2..] != TRAILER_MAGIC {
+                    return Err(malformed());
+                }
+                let len = u16::from_be_bytes([held[end], held[end + 1]]) as usize;
+                let start = end.checked_sub(len).ok_or_else(malformed)?;
+                let text = std::str::from_utf8(&held[start..end]).map_err(|_| malformed())?;
+                let signature = Signature::parse(text)?;
+
+                hasher.update(&held[..start]);
+                self.inner.write_all(&held[..start])?;
+                Some((hasher.finalize().into(), signature))
+            }
+        };
+        self.inner.flush()?;
+        Ok((self.inner, signed))
+    }
+}
+
+impl<W: Write> Write for VerifyingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match &self.state {
+            VerifyState::Unsigned => self.inner.write_all(buf)?,
+            VerifyState::Signed { .. } => self.write_signed(buf)?,
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rsa::RsaPrivateKey;
+
+    #[test]
+    fn test_detached_signatures() {
+        let ed25519 = PrivateKey::Ed25519(keys::generate_ed25519());
+        let rsa = PrivateKey::Rsa(RsaPrivateKey::new(&mut OsRng, 1024).unwrap());
+        let data = b"release-1.0.tar.gz contents";
+
+        for key in [&ed25519, &rsa] {
+            let signature = sign(key, &data[..]).unwrap();
+            let parsed = Signature::parse(&signature.encode()).unwrap();
+            assert_eq!(parsed, signature);
+            verify(&key.public_key(), &data[..], &parsed).unwrap();
+            assert!(matches!(
+                verify(&key.public_key(), &b"tampered"[..], &parsed),
+                Err(CryptoError::SignatureInvalid)
+            ));
+        }
+
+        let signature = sign(&ed25519, &data[..]).unwrap();
+        assert!(matches!(
+            verify(&rsa.public_key(), &data[..], &signature),
+            Err(CryptoError::SignatureKeyMismatch { .. })
+        ));
+        assert!(matches!(Signature::parse("hello"), Err(CryptoError::MalformedSignature(_))));
+    }
+
+    fn sign_then_split(data: &[u8], key: &PrivateKey, write_size: usize) -> (Vec<u8>, Option<(DataDigest, Signature)>) {
+        let mut signed = Vec::new();
+        SigningReader::new(data, key).read_to_end(&mut signed).unwrap();
+
+        let mut writer = VerifyingWriter::new(Vec::new(), true);
+        for chunk in signed.chunks(write_size) {
+            writer.write_all(chunk).unwrap();
+        }
+        writer.finish().unwrap()
+    }
+
+    #[test]
+    fn test_sign_then_encrypt_trailer() {
+        let key = PrivateKey::Ed25519(keys::generate_ed25519());
+        let data: Vec<u8> = (0..20_000u32).map(|i| i as u8).collect();
+
+        for write_size in [1, 7, 4096, 100_000] {
+            let (output, signed) = sign_then_split(&data, &key, write_size);
+            assert_eq!(output, data);
+            let (digest, signature) = signed.unwrap();
+            verify_digest(&key.public_key(), &digest, &signature).unwrap();
+        }
+
+        let (output, signed) = sign_then_split(b"", &key, 3);
+        assert!(output.is_empty());
+        assert!(signed.is_some());
+
+        // Unsigned data passes through, even when it looks like signed data
+        let mut signed = Vec::new();
+        SigningReader::new(&data[..], &key).read_to_end(&mut signed).unwrap();
+        for data in [&b"abc"[..], &signed[..]] {
+            let mut writer = VerifyingWriter::new(Vec::new(), false);
+            writer.write_all(data).unwrap();
+            let (output, signed) = writer.finish().unwrap();
+            assert_eq!(output, data);
+            assert!(signed.is_none());
+        }
+    }
+}
@@ -0,0 +1,267 @@
+use std::io::{self, Read, Write};
+
+use aes_gcm::aead::Payload;
+use rand::RngCore;
+use rand::rngs::OsRng;
+
+use crate::container::{Cipher, Header, KEY_LEN, SALT_LEN, STREAM_VERSION};
+use crate::kdf::Kdf;
+use crate::secret::Secret;
+use crate::types::{CryptoError, Result};
+
+// Chunked AEAD in the style of the STREAM construction (Hoang, Reyhanitabar,
+// Rogaway, Vizár). The plaintext is split into chunks of `chunk_size` bytes;
+// chunk i is sealed under the nonce
+//
+//   nonce prefix [7] | i as u32 big-endian | 1 if last chunk else 0
+//
+// with the container header as associated data. Reordered chunks fail
+// authentication because the counter no longer matches, and a stream cut
+// at a chunk boundary is detected because its new final chunk was not
+// sealed with the last-chunk flag. Memory use is bounded by the chunk size.
+
+/// Plaintext bytes per chunk written by the CLI
+pub const DEFAULT_CHUNK_SIZE: u32 = 64 * 1024;
+/// Largest chunk size accepted when reading, which bounds memory use
+pub const MAX_CHUNK_SIZE: u32 = 16 * 1024 * 1024;
+
+pub(crate) const NONCE_PREFIX_LEN: usize = 7;
+const TAG_LEN: usize = 16;
+
+fn chunk_nonce(prefix: &[u8], counter: u32, last: bool) -> [u8; 12] {
+    let mut nonce = [0u8; 12];
+    nonce[..NONCE_PREFIX_LEN].copy_from_slice(prefix);
+    nonce[NONCE_PREFIX_LEN..11].copy_from_slice(&counter.to_be_bytes());
+    nonce[11] = last as u8;
+    nonce
+}
+
+/// Read until `buf` holds `want` bytes or the reader is exhausted
+fn fill<R: Read>(reader: &mut R, buf: &mut Vec<u8>, want: usize) -> io::Result<usize> {
+    let start = buf.len();
+    while buf.len() < want {
+        let len = buf.len();
+        buf.resize(want, 0);
+        match reader.read(&mut buf[len..]) {
+            Ok(0) => {
+                buf.truncate(len);
+                break;
+            }
+            Ok(n) => buf.truncate(len + n),
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => buf.truncate(len),
+            Err(e) => {
+                buf.truncate(len);
+                return Err(e);
+            }
+        }
+    }
+    Ok(buf.len() - start)
+}
+
+fn next_counter(counter: u32) -> Result<u32> {
+    counter
+        .checked_add(1)
+        .ok_or_else(|| CryptoError::CryptoError("Stream too long for the chunk counter".to_string()))
+}
+
+/// Encrypt everything from `reader` into `writer` as a chunked stream
+///
+/// `signed` marks the plaintext as carrying a signature trailer in the header.
+/// `progress` is called with the number of bytes read after every chunk.
+/// Returns the total number of plaintext bytes.
+#[allow(clippy::too_many_arguments)]
+pub fn encrypt_stream<R: Read, W: Write>(
+    mut reader: R,
+    mut writer: W,
+    secret: &Secret,
+    cipher: Cipher,
+    kdf: Kdf,
+    chunk_size: u32,
+    signed: bool,
+    progress: &mut dyn FnMut(u64),
+) -> Result<u64> {
+    kdf.validate()?;
+    if chunk_size == 0 || chunk_size > MAX_CHUNK_SIZE {
+        return Err(CryptoError::CryptoError(format!("Chunk size must be between 1 and {}", MAX_CHUNK_SIZE)));
+    }
+
+    let mut salt = vec![0u8; SALT_LEN];
+    let mut prefix = vec![0u8; NONCE_PREFIX_LEN];
+    OsRng.fill_bytes(&mut salt);
+    OsRng.fill_bytes(&mut prefix);
+
+    let header = Header { version: STREAM_VERSION, signed, cipher, kdf, salt, nonce: prefix, chunk_size: Some(chunk_size) };
+    let aad = header.encode();
+    let key = secret.derive_key(kdf, &header.salt, KEY_LEN)?;
+    writer.write_all(&aad)?;
+
+    let chunk_size = chunk_size as usize;
+    let mut buf = Vec::with_capacity(chunk_size + 1);
+    let mut counter = 0u32;
+    let mut total = 0u64;
+    loop {
+        // One byte of lookahead tells whether this is the last chunk
+        let read = fill(&mut reader, &mut buf, chunk_size + 1)?;
+        total += read as u64;
+        progress(read as u64);
+
+        let last = buf.len() <= chunk_size;
+        let len = buf.len().min(chunk_size);
+        let nonce = chunk_nonce(&header.nonce, counter, last);
+        let sealed = cipher.encrypt(&key, &nonce, Payload { msg: &buf[..len], aad: &aad })?;
+        writer.write_all(&sealed)?;
+
+        if last {
+            break;
+        }
+        buf.drain(..len);
+        counter = next_counter(counter)?;
+    }
+
+    writer.flush()?;
+    Ok(total)
+}
+
+/// Decrypt a chunked stream from `reader` into `writer`
+///
+/// Single-shot containers are accepted too, but read into memory.
+/// Chunks are written as soon as they are authenticated, so on error the
+/// output holds a prefix of the plaintext and should be discarded.
+/// `progress` is called with the number of bytes read after every chunk.
+/// Returns the total number of plaintext bytes.
+pub fn decrypt_stream<R: Read, W: Write>(
+    mut reader: R,
+    writer: W,
+    secret: &Secret,
+    progress: &mut dyn FnMut(u64),
+) -> Result<u64> {
+    let header = Header::read(&mut reader)?;
+    decrypt_stream_body(&header, reader, writer, secret, progress)
+}
+
+/// Decrypt what follows a header already read with [`Header::read`]
+///
+/// Lets the caller look at the header, e.g. whether the data is signed,
+/// before choosing the writer.
+pub fn decrypt_stream_body<R: Read, W: Write>(
+    header: &Header,
+    mut reader: R,
+    mut writer: W,
+    secret: &Secret,
+    progress: &mut dyn FnMut(u64),
+) -> Result<u64> {
+    let aad = header.encode();
+    progress(aad.len() as u64);
+    let key = secret.derive_key(header.kdf, &header.salt, KEY_LEN)?;
+
+    let Some(chunk_size) = header.chunk_size else {
+        // Single-shot containers have one tag for everything and are read whole
+        let mut ciphertext = Vec::new();
+        reader.read_to_end(&mut ciphertext)?;
+        progress(ciphertext.len() as u64);
+        let plaintext = header.cipher.decrypt(&key, &header.nonce, Payload { msg: &ciphertext, aad: &aad })?;
+        writer.write_all(&plaintext)?;
+        writer.flush()?;
+        return Ok(plaintext.len() as u64);
+    };
+
+    let sealed_size = chunk_size as usize + TAG_LEN;
+    let mut buf = Vec::with_capacity(sealed_size + 1);
+    let mut counter = 0u32;
+    let mut total = 0u64;
+    loop {
+        let read = fill(&mut reader, &mut buf, sealed_size + 1)?;
+        progress(read as u64);
+
+        let last = buf.len() <= sealed_size;
+        let len = buf.len().min(sealed_size);
+        if len < TAG_LEN {
+            return Err(CryptoError::Truncated);
+        }
+
+        let nonce = chunk_nonce(&header.nonce, counter, last);
+        let chunk = match header.cipher.decrypt(&key, &nonce, Payload { msg: &buf[..len], aad: &aad }) {
+            Ok(chunk) => chunk,
+            Err(CryptoError::AuthenticationFailed) if last => {
+                // A valid chunk that is not flagged as last means the rest is missing
+                let nonce = chunk_nonce(&header.nonce, counter, false);
+                return match header.cipher.decrypt(&key, &nonce, Payload { msg: &buf[..len], aad: &aad }) {
+                    Ok(_) => Err(CryptoError::Truncated),
+                    Err(e) => Err(e),
+                };
+            }
+            Err(e) => return Err(e),
+        };
+        writer.write_all(&chunk)?;
+        total += chunk.len() as u64;
+
+        if last {
+            break;
+        }
+        buf.drain(..len);
+        counter = next_counter(counter)?;
+    }
+
+    writer.flush()?;
+    Ok(total)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::container;
+
+    const FAST: Kdf = Kdf::Pbkdf2Sha256 { iterations: 1_000 };
+    const CHUNK: u32 = 100;
+
+    fn encrypt(data: &[u8]) -> Vec<u8> {
+        let mut output = Vec::new();
+        encrypt_stream(data, &mut output, &Secret::from_password("secret"), Cipher::ChaCha20Poly1305, FAST, CHUNK, false, &mut |_| {}).unwrap();
+        output
+    }
+
+    fn decrypt(data: &[u8]) -> Result<Vec<u8>> {
+        let mut output = Vec::new();
+        decrypt_stream(data, &mut output, &Secret::from_password("secret"), &mut |_| {}).map(|_| output)
+    }
+
+    #[test]
+    fn test_round_trip_at_chunk_boundaries() {
+        for len in [0usize, 1, 99, 100, 101, 200, 1234] {
+            let data: Vec<u8> = (0..len).map(|i| i as u8).collect();
+            let sealed = encrypt(&data);
+            let chunks = len.div_ceil(CHUNK as usize).max(1);
+            assert_eq!(sealed.len() - Header::parse(&sealed).unwrap().1, len + chunks * TAG_LEN);
+            assert_eq!(decrypt(&sealed).unwrap(), data, "length {}", len);
+            assert_eq!(container::open(&sealed, &Secret::from_password("secret")).unwrap(), data);
+        }
+
+        let single = container::seal(b"single shot", &Secret::from_password("secret"), Cipher::Aes256Gcm, FAST).unwrap();
+        assert_eq!(decrypt(&single).unwrap(), b"single shot");
+    }
+
+    #[test]
+    fn test_truncation_and_reordering_are_detected() {
+        let data = vec![42u8; 350];
+        let sealed = encrypt(&data);
+        let header_len = Header::parse(&sealed).unwrap().1;
+        let sealed_chunk = CHUNK as usize + TAG_LEN;
+
+        // Cut at a chunk boundary or right after the header
+        for cut in [header_len + sealed_chunk, header_len + 3 * sealed_chunk, header_len] {
+            assert!(matches!(decrypt(&sealed[..cut]), Err(CryptoError::Truncated)), "cut at {}", cut);
+        }
+        // A cut inside a chunk leaves a chunk that does not authenticate
+        assert!(matches!(decrypt(&sealed[..sealed.len() - 5]), Err(CryptoError::AuthenticationFailed)));
+
+        let mut reordered = sealed[..header_len].to_vec();
+        reordered.extend_from_slice(&sealed[header_len + sealed_chunk..header_len + 2 * sealed_chunk]);
+        reordered.extend_from_slice(&sealed[header_len..header_len + sealed_chunk]);
+        reordered.extend_from_slice(&sealed[header_len + 2 * sealed_chunk..]);
+        assert!(matches!(decrypt(&reordered), Err(CryptoError::AuthenticationFailed)));
+
+        let mut extended = sealed.clone();
+        extended.extend_from_slice(&[0u8; 20]);
+        assert!(matches!(decrypt(&extended), Err(CryptoError::AuthenticationFailed)));
+    }
+}
@@ -8,6 +8,8 @@ pub enum CryptoMethod {
     Des,
     #[value(name = "rsa")]
     Rsa,
+    #[value(name = "chacha20")]
+    Chacha20,
 }
 
 #[derive(Debug)]
@@ -17,6 +19,17 @@ pub enum CryptoError {
     Base64Error(base64::DecodeError),
     InvalidKeyLength,
     InvalidDataLength,
+    InvalidFormat(String),
+    UnsupportedVersion(u8),
+    AuthenticationFailed,
+    KeyError(String),
+    Truncated,
+    SignatureInvalid,
+    SignatureKeyMismatch { expected: String, found: String },
+    SignatureMissing,
+    MalformedSignature(String),
+    ManifestMismatch { modified: usize, missing: usize, extra: usize },
+    ArchiveError(String),
 }
 
 impl From<io::Error> for CryptoError {
@@ -39,6 +52,25 @@ impl std::fmt::Display for CryptoError {
             CryptoError::Base64Error(e) => write!(f, "Base64 Error: {}", e),
             CryptoError::InvalidKeyLength => write!(f, "Invalid key length"),
             CryptoError::InvalidDataLength => write!(f, "Invalid data length"),
+            CryptoError::InvalidFormat(e) => write!(f, "Invalid format: {}", e),
+            CryptoError::UnsupportedVersion(v) => write!(f, "Unsupported format version {}", v),
+            CryptoError::KeyError(e) => write!(f, "Key Error: {}", e),
+            CryptoError::Truncated => write!(f, "Truncated data: the end of the encrypted stream is missing"),
+            CryptoError::SignatureInvalid => write!(f, "Bad signature: the data or the signature was modified"),
+            CryptoError::SignatureKeyMismatch { expected, found } => {
+                write!(f, "Signature was made by key {}, not {}", found, expected)
+            }
+            CryptoError::SignatureMissing => write!(f, "The data is not signed"),
+            CryptoError::MalformedSignature(e) => write!(f, "Malformed signature: {}", e),
+            CryptoError::ManifestMismatch { modified, missing, extra } => write!(
+                f,
+                "Manifest check failed: {} modified, {} missing, {} extra",
+                modified, missing, extra
+            ),
+            CryptoError::ArchiveError(e) => write!(f, "Archive Error: {}", e),
+            CryptoError::AuthenticationFailed => {
+                write!(f, "Authentication failed: wrong password or the data was modified")
+            }
         }
     }
 }
@@ -0,0 +1,205 @@
+use std::collections::VecDeque;
+use std::io;
+use std::process::{Child, Command};
+use std::time::Duration;
+
+use chrono::{NaiveDateTime, TimeDelta};
+
+use crate::filter::Filter;
+use crate::types::{LogAnalyzerError, LogEntry, Result};
+use crate::utils::format_duration;
+
+// Windows are measured with the entries' own timestamps rather than the
+// clock, so a rule behaves the same on a live stream and on a replayed file.
+
+/// Fires when more than `threshold` matching entries fall within `window`
+pub struct AlertRule {
+    pub name: String,
+    filter: Filter,
+    pub threshold: usize,
+    pub window: Duration,
+    /// Shell command run when the rule fires
+    pub command: Option<String>,
+}
+
+/// A rule that fired
+#[derive(Debug, Clone, PartialEq)]
+pub struct Alert {
+    pub rule: String,
+    pub count: usize,
+    pub threshold: usize,
+    pub window: Duration,
+    /// The entry that took the count over the threshold
+    pub entry: LogEntry,
+    pub command: Option<String>,
+}
+
+struct RuleState {
+    rule: AlertRule,
+    window: TimeDelta,
+    times: VecDeque<NaiveDateTime>,
+    /// Cleared when the rule fires, set again once the count is back at the threshold
+    armed: bool,
+}
+
+/// Tracks entries against alert rules
+pub struct AlertMonitor {
+    rules: Vec<RuleState>,
+}
+
+impl AlertRule {
+    pub fn new(name: &str, filter: Filter, threshold: usize, window: Duration, command: Option<String>) -> Result<Self> {
+        if TimeDelta::from_std(window).is_err() {
+            return Err(LogAnalyzerError::ConfigError(format!("alert '{}': window is too long", name)));
+        }
+        Ok(AlertRule { name: name.to_string(), filter, threshold, window, command })
+    }
+}
+
+impl AlertMonitor {
+    pub fn new(rules: Vec<AlertRule>) -> Self {
+        let rules = rules
+            .into_iter()
+            .map(|rule| RuleState {
+                window: TimeDelta::from_std(rule.window).expect("checked by AlertRule::new"),
+                rule,
+                times: VecDeque::new(),
+                armed: true,
+            })
+            .collect();
+        AlertMonitor { rules }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    /// Count an entry, returning the alerts it sets off
+    ///
+    /// A rule fires once when its count goes over the threshold, and not
+    /// again until the count has dropped back to the threshold.
+    pub fn observe(&mut self, entry: &LogEntry) -> Vec<Alert> {
+        let mut alerts = Vec::new();
+        for state in &mut self.rules {
+            if !state.rule.filter.matches(entry) {
+                continue;
+            }
+            let position = state.times.partition_point(|time| *time <= entry.timestamp);
+            state.times.insert(position, entry.timestamp);
+            let newest = *state.times.back().expect("just inserted");
+            while state.times.front().is_some_and(|time| *time <= newest - state.window) {
+                state.times.pop_front();
+            }
+
+            let count = state.times.len();
+            if count <= state.rule.threshold {
+                state.armed = true;
+            } else if state.armed {
+                state.armed = false;
+                alerts.push(Alert {
+                    rule: state.rule.name.clone(),
+                    count,
+                    threshold: state.rule.threshold,
+                    window: state.rule.window,
+                    entry: entry.clone(),
+                    command: state.rule.command.clone(),
+                });
+            }
+        }
+        alerts
+    }
+}
+
+impl std::fmt::Display for Alert {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}: {} entries within {} (threshold {}), latest {}:{} [{}] {}",
+            self.rule,
+            self.count,
+            format_duration(self.window),
+            self.threshold,
+            self.entry.source_file.display(),
+            self.entry.line_no,
+            self.entry.level,
+            self.entry.message
+        )
+    }
+}
+
+impl Alert {
+    /// Start the rule's command through the shell, without waiting for it
+    ///
+    /// The command sees the alert in `LOG_ALERT_RULE`, `LOG_ALERT_COUNT`
+    /// and `LOG_ALERT_MESSAGE`.
+    pub fn spawn_command(&self) -> Option<io::Result<Child>> {
+        let command = self.command.as_ref()?;
+        let mut shell = if cfg!(windows) {
+            let mut shell = Command::new("cmd");
+            shell.arg("/C");
+            shell
+        } else {
+            let mut shell = Command::new("sh");
+            shell.arg("-c");
+            shell
+        };
+        Some(
+            shell
+                .arg(command)
+                .env("LOG_ALERT_RULE", &self.rule)
+                .env("LOG_ALERT_COUNT", self.count.to_string())
+                .env("LOG_ALERT_MESSAGE", self.to_string())
+                .spawn(),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::LogLevel;
+    use std::path::PathBuf;
+
+    fn entry(seconds: i64, level: LogLevel) -> LogEntry {
+        LogEntry {
+            timestamp: NaiveDateTime::default() + TimeDelta::seconds(seconds),
+            level,
+            message: "Connection lost".to_string(),
+            source_file: PathBuf::from("app.log"),
+            line_no: seconds as usize,
+        }
+    }
+
+    #[test]
+    fn test_threshold_rule() {
+        let errors = Filter::new(vec![LogLevel::Error], None, None, None, false).unwrap();
+        let rule = AlertRule::new("errors", errors, 2, Duration::from_secs(60), None).unwrap();
+        let mut monitor = AlertMonitor::new(vec![rule]);
+        let mut fired = |seconds, level| monitor.observe(&entry(seconds, level)).len();
+
+        assert_eq!(fired(0, LogLevel::Error), 0);
+        assert_eq!(fired(10, LogLevel::Info), 0);
+        assert_eq!(fired(20, LogLevel::Error), 0);
+        assert_eq!(fired(30, LogLevel::Error), 1);
+        // Still over the threshold: no repeat
+        assert_eq!(fired(40, LogLevel::Error), 0);
+        // Everything up to 40s has left the window: re-armed
+        assert_eq!(fired(100, LogLevel::Error), 0);
+        assert_eq!(fired(90, LogLevel::Error), 0);
+        // Entries arriving out of order count within the window of the newest
+        assert_eq!(fired(95, LogLevel::Error), 1);
+    }
+
+    #[test]
+    fn test_alert_message() {
+        let rule = AlertRule::new("any", Filter::default(), 0, Duration::from_secs(60), Some("true".to_string())).unwrap();
+        let mut monitor = AlertMonitor::new(vec![rule]);
+        let alerts = monitor.observe(&entry(5, LogLevel::Warn));
+        assert_eq!(
+            alerts[0].to_string(),
+            "any: 1 entries within 1m (threshold 0), latest app.log:5 [WARN] Connection lost"
+        );
+        #[cfg(unix)]
+        assert!(alerts[0].spawn_command().unwrap().unwrap().wait().unwrap().success());
+    }
+}
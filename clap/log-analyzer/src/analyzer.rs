@@ -0,0 +1,161 @@
+use std::fs::File;
+use std::io::{self, BufRead, BufReader};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use crate::filter::Filter;
+use crate::format::{DETECT_SAMPLE_LINES, Formats, LogFormat};
+use crate::parser::LogParser;
+use crate::types::{LogAnalyzerError, LogEntry, Result};
+
+/// Entries that passed the filter, and the lines that could not be parsed
+#[derive(Debug, Default)]
+pub struct Analysis {
+    /// Matching entries, ordered by timestamp; ties keep file order
+    pub entries: Vec<LogEntry>,
+    /// A `ParseError` for each malformed line that was skipped
+    pub skipped: Vec<LogAnalyzerError>,
+}
+
+/// How the format of each file is chosen
+pub enum FormatSelection {
+    /// Detect it from the first lines of each file
+    Detect(Formats),
+    /// Read every file in this format
+    Fixed(Arc<dyn LogFormat>),
+}
+
+pub struct LogAnalyzer {
+    selection: FormatSelection,
+    filter: Filter,
+}
+
+/// An IO error with the path it happened on
+pub(crate) fn with_path(path: &Path, e: io::Error) -> LogAnalyzerError {
+    LogAnalyzerError::IoError(io::Error::new(e.kind(), format!("{}: {}", path.display(), e)))
+}
+
+/// Read the next line, replacing invalid UTF-8, or `None` at the end
+fn next_line<R: BufRead>(reader: &mut R, buf: &mut Vec<u8>) -> io::Result<Option<String>> {
+    buf.clear();
+    if reader.read_until(b'\n', buf)? == 0 {
+        return Ok(None);
+    }
+    let line = String::from_utf8_lossy(buf);
+    Ok(Some(line.trim_end_matches(['\n', '\r']).to_string()))
+}
+
+impl LogAnalyzer {
+    pub fn new(filter: Filter, selection: FormatSelection) -> Self {
+        LogAnalyzer { selection, filter }
+    }
+
+    /// Parse and filter every file, merging their entries
+    pub fn analyze(&self, files: &[PathBuf]) -> Result<Analysis> {
+        let mut analysis = Analysis::default();
+        for path in files {
+            let file = File::open(path).map_err(|e| with_path(path, e))?;
+            self.analyze_reader(BufReader::new(file), path, &mut analysis)?;
+        }
+        analysis.entries.sort_by_key(|entry| entry.timestamp);
+        Ok(analysis)
+    }
+
+    /// Parse and filter the lines of `reader`, read from `source_file`
+    ///
+    /// Invalid UTF-8 is replaced rather than failing the whole file, and
+    /// blank lines are ignored. Fails if the format has to be detected and
+    /// no format matches the first lines.
+    pub fn analyze_reader<R: BufRead>(&self, mut reader: R, source_file: &Path, analysis: &mut Analysis) -> Result<()> {
+        let mut buf = Vec::new();
+        let mut line_no = 0;
+
+        // Hold the first lines back until the format is known
+        let mut sample = Vec::new();
+        while sample.len() < DETECT_SAMPLE_LINES {
+            let Some(line) = next_line(&mut reader, &mut buf).map_err(|e| with_path(source_file, e))? else {
+                break;
+            };
+            line_no += 1;
+            if !line.trim().is_empty() {
+                sample.push((line_no, line));
+            }
+        }
+        if sample.is_empty() {
+            return Ok(());
+        }
+
+        let parser = self.parser_for(source_file, sample.iter().map(|(_, line)| line.as_str()))?;
+        for (line_no, line) in &sample {
+            self.add_line(&parser, line, source_file, *line_no, analysis);
+        }
+        while let Some(line) = next_line(&mut reader, &mut buf).map_err(|e| with_path(source_file, e))? {
+            line_no += 1;
+            if !line.trim().is_empty() {
+                self.add_line(&parser, &line, source_file, line_no, analysis);
+            }
+        }
+        Ok(())
+    }
+
+    /// A parser for `source_file`, detecting its format from `sample` if needed
+    pub fn parser_for<'a>(&self, source_file: &Path, sample: impl IntoIterator<Item = &'a str>) -> Result<LogParser> {
+        let format = match &self.selection {
+            FormatSelection::Fixed(format) => Arc::clone(format),
+            FormatSelection::Detect(formats) => formats.detect(sample).ok_or_else(|| {
+                LogAnalyzerError::UnknownFormat(format!(
+                    "{}: none of {} matches its first lines; choose one with --format or define one with --config",
+                    source_file.display(),
+                    formats.names().join(", ")
+                ))
+            })?,
+        };
+        Ok(LogParser::new(format))
+    }
+
+    /// Parse a line, adding it to `analysis` if it passes the filter
+    pub fn add_line(&self, parser: &LogParser, line: &str, source_file: &Path, line_no: usize, analysis: &mut Analysis) {
+        match parser.parse_line(line, source_file, line_no) {
+            Ok(entry) if self.filter.matches(&entry) => analysis.entries.push(entry),
+            Ok(_) => {}
+            Err(e) => analysis.skipped.push(e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::LogLevel;
+
+    #[test]
+    fn test_analyze_reader() {
+        let log = b"2024-01-15 10:30:00 [INFO] Server started\r\n\
+                   \n\
+                   garbage\n\
+                   2024-01-15 10:31:00 [ERROR] Connection \xff lost\n\
+                   2024-01-15 10:32:00 [DEBUG] Heartbeat";
+        let filter = Filter::new(vec![LogLevel::Info, LogLevel::Error], None, None, None, false).unwrap();
+        let analyzer = LogAnalyzer::new(filter, FormatSelection::Detect(Formats::builtin()));
+        let mut analysis = Analysis::default();
+        analyzer.analyze_reader(&log[..], Path::new("app.log"), &mut analysis).unwrap();
+
+        let lines: Vec<_> = analysis.entries.iter().map(|entry| (entry.line_no, entry.message.as_str())).collect();
+        assert_eq!(lines, [(1, "Server started"), (4, "Connection \u{fffd} lost")]);
+        assert_eq!(analysis.skipped.len(), 1);
+        assert!(analysis.skipped[0].to_string().contains("app.log:3:"));
+    }
+
+    #[test]
+    fn test_detection_failure() {
+        let analyzer = LogAnalyzer::new(Filter::default(), FormatSelection::Detect(Formats::builtin()));
+        let mut analysis = Analysis::default();
+        let result = analyzer.analyze_reader(&b"what\nis this\n"[..], Path::new("odd.log"), &mut analysis);
+        assert!(matches!(result, Err(LogAnalyzerError::UnknownFormat(msg)) if msg.starts_with("odd.log: ")));
+        assert!(analyzer.analyze_reader(&b"\n\n"[..], Path::new("empty.log"), &mut analysis).is_ok());
+
+        let fixed = LogAnalyzer::new(Filter::default(), FormatSelection::Fixed(Formats::builtin().get("json").unwrap()));
+        fixed.analyze_reader(&b"what\n"[..], Path::new("odd.log"), &mut analysis).unwrap();
+        assert_eq!(analysis.skipped.len(), 1);
+    }
+}
@@ -0,0 +1,168 @@
+use std::fs;
+use std::path::Path;
+use std::sync::Arc;
+
+use serde::Deserialize;
+
+use crate::alert::AlertRule;
+use crate::filter::Filter;
+use crate::format::{Formats, RegexFormat};
+use crate::types::{LogAnalyzerError, LogLevel, Result};
+use crate::utils::parse_duration;
+
+/// Settings read from the `--config` TOML file
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    /// User-defined formats, from `[[format]]` tables
+    #[serde(default, rename = "format")]
+    pub formats: Vec<FormatConfig>,
+
+    /// Alert rules for `--follow`, from `[[alert]]` tables
+    #[serde(default, rename = "alert")]
+    pub alerts: Vec<AlertConfig>,
+}
+
+/// A regex format:
+///
+/// ```toml
+/// [[format]]
+/// name = "myapp"
+/// pattern = '^(?P<timestamp>\S+ \S+) (?P<level>\w+) (?P<message>.*)$'
+/// timestamp_format = "%d.%m.%Y %H:%M:%S"  # optional
+/// default_level = "info"                  # optional, for lines without a level
+/// ```
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FormatConfig {
+    pub name: String,
+    pub pattern: String,
+    pub timestamp_format: Option<String>,
+    pub default_level: Option<LogLevel>,
+}
+
+/// An alert rule:
+///
+/// ```toml
+/// [[alert]]
+/// name = "error burst"
+/// threshold = 10                # fires on more than this many entries
+/// window = "1m"                 # within this long
+/// levels = ["error"]            # optional, entries of these levels only
+/// pattern = "timeout"           # optional, entries matching this only
+/// command = "notify-send \"$LOG_ALERT_MESSAGE\""  # optional
+/// ```
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AlertConfig {
+    pub name: String,
+    pub threshold: usize,
+    pub window: String,
+    #[serde(default)]
+    pub levels: Vec<LogLevel>,
+    pub pattern: Option<String>,
+    #[serde(default)]
+    pub case_sensitive: bool,
+    pub command: Option<String>,
+}
+
+impl Config {
+    pub fn load(path: &Path) -> Result<Self> {
+        let text = fs::read_to_string(path)
+            .map_err(|e| LogAnalyzerError::ConfigError(format!("{}: {}", path.display(), e)))?;
+        Self::parse(&text).map_err(|e| match e {
+            LogAnalyzerError::ConfigError(msg) => LogAnalyzerError::ConfigError(format!("{}: {}", path.display(), msg)),
+            other => other,
+        })
+    }
+
+    pub fn parse(text: &str) -> Result<Self> {
+        toml::from_str(text).map_err(|e| LogAnalyzerError::ConfigError(e.message().to_string()))
+    }
+
+    /// The built-in formats plus those defined here
+    pub fn formats(&self) -> Result<Formats> {
+        let mut formats = Formats::builtin();
+        let mut seen: Vec<&str> = formats.names();
+        seen.push("auto");
+        // Added in reverse so that detection prefers the first defined
+        for format in self.formats.iter().rev() {
+            if seen.contains(&format.name.as_str()) {
+                return Err(LogAnalyzerError::ConfigError(format!("format name '{}' is already taken", format.name)));
+            }
+            seen.push(&format.name);
+        }
+        for format in self.formats.iter().rev() {
+            formats.add(Arc::new(RegexFormat::new(
+                &format.name,
+                &format.pattern,
+                format.timestamp_format.clone(),
+                format.default_level.unwrap_or(LogLevel::Info),
+            )?));
+        }
+        Ok(formats)
+    }
+
+    pub fn alert_rules(&self) -> Result<Vec<AlertRule>> {
+        self.alerts
+            .iter()
+            .map(|alert| {
+                let invalid = |msg: String| LogAnalyzerError::ConfigError(format!("alert '{}': {}", alert.name, msg));
+                let window = parse_duration(&alert.window).map_err(invalid)?;
+                let filter = Filter::new(alert.levels.clone(), None, None, alert.pattern.as_deref(), alert.case_sensitive)
+                    .map_err(|e| invalid(e.to_string()))?;
+                AlertRule::new(&alert.name, filter, alert.threshold, window, alert.command.clone())
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_config_formats() {
+        let config = Config::parse(
+            r#"
+            [[format]]
+            name = "first"
+            pattern = '^(?P<timestamp>\S+) (?P<message>.*)$'
+
+            [[format]]
+            name = "second"
+            pattern = '^(?P<timestamp>\S+) (?P<level>\w+) (?P<message>.*)$'
+            default_level = "warn"
+            "#,
+        )
+        .unwrap();
+        let formats = config.formats().unwrap();
+        assert_eq!(formats.names()[..3], ["first", "second", "plain"]);
+        assert_eq!(formats.detect(["2024-01-15T10:30:00Z info up"]).unwrap().name(), "first");
+
+        let taken = Config::parse("[[format]]\nname = \"json\"\npattern = '(?P<timestamp>) (?P<message>)'").unwrap();
+        assert!(matches!(taken.formats(), Err(LogAnalyzerError::ConfigError(_))));
+        assert!(matches!(Config::parse("[[format]]\nname = \"x\""), Err(LogAnalyzerError::ConfigError(_))));
+    }
+
+    #[test]
+    fn test_config_alerts() {
+        let config = Config::parse(
+            r#"
+            [[alert]]
+            name = "errors"
+            threshold = 10
+            window = "1m"
+            levels = ["error"]
+            command = "echo alert"
+            "#,
+        )
+        .unwrap();
+        let rules = config.alert_rules().unwrap();
+        assert_eq!((rules[0].threshold, rules[0].window.as_secs()), (10, 60));
+        assert_eq!(rules[0].command.as_deref(), Some("echo alert"));
+
+        let bad_window = Config::parse("[[alert]]\nname = \"x\"\nthreshold = 1\nwindow = \"soon\"").unwrap();
+        assert!(matches!(bad_window.alert_rules(), Err(LogAnalyzerError::ConfigError(msg)) if msg.starts_with("alert 'x': ")));
+    }
+}
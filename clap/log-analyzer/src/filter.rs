@@ -0,0 +1,82 @@
+use chrono::NaiveDate;
+use regex::{Regex, RegexBuilder};
+
+use crate::types::{LogAnalyzerError, LogEntry, LogLevel, Result};
+
+/// Which entries to keep; an unset criterion keeps everything
+#[derive(Debug, Default)]
+pub struct Filter {
+    levels: Vec<LogLevel>,
+    from: Option<NaiveDate>,
+    to: Option<NaiveDate>,
+    pattern: Option<Regex>,
+}
+
+impl Filter {
+    /// Build a filter from the command line options
+    ///
+    /// `from` and `to` are inclusive. `pattern` is a regular expression
+    /// matched anywhere in the message, ignoring case unless `case_sensitive`.
+    pub fn new(
+        levels: Vec<LogLevel>,
+        from: Option<NaiveDate>,
+        to: Option<NaiveDate>,
+        pattern: Option<&str>,
+        case_sensitive: bool,
+    ) -> Result<Self> {
+        if let (Some(from), Some(to)) = (from, to)
+            && from > to
+        {
+            return Err(LogAnalyzerError::InvalidDate(format!("--from {} is after --to {}", from, to)));
+        }
+        let pattern = pattern
+            .map(|pattern| {
+                RegexBuilder::new(pattern)
+                    .case_insensitive(!case_sensitive)
+                    .build()
+                    .map_err(|e| LogAnalyzerError::InvalidPattern(e.to_string()))
+            })
+            .transpose()?;
+
+        Ok(Filter { levels, from, to, pattern })
+    }
+
+    pub fn matches(&self, entry: &LogEntry) -> bool {
+        let date = entry.timestamp.date();
+        (self.levels.is_empty() || self.levels.contains(&entry.level))
+            && self.from.is_none_or(|from| date >= from)
+            && self.to.is_none_or(|to| date <= to)
+            && self.pattern.as_ref().is_none_or(|pattern| pattern.is_match(&entry.message))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::sample_entry;
+
+    #[test]
+    fn test_filter() {
+        let date = |s| NaiveDate::parse_from_str(s, "%Y-%m-%d").ok();
+        let first = sample_entry("2024-01-01 00:00:00", LogLevel::Error, "Database timeout");
+        let last = sample_entry("2024-01-31 23:59:59", LogLevel::Info, "database connected");
+        let later = sample_entry("2024-02-01 00:00:00", LogLevel::Error, "Disk full");
+
+        let dates = Filter::new(vec![], date("2024-01-01"), date("2024-01-31"), None, false).unwrap();
+        assert!(dates.matches(&first) && dates.matches(&last) && !dates.matches(&later));
+
+        let errors = Filter::new(vec![LogLevel::Error, LogLevel::Warn], None, None, None, false).unwrap();
+        assert!(errors.matches(&first) && !errors.matches(&last) && errors.matches(&later));
+
+        let ignoring_case = Filter::new(vec![], None, None, Some("^database"), false).unwrap();
+        assert!(ignoring_case.matches(&first) && ignoring_case.matches(&last) && !ignoring_case.matches(&later));
+        let exact_case = Filter::new(vec![], None, None, Some("^database"), true).unwrap();
+        assert!(!exact_case.matches(&first) && exact_case.matches(&last));
+
+        assert!(matches!(
+            Filter::new(vec![], date("2024-02-01"), date("2024-01-01"), None, false),
+            Err(LogAnalyzerError::InvalidDate(_))
+        ));
+        assert!(matches!(Filter::new(vec![], None, None, Some("("), false), Err(LogAnalyzerError::InvalidPattern(_))));
+    }
+}
@@ -0,0 +1,291 @@
+use std::fs::{self, File, Metadata};
+use std::io::{self, BufRead, BufReader, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+
+use crate::analyzer::{Analysis, LogAnalyzer, with_path};
+use crate::format::DETECT_SAMPLE_LINES;
+use crate::parser::{LogParser, quote};
+use crate::types::{LogAnalyzerError, Result};
+
+// Files are polled rather than watched, which works the same on every
+// platform and filesystem. A file is reopened when its path points to a new
+// file (rotation by rename) or when it becomes shorter than what was read
+// (truncation, as by copytruncate). Rotation is recognised by inode on Unix;
+// elsewhere only truncation is. A missing file is opened once it appears.
+
+/// Most bytes read from the start of an existing file to detect its format,
+/// and from its end to find a partial last line
+const PEEK_LIMIT: u64 = 64 * 1024;
+
+/// Reads the lines appended to a file, following it across rotations
+pub struct Tailer {
+    path: PathBuf,
+    /// `None` while the file does not exist
+    reader: Option<BufReader<File>>,
+    identity: Option<(u64, u64)>,
+    /// Bytes read from the current file
+    position: u64,
+    /// The start of a line whose newline has not been written yet
+    pending: Vec<u8>,
+    line_no: usize,
+    /// `None` until there are lines to detect the format from
+    parser: Option<LogParser>,
+}
+
+#[cfg(unix)]
+fn identity(metadata: &Metadata) -> Option<(u64, u64)> {
+    use std::os::unix::fs::MetadataExt;
+    Some((metadata.dev(), metadata.ino()))
+}
+
+#[cfg(not(unix))]
+fn identity(_metadata: &Metadata) -> Option<(u64, u64)> {
+    None
+}
+
+impl Tailer {
+    /// Start following `path` from its current end
+    ///
+    /// The existing lines are not read, so however large the file, lines
+    /// appended to it are numbered from 1; after a rotation or truncation the
+    /// numbers are the real ones again. The first lines of the file are used
+    /// to detect the format. A file that does not exist yet is waited for,
+    /// and one whose format is not recognised is detected again from the
+    /// lines appended to it.
+    pub fn open(path: &Path, analyzer: &LogAnalyzer) -> Result<Self> {
+        let mut tailer = Tailer {
+            path: path.to_path_buf(),
+            reader: None,
+            identity: None,
+            position: 0,
+            pending: Vec::new(),
+            line_no: 0,
+            parser: None,
+        };
+        tailer.reopen()?;
+        let Some(reader) = tailer.reader.as_mut() else {
+            return Ok(tailer);
+        };
+
+        let mut sample = Vec::new();
+        let mut head = Vec::new();
+        (&mut *reader).take(PEEK_LIMIT).read_to_end(&mut head).map_err(|e| with_path(path, e))?;
+        for line in head.split_inclusive(|&byte| byte == b'\n').filter(|line| line.ends_with(b"\n")) {
+            let text = String::from_utf8_lossy(line);
+            let text = text.trim_end_matches(['\n', '\r']);
+            if sample.len() < DETECT_SAMPLE_LINES && !text.trim().is_empty() {
+                sample.push(text.to_string());
+            }
+        }
+
+        // Skip to the end, keeping a last line that has no newline yet
+        let end = reader.seek(SeekFrom::End(0)).map_err(|e| with_path(path, e))?;
+        let tail_start = reader.seek(SeekFrom::Start(end.saturating_sub(PEEK_LIMIT))).map_err(|e| with_path(path, e))?;
+        let mut tail = Vec::new();
+        reader.read_to_end(&mut tail).map_err(|e| with_path(path, e))?;
+        tailer.position = tail_start + tail.len() as u64;
+        tailer.pending = match tail.iter().rposition(|&byte| byte == b'\n') {
+            Some(newline) => tail.split_off(newline + 1),
+            None => tail,
+        };
+        if !sample.is_empty() {
+            tailer.parser = analyzer.parser_for(path, sample.iter().map(String::as_str)).ok();
+        }
+        Ok(tailer)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Whether the file exists, rather than being waited for
+    pub fn is_open(&self) -> bool {
+        self.reader.is_some()
+    }
+
+    /// Start reading the file now at the path from its beginning
+    fn reopen(&mut self) -> Result<()> {
+        self.reader = None;
+        self.identity = None;
+        self.position = 0;
+        self.pending.clear();
+        self.line_no = 0;
+        let file = match File::open(&self.path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+            Err(e) => return Err(with_path(&self.path, e)),
+        };
+        self.identity = identity(&file.metadata().map_err(|e| with_path(&self.path, e))?);
+        self.reader = Some(BufReader::new(file));
+        Ok(())
+    }
+
+    /// Parse the lines added since the last poll into `analysis`
+    ///
+    /// A missing file is taken to be between rotation and re-creation, and
+    /// is waited for. Lines that come before the format could be detected
+    /// are reported as skipped.
+    pub fn poll(&mut self, analyzer: &LogAnalyzer, analysis: &mut Analysis) -> Result<()> {
+        let mut lines = Vec::new();
+        self.read_lines(&mut lines)?;
+
+        match fs::metadata(&self.path) {
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => return Err(with_path(&self.path, e)),
+            Ok(metadata) => {
+                let created = self.reader.is_none();
+                let rotated = identity(&metadata) != self.identity;
+                let truncated = metadata.len() < self.position;
+                if created || rotated || truncated {
+                    // The rest of a rotated file was read above; a final line
+                    // without a newline is complete now
+                    if rotated && !self.pending.is_empty() {
+                        let line = std::mem::take(&mut self.pending);
+                        self.line_no += 1;
+                        lines.push((self.line_no, String::from_utf8_lossy(&line).trim_end().to_string()));
+                    }
+                    self.reopen()?;
+                    self.read_lines(&mut lines)?;
+                }
+            }
+        }
+
+        let lines: Vec<_> = lines.into_iter().filter(|(_, line)| !line.trim().is_empty()).collect();
+        if lines.is_empty() {
+            return Ok(());
+        }
+        if self.parser.is_none() {
+            let sample = lines.iter().take(DETECT_SAMPLE_LINES).map(|(_, line)| line.as_str());
+            let Ok(parser) = analyzer.parser_for(&self.path, sample) else {
+                for (line_no, line) in &lines {
+                    analysis.skipped.push(LogAnalyzerError::ParseError(format!(
+                        "{}:{}: no known format matches the lines so far: {:?}",
+                        self.path.display(),
+                        line_no,
+                        quote(line)
+                    )));
+                }
+                return Ok(());
+            };
+            self.parser = Some(parser);
+        }
+        let parser = self.parser.as_ref().expect("set above");
+        for (line_no, line) in &lines {
+            analyzer.add_line(parser, line, &self.path, *line_no, analysis);
+        }
+        Ok(())
+    }
+
+    /// Read the complete lines now in the file, keeping a partial last line pending
+    fn read_lines(&mut self, lines: &mut Vec<(usize, String)>) -> Result<()> {
+        let Some(reader) = self.reader.as_mut() else {
+            return Ok(());
+        };
+        loop {
+            let n = reader.read_until(b'\n', &mut self.pending).map_err(|e| with_path(&self.path, e))?;
+            self.position += n as u64;
+            if !self.pending.ends_with(b"\n") {
+                return Ok(());
+            }
+            self.line_no += 1;
+            let line = String::from_utf8_lossy(&self.pending).trim_end_matches(['\n', '\r']).to_string();
+            lines.push((self.line_no, line));
+            self.pending.clear();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::analyzer::FormatSelection;
+    use crate::filter::Filter;
+    use crate::format::Formats;
+    use std::io::Write;
+
+    fn poll(tailer: &mut Tailer, analyzer: &LogAnalyzer) -> Vec<(usize, String)> {
+        let mut analysis = Analysis::default();
+        tailer.poll(analyzer, &mut analysis).unwrap();
+        assert!(analysis.skipped.is_empty(), "{:?}", analysis.skipped);
+        analysis.entries.into_iter().map(|entry| (entry.line_no, entry.message)).collect()
+    }
+
+    fn append(path: &Path, text: &str) {
+        fs::OpenOptions::new().append(true).create(true).open(path).unwrap().write_all(text.as_bytes()).unwrap();
+    }
+
+    #[test]
+    fn test_follow_appends_rotation_and_truncation() {
+        let dir = std::env::temp_dir().join(format!("log-analyzer-follow-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("app.log");
+        fs::write(&path, "2024-01-15 10:00:00 [INFO] old\n").unwrap();
+
+        let filter = Filter::new(vec![], None, None, Some("new|next|after"), false).unwrap();
+        let analyzer = LogAnalyzer::new(filter, FormatSelection::Detect(Formats::builtin()));
+        let mut tailer = Tailer::open(&path, &analyzer).unwrap();
+        assert!(poll(&mut tailer, &analyzer).is_empty());
+
+        // Lines are numbered from where following started
+        append(&path, "2024-01-15 10:00:01 [INFO] new\n2024-01-15 10:00:02 [INFO] next, part");
+        assert_eq!(poll(&mut tailer, &analyzer), [(1, "new".to_string())]);
+        append(&path, "ial\n");
+        assert_eq!(poll(&mut tailer, &analyzer), [(2, "next, partial".to_string())]);
+
+        // Rotation by rename: the old file is finished, then the new one read from the start
+        append(&path, "2024-01-15 10:00:03 [INFO] next, unterminated");
+        fs::rename(&path, dir.join("app.log.1")).unwrap();
+        assert!(poll(&mut tailer, &analyzer).is_empty());
+        fs::write(&path, "2024-01-15 10:00:04 [INFO] after rotation\n").unwrap();
+        #[cfg(unix)]
+        assert_eq!(
+            poll(&mut tailer, &analyzer),
+            [(3, "next, unterminated".to_string()), (1, "after rotation".to_string())]
+        );
+
+        // Truncation in place
+        fs::write(&path, "").unwrap();
+        assert!(poll(&mut tailer, &analyzer).is_empty());
+        append(&path, "2024-01-15 10:00:05 [INFO] after truncation\n");
+        assert_eq!(poll(&mut tailer, &analyzer), [(1, "after truncation".to_string())]);
+
+        // A last line still being written when following starts is completed
+        let partial = dir.join("partial.log");
+        fs::write(&partial, "2024-01-15 10:00:00 [INFO] old\n2024-01-15 10:00:06 [INFO] next, half").unwrap();
+        let mut tailer = Tailer::open(&partial, &analyzer).unwrap();
+        append(&partial, " done\n");
+        assert_eq!(poll(&mut tailer, &analyzer), [(1, "next, half done".to_string())]);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_follow_missing_file_and_late_detection() {
+        let dir = std::env::temp_dir().join(format!("log-analyzer-follow-late-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("app.log");
+        let _ = fs::remove_file(&path);
+
+        let analyzer = LogAnalyzer::new(Filter::default(), FormatSelection::Detect(Formats::builtin()));
+        let mut tailer = Tailer::open(&path, &analyzer).unwrap();
+        assert!(!tailer.is_open());
+        assert!(poll(&mut tailer, &analyzer).is_empty());
+
+        // Created empty, then written a banner no format matches
+        fs::write(&path, "").unwrap();
+        assert!(poll(&mut tailer, &analyzer).is_empty());
+        assert!(tailer.is_open());
+        append(&path, "=== service starting ===\n");
+        let mut analysis = Analysis::default();
+        tailer.poll(&analyzer, &mut analysis).unwrap();
+        assert!(analysis.entries.is_empty());
+        assert_eq!(analysis.skipped.len(), 1);
+        assert!(analysis.skipped[0].to_string().contains("app.log:1: "), "{}", analysis.skipped[0]);
+
+        // Detection is retried on the lines that follow
+        append(&path, "2024-01-15 10:00:00 [INFO] started\n");
+        assert_eq!(poll(&mut tailer, &analyzer), [(2, "started".to_string())]);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
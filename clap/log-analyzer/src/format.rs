@@ -0,0 +1,583 @@
+use std::sync::Arc;
+
+use chrono::{DateTime, NaiveDateTime};
+use regex::Regex;
+use serde_json::{Map, Value};
+
+use crate::types::{LogAnalyzerError, LogLevel, Result};
+
+// Timestamps that carry an offset are converted to UTC so that entries from
+// different sources merge in order; timestamps without one are kept as written.
+
+/// Non-blank lines sampled from the start of a file to detect its format
+pub const DETECT_SAMPLE_LINES: usize = 20;
+
+/// Keys holding the timestamp, level and message in JSON and logfmt lines
+const TIMESTAMP_KEYS: &[&str] = &["timestamp", "time", "ts", "@timestamp"];
+const LEVEL_KEYS: &[&str] = &["level", "lvl", "severity"];
+const MESSAGE_KEYS: &[&str] = &["message", "msg"];
+
+/// The parts of a log line common to every format
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Record {
+    pub timestamp: NaiveDateTime,
+    pub level: LogLevel,
+    pub message: String,
+}
+
+/// A way of writing log lines
+pub trait LogFormat: Send + Sync {
+    /// Name accepted by `--format`
+    fn name(&self) -> &str;
+
+    /// Parse one line, failing with a `ParseError` that gives the reason
+    fn parse(&self, line: &str) -> Result<Record>;
+}
+
+fn malformed(reason: impl Into<String>) -> LogAnalyzerError {
+    LogAnalyzerError::ParseError(reason.into())
+}
+
+/// Parse an RFC 3339 or `YYYY-MM-DD HH:MM:SS[.fff]` timestamp, or Unix epoch seconds
+pub fn parse_timestamp(s: &str) -> Option<NaiveDateTime> {
+    if let Ok(timestamp) = DateTime::parse_from_rfc3339(s) {
+        return Some(timestamp.naive_utc());
+    }
+    for format in ["%Y-%m-%dT%H:%M:%S%.f", "%Y-%m-%d %H:%M:%S%.f"] {
+        if let Ok(timestamp) = NaiveDateTime::parse_from_str(s, format) {
+            return Some(timestamp);
+        }
+    }
+    from_epoch(s.parse().ok()?)
+}
+
+fn from_epoch(secs: f64) -> Option<NaiveDateTime> {
+    if !secs.is_finite() {
+        return None;
+    }
+    let whole = secs.floor();
+    let nanos = (((secs - whole) * 1e9).round() as u32).min(999_999_999);
+    DateTime::from_timestamp(whole as i64, nanos).map(|timestamp| timestamp.naive_utc())
+}
+
+fn parse_level(s: &str) -> Result<LogLevel> {
+    s.parse().map_err(|_| malformed(format!("unknown level '{}'", s)))
+}
+
+///////////////////////////
+// Registry
+///////////////////////////
+
+/// The formats `--format` can name and auto-detection chooses from
+#[derive(Clone)]
+pub struct Formats {
+    formats: Vec<Arc<dyn LogFormat>>,
+}
+
+impl Formats {
+    /// The built-in formats
+    pub fn builtin() -> Self {
+        Formats {
+            formats: vec![
+                Arc::new(PlainFormat::new()),
+                Arc::new(JsonFormat),
+                Arc::new(LogfmtFormat),
+                Arc::new(SyslogFormat::new()),
+                Arc::new(NginxFormat::new()),
+            ],
+        }
+    }
+
+    /// Add a format, preferred over those already present when detecting
+    pub fn add(&mut self, format: Arc<dyn LogFormat>) {
+        self.formats.insert(0, format);
+    }
+
+    pub fn names(&self) -> Vec<&str> {
+        self.formats.iter().map(|format| format.name()).collect()
+    }
+
+    pub fn get(&self, name: &str) -> Result<Arc<dyn LogFormat>> {
+        self.formats.iter().find(|format| format.name() == name).cloned().ok_or_else(|| {
+            LogAnalyzerError::UnknownFormat(format!("'{}'; expected auto or one of {}", name, self.names().join(", ")))
+        })
+    }
+
+    /// The format that parses the most sample lines, or `None` if none parses any
+    ///
+    /// Ties go to the format added last, then to the built-ins in order.
+    pub fn detect<'a>(&self, sample: impl IntoIterator<Item = &'a str>) -> Option<Arc<dyn LogFormat>> {
+        let sample: Vec<&str> = sample.into_iter().collect();
+        let mut best: Option<(usize, &Arc<dyn LogFormat>)> = None;
+        for format in &self.formats {
+            let parsed = sample.iter().filter(|line| format.parse(line).is_ok()).count();
+            if parsed > 0 && best.is_none_or(|(most, _)| parsed > most) {
+                best = Some((parsed, format));
+            }
+        }
+        best.map(|(_, format)| Arc::clone(format))
+    }
+}
+
+///////////////////////////
+// Built-in formats
+///////////////////////////
+
+/// `2024-01-15 10:30:00 [ERROR] message`
+pub struct PlainFormat {
+    regex: Regex,
+}
+
+impl PlainFormat {
+    pub fn new() -> Self {
+        let pattern = r"^(\d{4}-\d{2}-\d{2}\s+\d{2}:\d{2}:\d{2})\s+\[(\w+)\]\s+(.+)$";
+        PlainFormat { regex: Regex::new(pattern).expect("valid regex") }
+    }
+}
+
+impl Default for PlainFormat {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl LogFormat for PlainFormat {
+    fn name(&self) -> &str {
+        "plain"
+    }
+
+    fn parse(&self, line: &str) -> Result<Record> {
+        let captures = self
+            .regex
+            .captures(line)
+            .ok_or_else(|| malformed("expected 'YYYY-MM-DD HH:MM:SS [LEVEL] message'"))?;
+        // A space in the format matches any run of whitespace
+        let timestamp = NaiveDateTime::parse_from_str(&captures[1], "%Y-%m-%d %H:%M:%S")
+            .map_err(|e| malformed(format!("invalid timestamp ({})", e)))?;
+
+        Ok(Record { timestamp, level: parse_level(&captures[2])?, message: captures[3].trim_end().to_string() })
+    }
+}
+
+/// One JSON object per line, as written by tracing-subscriber's JSON formatter
+///
+/// The timestamp, level and message are looked up under their usual keys,
+/// top-level or inside `fields`. The other `fields` are appended to the
+/// message as `key=value`.
+pub struct JsonFormat;
+
+impl JsonFormat {
+    fn take(object: &mut Map<String, Value>, keys: &[&str]) -> Option<Value> {
+        keys.iter().find_map(|key| object.remove(*key))
+    }
+}
+
+impl LogFormat for JsonFormat {
+    fn name(&self) -> &str {
+        "json"
+    }
+
+    fn parse(&self, line: &str) -> Result<Record> {
+        let Value::Object(mut object) =
+            serde_json::from_str(line).map_err(|e| malformed(format!("invalid JSON ({})", e)))?
+        else {
+            return Err(malformed("expected a JSON object"));
+        };
+        let mut fields = match object.remove("fields") {
+            Some(Value::Object(fields)) => fields,
+            _ => Map::new(),
+        };
+
+        let timestamp = match Self::take(&mut object, TIMESTAMP_KEYS) {
+            Some(Value::String(s)) => parse_timestamp(&s),
+            Some(Value::Number(n)) => n.as_f64().and_then(from_epoch),
+            _ => return Err(malformed("no timestamp")),
+        }
+        .ok_or_else(|| malformed("invalid timestamp"))?;
+        let level = match Self::take(&mut object, LEVEL_KEYS).or_else(|| Self::take(&mut fields, LEVEL_KEYS)) {
+            Some(Value::String(s)) => parse_level(&s)?,
+            Some(other) => return Err(malformed(format!("invalid level {}", other))),
+            None => LogLevel::Info,
+        };
+        let mut message = match Self::take(&mut fields, MESSAGE_KEYS).or_else(|| Self::take(&mut object, MESSAGE_KEYS)) {
+            Some(Value::String(s)) => s,
+            Some(other) => other.to_string(),
+            None => String::new(),
+        };
+        for (key, value) in fields {
+            let value = match value {
+                Value::String(s) => s,
+                other => other.to_string(),
+            };
+            append_field(&mut message, &key, &value);
+        }
+
+        Ok(Record { timestamp, level, message })
+    }
+}
+
+/// Append ` key=value` to a message, quoting values with spaces
+fn append_field(message: &mut String, key: &str, value: &str) {
+    if !message.is_empty() {
+        message.push(' ');
+    }
+    if value.is_empty() || value.contains(char::is_whitespace) || value.contains('"') {
+        message.push_str(&format!("{}={:?}", key, value));
+    } else {
+        message.push_str(&format!("{}={}", key, value));
+    }
+}
+
+/// `time=2024-01-15T10:30:00Z level=info msg="Server started" port=8080`
+///
+/// Keys other than the timestamp, level and message are appended to the
+/// message as `key=value`.
+pub struct LogfmtFormat;
+
+impl LogfmtFormat {
+    /// Split a line into key/value pairs, or `None` if it is not logfmt
+    fn pairs(line: &str) -> Option<Vec<(&str, String)>> {
+        let mut pairs = Vec::new();
+        let mut rest = line.trim_start();
+        while !rest.is_empty() {
+            let key_end = rest.find(|c: char| c == '=' || c == '"' || c.is_whitespace()).unwrap_or(rest.len());
+            let key = &rest[..key_end];
+            if key.is_empty() {
+                return None;
+            }
+            rest = &rest[key_end..];
+
+            let value = match rest.strip_prefix('=') {
+                Some(after) => match after.strip_prefix('"') {
+                    Some(quoted) => {
+                        let mut value = String::new();
+                        let mut chars = quoted.char_indices();
+                        let mut end = None;
+                        while let Some((i, c)) = chars.next() {
+                            match c {
+                                '"' => {
+                                    end = Some(i + 1);
+                                    break;
+                                }
+                                '\\' => match chars.next()?.1 {
+                                    'n' => value.push('\n'),
+                                    't' => value.push('\t'),
+                                    escaped => value.push(escaped),
+                                },
+                                c => value.push(c),
+                            }
+                        }
+                        rest = &quoted[end?..];
+                        value
+                    }
+                    None => {
+                        let end = after.find(char::is_whitespace).unwrap_or(after.len());
+                        rest = &after[end..];
+                        after[..end].to_string()
+                    }
+                },
+                // A bare key is a flag
+                None => String::new(),
+            };
+            if !rest.is_empty() && !rest.starts_with(char::is_whitespace) {
+                return None;
+            }
+            pairs.push((key, value));
+            rest = rest.trim_start();
+        }
+        Some(pairs)
+    }
+}
+
+impl LogFormat for LogfmtFormat {
+    fn name(&self) -> &str {
+        "logfmt"
+    }
+
+    fn parse(&self, line: &str) -> Result<Record> {
+        let pairs = Self::pairs(line).ok_or_else(|| malformed("expected key=value pairs"))?;
+        let find = |keys: &[&str]| pairs.iter().find(|(key, _)| keys.contains(key)).map(|(key, value)| (*key, value));
+
+        let (timestamp_key, timestamp) = find(TIMESTAMP_KEYS).ok_or_else(|| malformed("no time key"))?;
+        let timestamp = parse_timestamp(timestamp).ok_or_else(|| malformed(format!("invalid timestamp '{}'", timestamp)))?;
+        let level = find(LEVEL_KEYS);
+        let message = find(MESSAGE_KEYS);
+        let used = [Some(timestamp_key), level.map(|(key, _)| key), message.map(|(key, _)| key)];
+
+        let mut message = message.map(|(_, value)| value.clone()).unwrap_or_default();
+        for (key, value) in &pairs {
+            if !used.contains(&Some(*key)) {
+                append_field(&mut message, key, value);
+            }
+        }
+
+        Ok(Record { timestamp, level: level.map_or(Ok(LogLevel::Info), |(_, level)| parse_level(level))?, message })
+    }
+}
+
+/// RFC 5424 syslog: `<34>1 2024-01-15T10:30:00Z host app 1234 ID47 [sd] message`
+///
+/// The level comes from the severity in the priority: emergency to error are
+/// errors, warning is warn, notice and informational are info. The message
+/// is prefixed with the app name and process id, as in `sshd[1234]: ...`.
+pub struct SyslogFormat {
+    header: Regex,
+}
+
+impl SyslogFormat {
+    pub fn new() -> Self {
+        let header = r"^<(\d{1,3})>[1-9]\d? (\S+) \S+ (\S+) (\S+) \S+ (.*)$";
+        SyslogFormat { header: Regex::new(header).expect("valid regex") }
+    }
+
+    /// The text after the structured data, or `None` if it is malformed
+    fn skip_structured_data(s: &str) -> Option<&str> {
+        if let Some(rest) = s.strip_prefix('-') {
+            return Some(rest);
+        }
+        let mut rest = s;
+        while rest.starts_with('[') {
+            let mut in_quotes = false;
+            let mut escaped = false;
+            let mut end = None;
+            for (i, c) in rest.char_indices().skip(1) {
+                match c {
+                    _ if escaped => escaped = false,
+                    '\\' if in_quotes => escaped = true,
+                    '"' => in_quotes = !in_quotes,
+                    ']' if !in_quotes => {
+                        end = Some(i);
+                        break;
+                    }
+                    _ => {}
+                }
+            }
+            rest = &rest[end? + 1..];
+        }
+        (rest.len() < s.len()).then_some(rest)
+    }
+}
+
+impl Default for SyslogFormat {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl LogFormat for SyslogFormat {
+    fn name(&self) -> &str {
+        "syslog"
+    }
+
+    fn parse(&self, line: &str) -> Result<Record> {
+        let captures = self
+            .header
+            .captures(line)
+            .ok_or_else(|| malformed("expected '<PRI>VERSION TIMESTAMP HOST APP PROCID MSGID SD MSG'"))?;
+        let priority: u8 = captures[1].parse().ok().filter(|priority| *priority <= 191).ok_or_else(|| malformed("invalid priority"))?;
+        let level = match priority % 8 {
+            0..=3 => LogLevel::Error,
+            4 => LogLevel::Warn,
+            5 | 6 => LogLevel::Info,
+            _ => LogLevel::Debug,
+        };
+        let timestamp = DateTime::parse_from_rfc3339(&captures[2])
+            .map_err(|_| malformed(format!("invalid timestamp '{}'", &captures[2])))?
+            .naive_utc();
+
+        let rest = Self::skip_structured_data(captures.get(5).map_or("", |m| m.as_str()))
+            .ok_or_else(|| malformed("invalid structured data"))?;
+        let text = match rest.strip_prefix(' ') {
+            Some(text) => text.trim_start_matches('\u{feff}').trim_end(),
+            None if rest.is_empty() => "",
+            None => return Err(malformed("invalid structured data")),
+        };
+        let message = match (&captures[3], &captures[4]) {
+            ("-", _) => text.to_string(),
+            (app, "-") => format!("{}: {}", app, text),
+            (app, pid) => format!("{}[{}]: {}", app, pid, text),
+        };
+
+        Ok(Record { timestamp, level, message })
+    }
+}
+
+/// nginx (and Apache) combined access log:
+/// `1.2.3.4 - user [15/Jan/2024:10:30:00 +0000] "GET / HTTP/1.1" 200 612 "referer" "agent"`
+///
+/// The level comes from the status: 5xx is error, 4xx is warn, others info.
+/// The message keeps the client, request, status, size and user agent.
+pub struct NginxFormat {
+    regex: Regex,
+}
+
+impl NginxFormat {
+    pub fn new() -> Self {
+        let quoted = r#""((?:[^"\\]|\\.)*)""#;
+        let pattern = format!(r"^(\S+) \S+ \S+ \[([^\]]+)\] {q} (\d{{3}}) (\d+|-) {q} {q}", q = quoted);
+        NginxFormat { regex: Regex::new(&pattern).expect("valid regex") }
+    }
+}
+
+impl Default for NginxFormat {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl LogFormat for NginxFormat {
+    fn name(&self) -> &str {
+        "nginx"
+    }
+
+    fn parse(&self, line: &str) -> Result<Record> {
+        let captures = self.regex.captures(line).ok_or_else(|| malformed("expected a combined access log line"))?;
+        let timestamp = DateTime::parse_from_str(&captures[2], "%d/%b/%Y:%H:%M:%S %z")
+            .map_err(|e| malformed(format!("invalid timestamp ({})", e)))?
+            .naive_utc();
+        let level = match captures[4].as_bytes()[0] {
+            b'5' => LogLevel::Error,
+            b'4' => LogLevel::Warn,
+            _ => LogLevel::Info,
+        };
+        let message = format!("{} \"{}\" {} {} \"{}\"", &captures[1], &captures[3], &captures[4], &captures[5], &captures[7]);
+
+        Ok(Record { timestamp, level, message })
+    }
+}
+
+///////////////////////////
+// User-defined formats
+///////////////////////////
+
+/// A regular expression with named groups `timestamp`, `message` and optionally `level`
+pub struct RegexFormat {
+    name: String,
+    regex: Regex,
+    /// chrono format of the timestamp; the usual formats are tried without one
+    timestamp_format: Option<String>,
+    /// Level of lines without a `level` group, or where it did not match
+    default_level: LogLevel,
+}
+
+impl RegexFormat {
+    pub fn new(name: &str, pattern: &str, timestamp_format: Option<String>, default_level: LogLevel) -> Result<Self> {
+        let regex = Regex::new(pattern).map_err(|e| LogAnalyzerError::InvalidPattern(format!("format '{}': {}", name, e)))?;
+        for group in ["timestamp", "message"] {
+            if !regex.capture_names().flatten().any(|name| name == group) {
+                return Err(LogAnalyzerError::InvalidPattern(format!(
+                    "format '{}' has no (?P<{}>...) group",
+                    name, group
+                )));
+            }
+        }
+        Ok(RegexFormat { name: name.to_string(), regex, timestamp_format, default_level })
+    }
+
+    fn parse_timestamp(&self, s: &str) -> Option<NaiveDateTime> {
+        match &self.timestamp_format {
+            Some(format) => DateTime::parse_from_str(s, format)
+                .map(|timestamp| timestamp.naive_utc())
+                .or_else(|_| NaiveDateTime::parse_from_str(s, format))
+                .ok(),
+            None => parse_timestamp(s),
+        }
+    }
+}
+
+impl LogFormat for RegexFormat {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn parse(&self, line: &str) -> Result<Record> {
+        let captures = self
+            .regex
+            .captures(line)
+            .ok_or_else(|| malformed(format!("does not match format '{}'", self.name)))?;
+        let timestamp = &captures["timestamp"];
+        let timestamp =
+            self.parse_timestamp(timestamp).ok_or_else(|| malformed(format!("invalid timestamp '{}'", timestamp)))?;
+        let level = match captures.name("level") {
+            Some(level) => parse_level(level.as_str())?,
+            None => self.default_level,
+        };
+
+        Ok(Record { timestamp, level, message: captures["message"].trim_end().to_string() })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn time(s: &str) -> NaiveDateTime {
+        NaiveDateTime::parse_from_str(s, "%Y-%m-%d %H:%M:%S%.f").unwrap()
+    }
+
+    #[test]
+    fn test_builtin_formats() {
+        let formats = Formats::builtin();
+        let parse = |name: &str, line: &str| formats.get(name).unwrap().parse(line).unwrap();
+
+        let json = parse(
+            "json",
+            r#"{"timestamp":"2024-01-15T10:30:00.250+01:00","level":"TRACE","fields":{"message":"query done","rows":3,"table":"user accounts"},"target":"db"}"#,
+        );
+        assert_eq!(json.timestamp, time("2024-01-15 09:30:00.25"));
+        assert_eq!(json.level, LogLevel::Debug);
+        assert_eq!(json.message, r#"query done rows=3 table="user accounts""#);
+        assert_eq!(parse("json", r#"{"ts":1700000000,"msg":"up"}"#).timestamp, time("2023-11-14 22:13:20"));
+
+        let logfmt = parse("logfmt", r#"time=2024-01-15T10:30:00Z level=warn msg="disk \"/\" at 91%" retry"#);
+        assert_eq!((logfmt.timestamp, logfmt.level), (time("2024-01-15 10:30:00"), LogLevel::Warn));
+        assert_eq!(logfmt.message, r#"disk "/" at 91% retry="""#);
+
+        let syslog = parse(
+            "syslog",
+            r#"<34>1 2024-01-15T10:30:00.003Z web01 sshd 4321 ID47 [auth@32473 user="a \"b\"" ip="10.0.0.1"] Failed password"#,
+        );
+        assert_eq!((syslog.timestamp, syslog.level), (time("2024-01-15 10:30:00.003"), LogLevel::Error));
+        assert_eq!(syslog.message, "sshd[4321]: Failed password");
+        assert_eq!(parse("syslog", "<165>1 2024-01-15T10:30:00Z - - - - -").level, LogLevel::Info);
+
+        let nginx = parse(
+            "nginx",
+            r#"10.1.2.3 - bob [15/Jan/2024:10:30:00 -0500] "GET /api/users HTTP/1.1" 503 0 "-" "curl/8.4.0""#,
+        );
+        assert_eq!((nginx.timestamp, nginx.level), (time("2024-01-15 15:30:00"), LogLevel::Error));
+        assert_eq!(nginx.message, r#"10.1.2.3 "GET /api/users HTTP/1.1" 503 0 "curl/8.4.0""#);
+
+        assert!(formats.get("syslog").unwrap().parse("<34>1 2024-01-15T10:30:00Z h a p m [unclosed").is_err());
+        assert!(formats.get("logfmt").unwrap().parse("2024-01-15 10:30:00 [INFO] plain").is_err());
+        assert!(matches!(formats.get("xml"), Err(LogAnalyzerError::UnknownFormat(_))));
+    }
+
+    #[test]
+    fn test_detect() {
+        let formats = Formats::builtin();
+        let detect = |sample: &[&str]| formats.detect(sample.iter().copied()).map(|format| format.name().to_string());
+
+        assert_eq!(detect(&["2024-01-15 10:30:00 [INFO] a", "garbage"]).as_deref(), Some("plain"));
+        assert_eq!(detect(&[r#"{"time":"2024-01-15T10:30:00Z","level":"info","msg":"a"}"#]).as_deref(), Some("json"));
+        assert_eq!(detect(&["ts=2024-01-15T10:30:00Z level=info msg=a", "garbage"]).as_deref(), Some("logfmt"));
+        assert_eq!(detect(&["garbage", "more garbage"]), None);
+
+        let mut custom = formats.clone();
+        let pattern = r"^(?P<timestamp>\d{4}-\d{2}-\d{2} \d{2}:\d{2}:\d{2}) \[(?P<level>\w+)\] (?P<message>.*)$";
+        custom.add(Arc::new(RegexFormat::new("mine", pattern, None, LogLevel::Info).unwrap()));
+        let detected = custom.detect(["2024-01-15 10:30:00 [INFO] a"]).unwrap();
+        assert_eq!(detected.name(), "mine");
+    }
+
+    #[test]
+    fn test_regex_format() {
+        let pattern = r"^(?P<timestamp>\S+ \S+) (?P<message>.*)$";
+        let format = RegexFormat::new("app", pattern, Some("%d.%m.%Y %H:%M".to_string()), LogLevel::Warn).unwrap();
+        let record = format.parse("15.01.2024 10:30 low memory").unwrap();
+        assert_eq!(record, Record { timestamp: time("2024-01-15 10:30:00"), level: LogLevel::Warn, message: "low memory".to_string() });
+
+        assert!(format.parse("yesterday noon low memory").is_err());
+        assert!(matches!(RegexFormat::new("bad", r"(?P<message>.*)", None, LogLevel::Info), Err(LogAnalyzerError::InvalidPattern(_))));
+    }
+}
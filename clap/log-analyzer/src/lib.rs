@@ -1 +1,11 @@
-pub mod utils;
\ No newline at end of file
+pub mod alert;
+pub mod analyzer;
+pub mod config;
+pub mod filter;
+pub mod follow;
+pub mod format;
+pub mod output;
+pub mod parser;
+pub mod stats;
+pub mod types;
+pub mod utils;
@@ -1,24 +1,36 @@
-use clap::{Parser, ValueEnum};
-use chrono::{NaiveDate, NaiveDateTime};
-use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
-use std::fs::File;
-use std::io::{BufRead, BufReader};
+use clap::Parser;
+use chrono::{NaiveDate, TimeDelta};
+use std::io::{self, Write};
 use std::path::PathBuf;
-
-use log_analyzer::utils::{parse_date};
+use std::process::{self, Child};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use log_analyzer::alert::{AlertMonitor, AlertRule};
+use log_analyzer::analyzer::{Analysis, FormatSelection, LogAnalyzer};
+use log_analyzer::config::Config;
+use log_analyzer::filter::Filter;
+use log_analyzer::follow::Tailer;
+use log_analyzer::output::{write_entries, write_entry_line, write_stats};
+use log_analyzer::stats::{Bucket, RollingStats, Stats};
+use log_analyzer::types::{LogLevel, OutputFormat, Result};
+use log_analyzer::utils::{parse_date, parse_duration};
+
+/// Most malformed lines reported individually
+const MAX_REPORTED_SKIPS: usize = 10;
 
 #[derive(Parser)]
 #[command(name = "log-analyzer")]
 #[command(about = "A powerful log file analyzer")]
 #[command(version = "1.0.0")]
-#[command(author = "Your Name <your.email@example.com>")]struct Cli {
+#[command(author = "Your Name <your.email@example.com>")]
+struct Cli {
     /// Log files to analyze (required, multiple allowed)
     #[arg(required = true)]
     files: Vec<PathBuf>,
 
-    /// Log levels to filter (multiple allowed)
-    #[arg(short, long, value_enum)]
+    /// Log levels to filter (comma-separated or repeated)
+    #[arg(short, long, value_enum, value_delimiter = ',')]
     level: Vec<LogLevel>,
 
     /// Start date for filtering (YYYY-MM-DD format)
@@ -29,106 +41,183 @@ use log_analyzer::utils::{parse_date};
     #[arg(long, value_parser = parse_date)]
     to: Option<NaiveDate>,
 
+    /// Log format: auto, plain, json, logfmt, syslog, nginx, or one defined in --config
+    #[arg(short, long, default_value = "auto")]
+    format: String,
+
+    /// TOML file defining extra log formats
+    #[arg(short, long)]
+    config: Option<PathBuf>,
+
     /// Output format
     #[arg(short, long, value_enum, default_value_t = OutputFormat::Table)]
     output_format: OutputFormat,
 
-    /// Show statistics
+    /// Show statistics instead of the entries
     #[arg(long)]
     stats: bool,
 
+    /// Time bucket of the --stats histogram
+    #[arg(long, value_enum, default_value_t = Bucket::Auto)]
+    bucket: Bucket,
+
+    /// Number of most frequent message templates in --stats
+    #[arg(long, default_value_t = 10)]
+    top: usize,
+
     /// Case-sensitive filtering
     #[arg(long)]
     case_sensitive: bool,
 
-    /// Pattern to search for in log messages
+    /// Pattern (regular expression) to search for in log messages
     #[arg(short, long)]
     pattern: Option<String>,
 
     /// Maximum number of log entries to display
-    #[arg(short, long)]
+    #[arg(short = 'n', long)]
     limit: Option<usize>,
-}
 
-///////////////////////////
-/// Log level Enum
-///////////////////////////
-#[derive(Debug, Clone, ValueEnum, PartialEq, Eq, Hash, Serialize, Deserialize)]
-#[serde(rename_all = "lowercase")]
-enum LogLevel {
-    Debug,
-    Info,
-    Warn,
-    Error,
-}
+    /// Keep printing entries as they are appended, across rotation and truncation
+    #[arg(long, conflicts_with = "limit")]
+    follow: bool,
 
-impl std::fmt::Display for LogLevel {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        match self {
-            LogLevel::Debug => write!(f, "DEBUG"),
-            LogLevel::Info => write!(f, "INFO"),
-            LogLevel::Warn => write!(f, "WARN"),
-            LogLevel::Error => write!(f, "ERROR"),
-        }
-    }
-}
-///////////////////////
-// Error
-///////////////////////
-#[derive(Debug)]
-enum LogAnalyzerError {
-    IoError(std::io::Error),
-    ParseError(String),
-    InvalidDate(String),
-}
+    /// How often --follow checks the files (e.g. 500ms, 2s)
+    #[arg(long, value_parser = parse_duration, default_value = "500ms")]
+    poll_interval: Duration,
 
-impl std::fmt::Display for LogAnalyzerError {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        match self {
-            LogAnalyzerError::IoError(e) => write!(f, "IO error: {}", e),
-            LogAnalyzerError::ParseError(msg) => write!(f, "Parse error: {}", msg),
-            LogAnalyzerError::InvalidDate(msg) => write!(f, "Invalid date: {}", msg),
-        }
-    }
-}
+    /// How often --follow --stats prints a report
+    #[arg(long, value_parser = parse_duration, default_value = "1m")]
+    stats_interval: Duration,
 
-impl std::error::Error for LogAnalyzerError {}
+    /// Span of the latest entries covered by --follow --stats reports
+    #[arg(long, value_parser = parse_duration, default_value = "1h")]
+    stats_window: Duration,
+}
 
-impl From<std::io::Error> for LogAnalyzerError {
-    fn from(error: std::io::Error) -> Self {
-        LogAnalyzerError::IoError(error)
+fn run(cli: Cli) -> Result<()> {
+    let filter = Filter::new(cli.level.clone(), cli.from, cli.to, cli.pattern.as_deref(), cli.case_sensitive)?;
+    let config = cli.config.as_deref().map(Config::load).transpose()?.unwrap_or_default();
+    let formats = config.formats()?;
+    let selection = match cli.format.as_str() {
+        "auto" => FormatSelection::Detect(formats),
+        name => FormatSelection::Fixed(formats.get(name)?),
+    };
+    let rules = config.alert_rules()?;
+    if cli.follow {
+        // Alert rules see every entry; the filters only pick what is shown
+        let analyzer = LogAnalyzer::new(Filter::default(), selection);
+        return handle_follow(&cli, &analyzer, &filter, rules);
     }
-}
+    let analyzer = LogAnalyzer::new(filter, selection);
+    let mut analysis = analyzer.analyze(&cli.files)?;
 
-type Result<T> = std::result::Result<T, LogAnalyzerError>;
+    for error in analysis.skipped.iter().take(MAX_REPORTED_SKIPS) {
+        eprintln!("Warning: skipped line: {}", error);
+    }
+    if analysis.skipped.len() > MAX_REPORTED_SKIPS {
+        eprintln!("Warning: skipped {} more malformed lines", analysis.skipped.len() - MAX_REPORTED_SKIPS);
+    }
 
-struct LogParser {
-    regex: regex::Regex,
+    if cli.stats {
+        let stats = Stats::compute(&analysis.entries, cli.bucket, cli.top);
+        return write_stats(&stats, cli.output_format, io::stdout().lock());
+    }
+    if let Some(limit) = cli.limit {
+        analysis.entries.truncate(limit);
+    }
+    write_entries(&analysis.entries, cli.output_format, io::stdout().lock())
 }
 
-impl LogParser {
-    fn new() -> Result<Self> {
-        let pattern = r"^(\d{4}-\d{2}-\d{2}\s+\d{2}:\d{2}:\d{2})\s+\[(\w+)\]\s+(.+)$";
-        let regex = regex::Regex::new(pattern)
-            .map_err(|e| LogAnalyzerError::ParseError(format!("Regex error: {}", e)))?;
+/// Print new entries, raise alerts and report rolling stats until interrupted
+///
+/// `analyzer` should not filter, since alert rules look at every entry;
+/// `filter` applies to the printed entries and the stats.
+fn handle_follow(cli: &Cli, analyzer: &LogAnalyzer, filter: &Filter, rules: Vec<AlertRule>) -> Result<()> {
+    let mut tailers = cli.files.iter().map(|path| Tailer::open(path, analyzer)).collect::<Result<Vec<_>>>()?;
+    let mut monitor = AlertMonitor::new(rules);
+    let mut rolling = RollingStats::new(TimeDelta::from_std(cli.stats_window).unwrap_or(TimeDelta::MAX));
+    let mut last_report = Instant::now();
+    let mut header = true;
+    let mut commands: Vec<Child> = Vec::new();
+
+    for tailer in tailers.iter().filter(|tailer| !tailer.is_open()) {
+        eprintln!("Waiting for {} to be created", tailer.path().display());
+    }
+    eprintln!("Following {} file(s); press Ctrl-C to stop", tailers.len());
+    loop {
+        let mut batch = Analysis::default();
+        for tailer in &mut tailers {
+            tailer.poll(analyzer, &mut batch)?;
+        }
+        for error in &batch.skipped {
+            eprintln!("Warning: skipped line: {}", error);
+        }
+        batch.entries.sort_by_key(|entry| entry.timestamp);
+
+        let mut out = io::stdout().lock();
+        for entry in batch.entries {
+            for alert in monitor.observe(&entry) {
+                eprintln!("ALERT {}", alert);
+                match alert.spawn_command() {
+                    Some(Ok(child)) => commands.push(child),
+                    Some(Err(e)) => eprintln!("Warning: command of alert '{}' failed: {}", alert.rule, e),
+                    None => {}
+                }
+            }
+            if !filter.matches(&entry) {
+                continue;
+            }
+            if cli.stats {
+                rolling.push(entry);
+            } else {
+                write_entry_line(&entry, cli.output_format, header, &mut out)?;
+                header = false;
+            }
+        }
+        if cli.stats && last_report.elapsed() >= cli.stats_interval {
+            write_stats(&rolling.stats(cli.bucket, cli.top), cli.output_format, &mut out)?;
+            if cli.output_format == OutputFormat::Table {
+                writeln!(out)?;
+            }
+            last_report = Instant::now();
+        }
+        out.flush()?;
+        drop(out);
 
-        Ok(LogParser { regex })
+        // Reap the commands that have finished
+        commands.retain_mut(|child| matches!(child.try_wait(), Ok(None)));
+        thread::sleep(cli.poll_interval);
     }
 }
 
-#[derive(Debug, Clone, ValueEnum, PartialEq)]
-enum OutputFormat {
-    Json,
-    Csv,
-    Table,
-}
+fn main() {
+    let cli = Cli::parse();
 
-struct LogAnalyzer {
-    parser: LogParser,
+    if let Err(e) = run(cli) {
+        eprintln!("Error: {}", e);
+        process::exit(1);
+    }
 }
 
-fn main() -> Result<()> {
-    let args = Cli::parse();
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::CommandFactory;
+
+    #[test]
+    fn test_cli() {
+        Cli::command().debug_assert();
+
+        for args in [
+            &["log-analyzer", "--level", "error", "app.log"][..],
+            &["log-analyzer", "app.log", "--level", "error"],
+        ] {
+            let cli = Cli::try_parse_from(args).unwrap();
+            assert_eq!((cli.files, cli.level), (vec![PathBuf::from("app.log")], vec![LogLevel::Error]));
+        }
 
-    Ok(())
+        let cli = Cli::try_parse_from(["log-analyzer", "-l", "error,warn", "-l", "info", "a.log", "b.log"]).unwrap();
+        assert_eq!(cli.level, [LogLevel::Error, LogLevel::Warn, LogLevel::Info]);
+        assert_eq!(cli.files.len(), 2);
+    }
 }
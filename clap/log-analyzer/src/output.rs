@@ -0,0 +1,321 @@
+use std::io::Write;
+
+use chrono::NaiveDateTime;
+use serde::Serialize;
+
+use crate::stats::{MAX_BUCKETS, Stats};
+use crate::types::{LogEntry, OutputFormat, Result};
+
+/// Width of the longest bar in the histogram
+const HISTOGRAM_WIDTH: usize = 40;
+
+/// Write entries to `out` in the chosen format
+pub fn write_entries<W: Write>(entries: &[LogEntry], format: OutputFormat, out: W) -> Result<()> {
+    match format {
+        OutputFormat::Table => write_table(entries, out),
+        OutputFormat::Json => write_json(entries, out),
+        OutputFormat::Csv => write_csv(entries, out),
+    }
+}
+
+/// Write one entry as it arrives: a table row, a JSON line, or a CSV record
+///
+/// With `header`, CSV output starts with the header row.
+pub fn write_entry_line<W: Write>(entry: &LogEntry, format: OutputFormat, header: bool, mut out: W) -> Result<()> {
+    match format {
+        OutputFormat::Table => writeln!(
+            out,
+            "{}  {:<5}  {}:{}  {}",
+            entry.timestamp.format("%Y-%m-%d %H:%M:%S"),
+            entry.level.to_string(),
+            entry.source_file.display(),
+            entry.line_no,
+            entry.message
+        )?,
+        OutputFormat::Json => {
+            serde_json::to_writer(&mut out, entry)?;
+            writeln!(out)?;
+        }
+        OutputFormat::Csv => {
+            let mut writer = csv::WriterBuilder::new().has_headers(header).from_writer(out);
+            writer.serialize(entry)?;
+            writer.flush()?;
+        }
+    }
+    Ok(())
+}
+
+/// Aligned columns, the message last and unpadded
+fn write_table<W: Write>(entries: &[LogEntry], mut out: W) -> Result<()> {
+    let sources: Vec<String> =
+        entries.iter().map(|entry| format!("{}:{}", entry.source_file.display(), entry.line_no)).collect();
+    let source_width = sources.iter().map(String::len).max().unwrap_or(0).max("SOURCE".len());
+
+    writeln!(out, "{:<19}  {:<5}  {:<source_width$}  MESSAGE", "TIMESTAMP", "LEVEL", "SOURCE")?;
+    for (entry, source) in entries.iter().zip(&sources) {
+        writeln!(
+            out,
+            "{:<19}  {:<5}  {:<source_width$}  {}",
+            entry.timestamp.format("%Y-%m-%d %H:%M:%S").to_string(),
+            entry.level.to_string(),
+            source,
+            entry.message
+        )?;
+    }
+    Ok(())
+}
+
+/// Pretty-printed JSON
+fn write_json<W: Write, T: Serialize + ?Sized>(value: &T, mut out: W) -> Result<()> {
+    serde_json::to_writer_pretty(&mut out, value)?;
+    writeln!(out)?;
+    Ok(())
+}
+
+/// CSV with a header row
+fn write_csv<W: Write>(entries: &[LogEntry], out: W) -> Result<()> {
+    let mut writer = csv::Writer::from_writer(out);
+    for entry in entries {
+        writer.serialize(entry)?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+/// Write a statistics report to `out` in the chosen format
+pub fn write_stats<W: Write>(stats: &Stats, format: OutputFormat, out: W) -> Result<()> {
+    match format {
+        OutputFormat::Table => write_stats_table(stats, out),
+        OutputFormat::Json => write_json(stats, out),
+        OutputFormat::Csv => write_stats_csv(stats, out),
+    }
+}
+
+fn percent(rate: f64) -> String {
+    format!("{:.1}%", rate * 100.0)
+}
+
+fn format_timestamp(timestamp: NaiveDateTime) -> String {
+    timestamp.format("%Y-%m-%d %H:%M:%S").to_string()
+}
+
+/// Summary, level counts, histogram and top templates as text sections
+fn write_stats_table<W: Write>(stats: &Stats, mut out: W) -> Result<()> {
+    write!(out, "Entries: {}, errors: {} ({})", stats.total, stats.errors, percent(stats.error_rate()))?;
+    if let (Some(first), Some(last)) = (stats.first, stats.last) {
+        write!(out, ", from {} to {}", format_timestamp(first), format_timestamp(last))?;
+    }
+    writeln!(out)?;
+    let count_width = stats.total.to_string().len().max("COUNT".len());
+
+    writeln!(out)?;
+    writeln!(out, "LEVEL  {:>count_width$}  PERCENT", "COUNT")?;
+    for (level, count) in &stats.levels {
+        let share = if stats.total == 0 { 0.0 } else { *count as f64 / stats.total as f64 };
+        writeln!(out, "{:<5}  {:>count_width$}  {:>7}", level.to_string(), count, percent(share))?;
+    }
+
+    if !stats.buckets.is_empty() {
+        let label_format = stats.bucket.label_format();
+        let label_width = stats.buckets[0].start.format(label_format).to_string().len();
+        let most = stats.buckets.iter().map(|bucket| bucket.count).max().unwrap_or(0).max(1);
+
+        writeln!(out)?;
+        writeln!(out, "{:<label_width$}  {:>count_width$}  {:>6}  HISTOGRAM", "TIME", "COUNT", "ERR%")?;
+        for bucket in &stats.buckets {
+            let bar = (bucket.count * HISTOGRAM_WIDTH).div_ceil(most);
+            let line = format!(
+                "{:<label_width$}  {:>count_width$}  {:>6}  {}",
+                bucket.start.format(label_format).to_string(),
+                bucket.count,
+                percent(bucket.error_rate()),
+                "#".repeat(bar)
+            );
+            writeln!(out, "{}", line.trim_end())?;
+        }
+    }
+    if stats.sparse {
+        writeln!(out, "Empty buckets omitted: the entries span more than {} days", MAX_BUCKETS)?;
+    }
+    if let Some(trend) = &stats.error_trend {
+        writeln!(
+            out,
+            "Error rate {}: {} in the first half, {} in the second",
+            trend.direction,
+            percent(trend.earlier_rate),
+            percent(trend.later_rate)
+        )?;
+    }
+
+    if !stats.top_templates.is_empty() {
+        writeln!(out)?;
+        writeln!(out, "Top {} of {} message templates", stats.top_templates.len(), stats.template_count)?;
+        let errors_width = count_width.max("ERRORS".len());
+        writeln!(out, "{:>count_width$}  {:>errors_width$}  {:<19}  {:<19}  TEMPLATE", "COUNT", "ERRORS", "FIRST", "LAST")?;
+        for template in &stats.top_templates {
+            writeln!(
+                out,
+                "{:>count_width$}  {:>errors_width$}  {}  {}  {}",
+                template.count,
+                template.errors,
+                format_timestamp(template.first),
+                format_timestamp(template.last),
+                template.template
+            )?;
+        }
+    }
+    Ok(())
+}
+
+/// One CSV row of the statistics report; fields that do not apply are empty
+#[derive(Serialize)]
+struct StatsRow<'a> {
+    section: &'static str,
+    key: String,
+    count: Option<usize>,
+    errors: Option<usize>,
+    error_rate: Option<f64>,
+    first: Option<NaiveDateTime>,
+    last: Option<NaiveDateTime>,
+    template: Option<&'a str>,
+}
+
+impl StatsRow<'_> {
+    fn new(section: &'static str, key: impl ToString) -> Self {
+        StatsRow {
+            section,
+            key: key.to_string(),
+            count: None,
+            errors: None,
+            error_rate: None,
+            first: None,
+            last: None,
+            template: None,
+        }
+    }
+}
+
+/// A single table with a `section` column: total, level, bucket, trend and template
+fn write_stats_csv<W: Write>(stats: &Stats, out: W) -> Result<()> {
+    let mut writer = csv::Writer::from_writer(out);
+    writer.serialize(StatsRow {
+        count: Some(stats.total),
+        errors: Some(stats.errors),
+        error_rate: Some(stats.error_rate()),
+        first: stats.first,
+        last: stats.last,
+        ..StatsRow::new("total", "all")
+    })?;
+    for (level, count) in &stats.levels {
+        writer.serialize(StatsRow { count: Some(*count), ..StatsRow::new("level", level.to_string().to_lowercase()) })?;
+    }
+    for bucket in &stats.buckets {
+        writer.serialize(StatsRow {
+            count: Some(bucket.count),
+            errors: Some(bucket.errors),
+            error_rate: Some(bucket.error_rate()),
+            ..StatsRow::new("bucket", bucket.start.format("%Y-%m-%dT%H:%M:%S"))
+        })?;
+    }
+    if let Some(trend) = &stats.error_trend {
+        writer.serialize(StatsRow::new("trend", trend.direction))?;
+        writer.serialize(StatsRow { error_rate: Some(trend.earlier_rate), ..StatsRow::new("trend", "earlier") })?;
+        writer.serialize(StatsRow { error_rate: Some(trend.later_rate), ..StatsRow::new("trend", "later") })?;
+    }
+    for (rank, template) in stats.top_templates.iter().enumerate() {
+        writer.serialize(StatsRow {
+            count: Some(template.count),
+            errors: Some(template.errors),
+            error_rate: Some(template.errors as f64 / template.count as f64),
+            first: Some(template.first),
+            last: Some(template.last),
+            template: Some(&template.template),
+            ..StatsRow::new("template", rank + 1)
+        })?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::LogLevel;
+    use std::path::PathBuf;
+
+    fn entries() -> Vec<LogEntry> {
+        vec![LogEntry {
+            timestamp: chrono::NaiveDateTime::parse_from_str("2024-01-15 10:30:00", "%Y-%m-%d %H:%M:%S").unwrap(),
+            level: LogLevel::Warn,
+            message: "Slow query, 2.5s".to_string(),
+            source_file: PathBuf::from("db.log"),
+            line_no: 12,
+        }]
+    }
+
+    fn render(format: OutputFormat) -> String {
+        let mut out = Vec::new();
+        write_entries(&entries(), format, &mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn test_formats() {
+        assert_eq!(
+            render(OutputFormat::Table),
+            "TIMESTAMP            LEVEL  SOURCE     MESSAGE\n\
+             2024-01-15 10:30:00  WARN   db.log:12  Slow query, 2.5s\n"
+        );
+        assert_eq!(
+            render(OutputFormat::Csv),
+            "timestamp,level,message,source_file,line_no\n\
+             2024-01-15T10:30:00,warn,\"Slow query, 2.5s\",db.log,12\n"
+        );
+        let json: Vec<LogEntry> = serde_json::from_str(&render(OutputFormat::Json)).unwrap();
+        assert_eq!(json, entries());
+    }
+
+    #[test]
+    fn test_entry_lines() {
+        let entry = &entries()[0];
+        let line = |format, header| {
+            let mut out = Vec::new();
+            write_entry_line(entry, format, header, &mut out).unwrap();
+            String::from_utf8(out).unwrap()
+        };
+        assert_eq!(line(OutputFormat::Table, true), "2024-01-15 10:30:00  WARN   db.log:12  Slow query, 2.5s\n");
+        assert_eq!(serde_json::from_str::<LogEntry>(&line(OutputFormat::Json, false)).unwrap(), *entry);
+        assert_eq!(line(OutputFormat::Csv, true).lines().count(), 2);
+        assert_eq!(line(OutputFormat::Csv, false), "2024-01-15T10:30:00,warn,\"Slow query, 2.5s\",db.log,12\n");
+    }
+
+    #[test]
+    fn test_stats_formats() {
+        let mut entries = entries();
+        entries.push(LogEntry { level: LogLevel::Error, message: "Slow query, 3.1s".to_string(), ..entries[0].clone() });
+        entries[1].timestamp += chrono::TimeDelta::hours(2);
+        let stats = Stats::compute(&entries, crate::stats::Bucket::Hour, 5);
+
+        let mut table = Vec::new();
+        write_stats(&stats, OutputFormat::Table, &mut table).unwrap();
+        let table = String::from_utf8(table).unwrap();
+        assert!(table.starts_with("Entries: 2, errors: 1 (50.0%), from 2024-01-15 10:30:00 to 2024-01-15 12:30:00\n"));
+        assert!(table.contains("\nERROR      1    50.0%\n"), "{}", table);
+        assert!(table.contains("\nTIME              COUNT    ERR%  HISTOGRAM\n"), "{}", table);
+        assert!(table.contains(&format!("\n2024-01-15 10:00      1    0.0%  {}\n", "#".repeat(HISTOGRAM_WIDTH))));
+        assert!(table.contains("\n2024-01-15 11:00      0    0.0%\n"));
+        assert!(table.contains("\nError rate rising: 0.0% in the first half, 100.0% in the second\n"));
+        assert!(table.ends_with("    2       1  2024-01-15 10:30:00  2024-01-15 12:30:00  Slow query, <n>s\n"));
+
+        let mut csv = Vec::new();
+        write_stats(&stats, OutputFormat::Csv, &mut csv).unwrap();
+        let csv = String::from_utf8(csv).unwrap();
+        let lines: Vec<&str> = csv.lines().collect();
+        assert_eq!(lines[0], "section,key,count,errors,error_rate,first,last,template");
+        assert_eq!(lines[1], "total,all,2,1,0.5,2024-01-15T10:30:00,2024-01-15T12:30:00,");
+        assert_eq!(lines[5], "level,error,1,,,,,");
+        assert_eq!(lines[6], "bucket,2024-01-15T10:00:00,1,0,0.0,,,");
+        assert_eq!(lines[9], "trend,rising,,,,,,");
+        assert_eq!(lines[12], "template,1,2,1,0.5,2024-01-15T10:30:00,2024-01-15T12:30:00,\"Slow query, <n>s\"");
+    }
+}
@@ -0,0 +1,74 @@
+use std::path::Path;
+use std::sync::Arc;
+
+use crate::format::LogFormat;
+use crate::types::{LogAnalyzerError, LogEntry, Result};
+
+/// Longest part of a malformed line quoted in its error
+const MAX_QUOTED_LEN: usize = 80;
+
+/// Parses the lines of a file written in one format
+pub struct LogParser {
+    format: Arc<dyn LogFormat>,
+}
+
+impl LogParser {
+    pub fn new(format: Arc<dyn LogFormat>) -> Self {
+        LogParser { format }
+    }
+
+    /// Parse one line read from `source_file`
+    ///
+    /// Lines the format rejects fail with a `ParseError` naming the file,
+    /// the line and the reason.
+    pub fn parse_line(&self, line: &str, source_file: &Path, line_no: usize) -> Result<LogEntry> {
+        let record = self.format.parse(line).map_err(|e| {
+            let reason = match e {
+                LogAnalyzerError::ParseError(reason) => reason,
+                other => other.to_string(),
+            };
+            LogAnalyzerError::ParseError(format!("{}:{}: {}: {:?}", source_file.display(), line_no, reason, quote(line)))
+        })?;
+
+        Ok(LogEntry {
+            timestamp: record.timestamp,
+            level: record.level,
+            message: record.message,
+            source_file: source_file.to_path_buf(),
+            line_no,
+        })
+    }
+}
+
+/// The start of `line`, shortened for an error message
+pub(crate) fn quote(line: &str) -> String {
+    let mut quoted: String = line.chars().take(MAX_QUOTED_LEN).collect();
+    if quoted.len() < line.len() {
+        quoted.push_str("...");
+    }
+    quoted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::format::PlainFormat;
+    use crate::types::LogLevel;
+
+    #[test]
+    fn test_parse_line() {
+        let parser = LogParser::new(Arc::new(PlainFormat::new()));
+        let source = Path::new("app.log");
+
+        let entry = parser.parse_line("2024-01-15 10:30:00  [warning] Disk at 91%", source, 7).unwrap();
+        assert_eq!(entry.timestamp.to_string(), "2024-01-15 10:30:00");
+        assert_eq!(entry.level, LogLevel::Warn);
+        assert_eq!(entry.message, "Disk at 91%");
+        assert_eq!((entry.source_file.as_path(), entry.line_no), (source, 7));
+
+        for line in ["not a log line", "2024-13-45 10:30:00 [INFO] bad date", "2024-01-15 10:30:00 [LOUD] level"] {
+            let error = parser.parse_line(line, source, 3).unwrap_err();
+            assert!(matches!(&error, LogAnalyzerError::ParseError(msg) if msg.starts_with("app.log:3: ")), "{}", error);
+        }
+    }
+}
@@ -0,0 +1,382 @@
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::sync::LazyLock;
+
+use chrono::{NaiveDateTime, TimeDelta, Timelike};
+use clap::ValueEnum;
+use regex::{Captures, Regex};
+use serde::Serialize;
+
+use crate::types::{LogEntry, LogLevel};
+
+/// Most buckets `auto` picks before moving to a coarser bucket
+const MAX_AUTO_BUCKETS: i64 = 60;
+
+/// Most buckets in a histogram; a finer bucket that needs more is made coarser
+pub const MAX_BUCKETS: i64 = 10_000;
+
+/// Change in error rate, in percentage points, below which the trend is steady
+const STEADY_THRESHOLD: f64 = 0.01;
+
+/// Numbers and IDs replaced by placeholders in message templates
+static VARIABLE: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(concat!(
+        r"(?P<uuid>\b[0-9a-fA-F]{8}-[0-9a-fA-F]{4}-[0-9a-fA-F]{4}-[0-9a-fA-F]{4}-[0-9a-fA-F]{12}\b)",
+        r"|(?P<ip>\b\d{1,3}(?:\.\d{1,3}){3}(?::\d+)?\b)",
+        r"|(?P<hex>\b(?:0x[0-9a-fA-F]+|[0-9a-fA-F]{8,})\b)",
+        r"|(?P<n>\d+(?:\.\d+)?)",
+    ))
+    .expect("valid regex")
+});
+
+/// Width of time buckets
+#[derive(Debug, Clone, Copy, ValueEnum, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Bucket {
+    /// The finest that gives at most 60 buckets
+    Auto,
+    Minute,
+    Hour,
+    Day,
+}
+
+impl Bucket {
+    fn step(self) -> TimeDelta {
+        match self {
+            Bucket::Minute => TimeDelta::minutes(1),
+            Bucket::Hour => TimeDelta::hours(1),
+            Bucket::Day | Bucket::Auto => TimeDelta::days(1),
+        }
+    }
+
+    /// Start of the bucket holding `timestamp`
+    fn start(self, timestamp: NaiveDateTime) -> NaiveDateTime {
+        let minute = timestamp.with_second(0).and_then(|t| t.with_nanosecond(0)).expect("valid time");
+        match self {
+            Bucket::Minute => minute,
+            Bucket::Hour => minute.with_minute(0).expect("valid time"),
+            Bucket::Day | Bucket::Auto => timestamp.date().and_hms_opt(0, 0, 0).expect("valid time"),
+        }
+    }
+
+    /// Number of buckets from the one holding `first` to the one holding `last`
+    fn count(self, first: NaiveDateTime, last: NaiveDateTime) -> i64 {
+        (self.start(last) - self.start(first)).num_seconds() / self.step().num_seconds() + 1
+    }
+
+    /// The bucket to use for entries spanning `first` to `last`
+    ///
+    /// `Auto` becomes the finest bucket giving at most 60 buckets; others are
+    /// made coarser until they give at most `MAX_BUCKETS`.
+    fn resolve(self, first: NaiveDateTime, last: NaiveDateTime) -> Bucket {
+        let (finest, limit) = match self {
+            Bucket::Auto => (Bucket::Minute, MAX_AUTO_BUCKETS),
+            bucket => (bucket, MAX_BUCKETS),
+        };
+        [Bucket::Minute, Bucket::Hour]
+            .into_iter()
+            .skip_while(|bucket| *bucket != finest)
+            .find(|bucket| bucket.count(first, last) <= limit)
+            .unwrap_or(Bucket::Day)
+    }
+
+    /// How the start of a bucket is shown in a table
+    pub fn label_format(self) -> &'static str {
+        match self {
+            Bucket::Minute | Bucket::Hour => "%Y-%m-%d %H:%M",
+            Bucket::Day | Bucket::Auto => "%Y-%m-%d",
+        }
+    }
+}
+
+/// Entries in one time bucket
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct BucketCount {
+    pub start: NaiveDateTime,
+    pub count: usize,
+    pub errors: usize,
+}
+
+/// Entries whose messages share a template
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct TemplateCount {
+    pub template: String,
+    pub count: usize,
+    pub errors: usize,
+    pub first: NaiveDateTime,
+    pub last: NaiveDateTime,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Direction {
+    Rising,
+    Falling,
+    Steady,
+}
+
+impl std::fmt::Display for Direction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Direction::Rising => write!(f, "rising"),
+            Direction::Falling => write!(f, "falling"),
+            Direction::Steady => write!(f, "steady"),
+        }
+    }
+}
+
+/// Error rate in the first half of the buckets against the second half
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ErrorTrend {
+    pub direction: Direction,
+    pub earlier_rate: f64,
+    pub later_rate: f64,
+}
+
+/// Aggregates over a set of entries
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Stats {
+    pub total: usize,
+    pub errors: usize,
+    pub first: Option<NaiveDateTime>,
+    pub last: Option<NaiveDateTime>,
+    /// Entries per level, including levels with none
+    pub levels: BTreeMap<LogLevel, usize>,
+    pub bucket: Bucket,
+    /// Consecutive buckets from the first entry to the last, empty ones
+    /// included unless there would be more than `MAX_BUCKETS` days
+    pub buckets: Vec<BucketCount>,
+    /// Whether empty buckets were left out of `buckets`
+    pub sparse: bool,
+    /// `None` with fewer than two buckets
+    pub error_trend: Option<ErrorTrend>,
+    /// The most frequent templates, most frequent first
+    pub top_templates: Vec<TemplateCount>,
+    /// Number of distinct templates
+    pub template_count: usize,
+}
+
+/// A message with numbers and IDs replaced by `<n>`, `<hex>`, `<ip>` and `<uuid>`
+///
+/// Hex strings must contain a digit, so that long words made of the
+/// letters a to f are kept.
+pub fn template(message: &str) -> String {
+    VARIABLE
+        .replace_all(message, |captures: &Captures| {
+            let (name, text) = ["uuid", "ip", "hex", "n"]
+                .into_iter()
+                .find_map(|name| captures.name(name).map(|m| (name, m.as_str())))
+                .expect("one group matches");
+            if name == "hex" && !text.bytes().any(|b| b.is_ascii_digit()) {
+                text.to_string()
+            } else {
+                format!("<{}>", name)
+            }
+        })
+        .into_owned()
+}
+
+fn rate(errors: usize, count: usize) -> f64 {
+    if count == 0 { 0.0 } else { errors as f64 / count as f64 }
+}
+
+impl Stats {
+    /// Aggregate `entries`, keeping the `top` most frequent templates
+    pub fn compute(entries: &[LogEntry], bucket: Bucket, top: usize) -> Self {
+        let first = entries.iter().map(|entry| entry.timestamp).min();
+        let last = entries.iter().map(|entry| entry.timestamp).max();
+        let bucket = bucket.resolve(first.unwrap_or_default(), last.unwrap_or_default());
+
+        let mut levels: BTreeMap<LogLevel, usize> = LogLevel::value_variants().iter().map(|level| (*level, 0)).collect();
+        let mut counts: BTreeMap<NaiveDateTime, (usize, usize)> = BTreeMap::new();
+        let mut templates: HashMap<String, TemplateCount> = HashMap::new();
+        let mut errors = 0;
+        for entry in entries {
+            let is_error = usize::from(entry.level == LogLevel::Error);
+            errors += is_error;
+            *levels.entry(entry.level).or_default() += 1;
+
+            let count = counts.entry(bucket.start(entry.timestamp)).or_default();
+            count.0 += 1;
+            count.1 += is_error;
+
+            let template = template(&entry.message);
+            let stats = templates.entry(template.clone()).or_insert_with(|| TemplateCount {
+                template,
+                count: 0,
+                errors: 0,
+                first: entry.timestamp,
+                last: entry.timestamp,
+            });
+            stats.count += 1;
+            stats.errors += is_error;
+            stats.first = stats.first.min(entry.timestamp);
+            stats.last = stats.last.max(entry.timestamp);
+        }
+
+        // A stray timestamp far from the rest could otherwise fill millions of days
+        let sparse = bucket.count(first.unwrap_or_default(), last.unwrap_or_default()) > MAX_BUCKETS;
+        let mut buckets = Vec::new();
+        if sparse {
+            buckets.extend(counts.iter().map(|(&start, &(count, errors))| BucketCount { start, count, errors }));
+        } else if let (Some(&start), Some(&end)) = (counts.keys().next(), counts.keys().next_back()) {
+            let mut start = start;
+            while start <= end {
+                let (count, errors) = counts.get(&start).copied().unwrap_or_default();
+                buckets.push(BucketCount { start, count, errors });
+                start += bucket.step();
+            }
+        }
+
+        let template_count = templates.len();
+        let mut top_templates: Vec<TemplateCount> = templates.into_values().collect();
+        top_templates.sort_by(|a, b| b.count.cmp(&a.count).then(a.first.cmp(&b.first)).then(a.template.cmp(&b.template)));
+        top_templates.truncate(top);
+
+        Stats {
+            total: entries.len(),
+            errors,
+            first,
+            last,
+            levels,
+            bucket,
+            error_trend: Self::error_trend(&buckets),
+            buckets,
+            sparse,
+            top_templates,
+            template_count,
+        }
+    }
+
+    fn error_trend(buckets: &[BucketCount]) -> Option<ErrorTrend> {
+        if buckets.len() < 2 {
+            return None;
+        }
+        let (earlier, later) = buckets.split_at(buckets.len() / 2);
+        let half_rate = |half: &[BucketCount]| {
+            rate(half.iter().map(|b| b.errors).sum(), half.iter().map(|b| b.count).sum())
+        };
+        let (earlier_rate, later_rate) = (half_rate(earlier), half_rate(later));
+        let direction = if (later_rate - earlier_rate).abs() < STEADY_THRESHOLD {
+            Direction::Steady
+        } else if later_rate > earlier_rate {
+            Direction::Rising
+        } else {
+            Direction::Falling
+        };
+        Some(ErrorTrend { direction, earlier_rate, later_rate })
+    }
+
+    /// Share of entries that are errors
+    pub fn error_rate(&self) -> f64 {
+        rate(self.errors, self.total)
+    }
+}
+
+impl BucketCount {
+    pub fn error_rate(&self) -> f64 {
+        rate(self.errors, self.count)
+    }
+}
+
+/// The entries of a live stream within a window of the newest, for `--follow --stats`
+pub struct RollingStats {
+    window: TimeDelta,
+    /// Ordered by timestamp
+    entries: VecDeque<LogEntry>,
+}
+
+impl RollingStats {
+    pub fn new(window: TimeDelta) -> Self {
+        RollingStats { window, entries: VecDeque::new() }
+    }
+
+    pub fn push(&mut self, entry: LogEntry) {
+        let position = self.entries.partition_point(|kept| kept.timestamp <= entry.timestamp);
+        self.entries.insert(position, entry);
+        let newest = self.entries.back().expect("just inserted").timestamp;
+        while self.entries.front().is_some_and(|oldest| oldest.timestamp <= newest - self.window) {
+            self.entries.pop_front();
+        }
+    }
+
+    pub fn stats(&mut self, bucket: Bucket, top: usize) -> Stats {
+        Stats::compute(self.entries.make_contiguous(), bucket, top)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::sample_entry;
+
+    #[test]
+    fn test_template() {
+        assert_eq!(template("User 42 logged in from 10.0.0.7:5123"), "User <n> logged in from <ip>");
+        assert_eq!(
+            template("request 3f2a1c9e-0b7d-4e55-9a61-2f0c8d4b7e10 took 2.5s, trace 0xdeadbeef 5f3a9c0d1e"),
+            "request <uuid> took <n>s, trace <hex> <hex>"
+        );
+        assert_eq!(template("job42 defaced"), "job<n> defaced");
+    }
+
+    #[test]
+    fn test_compute() {
+        let entries = [
+            sample_entry("2024-01-15 10:05:00", LogLevel::Info, "User 1 logged in"),
+            sample_entry("2024-01-15 10:50:00", LogLevel::Info, "User 2 logged in"),
+            sample_entry("2024-01-15 12:10:00", LogLevel::Error, "Timeout after 30s"),
+            sample_entry("2024-01-15 12:20:00", LogLevel::Info, "User 3 logged in"),
+            sample_entry("2024-01-15 12:40:00", LogLevel::Error, "Timeout after 31s"),
+        ];
+        let stats = Stats::compute(&entries, Bucket::Auto, 1);
+
+        assert_eq!((stats.total, stats.errors, stats.bucket), (5, 2, Bucket::Hour));
+        assert_eq!(stats.levels.values().copied().collect::<Vec<_>>(), [0, 3, 0, 2]);
+        let buckets: Vec<_> = stats.buckets.iter().map(|b| (b.start.hour(), b.count, b.errors)).collect();
+        assert_eq!(buckets, [(10, 2, 0), (11, 0, 0), (12, 3, 2)]);
+
+        let trend = stats.error_trend.unwrap();
+        assert_eq!((trend.direction, trend.earlier_rate), (Direction::Rising, 0.0));
+        assert!((trend.later_rate - 2.0 / 3.0).abs() < 1e-9);
+
+        assert_eq!(stats.template_count, 2);
+        let top = &stats.top_templates[0];
+        assert_eq!((top.template.as_str(), top.count, top.errors), ("User <n> logged in", 3, 0));
+        assert_eq!((top.first, top.last), (entries[0].timestamp, entries[3].timestamp));
+
+        assert_eq!(Stats::compute(&entries, Bucket::Day, 10).buckets.len(), 1);
+        assert_eq!(Stats::compute(&entries[..2], Bucket::Auto, 10).bucket, Bucket::Minute);
+        let mut rolling = RollingStats::new(TimeDelta::hours(1));
+        for entry in &entries {
+            rolling.push(entry.clone());
+        }
+        let stats = rolling.stats(Bucket::Auto, 10);
+        assert_eq!((stats.total, stats.first), (3, Some(entries[2].timestamp)));
+
+        let empty = Stats::compute(&[], Bucket::Auto, 10);
+        assert!(!empty.sparse);
+        assert!(empty.buckets.is_empty() && empty.error_trend.is_none() && empty.first.is_none());
+    }
+
+    #[test]
+    fn test_bucket_cap() {
+        let ten_days = [
+            sample_entry("2024-01-01 00:00:00", LogLevel::Info, "start"),
+            sample_entry("2024-01-10 23:59:00", LogLevel::Info, "end"),
+        ];
+        let stats = Stats::compute(&ten_days, Bucket::Minute, 10);
+        assert_eq!((stats.bucket, stats.buckets.len(), stats.sparse), (Bucket::Hour, 240, false));
+        assert_eq!(Stats::compute(&ten_days[..1], Bucket::Minute, 10).bucket, Bucket::Minute);
+
+        // An epoch-zero timestamp next to current ones
+        let stray = [
+            sample_entry("1970-01-01 00:00:00", LogLevel::Info, "ts=0"),
+            sample_entry("2024-01-15 10:00:00", LogLevel::Error, "real"),
+            sample_entry("2024-01-15 10:01:00", LogLevel::Info, "real"),
+        ];
+        let stats = Stats::compute(&stray, Bucket::Minute, 10);
+        assert_eq!((stats.bucket, stats.sparse), (Bucket::Day, true));
+        let counts: Vec<_> = stats.buckets.iter().map(|b| (b.count, b.errors)).collect();
+        assert_eq!(counts, [(1, 0), (2, 1)]);
+    }
+}
@@ -0,0 +1,129 @@
+use std::path::PathBuf;
+
+use chrono::NaiveDateTime;
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+
+///////////////////////////
+/// Log level Enum
+///////////////////////////
+#[derive(Debug, Clone, Copy, ValueEnum, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogLevel {
+    Debug,
+    Info,
+    Warn,
+    Error,
+}
+
+impl std::fmt::Display for LogLevel {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LogLevel::Debug => write!(f, "DEBUG"),
+            LogLevel::Info => write!(f, "INFO"),
+            LogLevel::Warn => write!(f, "WARN"),
+            LogLevel::Error => write!(f, "ERROR"),
+        }
+    }
+}
+
+impl std::str::FromStr for LogLevel {
+    type Err = LogAnalyzerError;
+
+    /// Parse a level as written in logs, ignoring case
+    ///
+    /// Levels of other schemes map to the nearest one: `trace` is debug,
+    /// `notice` is info, and `fatal`, `critical` and the like are errors.
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "trace" | "debug" => Ok(LogLevel::Debug),
+            "info" | "notice" => Ok(LogLevel::Info),
+            "warn" | "warning" => Ok(LogLevel::Warn),
+            "error" | "err" | "fatal" | "critical" | "crit" | "alert" | "emerg" | "panic" => Ok(LogLevel::Error),
+            _ => Err(LogAnalyzerError::ParseError(format!("unknown log level '{}'", s))),
+        }
+    }
+}
+
+///////////////////////////
+/// Log entry
+///////////////////////////
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LogEntry {
+    pub timestamp: NaiveDateTime,
+    pub level: LogLevel,
+    pub message: String,
+    /// File the entry was read from
+    pub source_file: PathBuf,
+    /// 1-based line number in `source_file`
+    pub line_no: usize,
+}
+
+#[cfg(test)]
+/// An entry of `app.log` at a `%Y-%m-%d %H:%M:%S` timestamp, for tests
+pub(crate) fn sample_entry(timestamp: &str, level: LogLevel, message: &str) -> LogEntry {
+    LogEntry {
+        timestamp: NaiveDateTime::parse_from_str(timestamp, "%Y-%m-%d %H:%M:%S").unwrap(),
+        level,
+        message: message.to_string(),
+        source_file: PathBuf::from("app.log"),
+        line_no: 1,
+    }
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum, PartialEq)]
+pub enum OutputFormat {
+    Json,
+    Csv,
+    Table,
+}
+
+///////////////////////
+// Error
+///////////////////////
+#[derive(Debug)]
+pub enum LogAnalyzerError {
+    IoError(std::io::Error),
+    ParseError(String),
+    InvalidDate(String),
+    InvalidPattern(String),
+    OutputError(String),
+    UnknownFormat(String),
+    ConfigError(String),
+}
+
+impl std::fmt::Display for LogAnalyzerError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LogAnalyzerError::IoError(e) => write!(f, "IO error: {}", e),
+            LogAnalyzerError::ParseError(msg) => write!(f, "Parse error: {}", msg),
+            LogAnalyzerError::InvalidDate(msg) => write!(f, "Invalid date: {}", msg),
+            LogAnalyzerError::InvalidPattern(msg) => write!(f, "Invalid pattern: {}", msg),
+            LogAnalyzerError::OutputError(msg) => write!(f, "Output error: {}", msg),
+            LogAnalyzerError::UnknownFormat(msg) => write!(f, "Unknown log format: {}", msg),
+            LogAnalyzerError::ConfigError(msg) => write!(f, "Config error: {}", msg),
+        }
+    }
+}
+
+impl std::error::Error for LogAnalyzerError {}
+
+impl From<std::io::Error> for LogAnalyzerError {
+    fn from(error: std::io::Error) -> Self {
+        LogAnalyzerError::IoError(error)
+    }
+}
+
+impl From<serde_json::Error> for LogAnalyzerError {
+    fn from(error: serde_json::Error) -> Self {
+        LogAnalyzerError::OutputError(error.to_string())
+    }
+}
+
+impl From<csv::Error> for LogAnalyzerError {
+    fn from(error: csv::Error) -> Self {
+        LogAnalyzerError::OutputError(error.to_string())
+    }
+}
+
+pub type Result<T> = std::result::Result<T, LogAnalyzerError>;
@@ -1,7 +1,61 @@
 use chrono::NaiveDate;
+use std::time::Duration;
 
 pub fn parse_date(s: &str) -> std::result::Result<NaiveDate, String> {
     NaiveDate::parse_from_str(s, "%Y-%m-%d")
         .map_err(|e| format!("Invalid date format '{}': {}", s, e))
 }
 
+/// Parse a duration such as `500ms`, `30s`, `5m`, `1h` or `2d`
+pub fn parse_duration(s: &str) -> std::result::Result<Duration, String> {
+    let invalid = || format!("Invalid duration '{}': expected a number followed by ms, s, m, h or d", s);
+    let split = s.find(|c: char| !c.is_ascii_digit()).ok_or_else(invalid)?;
+    let (number, unit) = s.split_at(split);
+    let number: u64 = number.parse().map_err(|_| invalid())?;
+    let scale = match unit {
+        "ms" => return positive(Duration::from_millis(number), s),
+        "s" => 1,
+        "m" => 60,
+        "h" => 3_600,
+        "d" => 86_400,
+        _ => return Err(invalid()),
+    };
+    let secs = number.checked_mul(scale).ok_or_else(|| format!("Invalid duration '{}': too long", s))?;
+    positive(Duration::from_secs(secs), s)
+}
+
+/// `duration`, unless it is zero
+fn positive(duration: Duration, s: &str) -> std::result::Result<Duration, String> {
+    if duration.is_zero() {
+        return Err(format!("Invalid duration '{}': must be positive", s));
+    }
+    Ok(duration)
+}
+
+/// Format a duration in the largest unit that divides it, as `parse_duration` reads it
+pub fn format_duration(duration: Duration) -> String {
+    let millis = duration.as_millis();
+    for (unit, size) in [("d", 86_400_000), ("h", 3_600_000), ("m", 60_000), ("s", 1_000)] {
+        if millis >= size && millis.is_multiple_of(size) {
+            return format!("{}{}", millis / size, unit);
+        }
+    }
+    format!("{}ms", millis)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_durations() {
+        assert_eq!(parse_duration("500ms"), Ok(Duration::from_millis(500)));
+        assert_eq!(parse_duration("5m"), Ok(Duration::from_secs(300)));
+        for invalid in ["", "10", "m", "1.5s", "-1s", "0s", "3w", "99999999999999999d"] {
+            assert!(parse_duration(invalid).is_err(), "{}", invalid);
+        }
+        assert_eq!(format_duration(Duration::from_secs(3_600)), "1h");
+        assert_eq!(format_duration(Duration::from_secs(90)), "90s");
+        assert_eq!(format_duration(Duration::from_millis(1_500)), "1500ms");
+    }
+}
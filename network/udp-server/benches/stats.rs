@@ -0,0 +1,96 @@
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use udp_echo_server::{ClientStats, LatencyHistogram, StatsCollector};
+
+const DATAGRAMS_PER_THREAD: u64 = 10_000;
+
+/// The counters as they were kept before `StatsCollector`: one mutex taken
+/// for every received datagram, handler call and sent datagram
+#[derive(Default)]
+struct MutexStats {
+    messages_received: u64,
+    messages_sent: u64,
+    bytes_received: u64,
+    bytes_sent: u64,
+    handler_latency: LatencyHistogram,
+    clients: HashMap<SocketAddr, ClientStats>,
+}
+
+fn client(thread: usize) -> SocketAddr {
+    SocketAddr::from(([127, 0, 0, 1], 10_000 + thread as u16))
+}
+
+fn run_threads<F>(threads: usize, record: F)
+where
+    F: Fn(SocketAddr) + Send + Sync + 'static,
+{
+    let record = Arc::new(record);
+    let workers: Vec<_> = (0..threads)
+        .map(|i| {
+            let record = Arc::clone(&record);
+            thread::spawn(move || {
+                let addr = client(i);
+                for _ in 0..DATAGRAMS_PER_THREAD {
+                    record(addr);
+                }
+            })
+        })
+        .collect();
+    for worker in workers {
+        worker.join().unwrap();
+    }
+}
+
+fn bench_stats(c: &mut Criterion) {
+    let mut group = c.benchmark_group("record_datagram");
+
+    for threads in [1, 4, 8] {
+        group.throughput(Throughput::Elements(threads as u64 * DATAGRAMS_PER_THREAD));
+
+        group.bench_with_input(BenchmarkId::new("mutex", threads), &threads, |b, &threads| {
+            let stats = Arc::new(Mutex::new(MutexStats::default()));
+            b.iter(|| {
+                let stats = Arc::clone(&stats);
+                run_threads(threads, move |addr| {
+                    {
+                        let mut stats = stats.lock().unwrap();
+                        stats.messages_received += 1;
+                        stats.bytes_received += 64;
+                        let client = stats.clients.entry(addr).or_default();
+                        client.messages_received += 1;
+                        client.bytes_received += 64;
+                    }
+                    stats.lock().unwrap().handler_latency.record(Duration::from_micros(20));
+                    let mut stats = stats.lock().unwrap();
+                    stats.messages_sent += 1;
+                    stats.bytes_sent += 64;
+                    let client = stats.clients.entry(addr).or_default();
+                    client.messages_sent += 1;
+                    client.bytes_sent += 64;
+                });
+            });
+        });
+
+        group.bench_with_input(BenchmarkId::new("atomic", threads), &threads, |b, &threads| {
+            let stats = Arc::new(StatsCollector::new(1024));
+            b.iter(|| {
+                let stats = Arc::clone(&stats);
+                run_threads(threads, move |addr| {
+                    stats.record_received(addr, 64);
+                    stats.record_handler(Duration::from_micros(20));
+                    stats.record_sent(addr, 64);
+                });
+            });
+        });
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, bench_stats);
+criterion_main!(benches);
@@ -1,4 +1,4 @@
-use udp_echo_server::{UdpEchoServer, ServerConfig};
+use udp_echo_server::UdpEchoServer;
 
 fn main() -> Result<(), Box<dyn std::error::Error>> {
     // Simple server with default configuration
@@ -1,10 +1,14 @@
 use std::net::UdpSocket;
 use std::io::{self, Write};
+use std::time::Duration;
 
 fn main() -> Result<(), Box<dyn std::error::Error>> {
     let socket = UdpSocket::bind("127.0.0.1:0")?;
     let server_addr = "127.0.0.1:8080";
     
+    // Don't hang forever if the request or the response is lost
+    socket.set_read_timeout(Some(Duration::from_secs(2)))?;
+    
     println!("UDP Client - Type messages to send to {}", server_addr);
     println!("Type 'quit' to exit");
     
@@ -30,6 +34,9 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
                 let response = std::str::from_utf8(&buffer[..size])?;
                 println!("Response from {}: {}", from, response);
             }
+            Err(e) if matches!(e.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut) => {
+                eprintln!("No response from {} (timed out)", server_addr);
+            }
             Err(e) => {
                 eprintln!("Error receiving response: {}", e);
             }
@@ -37,4 +44,4 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     }
     
     Ok(())
-}
\ No newline at end of file
+}
@@ -0,0 +1,50 @@
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use udp_echo_server::{MessageHandler, RejectReason, ServerConfig, UdpEchoServer};
+
+/// Handles a few text commands and echoes everything else
+struct CommandHandler {
+    requests: AtomicU64,
+}
+
+impl MessageHandler for CommandHandler {
+    fn handle_message(&self, message: &[u8], from: SocketAddr) -> Option<Vec<u8>> {
+        let count = self.requests.fetch_add(1, Ordering::Relaxed) + 1;
+        let text = String::from_utf8_lossy(message);
+        
+        let response = match text.trim() {
+            "ping" => "pong".to_string(),
+            "whoami" => from.to_string(),
+            "count" => count.to_string(),
+            // Fire-and-forget messages get no response
+            "quiet" => return None,
+            other => match other.strip_prefix("upper ") {
+                Some(rest) => rest.to_uppercase(),
+                None => other.to_string(),
+            },
+        };
+        
+        Some(response.into_bytes())
+    }
+    
+    fn on_error(&self, error: &str, from: Option<SocketAddr>) {
+        eprintln!("[custom handler] {} ({:?})", error, from);
+    }
+    
+    fn on_rejected(&self, from: SocketAddr, reason: RejectReason) {
+        eprintln!("[custom handler] {} {}", from, reason);
+    }
+}
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let config = ServerConfig::new().port(8080).workers(2);
+    let server = UdpEchoServer::new(config)?;
+    println!("Starting command server on {}", server.local_addr()?);
+    println!("Commands: ping, whoami, count, quiet, upper <text>");
+    
+    server.run_with_handler(CommandHandler {
+        requests: AtomicU64::new(0),
+    })?;
+    Ok(())
+}
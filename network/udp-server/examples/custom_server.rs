@@ -0,0 +1,21 @@
+use udp_echo_server::{ServerConfig, UdpEchoServer};
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    // Server tuned through the configuration builder
+    let config = ServerConfig::new()
+        .host("0.0.0.0")
+        .port(8080)
+        .buffer_size(4096)
+        .max_message_size(1024)
+        .rate_limit(100.0, 20)
+        .deny_cidr("10.0.0.0/8".parse()?)
+        .workers(4)
+        .verbose(true)
+        .stats(true)
+        .stats_interval(10);
+    
+    let server = UdpEchoServer::new(config)?;
+    println!("Starting custom echo server on {}", server.local_addr()?);
+    server.run()?;
+    Ok(())
+}
@@ -0,0 +1,65 @@
+use std::net::UdpSocket;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use udp_echo_server::{ServerConfig, UdpEchoServer};
+
+const CLIENTS: usize = 8;
+const MESSAGES_PER_CLIENT: usize = 1000;
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    // Usage: cargo run --example multi_server [workers]
+    let workers = match std::env::args().nth(1) {
+        Some(arg) => arg.parse()?,
+        None => thread::available_parallelism().map(|n| n.get()).unwrap_or(1).max(4),
+    };
+    
+    // Every worker gets its own socket on the same port
+    let config = ServerConfig::new().port(0).workers(workers);
+    let server = UdpEchoServer::new(config)?;
+    let server_addr = server.local_addr()?;
+    println!("Echo server on {} with {} sockets", server_addr, workers);
+    
+    let stats = server.stats_handle();
+    let _server = server.spawn();
+    
+    // Each client uses its own source port, so the kernel spreads them over the sockets
+    let started = Instant::now();
+    let clients: Vec<_> = (0..CLIENTS)
+        .map(|id| {
+            thread::spawn(move || -> std::io::Result<usize> {
+                let socket = UdpSocket::bind("127.0.0.1:0")?;
+                socket.connect(server_addr)?;
+                socket.set_read_timeout(Some(Duration::from_millis(500)))?;
+                
+                let mut buffer = [0; 1024];
+                let mut echoed = 0;
+                for i in 0..MESSAGES_PER_CLIENT {
+                    let message = format!("client {} message {}", id, i);
+                    socket.send(message.as_bytes())?;
+                    if let Ok(size) = socket.recv(&mut buffer) {
+                        if &buffer[..size] == message.as_bytes() {
+                            echoed += 1;
+                        }
+                    }
+                }
+                Ok(echoed)
+            })
+        })
+        .collect();
+    
+    let mut echoed = 0;
+    for client in clients {
+        echoed += client.join().expect("client thread panicked")?;
+    }
+    let elapsed = started.elapsed();
+    
+    let stats = stats.snapshot();
+    println!("{} of {} messages echoed in {:.2?}", echoed, CLIENTS * MESSAGES_PER_CLIENT, elapsed);
+    println!("Server received {} messages from {} clients ({:.0} msg/s)",
+        stats.messages_received,
+        stats.clients.len(),
+        echoed as f64 / elapsed.as_secs_f64());
+    
+    Ok(())
+}
@@ -0,0 +1,27 @@
+use std::env;
+use std::process;
+
+use udp_echo_server::{read_capture, replay, EchoHandler};
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    // Usage: cargo run --example replay -- <capture file>
+    let path = match env::args().nth(1) {
+        Some(path) => path,
+        None => {
+            eprintln!("Usage: replay <capture file>");
+            process::exit(2);
+        }
+    };
+    
+    let capture = read_capture(&path)?;
+    println!("Loaded {} datagrams from {}", capture.len(), path);
+    
+    // Swap in the handler under investigation to compare it with the recording
+    let report = replay(&capture, &EchoHandler);
+    println!("{}", report);
+    
+    if !report.is_clean() {
+        process::exit(1);
+    }
+    Ok(())
+}
@@ -0,0 +1,675 @@
+// async_server.rs
+use std::future::Future;
+use std::io;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use socket2::SockRef;
+use tokio::net::UdpSocket;
+use tokio::sync::mpsc::{self, error::TrySendError};
+use tokio::sync::watch;
+use tokio::task::{JoinHandle, JoinSet};
+
+use crate::filter::{PacketFilter, RateLimiter};
+use crate::metrics::{self, StatsReporter};
+use crate::socket;
+use crate::{
+    EchoHandler, MessageHandler, OverloadPolicy, RateLimit, RateLimitKey, RejectReason, ServerConfig, ServerStats,
+    StatsCollector,
+};
+
+/// Busy replies sent to one source IP, so a flood cannot be reflected at a spoofed address
+const BUSY_REPLY_LIMIT: RateLimit = RateLimit { per_second: 1.0, burst: 1 };
+
+/// Error type returned by the async server
+pub type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+/// Async message handler trait for custom message processing
+///
+/// Handler calls run on a bounded pool of worker tasks, so a slow call only
+/// occupies one worker instead of stalling the receive loop.
+pub trait AsyncMessageHandler: Send + Sync {
+    /// Process an incoming message and return the response
+    /// If None is returned, no response is sent
+    fn handle_message(&self, message: Vec<u8>, from: SocketAddr) -> impl Future<Output = Option<Vec<u8>>> + Send;
+
+    /// Called when an error occurs
+    fn on_error(&self, error: &str, from: Option<SocketAddr>) {
+        eprintln!("Error from {:?}: {}", from, error);
+    }
+
+    /// Called when a datagram is rejected by the access lists or the rate limit
+    /// Rejected datagrams never get a response
+    fn on_rejected(&self, _from: SocketAddr, _reason: RejectReason) {}
+}
+
+/// Runs a synchronous [`MessageHandler`] on tokio's blocking thread pool
+struct BlockingHandler<H>(Arc<H>);
+
+impl<H: MessageHandler + 'static> AsyncMessageHandler for BlockingHandler<H> {
+    async fn handle_message(&self, message: Vec<u8>, from: SocketAddr) -> Option<Vec<u8>> {
+        let handler = Arc::clone(&self.0);
+        match tokio::task::spawn_blocking(move || handler.handle_message(&message, from)).await {
+            Ok(response) => response,
+            Err(e) => {
+                self.0.on_error(&format!("Handler failed: {}", e), Some(from));
+                None
+            }
+        }
+    }
+
+    fn on_error(&self, error: &str, from: Option<SocketAddr>) {
+        self.0.on_error(error, from);
+    }
+
+    fn on_rejected(&self, from: SocketAddr, reason: RejectReason) {
+        self.0.on_rejected(from, reason);
+    }
+}
+
+/// State shared between the receive loop and the handler workers
+struct Dispatcher<H> {
+    socket: Arc<UdpSocket>,
+    stats: Arc<StatsCollector>,
+    handler: H,
+    verbose: bool,
+}
+
+impl<H: AsyncMessageHandler> Dispatcher<H> {
+    async fn process(&self, message: Vec<u8>, src_addr: SocketAddr) {
+        let started = Instant::now();
+        let response = self.handler.handle_message(message, src_addr).await;
+        self.stats.record_handler(started.elapsed());
+
+        match response {
+            Some(response) => self.send(&response, src_addr).await,
+            None => {
+                if self.verbose {
+                    println!("No response generated for message from {}", src_addr);
+                }
+            }
+        }
+    }
+
+    async fn send(&self, response: &[u8], dst_addr: SocketAddr) {
+        let result = self.socket.send_to(response, dst_addr).await;
+        self.record_send(result, dst_addr);
+    }
+
+    /// Send without waiting for the socket, dropping the response if it is not writable
+    fn try_send(&self, response: &[u8], dst_addr: SocketAddr) {
+        let result = self.socket.try_send_to(response, dst_addr);
+        self.record_send(result, dst_addr);
+    }
+
+    fn record_send(&self, result: io::Result<usize>, dst_addr: SocketAddr) {
+        match result {
+            Ok(sent_size) => {
+                self.stats.record_sent(dst_addr, sent_size);
+
+                if self.verbose {
+                    println!("Sent {} bytes to {}", sent_size, dst_addr);
+                }
+            }
+            Err(e) => {
+                self.handler.on_error(&format!("Failed to send response: {}", e), Some(dst_addr));
+                self.stats.record_error();
+            }
+        }
+    }
+}
+
+type Job = (Vec<u8>, SocketAddr);
+
+async fn worker<H: AsyncMessageHandler>(
+    jobs: Arc<tokio::sync::Mutex<mpsc::Receiver<Job>>>,
+    dispatcher: Arc<Dispatcher<H>>,
+) {
+    loop {
+        // Release the receiver before processing so other workers can pick up jobs
+        let job = jobs.lock().await.recv().await;
+        match job {
+            Some((message, src_addr)) => dispatcher.process(message, src_addr).await,
+            None => break,
+        }
+    }
+}
+
+/// Async UDP Echo Server built on `tokio::net::UdpSocket`
+///
+/// Unlike [`UdpEchoServer`](crate::UdpEchoServer), this server can be stopped:
+/// the receive loop exits once the shutdown signal resolves, queued and
+/// in-flight handler calls are drained, the statistics reporter is stopped and
+/// the final statistics are returned.
+///
+/// Datagrams are dispatched to at most `max_concurrency` workers through a
+/// queue of `queue_size` entries. When the queue is full the configured
+/// [`OverloadPolicy`] applies and the datagram is counted as dropped; busy
+/// replies are sent at most once a second per source IP. A worker whose
+/// handler panics is replaced.
+pub struct AsyncUdpServer {
+    socket: Arc<UdpSocket>,
+    config: ServerConfig,
+    filter: PacketFilter,
+    stats: Arc<StatsCollector>,
+    reporters: Vec<Arc<dyn StatsReporter>>,
+}
+
+impl AsyncUdpServer {
+    /// Create a new async UDP echo server with the given configuration
+    pub async fn new(config: ServerConfig) -> Result<Self, BoxError> {
+        config.validate()?;
+
+        let bind_addr = format!("{}:{}", config.host, config.port);
+        let socket = UdpSocket::bind(&bind_addr).await?;
+        socket::configure(SockRef::from(&socket), &config)?;
+
+        Ok(Self {
+            socket: Arc::new(socket),
+            filter: PacketFilter::from_config(&config),
+            stats: Arc::new(StatsCollector::new(config.max_tracked_clients)),
+            config,
+            reporters: Vec::new(),
+        })
+    }
+
+    /// Get the local address the server is bound to
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.socket.local_addr()
+    }
+
+    /// Get a copy of the current statistics
+    pub fn stats(&self) -> ServerStats {
+        self.stats.snapshot()
+    }
+
+    /// Publish statistics to a reporter every `stats_interval` seconds and once more on shutdown
+    ///
+    /// Adding a reporter replaces the default stdout report.
+    pub fn with_reporter<R: StatsReporter + 'static>(mut self, reporter: R) -> Self {
+        self.reporters.push(Arc::new(reporter));
+        self
+    }
+
+    /// Run the server with the default echo handler until `signal` resolves
+    pub async fn run_until<F>(&self, signal: F) -> Result<ServerStats, BoxError>
+    where
+        F: Future<Output = ()>,
+    {
+        self.run_with_handler_until(EchoHandler, signal).await
+    }
+
+    /// Run the server with a custom message handler until `signal` resolves
+    ///
+    /// The handler runs on tokio's blocking thread pool.
+    /// Returns the final statistics once the server has stopped.
+    pub async fn run_with_handler_until<H, F>(&self, handler: H, signal: F) -> Result<ServerStats, BoxError>
+    where
+        H: MessageHandler + 'static,
+        F: Future<Output = ()>,
+    {
+        self.run_with_async_handler_until(BlockingHandler(Arc::new(handler)), signal).await
+    }
+
+    /// Run the server with an async message handler until `signal` resolves
+    ///
+    /// Returns the final statistics once the server has stopped.
+    pub async fn run_with_async_handler_until<H, F>(&self, handler: H, signal: F) -> Result<ServerStats, BoxError>
+    where
+        H: AsyncMessageHandler + 'static,
+        F: Future<Output = ()>,
+    {
+        if self.config.verbose {
+            println!("Async UDP Echo Server starting on {}", self.local_addr()?);
+            println!("Configuration: {:?}", self.config);
+        }
+
+        // Start statistics reporting task if enabled
+        let reporters = metrics::active_reporters(&self.config, &self.reporters);
+        let reporter = if reporters.is_empty() {
+            None
+        } else {
+            Some(tokio::spawn(report_stats(
+                Arc::clone(&self.stats),
+                Duration::from_secs(self.config.stats_interval),
+                reporters.clone(),
+            )))
+        };
+
+        let dispatcher = Arc::new(Dispatcher {
+            socket: Arc::clone(&self.socket),
+            stats: Arc::clone(&self.stats),
+            handler,
+            verbose: self.config.verbose,
+        });
+
+        // Start the handler worker pool
+        let (queue, jobs) = mpsc::channel::<Job>(self.config.queue_size);
+        let jobs = Arc::new(tokio::sync::Mutex::new(jobs));
+        let mut workers = JoinSet::new();
+        for _ in 0..self.config.max_concurrency {
+            workers.spawn(worker(Arc::clone(&jobs), Arc::clone(&dispatcher)));
+        }
+        let busy_replies = RateLimiter::new(BUSY_REPLY_LIMIT, RateLimitKey::SourceIp);
+
+        tokio::pin!(signal);
+        let mut buffer = vec![0; self.config.buffer_size];
+
+        loop {
+            // `recv_from` is cancel safe, so shutting down here never loses a
+            // datagram that has already been queued for the workers.
+            let (size, src_addr) = tokio::select! {
+                _ = &mut signal => break,
+                // Workers only exit early when a handler panics
+                Some(result) = workers.join_next() => {
+                    let error = result.err().map_or_else(|| "exited".to_string(), |e| e.to_string());
+                    dispatcher.handler.on_error(&format!("Handler worker failed: {}", error), None);
+                    self.stats.record_error();
+                    workers.spawn(worker(Arc::clone(&jobs), Arc::clone(&dispatcher)));
+                    continue;
+                }
+                result = self.socket.recv_from(&mut buffer) => match result {
+                    Ok(received) => received,
+                    Err(e) => {
+                        dispatcher.handler.on_error(&format!("Failed to receive data: {}", e), None);
+                        self.stats.record_error();
+
+                        // Small delay to prevent busy-waiting on persistent errors
+                        tokio::time::sleep(Duration::from_millis(10)).await;
+                        continue;
+                    }
+                },
+            };
+
+            self.stats.record_received(src_addr, size);
+
+            if self.config.verbose {
+                println!("Received {} bytes from {}", size, src_addr);
+            }
+
+            // Drop denied and rate limited sources without a response
+            if let Err(reason) = self.filter.check(src_addr) {
+                dispatcher.handler.on_rejected(src_addr, reason);
+                self.stats.record_rejection(reason);
+                continue;
+            }
+
+            if size > self.config.max_message_size {
+                let error_msg = format!("Message too large: {} bytes (max: {})",
+                    size, self.config.max_message_size);
+                dispatcher.handler.on_error(&error_msg, Some(src_addr));
+                self.stats.record_error();
+                continue;
+            }
+
+            match queue.try_send((buffer[..size].to_vec(), src_addr)) {
+                Ok(()) => {}
+                Err(TrySendError::Full(_)) => {
+                    self.stats.record_dropped();
+
+                    if self.config.verbose {
+                        println!("Handler queue full, dropping message from {}", src_addr);
+                    }
+
+                    // Waiting for the socket here would stall the receive loop
+                    if let OverloadPolicy::Busy(reply) = &self.config.overload_policy {
+                        if busy_replies.allow(src_addr) {
+                            dispatcher.try_send(reply, src_addr);
+                        }
+                    }
+                }
+                Err(TrySendError::Closed(_)) => {
+                    return Err("Handler workers stopped unexpectedly".into());
+                }
+            }
+        }
+
+        // Closing the queue lets the workers finish the remaining jobs and exit
+        drop(queue);
+        while let Some(result) = workers.join_next().await {
+            if let Err(e) = result {
+                dispatcher.handler.on_error(&format!("Handler worker failed: {}", e), None);
+                self.stats.record_error();
+            }
+        }
+
+        if let Some(reporter) = reporter {
+            reporter.abort();
+            let _ = reporter.await;
+        }
+
+        let final_stats = self.stats();
+        for reporter in &reporters {
+            reporter.report(&final_stats);
+        }
+
+        if self.config.verbose {
+            println!("Async UDP Echo Server on {} stopped", self.local_addr()?);
+        }
+
+        Ok(final_stats)
+    }
+
+    /// Run the server with the default echo handler on a background task
+    pub fn spawn(self) -> ShutdownHandle {
+        self.spawn_with_handler(EchoHandler)
+    }
+
+    /// Run the server with a custom handler on a background task
+    ///
+    /// The returned handle stops the server. Dropping the handle detaches the
+    /// task and leaves the server running.
+    pub fn spawn_with_handler<H: MessageHandler + 'static>(self, handler: H) -> ShutdownHandle {
+        self.spawn_with_async_handler(BlockingHandler(Arc::new(handler)))
+    }
+
+    /// Run the server with an async handler on a background task
+    ///
+    /// The returned handle stops the server. Dropping the handle detaches the
+    /// task and leaves the server running.
+    pub fn spawn_with_async_handler<H: AsyncMessageHandler + 'static>(self, handler: H) -> ShutdownHandle {
+        let (trigger, mut requested) = watch::channel(false);
+
+        let task = tokio::spawn(async move {
+            let signal = async move {
+                if requested.wait_for(|stop| *stop).await.is_err() {
+                    std::future::pending::<()>().await;
+                }
+            };
+            self.run_with_async_handler_until(handler, signal).await
+        });
+
+        ShutdownHandle { trigger, task }
+    }
+}
+
+/// Handle to a server started with [`AsyncUdpServer::spawn`]
+pub struct ShutdownHandle {
+    trigger: watch::Sender<bool>,
+    task: JoinHandle<Result<ServerStats, BoxError>>,
+}
+
+impl ShutdownHandle {
+    /// Stop the server and wait for it to finish
+    ///
+    /// Returns the final statistics of the server.
+    pub async fn shutdown(self) -> Result<ServerStats, BoxError> {
+        let _ = self.trigger.send(true);
+        self.task.await?
+    }
+
+    /// Check whether the server task has already finished
+    pub fn is_finished(&self) -> bool {
+        self.task.is_finished()
+    }
+}
+
+async fn report_stats(stats: Arc<StatsCollector>, interval: Duration, reporters: Vec<Arc<dyn StatsReporter>>) {
+    let mut ticker = tokio::time::interval(interval);
+    // The first tick completes immediately
+    ticker.tick().await;
+
+    loop {
+        ticker.tick().await;
+        let snapshot = stats.snapshot();
+        for reporter in &reporters {
+            reporter.report(&snapshot);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn echo(server_addr: SocketAddr, message: &[u8]) -> Vec<u8> {
+        let client = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        client.send_to(message, server_addr).await.unwrap();
+
+        let mut buffer = [0; 1024];
+        let (size, _) = tokio::time::timeout(Duration::from_secs(2), client.recv_from(&mut buffer))
+            .await
+            .expect("timed out waiting for echo")
+            .unwrap();
+        buffer[..size].to_vec()
+    }
+
+    #[tokio::test]
+    async fn test_async_echo_and_shutdown() {
+        let server = AsyncUdpServer::new(ServerConfig::new().port(0)).await.unwrap();
+        let server_addr = server.local_addr().unwrap();
+        let handle = server.spawn();
+
+        assert_eq!(echo(server_addr, b"Hello, Server!").await, b"Hello, Server!");
+        assert_eq!(echo(server_addr, b"again").await, b"again");
+
+        let stats = handle.shutdown().await.unwrap();
+        assert_eq!(stats.messages_received, 2);
+        assert_eq!(stats.messages_sent, 2);
+        assert_eq!(stats.bytes_sent, 19);
+    }
+
+    #[tokio::test]
+    async fn test_shutdown_stops_stats_reporter() {
+        let config = ServerConfig::new().port(0).stats(true).stats_interval(1);
+        let server = AsyncUdpServer::new(config).await.unwrap();
+        let handle = server.spawn();
+
+        let stats = tokio::time::timeout(Duration::from_secs(2), handle.shutdown())
+            .await
+            .expect("shutdown did not complete")
+            .unwrap();
+        assert_eq!(stats.messages_received, 0);
+
+        // A zero interval would make the reporter's timer panic
+        let config = ServerConfig::new().port(0).stats(true).stats_interval(0);
+        assert!(AsyncUdpServer::new(config).await.is_err());
+    }
+
+    /// Holds every message until the test releases it
+    struct GatedHandler {
+        started: mpsc::UnboundedSender<()>,
+        release: Arc<tokio::sync::Semaphore>,
+    }
+
+    impl AsyncMessageHandler for GatedHandler {
+        async fn handle_message(&self, message: Vec<u8>, _from: SocketAddr) -> Option<Vec<u8>> {
+            let _ = self.started.send(());
+            self.release.acquire().await.unwrap().forget();
+            Some(message)
+        }
+    }
+
+    fn gated() -> (GatedHandler, mpsc::UnboundedReceiver<()>, Arc<tokio::sync::Semaphore>) {
+        let (started, started_rx) = mpsc::unbounded_channel();
+        let release = Arc::new(tokio::sync::Semaphore::new(0));
+        (GatedHandler { started, release: Arc::clone(&release) }, started_rx, release)
+    }
+
+    struct SlowHandler;
+
+    impl AsyncMessageHandler for SlowHandler {
+        async fn handle_message(&self, message: Vec<u8>, _from: SocketAddr) -> Option<Vec<u8>> {
+            if message == b"slow" {
+                tokio::time::sleep(Duration::from_millis(300)).await;
+            }
+            Some(message)
+        }
+    }
+
+    #[tokio::test]
+    async fn test_slow_handler_does_not_stall_other_clients() {
+        let server = AsyncUdpServer::new(ServerConfig::new().port(0)).await.unwrap();
+        let server_addr = server.local_addr().unwrap();
+        let handle = server.spawn_with_async_handler(SlowHandler);
+
+        let slow_client = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        slow_client.send_to(b"slow", server_addr).await.unwrap();
+
+        let started = std::time::Instant::now();
+        assert_eq!(echo(server_addr, b"fast").await, b"fast");
+        assert!(started.elapsed() < Duration::from_millis(300));
+
+        let stats = handle.shutdown().await.unwrap();
+        assert_eq!(stats.messages_sent, 2);
+    }
+
+    #[tokio::test]
+    async fn test_full_queue_drops_messages() {
+        let config = ServerConfig::new().port(0).max_concurrency(1).queue_size(1);
+        let server = AsyncUdpServer::new(config).await.unwrap();
+        let server_addr = server.local_addr().unwrap();
+        let (handler, mut started, release) = gated();
+        let handle = server.spawn_with_async_handler(handler);
+
+        let client = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        client.send_to(b"first", server_addr).await.unwrap();
+        started.recv().await.unwrap();
+        client.send_to(b"queued", server_addr).await.unwrap();
+        client.send_to(b"dropped", server_addr).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        release.add_permits(3);
+        let mut buffer = [0; 64];
+        for expected in [&b"first"[..], b"queued"] {
+            let (size, _) = client.recv_from(&mut buffer).await.unwrap();
+            assert_eq!(&buffer[..size], expected);
+        }
+
+        let stats = handle.shutdown().await.unwrap();
+        assert_eq!(stats.messages_received, 3);
+        assert_eq!(stats.messages_sent, 2);
+        assert_eq!(stats.messages_dropped, 1);
+    }
+
+    #[tokio::test]
+    async fn test_full_queue_replies_busy() {
+        let config = ServerConfig::new()
+            .port(0)
+            .max_concurrency(1)
+            .queue_size(1)
+            .overload_policy(OverloadPolicy::Busy(b"busy".to_vec()));
+        let server = AsyncUdpServer::new(config).await.unwrap();
+        let server_addr = server.local_addr().unwrap();
+        let (handler, mut started, release) = gated();
+        let handle = server.spawn_with_async_handler(handler);
+
+        let client = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        client.send_to(b"first", server_addr).await.unwrap();
+        started.recv().await.unwrap();
+        client.send_to(b"queued", server_addr).await.unwrap();
+        client.send_to(b"rejected", server_addr).await.unwrap();
+        client.send_to(b"rejected again", server_addr).await.unwrap();
+
+        let mut buffer = [0; 64];
+        let (size, _) = client.recv_from(&mut buffer).await.unwrap();
+        assert_eq!(&buffer[..size], b"busy");
+
+        // Shutdown drains the queued message once the handler is released;
+        // the second rejection came too soon for another busy reply
+        release.add_permits(2);
+        let stats = handle.shutdown().await.unwrap();
+        assert_eq!(stats.messages_sent, 3);
+        assert_eq!(stats.messages_dropped, 2);
+    }
+
+    struct PanickingHandler;
+
+    impl AsyncMessageHandler for PanickingHandler {
+        async fn handle_message(&self, message: Vec<u8>, _from: SocketAddr) -> Option<Vec<u8>> {
+            assert_ne!(message, b"panic", "handler panicked on purpose");
+            Some(message)
+        }
+
+        fn on_error(&self, _error: &str, _from: Option<SocketAddr>) {}
+    }
+
+    #[tokio::test]
+    async fn test_panicking_handler_worker_is_replaced() {
+        let server = AsyncUdpServer::new(ServerConfig::new().port(0).max_concurrency(1)).await.unwrap();
+        let server_addr = server.local_addr().unwrap();
+        let handle = server.spawn_with_async_handler(PanickingHandler);
+
+        let client = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        for _ in 0..2 {
+            client.send_to(b"panic", server_addr).await.unwrap();
+        }
+        assert_eq!(echo(server_addr, b"still served").await, b"still served");
+
+        let stats = handle.shutdown().await.unwrap();
+        assert_eq!(stats.errors, 2);
+        assert_eq!(stats.messages_sent, 1);
+    }
+
+    #[derive(Default)]
+    struct CountingHandler {
+        rejected: std::sync::atomic::AtomicUsize,
+    }
+
+    impl MessageHandler for CountingHandler {
+        fn handle_message(&self, message: &[u8], _from: SocketAddr) -> Option<Vec<u8>> {
+            Some(message.to_vec())
+        }
+
+        fn on_rejected(&self, _from: SocketAddr, reason: RejectReason) {
+            assert_eq!(reason, RejectReason::RateLimited);
+            self.rejected.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_rate_limited_source_is_not_answered() {
+        let config = ServerConfig::new().port(0).rate_limit(0.001, 2);
+        let server = AsyncUdpServer::new(config).await.unwrap();
+        let server_addr = server.local_addr().unwrap();
+        let handler = Arc::new(CountingHandler::default());
+        let handle = server.spawn_with_handler(Arc::clone(&handler));
+
+        let client = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        for _ in 0..4 {
+            client.send_to(b"ping", server_addr).await.unwrap();
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        let stats = handle.shutdown().await.unwrap();
+        assert_eq!(stats.messages_received, 4);
+        assert_eq!(stats.messages_sent, 2);
+        assert_eq!(stats.rate_limited, 2);
+        assert_eq!(handler.rejected.load(std::sync::atomic::Ordering::SeqCst), 2);
+    }
+
+    #[cfg(feature = "json")]
+    #[tokio::test]
+    async fn test_shutdown_publishes_final_report() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("stats.json");
+        let server = AsyncUdpServer::new(ServerConfig::new().port(0))
+            .await
+            .unwrap()
+            .with_reporter(crate::JsonReporter::new(&path));
+        let server_addr = server.local_addr().unwrap();
+        let handle = server.spawn();
+
+        let client = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let client_addr = client.local_addr().unwrap();
+        client.send_to(b"metrics", server_addr).await.unwrap();
+        let mut buffer = [0; 64];
+        client.recv_from(&mut buffer).await.unwrap();
+
+        let stats = handle.shutdown().await.unwrap();
+        assert_eq!(stats.handler_latency.count(), 1);
+        assert_eq!(stats.clients[&client_addr].bytes_sent, 7);
+
+        let snapshot: serde_json::Value = serde_json::from_slice(&std::fs::read(&path).unwrap()).unwrap();
+        assert_eq!(snapshot["messages_received"], 1);
+        assert_eq!(snapshot["clients"][client_addr.to_string()]["messages_sent"], 1);
+    }
+
+    #[tokio::test]
+    async fn test_run_until_signal() {
+        let server = AsyncUdpServer::new(ServerConfig::new().port(0)).await.unwrap();
+        let stats = server.run_until(async {}).await.unwrap();
+        assert_eq!(stats.errors, 0);
+    }
+}
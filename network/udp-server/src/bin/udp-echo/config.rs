@@ -0,0 +1,308 @@
+// config.rs
+use std::fs;
+use std::net::Ipv4Addr;
+use std::path::Path;
+use std::str::FromStr;
+
+use clap::ValueEnum;
+use serde::Deserialize;
+use udp_echo_server::{RateLimitKey, ServerConfig};
+
+/// Prefix of the environment variables that override the config file
+pub const ENV_PREFIX: &str = "UDP_ECHO_";
+
+fn env_var<F: Fn(&str) -> Option<String>>(var: &F, name: &str) -> Option<String> {
+    var(&format!("{}{}", ENV_PREFIX, name))
+}
+
+/// Replace `field` with the parsed value of `UDP_ECHO_<name>` if it is set
+fn override_field<T, F>(field: &mut Option<T>, var: &F, name: &str) -> Result<(), String>
+where
+    T: FromStr,
+    T::Err: std::fmt::Display,
+    F: Fn(&str) -> Option<String>,
+{
+    if let Some(value) = env_var(var, name) {
+        let parsed = value.trim().parse()
+            .map_err(|e| format!("Invalid {}{}='{}': {}", ENV_PREFIX, name, value, e))?;
+        *field = Some(parsed);
+    }
+    Ok(())
+}
+
+fn split_list(value: &str) -> Vec<String> {
+    value.split(',').map(str::trim).filter(|s| !s.is_empty()).map(String::from).collect()
+}
+
+/// Built-in message handlers
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize, ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum HandlerKind {
+    #[default]
+    Echo,
+    Uppercase,
+    Reverse,
+    #[serde(alias = "jsonrpc")]
+    #[value(alias = "jsonrpc")]
+    JsonRpc,
+    Discard,
+}
+
+impl FromStr for HandlerKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        <HandlerKind as ValueEnum>::from_str(s, true)
+            .map_err(|_| format!("Unknown handler '{}' (echo, uppercase, reverse, json-rpc, discard)", s))
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RateLimitSection {
+    pub per_second: f64,
+    pub burst: u32,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MulticastSection {
+    pub group: Ipv4Addr,
+    #[serde(default = "unspecified")]
+    pub interface: Ipv4Addr,
+}
+
+fn unspecified() -> Ipv4Addr {
+    Ipv4Addr::UNSPECIFIED
+}
+
+/// Settings read from the config file; everything is optional
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FileConfig {
+    pub host: Option<String>,
+    pub port: Option<u16>,
+    pub buffer_size: Option<usize>,
+    pub max_message_size: Option<usize>,
+    pub verbose: Option<bool>,
+    pub stats: Option<bool>,
+    pub stats_interval: Option<u64>,
+    pub max_tracked_clients: Option<usize>,
+    pub workers: Option<usize>,
+    pub handler: Option<HandlerKind>,
+    pub rate_limit: Option<RateLimitSection>,
+    /// "source-ip" or "source-addr"
+    pub rate_limit_key: Option<String>,
+    pub allow: Option<Vec<String>>,
+    pub deny: Option<Vec<String>>,
+    pub broadcast: Option<bool>,
+    pub multicast_ttl: Option<u32>,
+    pub multicast_loop: Option<bool>,
+    pub multicast: Option<Vec<MulticastSection>>,
+}
+
+/// Everything the binary needs to start or reload the server
+#[derive(Debug, Clone)]
+pub struct Settings {
+    pub server: ServerConfig,
+    pub handler: HandlerKind,
+}
+
+impl FileConfig {
+    /// Parse a TOML or YAML file, picked by its extension
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let text = fs::read_to_string(path)
+            .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some("toml") => Self::from_toml(&text),
+            Some("yaml") | Some("yml") => Self::from_yaml(&text),
+            _ => Err(format!("Unknown config format for {} (use .toml, .yaml or .yml)", path.display())),
+        }
+        .map_err(|e| format!("{}: {}", path.display(), e))
+    }
+
+    pub fn from_toml(text: &str) -> Result<Self, String> {
+        toml::from_str(text).map_err(|e| e.to_string())
+    }
+
+    pub fn from_yaml(text: &str) -> Result<Self, String> {
+        // An empty YAML document is null, not an empty map
+        if text.trim().is_empty() {
+            return Ok(Self::default());
+        }
+        serde_yaml::from_str(text).map_err(|e| e.to_string())
+    }
+
+    /// Override settings from `UDP_ECHO_*` variables, e.g. `UDP_ECHO_PORT=9000`
+    ///
+    /// Lists are comma separated and the rate limit is `<per second>/<burst>`.
+    pub fn apply_env<F>(&mut self, var: F) -> Result<(), String>
+    where
+        F: Fn(&str) -> Option<String>,
+    {
+        override_field(&mut self.host, &var, "HOST")?;
+        override_field(&mut self.port, &var, "PORT")?;
+        override_field(&mut self.buffer_size, &var, "BUFFER_SIZE")?;
+        override_field(&mut self.max_message_size, &var, "MAX_MESSAGE_SIZE")?;
+        override_field(&mut self.verbose, &var, "VERBOSE")?;
+        override_field(&mut self.stats, &var, "STATS")?;
+        override_field(&mut self.stats_interval, &var, "STATS_INTERVAL")?;
+        override_field(&mut self.max_tracked_clients, &var, "MAX_TRACKED_CLIENTS")?;
+        override_field(&mut self.workers, &var, "WORKERS")?;
+        override_field(&mut self.handler, &var, "HANDLER")?;
+        override_field(&mut self.rate_limit_key, &var, "RATE_LIMIT_KEY")?;
+        override_field(&mut self.broadcast, &var, "BROADCAST")?;
+        override_field(&mut self.multicast_ttl, &var, "MULTICAST_TTL")?;
+        override_field(&mut self.multicast_loop, &var, "MULTICAST_LOOP")?;
+
+        if let Some(value) = env_var(&var, "ALLOW") {
+            self.allow = Some(split_list(&value));
+        }
+        if let Some(value) = env_var(&var, "DENY") {
+            self.deny = Some(split_list(&value));
+        }
+
+        if let Some(value) = env_var(&var, "RATE_LIMIT") {
+            let invalid = || format!("Invalid {}RATE_LIMIT='{}': expected <per second>/<burst>", ENV_PREFIX, value);
+            let (per_second, burst) = value.split_once('/').ok_or_else(invalid)?;
+            self.rate_limit = Some(RateLimitSection {
+                per_second: per_second.trim().parse().map_err(|_| invalid())?,
+                burst: burst.trim().parse().map_err(|_| invalid())?,
+            });
+        }
+
+        Ok(())
+    }
+
+    /// Build the server configuration on top of the library defaults
+    pub fn into_settings(self) -> Result<Settings, String> {
+        let mut config = ServerConfig::new();
+
+        if let Some(host) = self.host {
+            config = config.host(host);
+        }
+        if let Some(port) = self.port {
+            config = config.port(port);
+        }
+        if let Some(size) = self.buffer_size {
+            config = config.buffer_size(size);
+        }
+        if let Some(size) = self.max_message_size {
+            config = config.max_message_size(size);
+        }
+        if let Some(enabled) = self.verbose {
+            config = config.verbose(enabled);
+        }
+        if let Some(enabled) = self.stats {
+            config = config.stats(enabled);
+        }
+        if let Some(seconds) = self.stats_interval {
+            config = config.stats_interval(seconds);
+        }
+        if let Some(count) = self.max_tracked_clients {
+            config = config.max_tracked_clients(count);
+        }
+        if let Some(count) = self.workers {
+            config = config.workers(count);
+        }
+        if let Some(limit) = self.rate_limit {
+            config = config.rate_limit(limit.per_second, limit.burst);
+        }
+        if let Some(enabled) = self.broadcast {
+            config = config.broadcast(enabled);
+        }
+        if let Some(ttl) = self.multicast_ttl {
+            config = config.multicast_ttl(ttl);
+        }
+        if let Some(enabled) = self.multicast_loop {
+            config = config.multicast_loop(enabled);
+        }
+
+        if let Some(key) = self.rate_limit_key {
+            config = config.rate_limit_key(match key.as_str() {
+                "source-ip" => RateLimitKey::SourceIp,
+                "source-addr" => RateLimitKey::SourceAddr,
+                other => return Err(format!("Unknown rate_limit_key '{}' (source-ip, source-addr)", other)),
+            });
+        }
+        for network in self.allow.unwrap_or_default() {
+            config = config.allow_cidr(network.parse()?);
+        }
+        for network in self.deny.unwrap_or_default() {
+            config = config.deny_cidr(network.parse()?);
+        }
+        for group in self.multicast.unwrap_or_default() {
+            config = config.join_multicast_v4(group.group, group.interface);
+        }
+
+        config.validate()?;
+        Ok(Settings {
+            server: config,
+            handler: self.handler.unwrap_or_default(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_toml_and_yaml_agree() {
+        let toml = r#"
+            host = "0.0.0.0"
+            port = 9000
+            handler = "json-rpc"
+            deny = ["10.0.0.0/8"]
+            rate_limit = { per_second = 50.0, burst = 10 }
+        "#;
+        let yaml = "
+host: 0.0.0.0
+port: 9000
+handler: jsonrpc
+deny: [10.0.0.0/8]
+rate_limit:
+  per_second: 50.0
+  burst: 10
+";
+        for file in [FileConfig::from_toml(toml).unwrap(), FileConfig::from_yaml(yaml).unwrap()] {
+            let settings = file.into_settings().unwrap();
+            assert_eq!(settings.server.host, "0.0.0.0");
+            assert_eq!(settings.server.port, 9000);
+            assert_eq!(settings.server.deny.len(), 1);
+            assert_eq!(settings.server.rate_limit.unwrap().burst, 10);
+            assert_eq!(settings.handler, HandlerKind::JsonRpc);
+        }
+
+        assert!(FileConfig::from_toml("prot = 1").is_err());
+        assert!(FileConfig::from_yaml("").unwrap().into_settings().is_ok());
+    }
+
+    #[test]
+    fn test_env_overrides_file() {
+        let mut file = FileConfig::from_toml("port = 9000\nverbose = false").unwrap();
+        let env: HashMap<&str, &str> = [
+            ("UDP_ECHO_PORT", "9100"),
+            ("UDP_ECHO_VERBOSE", "true"),
+            ("UDP_ECHO_HANDLER", "Uppercase"),
+            ("UDP_ECHO_ALLOW", "127.0.0.0/8, ::1"),
+            ("UDP_ECHO_RATE_LIMIT", "20/5"),
+        ]
+        .into_iter()
+        .collect();
+        file.apply_env(|name| env.get(name).map(|v| v.to_string())).unwrap();
+
+        let settings = file.into_settings().unwrap();
+        assert_eq!(settings.server.port, 9100);
+        assert!(settings.server.verbose);
+        assert_eq!(settings.server.allow.len(), 2);
+        assert_eq!(settings.server.rate_limit.unwrap().per_second, 20.0);
+        assert_eq!(settings.handler, HandlerKind::Uppercase);
+
+        let mut file = FileConfig::default();
+        let result = file.apply_env(|name| (name == "UDP_ECHO_PORT").then(|| "http".to_string()));
+        assert!(result.unwrap_err().contains("UDP_ECHO_PORT"));
+    }
+}
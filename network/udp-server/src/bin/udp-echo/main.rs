@@ -0,0 +1,164 @@
+// main.rs
+mod config;
+
+use std::env;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::{Arc, PoisonError, RwLock};
+
+use clap::Parser;
+use udp_echo_server::{
+    DiscardHandler, EchoHandler, JsonRpcHandler, MessageHandler, RejectReason, ReverseHandler, UdpEchoServer,
+    UppercaseHandler,
+};
+
+use config::{FileConfig, HandlerKind, Settings};
+
+/// Configurable UDP echo server
+///
+/// Settings come from the config file, then `UDP_ECHO_*` environment
+/// variables, then the command line. Send SIGHUP to reload them.
+#[derive(Parser, Debug, Clone)]
+#[command(name = "udp-echo", version, about)]
+struct Cli {
+    /// Config file (.toml, .yaml or .yml)
+    #[arg(short, long)]
+    config: Option<PathBuf>,
+
+    /// Message handler
+    #[arg(long, value_enum)]
+    handler: Option<HandlerKind>,
+
+    /// Host address to bind to
+    #[arg(long)]
+    host: Option<String>,
+
+    /// Port to bind to
+    #[arg(short, long)]
+    port: Option<u16>,
+
+    /// Log every datagram
+    #[arg(short, long)]
+    verbose: bool,
+}
+
+fn load_settings(cli: &Cli) -> Result<Settings, String> {
+    let mut file = match &cli.config {
+        Some(path) => FileConfig::load(path)?,
+        None => FileConfig::default(),
+    };
+    file.apply_env(|name| env::var(name).ok())?;
+
+    if cli.handler.is_some() {
+        file.handler = cli.handler;
+    }
+    if cli.host.is_some() {
+        file.host = cli.host.clone();
+    }
+    if cli.port.is_some() {
+        file.port = cli.port;
+    }
+    if cli.verbose {
+        file.verbose = Some(true);
+    }
+
+    file.into_settings()
+}
+
+fn build_handler(kind: HandlerKind) -> Arc<dyn MessageHandler> {
+    match kind {
+        HandlerKind::Echo => Arc::new(EchoHandler),
+        HandlerKind::Uppercase => Arc::new(UppercaseHandler),
+        HandlerKind::Reverse => Arc::new(ReverseHandler),
+        HandlerKind::JsonRpc => Arc::new(JsonRpcHandler::with_builtin_methods()),
+        HandlerKind::Discard => Arc::new(DiscardHandler),
+    }
+}
+
+/// Forwards to the handler picked by the current configuration
+struct SwitchableHandler {
+    current: RwLock<(HandlerKind, Arc<dyn MessageHandler>)>,
+}
+
+impl SwitchableHandler {
+    fn new(kind: HandlerKind) -> Self {
+        Self {
+            current: RwLock::new((kind, build_handler(kind))),
+        }
+    }
+
+    /// Replace the handler unless it is already of the requested kind
+    fn switch(&self, kind: HandlerKind) {
+        let mut current = self.current.write().unwrap_or_else(PoisonError::into_inner);
+        if current.0 != kind {
+            *current = (kind, build_handler(kind));
+        }
+    }
+
+    fn get(&self) -> Arc<dyn MessageHandler> {
+        Arc::clone(&self.current.read().unwrap_or_else(PoisonError::into_inner).1)
+    }
+}
+
+impl MessageHandler for SwitchableHandler {
+    fn handle_message(&self, message: &[u8], from: SocketAddr) -> Option<Vec<u8>> {
+        self.get().handle_message(message, from)
+    }
+
+    fn on_error(&self, error: &str, from: Option<SocketAddr>) {
+        self.get().on_error(error, from)
+    }
+
+    fn on_rejected(&self, from: SocketAddr, reason: RejectReason) {
+        self.get().on_rejected(from, reason)
+    }
+}
+
+/// Reload the settings on SIGHUP while the server keeps its sockets
+#[cfg(unix)]
+fn reload_on_sighup(
+    cli: Cli,
+    reload: udp_echo_server::ReloadHandle,
+    handler: Arc<SwitchableHandler>,
+) -> std::io::Result<()> {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let runtime = tokio::runtime::Builder::new_current_thread().enable_all().build()?;
+    // Register now so a SIGHUP right after startup does not terminate the process
+    let mut hangup = runtime.block_on(async { signal(SignalKind::hangup()) })?;
+
+    std::thread::spawn(move || {
+        runtime.block_on(async move {
+            while hangup.recv().await.is_some() {
+                let result = load_settings(&cli).and_then(|settings| {
+                    reload.reload(settings.server)?;
+                    Ok(settings.handler)
+                });
+
+                match result {
+                    Ok(kind) => {
+                        handler.switch(kind);
+                        println!("Configuration reloaded, handler: {:?}", kind);
+                    }
+                    Err(e) => eprintln!("Reload failed, keeping the current configuration: {}", e),
+                }
+            }
+        })
+    });
+    Ok(())
+}
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let cli = Cli::parse();
+    let settings = load_settings(&cli)?;
+
+    let server = UdpEchoServer::new(settings.server)?;
+    println!("udp-echo listening on {} with the {:?} handler", server.local_addr()?, settings.handler);
+
+    let handler = Arc::new(SwitchableHandler::new(settings.handler));
+
+    #[cfg(unix)]
+    reload_on_sighup(cli, server.reload_handle(), Arc::clone(&handler))?;
+
+    server.run_with_handler(handler)
+}
@@ -0,0 +1,654 @@
+// capture.rs
+use std::collections::HashMap;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::MessageHandler;
+
+const PCAP_MAGIC: u32 = 0xa1b2_c3d4;
+const PCAP_SNAPLEN: u32 = 65535;
+/// Raw IPv4/IPv6 packets without a link layer header
+const LINKTYPE_RAW: u32 = 101;
+const BINARY_MAGIC: &[u8; 6] = b"UECAP\0";
+const BINARY_VERSION: u8 = 1;
+const UDP_PROTOCOL: u8 = 17;
+/// Largest UDP payload a datagram can carry
+const MAX_PAYLOAD_LEN: usize = u16::MAX as usize;
+/// Largest packet the recorder writes: an IPv6 header and the largest UDP datagram
+const MAX_PACKET_LEN: usize = 40 + u16::MAX as usize;
+
+/// File format written by [`CaptureRecorder`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CaptureFormat {
+    /// Classic pcap with synthesized IP and UDP headers, readable by Wireshark and tcpdump
+    Pcap,
+    /// Compact log of timestamps, directions, addresses and payloads
+    Binary,
+}
+
+/// Whether a datagram was received or sent by the server
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    Inbound,
+    Outbound,
+}
+
+/// One datagram read back from a capture
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CapturedDatagram {
+    pub timestamp: SystemTime,
+    pub direction: Direction,
+    /// The client address
+    pub peer: SocketAddr,
+    /// The server socket address
+    pub local: SocketAddr,
+    pub data: Vec<u8>,
+}
+
+/// Writes every datagram passing through a server to a capture file
+///
+/// Each record is flushed immediately so the file can be inspected while the
+/// server is running.
+pub struct CaptureRecorder {
+    format: CaptureFormat,
+    writer: Mutex<BufWriter<File>>,
+}
+
+impl CaptureRecorder {
+    /// Create (or truncate) a capture file
+    pub fn create<P: AsRef<Path>>(path: P, format: CaptureFormat) -> io::Result<Self> {
+        let mut writer = BufWriter::new(File::create(path)?);
+
+        match format {
+            CaptureFormat::Pcap => {
+                writer.write_all(&PCAP_MAGIC.to_le_bytes())?;
+                writer.write_all(&2u16.to_le_bytes())?;
+                writer.write_all(&4u16.to_le_bytes())?;
+                writer.write_all(&0i32.to_le_bytes())?; // GMT offset
+                writer.write_all(&0u32.to_le_bytes())?; // timestamp accuracy
+                writer.write_all(&PCAP_SNAPLEN.to_le_bytes())?;
+                writer.write_all(&LINKTYPE_RAW.to_le_bytes())?;
+            }
+            CaptureFormat::Binary => {
+                writer.write_all(BINARY_MAGIC)?;
+                writer.write_all(&[BINARY_VERSION, 0])?;
+            }
+        }
+        writer.flush()?;
+
+        Ok(Self {
+            format,
+            writer: Mutex::new(writer),
+        })
+    }
+
+    /// Get the format of the capture file
+    pub fn format(&self) -> CaptureFormat {
+        self.format
+    }
+
+    /// Append a datagram received from or sent to `peer` on the socket bound to `local`
+    pub fn record(&self, direction: Direction, peer: SocketAddr, local: SocketAddr, data: &[u8]) -> io::Result<()> {
+        let since_epoch = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+
+        let record = match self.format {
+            CaptureFormat::Pcap => {
+                let (src, dst) = match direction {
+                    Direction::Inbound => (peer, local),
+                    Direction::Outbound => (local, peer),
+                };
+                let packet = ip_udp_packet(src, dst, data)?;
+
+                let mut record = Vec::with_capacity(16 + packet.len());
+                record.extend_from_slice(&(since_epoch.as_secs() as u32).to_le_bytes());
+                record.extend_from_slice(&since_epoch.subsec_micros().to_le_bytes());
+                record.extend_from_slice(&(packet.len() as u32).to_le_bytes());
+                record.extend_from_slice(&(packet.len() as u32).to_le_bytes());
+                record.extend_from_slice(&packet);
+                record
+            }
+            CaptureFormat::Binary => {
+                let mut record = Vec::with_capacity(48 + data.len());
+                record.extend_from_slice(&(since_epoch.as_micros() as u64).to_be_bytes());
+                record.push(match direction {
+                    Direction::Inbound => 0,
+                    Direction::Outbound => 1,
+                });
+                encode_addr(&mut record, peer);
+                encode_addr(&mut record, local);
+                record.extend_from_slice(&(data.len() as u32).to_be_bytes());
+                record.extend_from_slice(data);
+                record
+            }
+        };
+
+        let mut writer = self.writer.lock().unwrap();
+        writer.write_all(&record)?;
+        writer.flush()
+    }
+}
+
+/// Build an IP packet carrying a UDP datagram, as a capture tool would see it
+///
+/// Mixed address families are written as IPv6 with IPv4-mapped addresses.
+fn ip_udp_packet(src: SocketAddr, dst: SocketAddr, data: &[u8]) -> io::Result<Vec<u8>> {
+    let udp_len = u16::try_from(8 + data.len())
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "Datagram too large to capture"))?;
+
+    let mut udp = Vec::with_capacity(8 + data.len());
+    udp.extend_from_slice(&src.port().to_be_bytes());
+    udp.extend_from_slice(&dst.port().to_be_bytes());
+    udp.extend_from_slice(&udp_len.to_be_bytes());
+    udp.extend_from_slice(&[0, 0]);
+    udp.extend_from_slice(data);
+
+    match (src.ip(), dst.ip()) {
+        (IpAddr::V4(src), IpAddr::V4(dst)) => {
+            let total_len = u16::try_from(20 + udp.len())
+                .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "Datagram too large to capture"))?;
+
+            let mut packet = Vec::with_capacity(total_len as usize);
+            packet.extend_from_slice(&[0x45, 0]);
+            packet.extend_from_slice(&total_len.to_be_bytes());
+            packet.extend_from_slice(&[0, 0, 0x40, 0]); // identification, don't fragment
+            packet.extend_from_slice(&[64, UDP_PROTOCOL, 0, 0]); // TTL, protocol, checksum
+            packet.extend_from_slice(&src.octets());
+            packet.extend_from_slice(&dst.octets());
+            let checksum = internet_checksum(&[&packet]);
+            packet[10..12].copy_from_slice(&checksum.to_be_bytes());
+
+            // A zero UDP checksum means "not computed" over IPv4
+            packet.extend_from_slice(&udp);
+            Ok(packet)
+        }
+        (src, dst) => {
+            let src = to_ipv6(src);
+            let dst = to_ipv6(dst);
+
+            let pseudo_header = [
+                &src.octets()[..],
+                &dst.octets()[..],
+                &(udp.len() as u32).to_be_bytes()[..],
+                &[0, 0, 0, UDP_PROTOCOL][..],
+            ];
+            let mut checksum = internet_checksum(&[pseudo_header.concat().as_slice(), &udp]);
+            if checksum == 0 {
+                checksum = 0xffff;
+            }
+            udp[6..8].copy_from_slice(&checksum.to_be_bytes());
+
+            let mut packet = Vec::with_capacity(40 + udp.len());
+            packet.extend_from_slice(&[0x60, 0, 0, 0]);
+            packet.extend_from_slice(&udp_len.to_be_bytes());
+            packet.extend_from_slice(&[UDP_PROTOCOL, 64]); // next header, hop limit
+            packet.extend_from_slice(&src.octets());
+            packet.extend_from_slice(&dst.octets());
+            packet.extend_from_slice(&udp);
+            Ok(packet)
+        }
+    }
+}
+
+fn to_ipv6(ip: IpAddr) -> Ipv6Addr {
+    match ip {
+        IpAddr::V4(ip) => ip.to_ipv6_mapped(),
+        IpAddr::V6(ip) => ip,
+    }
+}
+
+/// One's complement sum of 16-bit words over all parts
+fn internet_checksum(parts: &[&[u8]]) -> u16 {
+    let mut sum: u32 = 0;
+    let bytes = parts.iter().flat_map(|part| part.iter().copied());
+    let mut high = None;
+    for byte in bytes {
+        match high.take() {
+            None => high = Some(byte),
+            Some(h) => sum += u16::from_be_bytes([h, byte]) as u32,
+        }
+    }
+    if let Some(h) = high {
+        sum += u16::from_be_bytes([h, 0]) as u32;
+    }
+    while sum > 0xffff {
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+    !(sum as u16)
+}
+
+fn encode_addr(buf: &mut Vec<u8>, addr: SocketAddr) {
+    match addr.ip() {
+        IpAddr::V4(ip) => {
+            buf.push(4);
+            buf.extend_from_slice(&ip.octets());
+        }
+        IpAddr::V6(ip) => {
+            buf.push(6);
+            buf.extend_from_slice(&ip.octets());
+        }
+    }
+    buf.extend_from_slice(&addr.port().to_be_bytes());
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+/// Read exactly `N` bytes, or `None` at a clean end of file
+fn read_array<const N: usize, R: Read>(reader: &mut R) -> io::Result<Option<[u8; N]>> {
+    let mut buf = [0u8; N];
+    let mut filled = 0;
+    while filled < N {
+        match reader.read(&mut buf[filled..])? {
+            0 if filled == 0 => return Ok(None),
+            0 => return Err(invalid("Capture ends in the middle of a record")),
+            n => filled += n,
+        }
+    }
+    Ok(Some(buf))
+}
+
+fn read_exact_array<const N: usize, R: Read>(reader: &mut R) -> io::Result<[u8; N]> {
+    read_array(reader)?.ok_or_else(|| invalid("Capture ends in the middle of a record"))
+}
+
+/// Read a length-prefixed field, refusing lengths above `max` before allocating
+fn read_vec<R: Read>(reader: &mut R, len: usize, max: usize) -> io::Result<Vec<u8>> {
+    if len > max {
+        return Err(invalid(&format!("Record length {} exceeds the maximum of {}", len, max)));
+    }
+    let mut data = vec![0; len];
+    reader.read_exact(&mut data).map_err(|_| invalid("Capture ends in the middle of a record"))?;
+    Ok(data)
+}
+
+fn decode_addr<R: Read>(reader: &mut R) -> io::Result<SocketAddr> {
+    let ip = match read_exact_array::<1, _>(reader)?[0] {
+        4 => IpAddr::V4(Ipv4Addr::from(read_exact_array::<4, _>(reader)?)),
+        6 => IpAddr::V6(Ipv6Addr::from(read_exact_array::<16, _>(reader)?)),
+        family => return Err(invalid(&format!("Unknown address family {}", family))),
+    };
+    let port = u16::from_be_bytes(read_exact_array(reader)?);
+    Ok(SocketAddr::new(ip, port))
+}
+
+/// Read all datagrams from a capture written by [`CaptureRecorder`]
+///
+/// The format is detected from the file header. Pcap files carry no
+/// direction, so the destination of the first packet is taken to be the
+/// server and packets sent to it are inbound.
+pub fn read_capture<P: AsRef<Path>>(path: P) -> io::Result<Vec<CapturedDatagram>> {
+    let mut reader = BufReader::new(File::open(path)?);
+    let header: [u8; 8] = read_exact_array(&mut reader)?;
+
+    if header[..6] == BINARY_MAGIC[..] {
+        if header[6] != BINARY_VERSION {
+            return Err(invalid(&format!("Unsupported capture version {}", header[6])));
+        }
+        read_binary(&mut reader)
+    } else if header[..4] == PCAP_MAGIC.to_le_bytes() {
+        read_pcap(&mut reader)
+    } else {
+        Err(invalid("Not a pcap or binary capture file"))
+    }
+}
+
+fn read_binary<R: Read>(reader: &mut R) -> io::Result<Vec<CapturedDatagram>> {
+    let mut datagrams = Vec::new();
+
+    while let Some(micros) = read_array::<8, _>(reader)? {
+        let direction = match read_exact_array::<1, _>(reader)?[0] {
+            0 => Direction::Inbound,
+            1 => Direction::Outbound,
+            other => return Err(invalid(&format!("Unknown direction {}", other))),
+        };
+        let peer = decode_addr(reader)?;
+        let local = decode_addr(reader)?;
+        let len = u32::from_be_bytes(read_exact_array(reader)?) as usize;
+
+        datagrams.push(CapturedDatagram {
+            timestamp: UNIX_EPOCH + Duration::from_micros(u64::from_be_bytes(micros)),
+            direction,
+            peer,
+            local,
+            data: read_vec(reader, len, MAX_PAYLOAD_LEN)?,
+        });
+    }
+
+    Ok(datagrams)
+}
+
+fn read_pcap<R: Read>(reader: &mut R) -> io::Result<Vec<CapturedDatagram>> {
+    // Rest of the global header after magic and version
+    let rest: [u8; 16] = read_exact_array(reader)?;
+    let linktype = u32::from_le_bytes(rest[12..16].try_into().unwrap());
+    if linktype != LINKTYPE_RAW {
+        return Err(invalid(&format!("Unsupported pcap link type {}", linktype)));
+    }
+
+    let mut server = None;
+    let mut datagrams = Vec::new();
+
+    while let Some(header) = read_array::<16, _>(reader)? {
+        let secs = u32::from_le_bytes(header[0..4].try_into().unwrap());
+        let micros = u32::from_le_bytes(header[4..8].try_into().unwrap());
+        let len = u32::from_le_bytes(header[8..12].try_into().unwrap()) as usize;
+        if micros >= 1_000_000 {
+            return Err(invalid(&format!("Invalid pcap timestamp: {} microseconds", micros)));
+        }
+        let packet = read_vec(reader, len, MAX_PACKET_LEN)?;
+
+        let (src, dst, data) = parse_ip_udp(&packet)?;
+        let server = *server.get_or_insert(dst);
+        let (direction, peer, local) = if dst == server {
+            (Direction::Inbound, src, dst)
+        } else {
+            (Direction::Outbound, dst, src)
+        };
+
+        datagrams.push(CapturedDatagram {
+            timestamp: UNIX_EPOCH + Duration::from_secs(u64::from(secs)) + Duration::from_nanos(u64::from(micros) * 1000),
+            direction,
+            peer,
+            local,
+            data: data.to_vec(),
+        });
+    }
+
+    Ok(datagrams)
+}
+
+fn parse_ip_udp(packet: &[u8]) -> io::Result<(SocketAddr, SocketAddr, &[u8])> {
+    let (src, dst, udp) = match packet.first().map(|b| b >> 4) {
+        Some(4) if packet.len() >= 20 => {
+            let header_len = (packet[0] & 0x0f) as usize * 4;
+            if packet[9] != UDP_PROTOCOL || packet.len() < header_len {
+                return Err(invalid("Captured packet is not UDP"));
+            }
+            let src = Ipv4Addr::from(<[u8; 4]>::try_from(&packet[12..16]).unwrap());
+            let dst = Ipv4Addr::from(<[u8; 4]>::try_from(&packet[16..20]).unwrap());
+            (IpAddr::V4(src), IpAddr::V4(dst), &packet[header_len..])
+        }
+        Some(6) if packet.len() >= 40 => {
+            if packet[6] != UDP_PROTOCOL {
+                return Err(invalid("Captured packet is not UDP"));
+            }
+            let src = Ipv6Addr::from(<[u8; 16]>::try_from(&packet[8..24]).unwrap());
+            let dst = Ipv6Addr::from(<[u8; 16]>::try_from(&packet[24..40]).unwrap());
+            (unmap(src), unmap(dst), &packet[40..])
+        }
+        _ => return Err(invalid("Captured packet is not IPv4 or IPv6")),
+    };
+
+    if udp.len() < 8 {
+        return Err(invalid("Captured UDP header is truncated"));
+    }
+    let src_port = u16::from_be_bytes([udp[0], udp[1]]);
+    let dst_port = u16::from_be_bytes([udp[2], udp[3]]);
+    let udp_len = (u16::from_be_bytes([udp[4], udp[5]]) as usize).clamp(8, udp.len());
+
+    Ok((SocketAddr::new(src, src_port), SocketAddr::new(dst, dst_port), &udp[8..udp_len]))
+}
+
+/// Undo the IPv4 mapping applied to mixed address families
+fn unmap(ip: Ipv6Addr) -> IpAddr {
+    ip.to_ipv4_mapped().map(IpAddr::V4).unwrap_or(IpAddr::V6(ip))
+}
+
+/// A request whose replayed response differs from the recorded one
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReplayMismatch {
+    /// Position of the request in the capture
+    pub index: usize,
+    pub peer: SocketAddr,
+    pub request: Vec<u8>,
+    /// `None` if the server sent no response
+    pub recorded: Option<Vec<u8>>,
+    /// `None` if the handler produced no response
+    pub replayed: Option<Vec<u8>>,
+}
+
+impl fmt::Display for ReplayMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "#{} from {}: request {}", self.index, self.peer, preview(Some(&self.request)))?;
+        writeln!(f, "  recorded: {}", preview(self.recorded.as_deref()))?;
+        write!(f, "  replayed: {}", preview(self.replayed.as_deref()))?;
+
+        if let (Some(recorded), Some(replayed)) = (&self.recorded, &self.replayed) {
+            let offset = recorded.iter().zip(replayed).position(|(a, b)| a != b)
+                .unwrap_or_else(|| recorded.len().min(replayed.len()));
+            write!(f, "\n  first difference at byte {}", offset)?;
+        }
+        Ok(())
+    }
+}
+
+/// Show a payload as text if it is printable UTF-8, otherwise as hex
+fn preview(data: Option<&[u8]>) -> String {
+    const MAX: usize = 64;
+
+    let data = match data {
+        Some(data) => data,
+        None => return "(no response)".to_string(),
+    };
+    let shown = &data[..data.len().min(MAX)];
+    let ellipsis = if data.len() > MAX { "..." } else { "" };
+
+    match std::str::from_utf8(shown) {
+        Ok(text) if !text.chars().any(|c| c.is_control() && c != '\n' && c != '\t') => {
+            format!("{:?}{} ({} bytes)", text, ellipsis, data.len())
+        }
+        _ => {
+            let hex: String = shown.iter().map(|b| format!("{:02x}", b)).collect();
+            format!("0x{}{} ({} bytes)", hex, ellipsis, data.len())
+        }
+    }
+}
+
+/// Outcome of replaying a capture
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ReplayReport {
+    /// Number of inbound datagrams fed to the handler
+    pub replayed: usize,
+    pub mismatches: Vec<ReplayMismatch>,
+}
+
+impl ReplayReport {
+    /// Check whether every response matched the recording
+    pub fn is_clean(&self) -> bool {
+        self.mismatches.is_empty()
+    }
+}
+
+impl fmt::Display for ReplayReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for mismatch in &self.mismatches {
+            writeln!(f, "{}", mismatch)?;
+        }
+        write!(f, "Replayed {} requests, {} mismatched", self.replayed, self.mismatches.len())
+    }
+}
+
+/// Feed the inbound datagrams of a capture into a handler and diff the responses
+///
+/// A recorded response is the first outbound datagram to the same peer after
+/// the request and before that peer's next request; the server answers each
+/// datagram before reading the next one from the same socket, so this pairing
+/// is exact. Datagrams the server rejected through its access lists or rate
+/// limit are replayed too and show up as mismatches.
+pub fn replay<H: MessageHandler + ?Sized>(capture: &[CapturedDatagram], handler: &H) -> ReplayReport {
+    let mut recorded: Vec<Option<&[u8]>> = vec![None; capture.len()];
+    let mut last_request: HashMap<SocketAddr, usize> = HashMap::new();
+
+    for (index, datagram) in capture.iter().enumerate() {
+        match datagram.direction {
+            Direction::Inbound => {
+                last_request.insert(datagram.peer, index);
+            }
+            Direction::Outbound => {
+                if let Some(request) = last_request.remove(&datagram.peer) {
+                    recorded[request] = Some(&datagram.data);
+                }
+            }
+        }
+    }
+
+    let mut report = ReplayReport::default();
+    for (index, datagram) in capture.iter().enumerate() {
+        if datagram.direction != Direction::Inbound {
+            continue;
+        }
+        report.replayed += 1;
+
+        let replayed = handler.handle_message(&datagram.data, datagram.peer);
+        if replayed.as_deref() != recorded[index] {
+            report.mismatches.push(ReplayMismatch {
+                index,
+                peer: datagram.peer,
+                request: datagram.data.clone(),
+                recorded: recorded[index].map(<[u8]>::to_vec),
+                replayed,
+            });
+        }
+    }
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{EchoHandler, ServerConfig, UdpEchoServer};
+    use std::net::UdpSocket;
+    use tempfile::tempdir;
+
+    fn addr(s: &str) -> SocketAddr {
+        s.parse().unwrap()
+    }
+
+    fn write_sample(path: &Path, format: CaptureFormat) {
+        let recorder = CaptureRecorder::create(path, format).unwrap();
+        let server = addr("127.0.0.1:8080");
+        recorder.record(Direction::Inbound, addr("127.0.0.1:5000"), server, b"ping").unwrap();
+        recorder.record(Direction::Outbound, addr("127.0.0.1:5000"), server, b"ping").unwrap();
+        recorder.record(Direction::Inbound, addr("[fd00::1]:6000"), server, &[0, 159, 146, 150]).unwrap();
+        recorder.record(Direction::Outbound, addr("[fd00::1]:6000"), server, b"odd").unwrap();
+    }
+
+    #[test]
+    fn test_capture_round_trip() {
+        let dir = tempdir().unwrap();
+
+        for format in [CaptureFormat::Pcap, CaptureFormat::Binary] {
+            let path = dir.path().join("capture");
+            write_sample(&path, format);
+
+            let capture = read_capture(&path).unwrap();
+            assert_eq!(capture.len(), 4, "{:?}", format);
+            assert_eq!(capture[0].direction, Direction::Inbound);
+            assert_eq!(capture[0].peer, addr("127.0.0.1:5000"));
+            assert_eq!(capture[0].local, addr("127.0.0.1:8080"));
+            assert_eq!(capture[1].direction, Direction::Outbound);
+            assert_eq!(capture[2].peer, addr("[fd00::1]:6000"));
+            assert_eq!(capture[2].data, [0, 159, 146, 150]);
+            assert_eq!(capture[3].data, b"odd");
+            assert!(capture[0].timestamp <= capture[3].timestamp);
+        }
+
+        assert_eq!(internet_checksum(&[&[0x45, 0x00, 0x00, 0x1c]]), !0x451c);
+    }
+
+    #[test]
+    fn test_malformed_captures_are_rejected() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("capture");
+
+        // Byte offsets into the first record of each sample
+        let pcap_micros = 24 + 4;
+        let pcap_len = 24 + 8;
+        let binary_len = 8 + 8 + 1 + 7 + 7;
+        let cases: [(CaptureFormat, usize, &[u8]); 4] = [
+            (CaptureFormat::Pcap, pcap_micros, &1_000_000u32.to_le_bytes()),
+            (CaptureFormat::Pcap, pcap_micros, &u32::MAX.to_le_bytes()),
+            (CaptureFormat::Pcap, pcap_len, &u32::MAX.to_le_bytes()),
+            (CaptureFormat::Binary, binary_len, &u32::MAX.to_be_bytes()),
+        ];
+        for (format, offset, bytes) in cases {
+            write_sample(&path, format);
+            let mut file = std::fs::read(&path).unwrap();
+            file[offset..offset + bytes.len()].copy_from_slice(bytes);
+            std::fs::write(&path, &file).unwrap();
+
+            let error = read_capture(&path).unwrap_err();
+            assert_eq!(error.kind(), io::ErrorKind::InvalidData, "{:?} at {}: {}", format, offset, error);
+        }
+
+        // A record cut short
+        for format in [CaptureFormat::Pcap, CaptureFormat::Binary] {
+            write_sample(&path, format);
+            let file = std::fs::read(&path).unwrap();
+            std::fs::write(&path, &file[..file.len() - 1]).unwrap();
+            assert_eq!(read_capture(&path).unwrap_err().kind(), io::ErrorKind::InvalidData, "{:?}", format);
+        }
+    }
+
+    #[test]
+    fn test_server_records_traffic() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("server.cap");
+
+        let recorder = CaptureRecorder::create(&path, CaptureFormat::Binary).unwrap();
+        let server = UdpEchoServer::new(ServerConfig::new().port(0)).unwrap().with_recorder(recorder);
+        let server_addr = server.local_addr().unwrap();
+        let _handle = server.spawn();
+
+        let client = UdpSocket::bind("127.0.0.1:0").unwrap();
+        client.set_read_timeout(Some(Duration::from_secs(2))).unwrap();
+        let mut buffer = [0; 64];
+        for message in [&b"one"[..], b"two"] {
+            client.send_to(message, server_addr).unwrap();
+            client.recv_from(&mut buffer).unwrap();
+        }
+
+        // The outbound record is written right after the send
+        std::thread::sleep(Duration::from_millis(50));
+        let capture = read_capture(&path).unwrap();
+        let directions: Vec<_> = capture.iter().map(|d| d.direction).collect();
+        assert_eq!(directions, [Direction::Inbound, Direction::Outbound, Direction::Inbound, Direction::Outbound]);
+        assert!(capture.iter().all(|d| d.peer == client.local_addr().unwrap() && d.local == server_addr));
+
+        assert!(replay(&capture, &EchoHandler).is_clean());
+    }
+
+    #[test]
+    fn test_replay_reports_differences() {
+        struct Upper;
+
+        impl MessageHandler for Upper {
+            fn handle_message(&self, message: &[u8], _from: SocketAddr) -> Option<Vec<u8>> {
+                Some(message.to_ascii_uppercase())
+            }
+        }
+
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("capture.pcap");
+        write_sample(&path, CaptureFormat::Pcap);
+        let capture = read_capture(&path).unwrap();
+
+        let report = replay(&capture, &Upper);
+        assert_eq!(report.replayed, 2);
+        assert_eq!(report.mismatches.len(), 2);
+        assert_eq!(report.mismatches[0].index, 0);
+        assert_eq!(report.mismatches[0].recorded.as_deref(), Some(&b"ping"[..]));
+        assert_eq!(report.mismatches[0].replayed.as_deref(), Some(&b"PING"[..]));
+
+        let text = report.to_string();
+        assert!(text.contains("first difference at byte 0"), "{}", text);
+        assert!(text.contains("0x009f9296"), "{}", text);
+        assert!(text.ends_with("Replayed 2 requests, 2 mismatched"));
+    }
+}
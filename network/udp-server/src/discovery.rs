@@ -0,0 +1,224 @@
+// discovery.rs
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr, UdpSocket};
+use std::time::{Duration, Instant};
+
+use socket2::SockRef;
+
+/// A reply received during discovery
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DiscoveryResponse {
+    /// Unicast address of the responder
+    pub from: SocketAddr,
+    pub payload: Vec<u8>,
+    /// Time between sending the probe and receiving the reply
+    pub elapsed: Duration,
+}
+
+/// Service discovery on the local network
+///
+/// Sends one probe to a broadcast or multicast address and collects every
+/// reply that arrives within the window. Servers answer with a normal
+/// unicast response, so any [`MessageHandler`](crate::MessageHandler) that
+/// recognizes the probe can act as a responder.
+#[derive(Debug, Clone)]
+pub struct Discovery {
+    target: SocketAddr,
+    probe: Vec<u8>,
+    window: Duration,
+    multicast_ttl: Option<u32>,
+    interface_v4: Option<Ipv4Addr>,
+    buffer_size: usize,
+}
+
+impl Discovery {
+    /// Discover servers listening on a broadcast or multicast address
+    pub fn new(target: SocketAddr) -> Self {
+        Self {
+            target,
+            probe: b"DISCOVER".to_vec(),
+            window: Duration::from_secs(1),
+            multicast_ttl: None,
+            interface_v4: None,
+            buffer_size: 8192,
+        }
+    }
+
+    /// Set the probe payload (default: "DISCOVER")
+    pub fn probe<P: Into<Vec<u8>>>(mut self, probe: P) -> Self {
+        self.probe = probe.into();
+        self
+    }
+
+    /// Set how long replies are collected (default: 1s)
+    pub fn window(mut self, window: Duration) -> Self {
+        self.window = window;
+        self
+    }
+
+    /// Set the TTL or hop limit of a multicast probe (default: system default, usually 1)
+    pub fn multicast_ttl(mut self, ttl: u32) -> Self {
+        self.multicast_ttl = Some(ttl);
+        self
+    }
+
+    /// Send an IPv4 multicast probe through the interface with the given address
+    pub fn interface_v4(mut self, interface: Ipv4Addr) -> Self {
+        self.interface_v4 = Some(interface);
+        self
+    }
+
+    /// Set the receive buffer size (default: 8192)
+    pub fn buffer_size(mut self, size: usize) -> Self {
+        self.buffer_size = size;
+        self
+    }
+
+    /// Send the probe and collect replies until the window closes
+    pub fn run(&self) -> io::Result<Vec<DiscoveryResponse>> {
+        let bind_addr = if self.target.is_ipv4() { "0.0.0.0:0" } else { "[::]:0" };
+        let socket = UdpSocket::bind(bind_addr)?;
+
+        match self.target.ip() {
+            IpAddr::V4(ip) if ip.is_multicast() => {
+                if let Some(interface) = &self.interface_v4 {
+                    SockRef::from(&socket).set_multicast_if_v4(interface)?;
+                }
+                if let Some(ttl) = self.multicast_ttl {
+                    socket.set_multicast_ttl_v4(ttl)?;
+                }
+            }
+            IpAddr::V4(_) => socket.set_broadcast(true)?,
+            IpAddr::V6(_) => {
+                if let Some(hops) = self.multicast_ttl {
+                    SockRef::from(&socket).set_multicast_hops_v6(hops)?;
+                }
+            }
+        }
+
+        let started = Instant::now();
+        let deadline = started + self.window;
+        socket.send_to(&self.probe, self.target)?;
+
+        let mut responses = Vec::new();
+        let mut buffer = vec![0; self.buffer_size];
+        loop {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                break;
+            }
+            socket.set_read_timeout(Some(remaining))?;
+
+            match socket.recv_from(&mut buffer) {
+                Ok((size, from)) => responses.push(DiscoveryResponse {
+                    from,
+                    payload: buffer[..size].to_vec(),
+                    elapsed: started.elapsed(),
+                }),
+                Err(e) if matches!(e.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut) => break,
+                Err(e) => return Err(e),
+            }
+        }
+
+        Ok(responses)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{MessageHandler, ServerConfig, UdpEchoServer};
+    use std::net::Ipv6Addr;
+
+    /// Answers discovery probes with its name and ignores everything else
+    struct Responder(&'static str);
+
+    impl MessageHandler for Responder {
+        fn handle_message(&self, message: &[u8], _from: SocketAddr) -> Option<Vec<u8>> {
+            (message == b"DISCOVER").then(|| self.0.as_bytes().to_vec())
+        }
+    }
+
+    fn start_responder(config: ServerConfig, name: &'static str) -> u16 {
+        let server = UdpEchoServer::new(config.host("0.0.0.0").port(0)).unwrap();
+        let port = server.local_addr().unwrap().port();
+        let _handle = server.spawn_with_handler(Responder(name));
+        port
+    }
+
+    #[test]
+    fn test_multicast_discovery_on_loopback() {
+        let group = Ipv4Addr::new(239, 255, 71, 1);
+        let config = ServerConfig::new()
+            .join_multicast_v4(group, Ipv4Addr::LOCALHOST)
+            .multicast_loop(true)
+            .multicast_ttl(1);
+        let port = start_responder(config, "alpha");
+
+        let started = Instant::now();
+        let window = Duration::from_millis(300);
+        let responses = Discovery::new(SocketAddr::new(group.into(), port))
+            .interface_v4(Ipv4Addr::LOCALHOST)
+            .window(window)
+            .run()
+            .unwrap();
+
+        assert_eq!(responses.len(), 1);
+        assert_eq!(responses[0].payload, b"alpha");
+        assert_eq!(responses[0].from.port(), port);
+        assert!(started.elapsed() >= window);
+
+        // A different probe gets no answers
+        let responses = Discovery::new(SocketAddr::new(group.into(), port))
+            .interface_v4(Ipv4Addr::LOCALHOST)
+            .probe("HELLO?")
+            .window(Duration::from_millis(100))
+            .run()
+            .unwrap();
+        assert!(responses.is_empty());
+    }
+
+    #[test]
+    fn test_broadcast_discovery_on_loopback() {
+        let port = start_responder(ServerConfig::new().broadcast(true), "beta");
+
+        let responses = Discovery::new(SocketAddr::from(([127, 255, 255, 255], port)))
+            .window(Duration::from_millis(200))
+            .run()
+            .unwrap();
+
+        assert_eq!(responses.len(), 1);
+        assert_eq!(responses[0].payload, b"beta");
+    }
+
+    #[test]
+    fn test_multicast_options_are_validated_and_applied() {
+        let config = ServerConfig::new().join_multicast_v4(Ipv4Addr::new(10, 0, 0, 1), Ipv4Addr::LOCALHOST);
+        assert!(config.validate().is_err());
+        assert!(ServerConfig::new().multicast_ttl(256).validate().is_err());
+
+        let config = ServerConfig::new().port(0).multicast_ttl(3).multicast_loop(false).broadcast(true);
+        let server = UdpEchoServer::new(config).unwrap();
+        let socket = SockRef::from(&server.sockets[0]);
+        assert_eq!(socket.multicast_ttl_v4().unwrap(), 3);
+        assert!(!socket.multicast_loop_v4().unwrap());
+        assert!(socket.broadcast().unwrap());
+    }
+
+    #[test]
+    fn test_ipv6_multicast_options_are_applied() {
+        // Joining an IPv6 group only needs an IPv6 socket, not a route
+        let config = ServerConfig::new()
+            .host("::")
+            .port(0)
+            .join_multicast_v6("ff02::4242".parse::<Ipv6Addr>().unwrap(), 0)
+            .multicast_ttl(2)
+            .multicast_loop(false);
+        let server = UdpEchoServer::new(config).expect("this test needs IPv6 on the loopback interface");
+        assert!(server.local_addr().unwrap().is_ipv6());
+
+        let socket = SockRef::from(&server.sockets[0]);
+        assert_eq!(socket.multicast_hops_v6().unwrap(), 2);
+        assert!(!socket.multicast_loop_v6().unwrap());
+    }
+}
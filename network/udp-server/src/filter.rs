@@ -0,0 +1,344 @@
+// filter.rs
+use std::collections::hash_map::RandomState;
+use std::collections::HashMap;
+use std::fmt;
+use std::hash::BuildHasher;
+use std::net::{IpAddr, SocketAddr};
+use std::str::FromStr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::ServerConfig;
+
+/// Maximum number of sources the rate limiter tracks
+const MAX_TRACKED_SOURCES: usize = 65536;
+
+/// Number of separately locked parts of the rate limiter, so sources rarely contend
+const RATE_LIMIT_SHARDS: usize = 16;
+
+/// How often each part forgets the sources whose buckets have refilled
+const SWEEP_INTERVAL: Duration = Duration::from_secs(10);
+
+/// An IPv4 or IPv6 network in CIDR notation, e.g. `10.0.0.0/8` or `fd00::/8`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cidr {
+    addr: IpAddr,
+    prefix_len: u8,
+}
+
+impl Cidr {
+    /// Create a network from an address and a prefix length
+    pub fn new(addr: IpAddr, prefix_len: u8) -> Result<Self, String> {
+        let max_len = match addr {
+            IpAddr::V4(_) => 32,
+            IpAddr::V6(_) => 128,
+        };
+        if prefix_len > max_len {
+            return Err(format!("Prefix length {} is too long for {}", prefix_len, addr));
+        }
+        Ok(Self { addr, prefix_len })
+    }
+
+    /// Check whether an address belongs to this network
+    ///
+    /// IPv4-mapped IPv6 addresses (`::ffff:a.b.c.d`) match IPv4 networks.
+    pub fn contains(&self, ip: &IpAddr) -> bool {
+        let ip = match ip {
+            IpAddr::V6(v6) => v6.to_ipv4_mapped().map(IpAddr::V4).unwrap_or(*ip),
+            IpAddr::V4(_) => *ip,
+        };
+
+        match (self.addr, ip) {
+            (IpAddr::V4(net), IpAddr::V4(ip)) => {
+                let mask = u32::MAX.checked_shl(32 - self.prefix_len as u32).unwrap_or(0);
+                u32::from(net) & mask == u32::from(ip) & mask
+            }
+            (IpAddr::V6(net), IpAddr::V6(ip)) => {
+                let mask = u128::MAX.checked_shl(128 - self.prefix_len as u32).unwrap_or(0);
+                u128::from(net) & mask == u128::from(ip) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+impl FromStr for Cidr {
+    type Err = String;
+
+    /// Parse `addr/prefix`; a bare address is a single-host network
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (addr, prefix_len) = match s.split_once('/') {
+            Some((addr, len)) => (addr, Some(len)),
+            None => (s, None),
+        };
+
+        let addr: IpAddr = addr.trim().parse()
+            .map_err(|e| format!("Invalid network address '{}': {}", s, e))?;
+        let prefix_len = match prefix_len {
+            Some(len) => len.trim().parse()
+                .map_err(|e| format!("Invalid prefix length in '{}': {}", s, e))?,
+            None if addr.is_ipv4() => 32,
+            None => 128,
+        };
+
+        Cidr::new(addr, prefix_len)
+    }
+}
+
+impl fmt::Display for Cidr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.addr, self.prefix_len)
+    }
+}
+
+/// What a rate limit bucket is keyed by
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum RateLimitKey {
+    /// One bucket per source IP address (default)
+    #[default]
+    SourceIp,
+    /// One bucket per source IP address and port
+    SourceAddr,
+}
+
+/// Token bucket rate limit applied to every source
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RateLimit {
+    /// Tokens added to a bucket per second
+    pub per_second: f64,
+    /// Maximum number of tokens in a bucket
+    pub burst: u32,
+}
+
+/// Why a datagram was rejected before reaching the handler
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RejectReason {
+    /// The source matched the deny list or missed the allow list
+    Denied,
+    /// The source exceeded its rate limit
+    RateLimited,
+}
+
+impl fmt::Display for RejectReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RejectReason::Denied => write!(f, "denied by access list"),
+            RejectReason::RateLimited => write!(f, "rate limited"),
+        }
+    }
+}
+
+struct TokenBucket {
+    tokens: f64,
+    last_refill: Instant,
+}
+
+struct Shard {
+    buckets: HashMap<SocketAddr, TokenBucket>,
+    next_sweep: Instant,
+}
+
+impl Shard {
+    /// Forget the sources whose buckets have refilled completely
+    fn sweep(&mut self, now: Instant, limit: RateLimit) {
+        let burst = limit.burst as f64;
+        self.buckets.retain(|_, bucket| {
+            bucket.tokens + now.duration_since(bucket.last_refill).as_secs_f64() * limit.per_second < burst
+        });
+        self.next_sweep = now + SWEEP_INTERVAL;
+    }
+}
+
+/// Token buckets per source, split into shards by a hash of the source
+///
+/// Each shard holds at most its part of [`MAX_TRACKED_SOURCES`]. Buckets that
+/// have refilled are dropped every [`SWEEP_INTERVAL`], since a new bucket
+/// starts full anyway; when a shard is still full, an arbitrary source is
+/// forgotten to make room.
+pub(crate) struct RateLimiter {
+    limit: RateLimit,
+    key: RateLimitKey,
+    hasher: RandomState,
+    shards: Vec<Mutex<Shard>>,
+}
+
+impl RateLimiter {
+    pub(crate) fn new(limit: RateLimit, key: RateLimitKey) -> Self {
+        let now = Instant::now();
+        Self {
+            limit,
+            key,
+            hasher: RandomState::new(),
+            shards: (0..RATE_LIMIT_SHARDS)
+                .map(|_| Mutex::new(Shard { buckets: HashMap::new(), next_sweep: now + SWEEP_INTERVAL }))
+                .collect(),
+        }
+    }
+
+    pub(crate) fn allow(&self, from: SocketAddr) -> bool {
+        // IPv4 peers of a dual-stack socket arrive as IPv4-mapped IPv6 addresses
+        let ip = from.ip().to_canonical();
+        let key = match self.key {
+            RateLimitKey::SourceIp => SocketAddr::new(ip, 0),
+            RateLimitKey::SourceAddr => SocketAddr::new(ip, from.port()),
+        };
+        let burst = self.limit.burst as f64;
+        let per_second = self.limit.per_second;
+        let now = Instant::now();
+
+        let shard = &self.shards[self.hasher.hash_one(key) as usize % self.shards.len()];
+        let mut shard = shard.lock().unwrap();
+        if now >= shard.next_sweep {
+            shard.sweep(now, self.limit);
+        }
+        if shard.buckets.len() >= MAX_TRACKED_SOURCES / RATE_LIMIT_SHARDS && !shard.buckets.contains_key(&key) {
+            if let Some(evicted) = shard.buckets.keys().next().copied() {
+                shard.buckets.remove(&evicted);
+            }
+        }
+
+        let bucket = shard.buckets.entry(key).or_insert(TokenBucket {
+            tokens: burst,
+            last_refill: now,
+        });
+
+        let elapsed = now.duration_since(bucket.last_refill).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * per_second).min(burst);
+        bucket.last_refill = now;
+
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+/// Access lists and rate limiting applied before a datagram reaches the handler
+pub(crate) struct PacketFilter {
+    allow: Vec<Cidr>,
+    deny: Vec<Cidr>,
+    limiter: Option<RateLimiter>,
+}
+
+impl PacketFilter {
+    pub(crate) fn from_config(config: &ServerConfig) -> Self {
+        Self {
+            allow: config.allow.clone(),
+            deny: config.deny.clone(),
+            limiter: config.rate_limit.map(|limit| RateLimiter::new(limit, config.rate_limit_key)),
+        }
+    }
+
+    /// Check a source against the deny list, the allow list and its rate limit
+    pub(crate) fn check(&self, from: SocketAddr) -> Result<(), RejectReason> {
+        let ip = from.ip();
+
+        if self.deny.iter().any(|net| net.contains(&ip)) {
+            return Err(RejectReason::Denied);
+        }
+
+        if !self.allow.is_empty() && !self.allow.iter().any(|net| net.contains(&ip)) {
+            return Err(RejectReason::Denied);
+        }
+
+        match &self.limiter {
+            Some(limiter) if !limiter.allow(from) => Err(RejectReason::RateLimited),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn addr(s: &str) -> SocketAddr {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn test_cidr_parsing_and_matching() {
+        let net: Cidr = "10.1.0.0/16".parse().unwrap();
+        assert!(net.contains(&"10.1.200.3".parse().unwrap()));
+        assert!(!net.contains(&"10.2.0.1".parse().unwrap()));
+        assert!(net.contains(&"::ffff:10.1.0.1".parse().unwrap()));
+
+        let host: Cidr = "192.168.1.1".parse().unwrap();
+        assert_eq!(host.to_string(), "192.168.1.1/32");
+
+        let v6: Cidr = "fd00::/8".parse().unwrap();
+        assert!(v6.contains(&"fd12::1".parse().unwrap()));
+        assert!(!v6.contains(&"10.0.0.1".parse().unwrap()));
+
+        let any: Cidr = "0.0.0.0/0".parse().unwrap();
+        assert!(any.contains(&"8.8.8.8".parse().unwrap()));
+
+        assert!("10.0.0.0/33".parse::<Cidr>().is_err());
+        assert!("not-an-ip/8".parse::<Cidr>().is_err());
+    }
+
+    #[test]
+    fn test_deny_takes_precedence_over_allow() {
+        let config = ServerConfig::new()
+            .allow_cidr("10.0.0.0/8".parse().unwrap())
+            .deny_cidr("10.0.0.66/32".parse().unwrap());
+        let filter = PacketFilter::from_config(&config);
+
+        assert_eq!(filter.check(addr("10.0.0.1:5000")), Ok(()));
+        assert_eq!(filter.check(addr("10.0.0.66:5000")), Err(RejectReason::Denied));
+        assert_eq!(filter.check(addr("192.168.0.1:5000")), Err(RejectReason::Denied));
+    }
+
+    #[test]
+    fn test_token_bucket_per_source() {
+        let config = ServerConfig::new().rate_limit(0.001, 2);
+        let filter = PacketFilter::from_config(&config);
+
+        assert_eq!(filter.check(addr("10.0.0.1:1000")), Ok(()));
+        assert_eq!(filter.check(addr("10.0.0.1:2000")), Ok(()));
+        assert_eq!(filter.check(addr("10.0.0.1:3000")), Err(RejectReason::RateLimited));
+        assert_eq!(filter.check(addr("10.0.0.2:1000")), Ok(()));
+
+        let config = ServerConfig::new()
+            .rate_limit(0.001, 1)
+            .rate_limit_key(RateLimitKey::SourceAddr);
+        let filter = PacketFilter::from_config(&config);
+
+        assert_eq!(filter.check(addr("10.0.0.1:1000")), Ok(()));
+        assert_eq!(filter.check(addr("10.0.0.1:2000")), Ok(()));
+        assert_eq!(filter.check(addr("10.0.0.1:1000")), Err(RejectReason::RateLimited));
+    }
+
+    #[test]
+    fn test_rate_limiter_bounds() {
+        let limiter = RateLimiter::new(RateLimit { per_second: 0.001, burst: 1 }, RateLimitKey::SourceIp);
+
+        // An IPv4 peer seen through a dual-stack socket shares the IPv4 bucket
+        assert!(limiter.allow(addr("10.0.0.1:1000")));
+        assert!(!limiter.allow(addr("[::ffff:10.0.0.1]:2000")));
+
+        // A flood of new sources cannot grow the table past its cap
+        for i in 0..2 * MAX_TRACKED_SOURCES as u32 {
+            limiter.allow(SocketAddr::new(IpAddr::V4(i.into()), 1000));
+        }
+        let tracked: usize = limiter.shards.iter().map(|shard| shard.lock().unwrap().buckets.len()).sum();
+        assert!(tracked <= MAX_TRACKED_SOURCES, "{} sources tracked", tracked);
+
+        // The periodic sweep drops buckets once they have refilled, spent tokens or not
+        let limit = RateLimit { per_second: 10.0, burst: 2 };
+        let limiter = RateLimiter::new(limit, RateLimitKey::SourceIp);
+        assert!(limiter.allow(addr("10.0.0.1:1000")));
+        assert!(limiter.allow(addr("10.0.0.1:1000")));
+        assert!(limiter.allow(addr("10.0.0.2:1000")));
+        let now = Instant::now();
+        for shard in &limiter.shards {
+            let mut shard = shard.lock().unwrap();
+            shard.sweep(now, limit);
+            shard.sweep(now + Duration::from_secs(1), limit);
+        }
+        let tracked: usize = limiter.shards.iter().map(|shard| shard.lock().unwrap().buckets.len()).sum();
+        assert_eq!(tracked, 0);
+    }
+}
@@ -0,0 +1,58 @@
+// handlers.rs
+use std::net::SocketAddr;
+
+use crate::MessageHandler;
+
+/// Replies with the message in upper case
+///
+/// UTF-8 text is converted with full Unicode rules, other payloads byte by
+/// byte as ASCII.
+pub struct UppercaseHandler;
+
+impl MessageHandler for UppercaseHandler {
+    fn handle_message(&self, message: &[u8], _from: SocketAddr) -> Option<Vec<u8>> {
+        match std::str::from_utf8(message) {
+            Ok(text) => Some(text.to_uppercase().into_bytes()),
+            Err(_) => Some(message.to_ascii_uppercase()),
+        }
+    }
+}
+
+/// Replies with the message reversed
+///
+/// UTF-8 text is reversed by characters, other payloads by bytes.
+pub struct ReverseHandler;
+
+impl MessageHandler for ReverseHandler {
+    fn handle_message(&self, message: &[u8], _from: SocketAddr) -> Option<Vec<u8>> {
+        match std::str::from_utf8(message) {
+            Ok(text) => Some(text.chars().rev().collect::<String>().into_bytes()),
+            Err(_) => Some(message.iter().rev().copied().collect()),
+        }
+    }
+}
+
+/// Accepts every message and never replies, like the discard service (RFC 863)
+pub struct DiscardHandler;
+
+impl MessageHandler for DiscardHandler {
+    fn handle_message(&self, _message: &[u8], _from: SocketAddr) -> Option<Vec<u8>> {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_builtin_handlers() {
+        let from: SocketAddr = "127.0.0.1:9".parse().unwrap();
+
+        assert_eq!(UppercaseHandler.handle_message("grüße".as_bytes(), from).unwrap(), "GRÜSSE".as_bytes());
+        assert_eq!(UppercaseHandler.handle_message(b"ab\xff", from).unwrap(), b"AB\xff");
+        assert_eq!(ReverseHandler.handle_message("añb".as_bytes(), from).unwrap(), "bña".as_bytes());
+        assert_eq!(ReverseHandler.handle_message(b"\x01\xff", from).unwrap(), b"\xff\x01");
+        assert_eq!(DiscardHandler.handle_message(b"anything", from), None);
+    }
+}
@@ -0,0 +1,515 @@
+// jsonrpc.rs
+use std::collections::HashMap;
+use std::fmt;
+use std::future::Future;
+use std::io;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_json::{json, Value};
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream, ToSocketAddrs};
+use tokio::task::JoinSet;
+
+use crate::MessageHandler;
+
+/// Pause after a failed accept, e.g. when the process is out of file descriptors
+const ACCEPT_BACKOFF: Duration = Duration::from_millis(100);
+
+/// Most requests in one batch; a longer batch gets a single Invalid Request
+const MAX_BATCH_LEN: usize = 100;
+
+/// Largest response to a datagram, as a multiple of the request size, so the
+/// handler cannot amplify traffic reflected at a spoofed source
+const MAX_UDP_AMPLIFICATION: usize = 3;
+
+/// JSON-RPC 2.0 error object
+#[derive(Debug, Clone, PartialEq)]
+pub struct JsonRpcError {
+    pub code: i64,
+    pub message: String,
+    pub data: Option<Value>,
+}
+
+impl JsonRpcError {
+    /// Invalid JSON was received
+    pub const PARSE_ERROR: i64 = -32700;
+    /// The JSON sent is not a valid request object
+    pub const INVALID_REQUEST: i64 = -32600;
+    /// The method does not exist
+    pub const METHOD_NOT_FOUND: i64 = -32601;
+    /// Invalid method parameters
+    pub const INVALID_PARAMS: i64 = -32602;
+    /// Internal JSON-RPC error
+    pub const INTERNAL_ERROR: i64 = -32603;
+
+    /// Create an error with a code and message
+    pub fn new<S: Into<String>>(code: i64, message: S) -> Self {
+        Self {
+            code,
+            message: message.into(),
+            data: None,
+        }
+    }
+
+    /// Attach additional data to the error
+    pub fn with_data(mut self, data: Value) -> Self {
+        self.data = Some(data);
+        self
+    }
+
+    /// Error for parameters a method cannot use
+    pub fn invalid_params<S: Into<String>>(message: S) -> Self {
+        Self::new(Self::INVALID_PARAMS, message)
+    }
+
+    /// Error for a failure inside the server
+    pub fn internal<S: Into<String>>(message: S) -> Self {
+        Self::new(Self::INTERNAL_ERROR, message)
+    }
+
+    /// Application error in the reserved server range, `-32000` to `-32099`
+    ///
+    /// `offset` is clamped to `0..=99` and subtracted from `-32000`.
+    pub fn server_error<S: Into<String>>(offset: i64, message: S) -> Self {
+        Self::new(-32000 - offset.clamp(0, 99), message)
+    }
+
+    fn invalid_request() -> Self {
+        Self::new(Self::INVALID_REQUEST, "Invalid Request")
+    }
+
+    fn to_value(&self) -> Value {
+        let mut error = json!({ "code": self.code, "message": self.message });
+        if let Some(data) = &self.data {
+            error["data"] = data.clone();
+        }
+        error
+    }
+}
+
+impl fmt::Display for JsonRpcError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "JSON-RPC error {}: {}", self.code, self.message)
+    }
+}
+
+impl std::error::Error for JsonRpcError {}
+
+type Method = Box<dyn Fn(Value) -> Result<Value, JsonRpcError> + Send + Sync>;
+
+/// JSON-RPC 2.0 method registry and dispatcher
+///
+/// Methods are registered with typed parameters and results that are
+/// converted with serde. Requests may be single objects or batches; requests
+/// without an `id` are notifications and get no response. The same handler
+/// serves datagrams as a [`MessageHandler`] and TCP connections through
+/// [`JsonRpcTcpServer`].
+pub struct JsonRpcHandler {
+    methods: HashMap<String, Method>,
+}
+
+impl JsonRpcHandler {
+    /// Create a handler without methods
+    pub fn new() -> Self {
+        Self {
+            methods: HashMap::new(),
+        }
+    }
+
+    /// Create a handler with the `echo`, `ping`, `uppercase` and `reverse` methods
+    pub fn with_builtin_methods() -> Self {
+        Self::new()
+            .method("echo", |params: Value| Ok(params))
+            .method("ping", |_: ()| Ok("pong"))
+            .method("uppercase", |text: String| Ok(text.to_uppercase()))
+            .method("reverse", |text: String| Ok(text.chars().rev().collect::<String>()))
+    }
+
+    /// Register a method with typed parameters and result
+    ///
+    /// Positional parameters deserialize into tuples or sequences, named
+    /// parameters into structs, and absent parameters into `()` or `Option`.
+    /// A single positional parameter also deserializes into a plain value,
+    /// so `["text"]` works for a `String` parameter.
+    pub fn method<P, R, F>(mut self, name: &str, method: F) -> Self
+    where
+        P: DeserializeOwned,
+        R: Serialize,
+        F: Fn(P) -> Result<R, JsonRpcError> + Send + Sync + 'static,
+    {
+        let method = move |params: Value| {
+            let params = parse_params::<P>(params)?;
+            let result = method(params)?;
+            serde_json::to_value(result)
+                .map_err(|e| JsonRpcError::internal(format!("Failed to encode result: {}", e)))
+        };
+        self.methods.insert(name.to_string(), Box::new(method));
+        self
+    }
+
+    /// Get the names of the registered methods
+    pub fn method_names(&self) -> Vec<&str> {
+        let mut names: Vec<&str> = self.methods.keys().map(String::as_str).collect();
+        names.sort_unstable();
+        names
+    }
+
+    /// Handle one encoded request or batch and return the encoded response
+    ///
+    /// Returns `None` when there is nothing to answer, i.e. for notifications
+    /// and batches made up of notifications only. Empty batches and batches
+    /// of more than 100 requests are answered with a single
+    /// Invalid Request error.
+    pub fn handle_request(&self, request: &[u8]) -> Option<Vec<u8>> {
+        let response = match serde_json::from_slice::<Value>(request) {
+            Ok(Value::Array(batch)) if batch.is_empty() || batch.len() > MAX_BATCH_LEN => {
+                Some(error_response(Value::Null, JsonRpcError::invalid_request()))
+            }
+            Ok(Value::Array(batch)) => {
+                let responses: Vec<Value> = batch.into_iter().filter_map(|request| self.call(request)).collect();
+                (!responses.is_empty()).then_some(Value::Array(responses))
+            }
+            Ok(request) => self.call(request),
+            Err(e) => Some(error_response(Value::Null, JsonRpcError::new(JsonRpcError::PARSE_ERROR, e.to_string()))),
+        };
+        response.map(|response| response.to_string().into_bytes())
+    }
+
+    /// Run one request object; `None` for notifications
+    fn call(&self, request: Value) -> Option<Value> {
+        let mut request = match request {
+            Value::Object(request) => request,
+            _ => return Some(error_response(Value::Null, JsonRpcError::invalid_request())),
+        };
+
+        // A missing id makes a notification; an explicit null id still gets a response
+        let id = request.remove("id");
+        let valid_id = matches!(id, None | Some(Value::Null | Value::Number(_) | Value::String(_)));
+        let params = request.remove("params").unwrap_or(Value::Null);
+        let method = match (request.get("jsonrpc"), request.get("method")) {
+            (Some(Value::String(version)), Some(Value::String(method)))
+                if version == "2.0" && valid_id && matches!(params, Value::Null | Value::Array(_) | Value::Object(_)) =>
+            {
+                method
+            }
+            _ => {
+                let id = if valid_id { id.unwrap_or(Value::Null) } else { Value::Null };
+                return Some(error_response(id, JsonRpcError::invalid_request()));
+            }
+        };
+
+        let result = match self.methods.get(method.as_str()) {
+            Some(method) => method(params),
+            None => Err(JsonRpcError::new(JsonRpcError::METHOD_NOT_FOUND, format!("Method not found: {}", method))),
+        };
+
+        let id = id?;
+        Some(match result {
+            Ok(result) => json!({ "jsonrpc": "2.0", "result": result, "id": id }),
+            Err(error) => error_response(id, error),
+        })
+    }
+}
+
+impl Default for JsonRpcHandler {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Datagram responses more than three times the size of the request are
+/// dropped and reported through `on_error`; use TCP for those
+impl MessageHandler for JsonRpcHandler {
+    fn handle_message(&self, message: &[u8], from: SocketAddr) -> Option<Vec<u8>> {
+        let response = self.handle_request(message)?;
+        if response.len() > message.len().saturating_mul(MAX_UDP_AMPLIFICATION) {
+            self.on_error(
+                &format!("Dropping a {} byte response to a {} byte request", response.len(), message.len()),
+                Some(from),
+            );
+            return None;
+        }
+        Some(response)
+    }
+}
+
+fn error_response(id: Value, error: JsonRpcError) -> Value {
+    json!({ "jsonrpc": "2.0", "error": error.to_value(), "id": id })
+}
+
+fn parse_params<P: DeserializeOwned>(params: Value) -> Result<P, JsonRpcError> {
+    match serde_json::from_value(params.clone()) {
+        Ok(params) => Ok(params),
+        Err(e) => match params {
+            Value::Array(mut items) if items.len() == 1 => {
+                serde_json::from_value(items.remove(0)).map_err(|_| JsonRpcError::invalid_params(e.to_string()))
+            }
+            _ => Err(JsonRpcError::invalid_params(e.to_string())),
+        },
+    }
+}
+
+/// Serves a [`JsonRpcHandler`] over TCP, one JSON request or batch per line
+///
+/// Each connection runs on its own task, like the tokio echo server; method
+/// calls run on the blocking thread pool. Responses are written as one line
+/// each, in request order. Lines longer than `max_line_length` close the
+/// connection.
+pub struct JsonRpcTcpServer {
+    listener: TcpListener,
+    handler: Arc<JsonRpcHandler>,
+    max_line_length: usize,
+}
+
+impl JsonRpcTcpServer {
+    /// Bind a listener for the given handler
+    pub async fn bind<A: ToSocketAddrs>(addr: A, handler: Arc<JsonRpcHandler>) -> io::Result<Self> {
+        Ok(Self {
+            listener: TcpListener::bind(addr).await?,
+            handler,
+            max_line_length: 1024 * 1024,
+        })
+    }
+
+    /// Set the maximum length of a request line in bytes (default: 1 MiB)
+    pub fn max_line_length(mut self, length: usize) -> Self {
+        self.max_line_length = length;
+        self
+    }
+
+    /// Get the local address the server is bound to
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    /// Accept connections until `signal` resolves, then close all open connections
+    ///
+    /// A failed accept is reported through the handler's `on_error` and
+    /// retried after a short pause; it does not stop the server.
+    pub async fn run_until<F>(&self, signal: F) -> io::Result<()>
+    where
+        F: Future<Output = ()>,
+    {
+        let mut connections = JoinSet::new();
+        tokio::pin!(signal);
+
+        loop {
+            tokio::select! {
+                _ = &mut signal => break,
+                accepted = self.listener.accept() => {
+                    let (socket, peer) = match accepted {
+                        Ok(accepted) => accepted,
+                        Err(e) => {
+                            self.handler.on_error(&format!("JSON-RPC accept failed: {}", e), None);
+                            tokio::select! {
+                                _ = &mut signal => break,
+                                _ = tokio::time::sleep(ACCEPT_BACKOFF) => continue,
+                            }
+                        }
+                    };
+                    let handler = Arc::clone(&self.handler);
+                    let max_line_length = self.max_line_length;
+
+                    connections.spawn(async move {
+                        if let Err(e) = serve_connection(socket, handler, max_line_length).await {
+                            eprintln!("JSON-RPC connection {} failed: {}", peer, e);
+                        }
+                    });
+                }
+                // Reap finished connections so the set does not grow without bound
+                Some(_) = connections.join_next(), if !connections.is_empty() => {}
+            }
+        }
+
+        connections.shutdown().await;
+        Ok(())
+    }
+
+    /// Accept connections forever
+    pub async fn run(&self) -> io::Result<()> {
+        self.run_until(std::future::pending()).await
+    }
+}
+
+async fn serve_connection(socket: TcpStream, handler: Arc<JsonRpcHandler>, max_line_length: usize) -> io::Result<()> {
+    let (reader, mut writer) = socket.into_split();
+    let mut reader = BufReader::new(reader);
+    let mut line = Vec::new();
+
+    loop {
+        line.clear();
+        let read = (&mut reader).take(max_line_length as u64 + 1).read_until(b'\n', &mut line).await?;
+        if read == 0 {
+            return Ok(());
+        }
+        if line.len() > max_line_length {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "Request line too long"));
+        }
+        if line.iter().all(u8::is_ascii_whitespace) {
+            continue;
+        }
+
+        let handler = Arc::clone(&handler);
+        let request = std::mem::take(&mut line);
+        let response = tokio::task::spawn_blocking(move || handler.handle_request(&request))
+            .await
+            .map_err(io::Error::other)?;
+
+        if let Some(mut response) = response {
+            response.push(b'\n');
+            writer.write_all(&response).await?;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::Deserialize;
+
+    #[derive(Deserialize)]
+    struct Divide {
+        dividend: f64,
+        divisor: f64,
+    }
+
+    fn calculator() -> JsonRpcHandler {
+        JsonRpcHandler::with_builtin_methods()
+            .method("add", |(a, b): (i64, i64)| Ok(a + b))
+            .method("divide", |p: Divide| {
+                if p.divisor == 0.0 {
+                    return Err(JsonRpcError::server_error(1, "Division by zero").with_data(json!(p.dividend)));
+                }
+                Ok(p.dividend / p.divisor)
+            })
+    }
+
+    fn call(handler: &JsonRpcHandler, request: &str) -> Value {
+        let response = handler.handle_request(request.as_bytes()).unwrap();
+        serde_json::from_slice(&response).unwrap()
+    }
+
+    #[test]
+    fn test_typed_methods_and_errors() {
+        let handler = calculator();
+
+        let response = call(&handler, r#"{"jsonrpc":"2.0","method":"add","params":[2,3],"id":1}"#);
+        assert_eq!(response, json!({"jsonrpc": "2.0", "result": 5, "id": 1}));
+
+        let response = call(&handler, r#"{"jsonrpc":"2.0","method":"divide","params":{"dividend":1,"divisor":4},"id":"d"}"#);
+        assert_eq!(response["result"], 0.25);
+
+        let response = call(&handler, r#"{"jsonrpc":"2.0","method":"divide","params":{"dividend":1,"divisor":0},"id":2}"#);
+        assert_eq!(response["error"], json!({"code": -32001, "message": "Division by zero", "data": 1.0}));
+
+        let response = call(&handler, r#"{"jsonrpc":"2.0","method":"uppercase","params":["abc"],"id":3}"#);
+        assert_eq!(response["result"], "ABC");
+
+        let response = call(&handler, r#"{"jsonrpc":"2.0","method":"add","params":["x",1],"id":4}"#);
+        assert_eq!(response["error"]["code"], JsonRpcError::INVALID_PARAMS);
+
+        let response = call(&handler, r#"{"jsonrpc":"2.0","method":"nope","id":null}"#);
+        assert_eq!(response["error"]["code"], JsonRpcError::METHOD_NOT_FOUND);
+        assert_eq!(response["id"], Value::Null);
+
+        for invalid in [r#"{"method":"ping","id":5}"#, r#"{"jsonrpc":"2.0","method":"ping","params":7,"id":6}"#, "42"] {
+            assert_eq!(call(&handler, invalid)["error"]["code"], JsonRpcError::INVALID_REQUEST, "{}", invalid);
+        }
+
+        let response = call(&handler, "{not json");
+        assert_eq!(response["error"]["code"], JsonRpcError::PARSE_ERROR);
+        assert_eq!(response["id"], Value::Null);
+    }
+
+    #[test]
+    fn test_batches_and_notifications() {
+        let handler = calculator();
+
+        // Notifications run but get no response
+        assert_eq!(handler.handle_request(br#"{"jsonrpc":"2.0","method":"add","params":[1,1]}"#), None);
+        assert_eq!(handler.handle_request(br#"[{"jsonrpc":"2.0","method":"ping"}]"#), None);
+
+        let response = call(&handler, r#"[
+            {"jsonrpc":"2.0","method":"add","params":[1,2],"id":1},
+            {"jsonrpc":"2.0","method":"ping"},
+            {"jsonrpc":"2.0","method":"missing","id":2},
+            1
+        ]"#);
+        let responses = response.as_array().unwrap();
+        assert_eq!(responses.len(), 3);
+        assert_eq!(responses[0]["result"], 3);
+        assert_eq!(responses[1]["error"]["code"], JsonRpcError::METHOD_NOT_FOUND);
+        assert_eq!(responses[2]["error"]["code"], JsonRpcError::INVALID_REQUEST);
+
+        assert_eq!(call(&handler, "[]")["error"]["code"], JsonRpcError::INVALID_REQUEST);
+
+        // An oversized batch is not run and gets one error, not one per request
+        let batch = format!("[{}]", vec![r#"{"jsonrpc":"2.0","method":"ping","id":1}"#; MAX_BATCH_LEN + 1].join(","));
+        let response = call(&handler, &batch);
+        assert_eq!(response["error"]["code"], JsonRpcError::INVALID_REQUEST);
+        assert_eq!(response["id"], Value::Null);
+    }
+
+    #[test]
+    fn test_udp_responses_do_not_amplify() {
+        let handler = calculator();
+        let from = SocketAddr::from(([127, 0, 0, 1], 9));
+
+        // A spoofed batch of tiny invalid requests would get ~80 bytes back per 2 sent
+        let batch = format!("[{}]", vec!["1"; MAX_BATCH_LEN].join(","));
+        assert!(handler.handle_request(batch.as_bytes()).unwrap().len() > batch.len() * MAX_UDP_AMPLIFICATION);
+        assert_eq!(handler.handle_message(batch.as_bytes(), from), None);
+
+        let request = br#"{"jsonrpc":"2.0","method":"add","params":[2,3],"id":1}"#;
+        assert!(handler.handle_message(request, from).is_some());
+    }
+
+    #[tokio::test]
+    async fn test_udp_and_tcp_share_the_registry() {
+        let handler = Arc::new(calculator());
+
+        let udp = crate::UdpEchoServer::new(crate::ServerConfig::new().port(0)).unwrap();
+        let udp_addr = udp.local_addr().unwrap();
+        let _udp = udp.spawn_with_handler(Arc::clone(&handler));
+
+        let client = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+        client.set_read_timeout(Some(std::time::Duration::from_secs(2))).unwrap();
+        client.send_to(br#"{"jsonrpc":"2.0","method":"add","params":[20,22],"id":1}"#, udp_addr).unwrap();
+        let mut buffer = [0; 1024];
+        let (size, _) = client.recv_from(&mut buffer).unwrap();
+        assert_eq!(serde_json::from_slice::<Value>(&buffer[..size]).unwrap()["result"], 42);
+
+        let tcp = JsonRpcTcpServer::bind("127.0.0.1:0", handler).await.unwrap().max_line_length(256);
+        let tcp_addr = tcp.local_addr().unwrap();
+        let (stop, stopped) = tokio::sync::oneshot::channel::<()>();
+        let server = tokio::spawn(async move {
+            tcp.run_until(async {
+                let _ = stopped.await;
+            })
+            .await
+        });
+
+        let stream = TcpStream::connect(tcp_addr).await.unwrap();
+        let (reader, mut writer) = stream.into_split();
+        let mut lines = BufReader::new(reader).lines();
+        writer.write_all(b"{\"jsonrpc\":\"2.0\",\"method\":\"ping\"}\n\n").await.unwrap();
+        writer.write_all(b"{\"jsonrpc\":\"2.0\",\"method\":\"add\",\"params\":[1,2],\"id\":7}\n").await.unwrap();
+        writer.write_all(b"[{\"jsonrpc\":\"2.0\",\"method\":\"reverse\",\"params\":[\"ab\"],\"id\":8}]\n").await.unwrap();
+
+        let first: Value = serde_json::from_str(&lines.next_line().await.unwrap().unwrap()).unwrap();
+        assert_eq!(first, json!({"jsonrpc": "2.0", "result": 3, "id": 7}));
+        let second: Value = serde_json::from_str(&lines.next_line().await.unwrap().unwrap()).unwrap();
+        assert_eq!(second[0]["result"], "ba");
+
+        // An overlong line closes the connection
+        writer.write_all(&[b'x'; 300]).await.unwrap();
+        assert_eq!(lines.next_line().await.unwrap(), None);
+
+        stop.send(()).unwrap();
+        server.await.unwrap().unwrap();
+    }
+}
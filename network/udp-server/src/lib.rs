@@ -1,10 +1,54 @@
 // lib.rs
-use std::net::{UdpSocket, SocketAddr};
+use std::collections::HashMap;
+use std::net::{Ipv4Addr, Ipv6Addr, UdpSocket, SocketAddr};
 use std::io;
 use std::sync::Arc;
 use std::thread;
 use std::time::{Duration, Instant};
 
+#[cfg(feature = "async")]
+mod async_server;
+mod capture;
+mod discovery;
+mod filter;
+mod handlers;
+#[cfg(feature = "jsonrpc")]
+mod jsonrpc;
+mod metrics;
+mod protocol;
+mod reload;
+#[cfg(feature = "secure")]
+mod secure;
+mod session;
+mod socket;
+mod stats;
+
+#[cfg(feature = "async")]
+pub use async_server::{AsyncMessageHandler, AsyncUdpServer, BoxError, ShutdownHandle};
+pub use capture::{
+    read_capture, replay, CaptureFormat, CaptureRecorder, CapturedDatagram, Direction, ReplayMismatch, ReplayReport,
+};
+pub use discovery::{Discovery, DiscoveryResponse};
+pub use filter::{Cidr, RateLimit, RateLimitKey, RejectReason};
+pub use handlers::{DiscardHandler, ReverseHandler, UppercaseHandler};
+#[cfg(feature = "jsonrpc")]
+pub use jsonrpc::{JsonRpcError, JsonRpcHandler, JsonRpcTcpServer};
+#[cfg(feature = "json")]
+pub use metrics::JsonReporter;
+pub use metrics::{
+    ClientStats, LatencyHistogram, PrometheusReporter, StatsReporter, StdoutReporter, LATENCY_BUCKETS,
+};
+pub use protocol::{
+    ClientError, Flags, Header, ProtocolError, ReliableHandler, RetryPolicy, UdpClient, HEADER_LEN,
+};
+pub use reload::ReloadHandle;
+#[cfg(feature = "secure")]
+pub use secure::{SecureClient, SecureError, SecureHandler, REPLAY_WINDOW};
+pub use session::{Session, SessionEndReason, SessionHandler, SessionLayer};
+pub use stats::StatsCollector;
+
+use reload::SharedRuntime;
+
 /// Configuration for the UDP echo server
 #[derive(Debug, Clone)]
 pub struct ServerConfig {
@@ -22,6 +66,43 @@ pub struct ServerConfig {
     pub stats_enabled: bool,
     /// Statistics reporting interval in seconds (default: 30)
     pub stats_interval: u64,
+    /// Maximum number of clients with their own traffic counters (default: 1024)
+    pub max_tracked_clients: usize,
+    /// Maximum number of datagrams handled concurrently by the async server (default: 64)
+    pub max_concurrency: usize,
+    /// Number of datagrams waiting for a free worker before the overload policy applies (default: 1024)
+    pub queue_size: usize,
+    /// What to do with a datagram when the queue is full (default: drop)
+    pub overload_policy: OverloadPolicy,
+    /// Token bucket rate limit applied to every source (default: none)
+    pub rate_limit: Option<RateLimit>,
+    /// What rate limit buckets are keyed by (default: source IP)
+    pub rate_limit_key: RateLimitKey,
+    /// Networks allowed to reach the handler; empty allows everyone (default: empty)
+    pub allow: Vec<Cidr>,
+    /// Networks that are always rejected (default: empty)
+    pub deny: Vec<Cidr>,
+    /// Number of sockets `UdpEchoServer` binds with SO_REUSEPORT, each with its own receive thread (default: 1)
+    pub workers: usize,
+    /// IPv4 multicast groups to join, each with the address of the local interface (default: none)
+    pub multicast_v4: Vec<(Ipv4Addr, Ipv4Addr)>,
+    /// IPv6 multicast groups to join, each with an interface index; 0 lets the system pick (default: none)
+    pub multicast_v6: Vec<(Ipv6Addr, u32)>,
+    /// TTL or hop limit of outgoing multicast datagrams (default: system default, usually 1)
+    pub multicast_ttl: Option<u32>,
+    /// Whether outgoing multicast datagrams are looped back to local sockets (default: system default)
+    pub multicast_loop: Option<bool>,
+    /// Allow sending datagrams to broadcast addresses (default: false)
+    pub broadcast: bool,
+}
+
+/// Policy applied when the async server's handler queue is full
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum OverloadPolicy {
+    /// Silently drop the datagram
+    Drop,
+    /// Drop the datagram and reply to the sender with the given busy message
+    Busy(Vec<u8>),
 }
 
 impl Default for ServerConfig {
@@ -34,6 +115,20 @@ impl Default for ServerConfig {
             verbose: false,
             stats_enabled: false,
             stats_interval: 30,
+            max_tracked_clients: 1024,
+            max_concurrency: 64,
+            queue_size: 1024,
+            overload_policy: OverloadPolicy::Drop,
+            rate_limit: None,
+            rate_limit_key: RateLimitKey::SourceIp,
+            allow: Vec::new(),
+            deny: Vec::new(),
+            workers: 1,
+            multicast_v4: Vec::new(),
+            multicast_v6: Vec::new(),
+            multicast_ttl: None,
+            multicast_loop: None,
+            broadcast: false,
         }
     }
 }
@@ -80,12 +175,98 @@ impl ServerConfig {
         self
     }
     
-    /// Set statistics reporting interval
+    /// Set statistics reporting interval in seconds, at least 1
     pub fn stats_interval(mut self, seconds: u64) -> Self {
         self.stats_interval = seconds;
         self
     }
     
+    /// Set the maximum number of clients with their own traffic counters
+    pub fn max_tracked_clients(mut self, count: usize) -> Self {
+        self.max_tracked_clients = count;
+        self
+    }
+    
+    /// Set the maximum number of concurrent handler calls
+    pub fn max_concurrency(mut self, limit: usize) -> Self {
+        self.max_concurrency = limit;
+        self
+    }
+    
+    /// Set the size of the queue in front of the handler workers
+    pub fn queue_size(mut self, size: usize) -> Self {
+        self.queue_size = size;
+        self
+    }
+    
+    /// Set the policy applied when the handler queue is full
+    pub fn overload_policy(mut self, policy: OverloadPolicy) -> Self {
+        self.overload_policy = policy;
+        self
+    }
+    
+    /// Limit every source to `per_second` datagrams with bursts of up to `burst`
+    pub fn rate_limit(mut self, per_second: f64, burst: u32) -> Self {
+        self.rate_limit = Some(RateLimit { per_second, burst });
+        self
+    }
+    
+    /// Set what rate limit buckets are keyed by
+    pub fn rate_limit_key(mut self, key: RateLimitKey) -> Self {
+        self.rate_limit_key = key;
+        self
+    }
+    
+    /// Add a network to the allow list
+    pub fn allow_cidr(mut self, network: Cidr) -> Self {
+        self.allow.push(network);
+        self
+    }
+    
+    /// Add a network to the deny list
+    pub fn deny_cidr(mut self, network: Cidr) -> Self {
+        self.deny.push(network);
+        self
+    }
+    
+    /// Set the number of sockets sharing the port, each served by its own thread
+    pub fn workers(mut self, count: usize) -> Self {
+        self.workers = count;
+        self
+    }
+    
+    /// Join an IPv4 multicast group on the interface with the given address
+    /// 
+    /// Bind to `0.0.0.0` or the group address to receive the group's traffic.
+    pub fn join_multicast_v4(mut self, group: Ipv4Addr, interface: Ipv4Addr) -> Self {
+        self.multicast_v4.push((group, interface));
+        self
+    }
+    
+    /// Join an IPv6 multicast group on the interface with the given index
+    pub fn join_multicast_v6(mut self, group: Ipv6Addr, interface: u32) -> Self {
+        self.multicast_v6.push((group, interface));
+        self
+    }
+    
+    /// Set the TTL or hop limit of outgoing multicast datagrams
+    pub fn multicast_ttl(mut self, ttl: u32) -> Self {
+        self.multicast_ttl = Some(ttl);
+        self
+    }
+    
+    /// Enable or disable looping outgoing multicast datagrams back to local sockets
+    pub fn multicast_loop(mut self, enabled: bool) -> Self {
+        self.multicast_loop = Some(enabled);
+        self
+    }
+    
+    /// Allow sending datagrams to broadcast addresses
+    pub fn broadcast(mut self, enabled: bool) -> Self {
+        self.broadcast = enabled;
+        self
+    }
+    
     /// Validate the configuration
     pub fn validate(&self) -> Result<(), String> {
         if self.buffer_size < 64 || self.buffer_size > 65536 {
@@ -100,6 +281,44 @@ impl ServerConfig {
             return Err("Max message size cannot exceed buffer size".to_string());
         }
         
+        if self.max_concurrency == 0 {
+            return Err("Max concurrency must be at least 1".to_string());
+        }
+        
+        if self.workers == 0 {
+            return Err("Workers must be at least 1".to_string());
+        }
+        
+        if self.queue_size == 0 {
+            return Err("Queue size must be at least 1".to_string());
+        }
+        
+        if self.stats_interval == 0 {
+            return Err("Stats interval must be at least 1 second".to_string());
+        }
+        
+        if let Some(group) = self.multicast_v4.iter().map(|(group, _)| group).find(|g| !g.is_multicast()) {
+            return Err(format!("{} is not an IPv4 multicast address", group));
+        }
+        
+        if let Some(group) = self.multicast_v6.iter().map(|(group, _)| group).find(|g| !g.is_multicast()) {
+            return Err(format!("{} is not an IPv6 multicast address", group));
+        }
+        
+        if self.multicast_ttl.is_some_and(|ttl| ttl > 255) {
+            return Err("Multicast TTL cannot exceed 255".to_string());
+        }
+        
+        if let Some(limit) = &self.rate_limit {
+            if limit.per_second <= 0.0 || !limit.per_second.is_finite() {
+                return Err("Rate limit must be a positive number of messages per second".to_string());
+            }
+            
+            if limit.burst == 0 {
+                return Err("Rate limit burst must be at least 1".to_string());
+            }
+        }
+        
         Ok(())
     }
 }
@@ -109,24 +328,20 @@ impl ServerConfig {
 pub struct ServerStats {
     pub messages_received: u64,
     pub messages_sent: u64,
+    pub messages_dropped: u64,
+    pub rate_limited: u64,
+    pub denied: u64,
     pub errors: u64,
     pub bytes_received: u64,
     pub bytes_sent: u64,
     pub start_time: Instant,
+    /// Handler execution times
+    pub handler_latency: LatencyHistogram,
+    /// Traffic counters of the first `max_tracked_clients` clients
+    pub clients: HashMap<SocketAddr, ClientStats>,
 }
 
 impl ServerStats {
-    fn new() -> Self {
-        Self {
-            messages_received: 0,
-            messages_sent: 0,
-            errors: 0,
-            bytes_received: 0,
-            bytes_sent: 0,
-            start_time: Instant::now(),
-        }
-    }
-    
     /// Get the uptime of the server
     pub fn uptime(&self) -> Duration {
         self.start_time.elapsed()
@@ -163,6 +378,25 @@ pub trait MessageHandler: Send + Sync {
     fn on_error(&self, error: &str, from: Option<SocketAddr>) {
         eprintln!("Error from {:?}: {}", from, error);
     }
+    
+    /// Called when a datagram is rejected by the access lists or the rate limit
+    /// Rejected datagrams never get a response
+    fn on_rejected(&self, _from: SocketAddr, _reason: RejectReason) {}
+}
+
+/// Shared handlers, e.g. one handler instance used by several servers
+impl<H: MessageHandler + ?Sized> MessageHandler for Arc<H> {
+    fn handle_message(&self, message: &[u8], from: SocketAddr) -> Option<Vec<u8>> {
+        (**self).handle_message(message, from)
+    }
+    
+    fn on_error(&self, error: &str, from: Option<SocketAddr>) {
+        (**self).on_error(error, from)
+    }
+    
+    fn on_rejected(&self, from: SocketAddr, reason: RejectReason) {
+        (**self).on_rejected(from, reason)
+    }
 }
 
 /// Default echo message handler
@@ -175,10 +409,16 @@ impl MessageHandler for EchoHandler {
 }
 
 /// UDP Echo Server
+///
+/// With `workers(n)` the server binds `n` sockets to the same address with
+/// SO_REUSEPORT and runs one receive loop per socket. All loops share the
+/// handler and the statistics.
 pub struct UdpEchoServer {
-    socket: Arc<UdpSocket>,
-    config: ServerConfig,
-    stats: Arc<std::sync::Mutex<ServerStats>>,
+    sockets: Vec<UdpSocket>,
+    runtime: SharedRuntime,
+    stats: Arc<StatsCollector>,
+    reporters: Vec<Arc<dyn StatsReporter>>,
+    recorder: Option<Arc<CaptureRecorder>>,
 }
 
 impl UdpEchoServer {
@@ -186,29 +426,55 @@ impl UdpEchoServer {
     pub fn new(config: ServerConfig) -> Result<Self, Box<dyn std::error::Error>> {
         config.validate()?;
         
-        let bind_addr = format!("{}:{}", config.host, config.port);
-        let socket = UdpSocket::bind(&bind_addr)?;
+        let sockets = socket::bind_sockets(&config)?;
         
         Ok(Self {
-            socket: Arc::new(socket),
-            config,
-            stats: Arc::new(std::sync::Mutex::new(ServerStats::new())),
+            sockets,
+            stats: Arc::new(StatsCollector::new(config.max_tracked_clients)),
+            runtime: SharedRuntime::new(config),
+            reporters: Vec::new(),
+            recorder: None,
         })
     }
     
     /// Create a new UDP echo server with default configuration
+    #[allow(clippy::should_implement_trait)]
     pub fn default() -> Result<Self, Box<dyn std::error::Error>> {
         Self::new(ServerConfig::default())
     }
     
     /// Get the local address the server is bound to
     pub fn local_addr(&self) -> io::Result<SocketAddr> {
-        self.socket.local_addr()
+        self.sockets[0].local_addr()
     }
     
     /// Get a copy of the current statistics
     pub fn stats(&self) -> ServerStats {
-        self.stats.lock().unwrap().clone()
+        self.stats.snapshot()
+    }
+    
+    /// Get the shared statistics collector, e.g. to take snapshots after `spawn`
+    pub fn stats_handle(&self) -> Arc<StatsCollector> {
+        Arc::clone(&self.stats)
+    }
+    
+    /// Publish statistics to a reporter every `stats_interval` seconds
+    /// 
+    /// Adding a reporter replaces the default stdout report.
+    pub fn with_reporter<R: StatsReporter + 'static>(mut self, reporter: R) -> Self {
+        self.reporters.push(Arc::new(reporter));
+        self
+    }
+    
+    /// Get a handle for changing the configuration while the server runs
+    pub fn reload_handle(&self) -> ReloadHandle {
+        ReloadHandle::new(self.runtime.clone())
+    }
+    
+    /// Write every received and sent datagram to a capture file
+    pub fn with_recorder(mut self, recorder: CaptureRecorder) -> Self {
+        self.recorder = Some(Arc::new(recorder));
+        self
     }
     
     /// Run the server with the default echo handler
@@ -218,90 +484,100 @@ impl UdpEchoServer {
     
     /// Run the server with a custom message handler
     pub fn run_with_handler<H: MessageHandler + 'static>(&self, handler: H) -> Result<(), Box<dyn std::error::Error>> {
-        let handler = Arc::new(handler);
-        
-        if self.config.verbose {
-            println!("UDP Echo Server starting on {}", self.local_addr()?);
-            println!("Configuration: {:?}", self.config);
+        let config = self.runtime.current().config.clone();
+        if config.verbose {
+            println!("UDP Echo Server starting on {} with {} socket(s)", self.local_addr()?, self.sockets.len());
+            println!("Configuration: {:?}", config);
         }
         
         // Start statistics reporting thread if enabled
-        if self.config.stats_enabled {
+        let reporters = metrics::active_reporters(&config, &self.reporters);
+        if !reporters.is_empty() {
             let stats_clone = Arc::clone(&self.stats);
-            let interval = self.config.stats_interval;
-            let verbose = self.config.verbose;
+            let runtime = self.runtime.clone();
             
             thread::spawn(move || {
                 loop {
+                    // Re-read the interval so a reload can change it
+                    let interval = runtime.current().config.stats_interval;
                     thread::sleep(Duration::from_secs(interval));
-                    let stats = stats_clone.lock().unwrap();
+                    let stats = stats_clone.snapshot();
                     
-                    if verbose {
-                        println!("=== Server Statistics ===");
-                        println!("Uptime: {:.2}s", stats.uptime().as_secs_f64());
-                        println!("Messages - Received: {}, Sent: {}, Errors: {}", 
-                            stats.messages_received, stats.messages_sent, stats.errors);
-                        println!("Bytes - Received: {}, Sent: {}", 
-                            stats.bytes_received, stats.bytes_sent);
-                        println!("Rate - RX: {:.2} msg/s, TX: {:.2} msg/s", 
-                            stats.messages_per_second_received(), 
-                            stats.messages_per_second_sent());
-                        println!("========================");
+                    for reporter in &reporters {
+                        reporter.report(&stats);
                     }
                 }
             });
         }
         
-        // Main server loop
-        let mut buffer = vec![0; self.config.buffer_size];
+        // One receive loop per socket; the first one runs on this thread
+        thread::scope(|scope| {
+            for socket in &self.sockets[1..] {
+                let handler = &handler;
+                scope.spawn(move || self.serve(socket, handler));
+            }
+            self.serve(&self.sockets[0], &handler)
+        })
+    }
+    
+    /// Receive loop of one socket
+    fn serve<H: MessageHandler>(&self, socket: &UdpSocket, handler: &H) -> ! {
+        let mut buffer = vec![0; self.runtime.current().config.buffer_size];
+        let local_addr = socket.local_addr().ok();
         
         loop {
-            match self.socket.recv_from(&mut buffer) {
+            match socket.recv_from(&mut buffer) {
                 Ok((size, src_addr)) => {
+                    let runtime = self.runtime.current();
+                    
                     // Update statistics
-                    {
-                        let mut stats = self.stats.lock().unwrap();
-                        stats.messages_received += 1;
-                        stats.bytes_received += size as u64;
-                    }
+                    self.stats.record_received(src_addr, size);
+                    self.capture(handler, Direction::Inbound, src_addr, local_addr, &buffer[..size]);
                     
-                    if self.config.verbose {
+                    if runtime.config.verbose {
                         println!("Received {} bytes from {}", size, src_addr);
                     }
                     
+                    // Drop denied and rate limited sources without a response
+                    if let Err(reason) = runtime.filter.check(src_addr) {
+                        handler.on_rejected(src_addr, reason);
+                        self.stats.record_rejection(reason);
+                        continue;
+                    }
+                    
                     // Check message size limit
-                    if size > self.config.max_message_size {
+                    if size > runtime.config.max_message_size {
                         let error_msg = format!("Message too large: {} bytes (max: {})", 
-                            size, self.config.max_message_size);
+                            size, runtime.config.max_message_size);
                         handler.on_error(&error_msg, Some(src_addr));
-                        
-                        let mut stats = self.stats.lock().unwrap();
-                        stats.errors += 1;
+                        self.stats.record_error();
                         continue;
                     }
                     
                     // Process message with handler
-                    match handler.handle_message(&buffer[..size], src_addr) {
+                    let started = Instant::now();
+                    let response = handler.handle_message(&buffer[..size], src_addr);
+                    self.stats.record_handler(started.elapsed());
+                    
+                    match response {
                         Some(response) => {
-                            match self.socket.send_to(&response, src_addr) {
+                            match socket.send_to(&response, src_addr) {
                                 Ok(sent_size) => {
-                                    let mut stats = self.stats.lock().unwrap();
-                                    stats.messages_sent += 1;
-                                    stats.bytes_sent += sent_size as u64;
+                                    self.stats.record_sent(src_addr, sent_size);
+                                    self.capture(handler, Direction::Outbound, src_addr, local_addr, &response);
                                     
-                                    if self.config.verbose {
+                                    if runtime.config.verbose {
                                         println!("Sent {} bytes to {}", sent_size, src_addr);
                                     }
                                 }
                                 Err(e) => {
                                     handler.on_error(&format!("Failed to send response: {}", e), Some(src_addr));
-                                    let mut stats = self.stats.lock().unwrap();
-                                    stats.errors += 1;
+                                    self.stats.record_error();
                                 }
                             }
                         }
                         None => {
-                            if self.config.verbose {
+                            if runtime.config.verbose {
                                 println!("No response generated for message from {}", src_addr);
                             }
                         }
@@ -309,8 +585,7 @@ impl UdpEchoServer {
                 }
                 Err(e) => {
                     handler.on_error(&format!("Failed to receive data: {}", e), None);
-                    let mut stats = self.stats.lock().unwrap();
-                    stats.errors += 1;
+                    self.stats.record_error();
                     
                     // Small delay to prevent busy-waiting on persistent errors
                     thread::sleep(Duration::from_millis(10));
@@ -319,6 +594,22 @@ impl UdpEchoServer {
         }
     }
     
+    /// Write a datagram to the recorder, if any
+    fn capture<H: MessageHandler>(
+        &self,
+        handler: &H,
+        direction: Direction,
+        peer: SocketAddr,
+        local: Option<SocketAddr>,
+        data: &[u8],
+    ) {
+        if let (Some(recorder), Some(local)) = (&self.recorder, local) {
+            if let Err(e) = recorder.record(direction, peer, local, data) {
+                handler.on_error(&format!("Failed to record datagram: {}", e), Some(peer));
+            }
+        }
+    }
+    
     /// Run the server in a separate thread and return a handle
     pub fn spawn(self) -> thread::JoinHandle<Result<(), Box<dyn std::error::Error + Send + Sync>>> {
         thread::spawn(move || {
@@ -399,4 +690,71 @@ mod tests {
         
         assert_eq!(&buffer[..size], message);
     }
-}
\ No newline at end of file
+    
+    #[test]
+    fn test_denied_source_gets_no_response() {
+        let config = ServerConfig::new()
+            .port(0)
+            .deny_cidr("127.0.0.0/8".parse().unwrap());
+        let server = UdpEchoServer::new(config).unwrap();
+        let server_addr = server.local_addr().unwrap();
+        let _handle = server.spawn();
+        
+        let client = UdpSocket::bind("127.0.0.1:0").unwrap();
+        client.set_read_timeout(Some(Duration::from_millis(200))).unwrap();
+        client.send_to(b"reflect me", server_addr).unwrap();
+        
+        let mut buffer = [0; 1024];
+        assert!(client.recv_from(&mut buffer).is_err());
+    }
+    
+    #[test]
+    fn test_workers_share_port_and_stats() {
+        let config = ServerConfig::new().port(0).workers(4);
+        let server = UdpEchoServer::new(config).unwrap();
+        let server_addr = server.local_addr().unwrap();
+        let stats = server.stats_handle();
+        let _handle = server.spawn();
+        
+        // Different source ports hash to different sockets
+        for i in 0..16 {
+            let client = UdpSocket::bind("127.0.0.1:0").unwrap();
+            client.set_read_timeout(Some(Duration::from_secs(2))).unwrap();
+            let message = format!("message {}", i);
+            client.send_to(message.as_bytes(), server_addr).unwrap();
+            
+            let mut buffer = [0; 1024];
+            let (size, from) = client.recv_from(&mut buffer).unwrap();
+            assert_eq!(&buffer[..size], message.as_bytes());
+            assert_eq!(from, server_addr);
+        }
+        
+        let stats = stats.snapshot();
+        assert_eq!(stats.messages_received, 16);
+        assert_eq!(stats.clients.len(), 16);
+        assert!(ServerConfig::new().workers(0).validate().is_err());
+    }
+    
+    #[test]
+    fn test_reload_keeps_socket() {
+        let server = UdpEchoServer::new(ServerConfig::new().port(0)).unwrap();
+        let server_addr = server.local_addr().unwrap();
+        let reload = server.reload_handle();
+        let _handle = server.spawn();
+        
+        let client = UdpSocket::bind("127.0.0.1:0").unwrap();
+        client.set_read_timeout(Some(Duration::from_millis(300))).unwrap();
+        let mut buffer = [0; 1024];
+        client.send_to(b"before", server_addr).unwrap();
+        assert!(client.recv_from(&mut buffer).is_ok());
+        
+        let mut config = reload.config().deny_cidr("127.0.0.0/8".parse().unwrap());
+        reload.reload(config.clone()).unwrap();
+        client.send_to(b"after", server_addr).unwrap();
+        assert!(client.recv_from(&mut buffer).is_err());
+        
+        config.port += 1;
+        let error = reload.reload(config.clone().stats(true)).unwrap_err();
+        assert!(error.contains("port, stats"), "{}", error);
+    }
+}
@@ -0,0 +1,476 @@
+// metrics.rs
+use std::fmt::Write as _;
+#[cfg(feature = "json")]
+use std::fs;
+use std::io::{self, Read, Write};
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+#[cfg(feature = "json")]
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+#[cfg(feature = "json")]
+use serde_json::json;
+
+use crate::{ServerConfig, ServerStats};
+
+/// Upper bounds of the handler latency histogram buckets, in seconds
+pub const LATENCY_BUCKETS: [f64; 16] = [
+    0.00005, 0.0001, 0.00025, 0.0005, 0.001, 0.0025, 0.005, 0.01,
+    0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0,
+];
+
+/// Histogram of handler execution times
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct LatencyHistogram {
+    /// Observations per bucket of [`LATENCY_BUCKETS`], plus a final `+Inf` bucket
+    counts: [u64; LATENCY_BUCKETS.len() + 1],
+    sum: f64,
+    count: u64,
+}
+
+impl LatencyHistogram {
+    pub(crate) fn from_parts(counts: [u64; LATENCY_BUCKETS.len() + 1], sum: Duration) -> Self {
+        Self {
+            counts,
+            sum: sum.as_secs_f64(),
+            count: counts.iter().sum(),
+        }
+    }
+
+    /// Index of the bucket an observation falls into
+    pub(crate) fn bucket_index(elapsed: Duration) -> usize {
+        let seconds = elapsed.as_secs_f64();
+        LATENCY_BUCKETS
+            .iter()
+            .position(|bound| seconds <= *bound)
+            .unwrap_or(LATENCY_BUCKETS.len())
+    }
+
+    /// Record one handler execution time
+    pub fn record(&mut self, elapsed: Duration) {
+        self.counts[Self::bucket_index(elapsed)] += 1;
+        self.sum += elapsed.as_secs_f64();
+        self.count += 1;
+    }
+
+    /// Number of recorded observations
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    /// Sum of all recorded observations
+    pub fn sum(&self) -> Duration {
+        Duration::from_secs_f64(self.sum)
+    }
+
+    /// Average handler execution time
+    pub fn mean(&self) -> Duration {
+        if self.count > 0 {
+            Duration::from_secs_f64(self.sum / self.count as f64)
+        } else {
+            Duration::ZERO
+        }
+    }
+
+    /// Cumulative counts per upper bound in seconds; the last bound is infinite
+    pub fn cumulative_buckets(&self) -> Vec<(f64, u64)> {
+        let bounds = LATENCY_BUCKETS.iter().copied().chain(std::iter::once(f64::INFINITY));
+        let mut total = 0;
+        bounds
+            .zip(self.counts.iter())
+            .map(|(bound, count)| {
+                total += count;
+                (bound, total)
+            })
+            .collect()
+    }
+}
+
+/// Traffic counters for a single client
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ClientStats {
+    pub messages_received: u64,
+    pub messages_sent: u64,
+    pub bytes_received: u64,
+    pub bytes_sent: u64,
+}
+
+impl ServerStats {
+    /// Render the statistics in the Prometheus text exposition format
+    pub fn to_prometheus(&self) -> String {
+        let mut out = String::new();
+
+        let counters = [
+            ("messages_received_total", "Datagrams received", self.messages_received),
+            ("messages_sent_total", "Datagrams sent", self.messages_sent),
+            ("messages_dropped_total", "Datagrams dropped because the handler queue was full", self.messages_dropped),
+            ("rate_limited_total", "Datagrams rejected by the rate limit", self.rate_limited),
+            ("denied_total", "Datagrams rejected by the access lists", self.denied),
+            ("errors_total", "Receive, send and handler errors", self.errors),
+            ("bytes_received_total", "Bytes received", self.bytes_received),
+            ("bytes_sent_total", "Bytes sent", self.bytes_sent),
+        ];
+        for (name, help, value) in counters {
+            let _ = writeln!(out, "# HELP udp_server_{} {}", name, help);
+            let _ = writeln!(out, "# TYPE udp_server_{} counter", name);
+            let _ = writeln!(out, "udp_server_{} {}", name, value);
+        }
+
+        let _ = writeln!(out, "# HELP udp_server_uptime_seconds Time since the server started");
+        let _ = writeln!(out, "# TYPE udp_server_uptime_seconds gauge");
+        let _ = writeln!(out, "udp_server_uptime_seconds {}", self.uptime().as_secs_f64());
+
+        let _ = writeln!(out, "# HELP udp_server_handler_duration_seconds Handler execution time");
+        let _ = writeln!(out, "# TYPE udp_server_handler_duration_seconds histogram");
+        for (bound, count) in self.handler_latency.cumulative_buckets() {
+            let le = if bound.is_infinite() { "+Inf".to_string() } else { bound.to_string() };
+            let _ = writeln!(out, "udp_server_handler_duration_seconds_bucket{{le=\"{}\"}} {}", le, count);
+        }
+        let _ = writeln!(out, "udp_server_handler_duration_seconds_sum {}", self.handler_latency.sum().as_secs_f64());
+        let _ = writeln!(out, "udp_server_handler_duration_seconds_count {}", self.handler_latency.count());
+
+        let mut clients: Vec<_> = self.clients.iter().collect();
+        clients.sort_by_key(|(addr, _)| **addr);
+
+        type ClientCounter = (&'static str, &'static str, fn(&ClientStats) -> u64);
+        let client_counters: [ClientCounter; 4] = [
+            ("client_messages_received_total", "Datagrams received per client", |c| c.messages_received),
+            ("client_messages_sent_total", "Datagrams sent per client", |c| c.messages_sent),
+            ("client_bytes_received_total", "Bytes received per client", |c| c.bytes_received),
+            ("client_bytes_sent_total", "Bytes sent per client", |c| c.bytes_sent),
+        ];
+        for (name, help, value) in client_counters {
+            let _ = writeln!(out, "# HELP udp_server_{} {}", name, help);
+            let _ = writeln!(out, "# TYPE udp_server_{} counter", name);
+            for (addr, client) in &clients {
+                let _ = writeln!(out, "udp_server_{}{{client=\"{}\"}} {}", name, addr, value(client));
+            }
+        }
+
+        out
+    }
+
+    /// Render the statistics as a JSON snapshot
+    #[cfg(feature = "json")]
+    pub fn to_json(&self) -> serde_json::Value {
+        let buckets: Vec<_> = self
+            .handler_latency
+            .cumulative_buckets()
+            .into_iter()
+            .map(|(bound, count)| {
+                let le = if bound.is_infinite() { json!("+Inf") } else { json!(bound) };
+                json!({ "le": le, "count": count })
+            })
+            .collect();
+
+        let clients: serde_json::Map<_, _> = self
+            .clients
+            .iter()
+            .map(|(addr, client)| {
+                (addr.to_string(), json!({
+                    "messages_received": client.messages_received,
+                    "messages_sent": client.messages_sent,
+                    "bytes_received": client.bytes_received,
+                    "bytes_sent": client.bytes_sent,
+                }))
+            })
+            .collect();
+
+        json!({
+            "uptime_seconds": self.uptime().as_secs_f64(),
+            "messages_received": self.messages_received,
+            "messages_sent": self.messages_sent,
+            "messages_dropped": self.messages_dropped,
+            "rate_limited": self.rate_limited,
+            "denied": self.denied,
+            "errors": self.errors,
+            "bytes_received": self.bytes_received,
+            "bytes_sent": self.bytes_sent,
+            "messages_per_second_received": self.messages_per_second_received(),
+            "messages_per_second_sent": self.messages_per_second_sent(),
+            "handler_latency": {
+                "count": self.handler_latency.count(),
+                "sum_seconds": self.handler_latency.sum().as_secs_f64(),
+                "mean_seconds": self.handler_latency.mean().as_secs_f64(),
+                "buckets": buckets,
+            },
+            "clients": clients,
+        })
+    }
+}
+
+/// Receives a statistics snapshot every `stats_interval` seconds
+pub trait StatsReporter: Send + Sync {
+    /// Publish a statistics snapshot
+    fn report(&self, stats: &ServerStats);
+}
+
+/// Prints statistics to stdout
+pub struct StdoutReporter;
+
+impl StatsReporter for StdoutReporter {
+    fn report(&self, stats: &ServerStats) {
+        println!("=== Server Statistics ===");
+        println!("Uptime: {:.2}s", stats.uptime().as_secs_f64());
+        println!("Messages - Received: {}, Sent: {}, Dropped: {}, Errors: {}",
+            stats.messages_received, stats.messages_sent, stats.messages_dropped, stats.errors);
+        println!("Rejected - Rate limited: {}, Denied: {}",
+            stats.rate_limited, stats.denied);
+        println!("Bytes - Received: {}, Sent: {}",
+            stats.bytes_received, stats.bytes_sent);
+        println!("Rate - RX: {:.2} msg/s, TX: {:.2} msg/s",
+            stats.messages_per_second_received(),
+            stats.messages_per_second_sent());
+        println!("Handler - Calls: {}, Mean: {:.3}ms",
+            stats.handler_latency.count(),
+            stats.handler_latency.mean().as_secs_f64() * 1000.0);
+        println!("Clients tracked: {}", stats.clients.len());
+        println!("========================");
+    }
+}
+
+/// Writes every snapshot as JSON to a file
+///
+/// The file is replaced atomically, so readers never see a partial snapshot.
+#[cfg(feature = "json")]
+pub struct JsonReporter {
+    path: PathBuf,
+}
+
+#[cfg(feature = "json")]
+impl JsonReporter {
+    /// Create a reporter writing to `path`
+    pub fn new<P: Into<PathBuf>>(path: P) -> Self {
+        Self { path: path.into() }
+    }
+
+    fn write(&self, stats: &ServerStats) -> io::Result<()> {
+        let mut tmp = self.path.clone().into_os_string();
+        tmp.push(".tmp");
+
+        let body = serde_json::to_vec_pretty(&stats.to_json())?;
+        fs::write(&tmp, body)?;
+        fs::rename(&tmp, &self.path)
+    }
+}
+
+#[cfg(feature = "json")]
+impl StatsReporter for JsonReporter {
+    fn report(&self, stats: &ServerStats) {
+        if let Err(e) = self.write(stats) {
+            eprintln!("Failed to write statistics to {}: {}", self.path.display(), e);
+        }
+    }
+}
+
+/// Serves the latest snapshot in the Prometheus text format over HTTP
+///
+/// Any path is answered with the metrics, so both `/` and `/metrics` work.
+/// Scrapes see the snapshot from the most recent report, so `stats_interval`
+/// should not be longer than the scrape interval.
+pub struct PrometheusReporter {
+    latest: Arc<Mutex<String>>,
+    local_addr: SocketAddr,
+    stopped: Arc<AtomicBool>,
+}
+
+impl PrometheusReporter {
+    /// Start serving metrics on the given address
+    pub fn bind<A: ToSocketAddrs>(addr: A) -> io::Result<Self> {
+        let listener = TcpListener::bind(addr)?;
+        let local_addr = listener.local_addr()?;
+        let latest = Arc::new(Mutex::new(String::new()));
+        let stopped = Arc::new(AtomicBool::new(false));
+
+        let body = Arc::clone(&latest);
+        let stop = Arc::clone(&stopped);
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                if stop.load(Ordering::SeqCst) {
+                    break;
+                }
+                if let Ok(stream) = stream {
+                    let body = body.lock().unwrap().clone();
+                    let _ = serve_scrape(stream, &body);
+                }
+            }
+        });
+
+        Ok(Self { latest, local_addr, stopped })
+    }
+
+    /// Get the address the metrics endpoint is listening on
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+}
+
+impl StatsReporter for PrometheusReporter {
+    fn report(&self, stats: &ServerStats) {
+        *self.latest.lock().unwrap() = stats.to_prometheus();
+    }
+}
+
+impl Drop for PrometheusReporter {
+    fn drop(&mut self) {
+        self.stopped.store(true, Ordering::SeqCst);
+
+        // Wake up the accept loop so the listener thread can exit
+        let mut wake_addr = self.local_addr;
+        if wake_addr.ip().is_unspecified() {
+            wake_addr.set_ip(if wake_addr.is_ipv4() {
+                Ipv4Addr::LOCALHOST.into()
+            } else {
+                Ipv6Addr::LOCALHOST.into()
+            });
+        }
+        let _ = TcpStream::connect_timeout(&wake_addr, Duration::from_millis(100));
+    }
+}
+
+/// Longest time one scrape may take; scrapes are served one at a time
+const SCRAPE_TIMEOUT: Duration = Duration::from_secs(2);
+
+fn serve_scrape(mut stream: TcpStream, body: &str) -> io::Result<()> {
+    // A client that trickles its request or stops reading the response
+    // must not hold up the scrapes after it
+    let deadline = Instant::now() + SCRAPE_TIMEOUT;
+    stream.set_write_timeout(Some(SCRAPE_TIMEOUT))?;
+
+    // Read the request head; the request itself does not matter
+    let mut request = Vec::new();
+    let mut chunk = [0; 1024];
+    while !request.windows(4).any(|w| w == b"\r\n\r\n") && request.len() < 8192 {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            return Err(io::ErrorKind::TimedOut.into());
+        }
+        stream.set_read_timeout(Some(remaining))?;
+        let n = stream.read(&mut chunk)?;
+        if n == 0 {
+            break;
+        }
+        request.extend_from_slice(&chunk[..n]);
+    }
+
+    let response = format!(
+        "HTTP/1.1 200 OK\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        body.len(),
+        body
+    );
+    stream.write_all(response.as_bytes())?;
+    stream.flush()
+}
+
+/// Reporters the statistics task publishes to
+///
+/// Without explicitly added reporters, enabling statistics prints to stdout.
+pub(crate) fn active_reporters(
+    config: &ServerConfig,
+    reporters: &[Arc<dyn StatsReporter>],
+) -> Vec<Arc<dyn StatsReporter>> {
+    if reporters.is_empty() && config.stats_enabled {
+        vec![Arc::new(StdoutReporter)]
+    } else {
+        reporters.to_vec()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample_stats() -> ServerStats {
+        let collector = crate::StatsCollector::new(16);
+        let client: SocketAddr = "127.0.0.1:5000".parse().unwrap();
+        collector.record_received(client, 10);
+        collector.record_handler(Duration::from_micros(300));
+        collector.record_sent(client, 10);
+        collector.snapshot()
+    }
+
+    #[test]
+    fn test_histogram_buckets() {
+        let mut histogram = LatencyHistogram::default();
+        histogram.record(Duration::from_micros(40));
+        histogram.record(Duration::from_millis(3));
+        histogram.record(Duration::from_secs(10));
+
+        let buckets = histogram.cumulative_buckets();
+        assert_eq!(buckets.len(), LATENCY_BUCKETS.len() + 1);
+        assert_eq!(buckets[0], (0.00005, 1));
+        assert_eq!(buckets[6], (0.005, 2));
+        assert_eq!(buckets.last().unwrap().1, 3);
+        assert_eq!(histogram.count(), 3);
+    }
+
+    #[test]
+    fn test_prometheus_rendering() {
+        let text = sample_stats().to_prometheus();
+
+        assert!(text.contains("# TYPE udp_server_messages_received_total counter\n"));
+        assert!(text.contains("udp_server_messages_received_total 1\n"));
+        assert!(text.contains("udp_server_handler_duration_seconds_bucket{le=\"0.00025\"} 0\n"));
+        assert!(text.contains("udp_server_handler_duration_seconds_bucket{le=\"0.0005\"} 1\n"));
+        assert!(text.contains("udp_server_handler_duration_seconds_bucket{le=\"+Inf\"} 1\n"));
+        assert!(text.contains("udp_server_client_bytes_sent_total{client=\"127.0.0.1:5000\"} 10\n"));
+    }
+
+    #[cfg(feature = "json")]
+    #[test]
+    fn test_json_reporter_writes_snapshot() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("stats.json");
+
+        JsonReporter::new(&path).report(&sample_stats());
+
+        let snapshot: serde_json::Value = serde_json::from_slice(&fs::read(&path).unwrap()).unwrap();
+        assert_eq!(snapshot["messages_sent"], 1);
+        assert_eq!(snapshot["handler_latency"]["count"], 1);
+        assert_eq!(snapshot["clients"]["127.0.0.1:5000"]["bytes_received"], 10);
+    }
+
+    #[test]
+    fn test_prometheus_endpoint_serves_latest_report() {
+        let reporter = PrometheusReporter::bind("127.0.0.1:0").unwrap();
+        reporter.report(&sample_stats());
+
+        let mut stream = TcpStream::connect(reporter.local_addr()).unwrap();
+        stream.write_all(b"GET /metrics HTTP/1.1\r\nHost: localhost\r\n\r\n").unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.contains("udp_server_messages_sent_total 1\n"));
+    }
+
+    #[test]
+    fn test_slow_client_does_not_block_scrapes() {
+        let reporter = PrometheusReporter::bind("127.0.0.1:0").unwrap();
+        reporter.report(&sample_stats());
+
+        // Trickle a request one byte at a time, each within the read timeout
+        let mut slow = TcpStream::connect(reporter.local_addr()).unwrap();
+        let trickle = thread::spawn(move || {
+            for _ in 0..20 {
+                if slow.write_all(b"G").is_err() {
+                    break;
+                }
+                thread::sleep(Duration::from_millis(200));
+            }
+        });
+        thread::sleep(Duration::from_millis(100));
+
+        let started = Instant::now();
+        let mut stream = TcpStream::connect(reporter.local_addr()).unwrap();
+        stream.write_all(b"GET /metrics HTTP/1.1\r\n\r\n").unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(started.elapsed() < SCRAPE_TIMEOUT + Duration::from_secs(1));
+        trickle.join().unwrap();
+    }
+}
@@ -0,0 +1,600 @@
+// protocol.rs
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::io;
+use std::net::{SocketAddr, ToSocketAddrs, UdpSocket};
+use std::ops::BitOr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use crate::{MessageHandler, RejectReason};
+
+/// Length of the frame header in bytes
+pub const HEADER_LEN: usize = 12;
+
+const MAGIC: [u8; 2] = *b"UE";
+const VERSION: u8 = 1;
+
+/// Header flags
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Flags(u8);
+
+impl Flags {
+    /// The frame is a request
+    pub const REQUEST: Flags = Flags(0b0000_0001);
+    /// The frame is a response
+    pub const RESPONSE: Flags = Flags(0b0000_0010);
+    /// The request is a retransmission of an earlier attempt
+    pub const RETRANSMIT: Flags = Flags(0b0000_0100);
+    /// The response was replayed from the server's duplicate cache
+    pub const DUPLICATE: Flags = Flags(0b0000_1000);
+
+    /// Create flags from their wire representation
+    pub fn from_bits(bits: u8) -> Self {
+        Flags(bits)
+    }
+
+    /// Get the wire representation of the flags
+    pub fn bits(self) -> u8 {
+        self.0
+    }
+
+    /// Check whether all of `other` is set
+    pub fn contains(self, other: Flags) -> bool {
+        self.0 & other.0 == other.0
+    }
+}
+
+impl BitOr for Flags {
+    type Output = Flags;
+
+    fn bitor(self, rhs: Flags) -> Flags {
+        Flags(self.0 | rhs.0)
+    }
+}
+
+/// Frame header
+///
+/// Wire layout (big endian):
+/// `magic "UE" (2) | version (1) | flags (1) | request id (4) | sequence (4)`
+///
+/// The sequence number is the transmission attempt of a request, starting at
+/// zero. Responses echo the sequence of the attempt they answer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Header {
+    pub request_id: u32,
+    pub sequence: u32,
+    pub flags: Flags,
+}
+
+impl Header {
+    /// Encode the header followed by `payload` into a datagram
+    pub fn encode(&self, payload: &[u8]) -> Vec<u8> {
+        let mut frame = Vec::with_capacity(HEADER_LEN + payload.len());
+        frame.extend_from_slice(&MAGIC);
+        frame.push(VERSION);
+        frame.push(self.flags.bits());
+        frame.extend_from_slice(&self.request_id.to_be_bytes());
+        frame.extend_from_slice(&self.sequence.to_be_bytes());
+        frame.extend_from_slice(payload);
+        frame
+    }
+
+    /// Split a datagram into its header and payload
+    pub fn decode(frame: &[u8]) -> Result<(Header, &[u8]), ProtocolError> {
+        if frame.len() < HEADER_LEN {
+            return Err(ProtocolError::TooShort(frame.len()));
+        }
+        if frame[..2] != MAGIC {
+            return Err(ProtocolError::BadMagic);
+        }
+        if frame[2] != VERSION {
+            return Err(ProtocolError::UnsupportedVersion(frame[2]));
+        }
+
+        let header = Header {
+            flags: Flags::from_bits(frame[3]),
+            request_id: u32::from_be_bytes([frame[4], frame[5], frame[6], frame[7]]),
+            sequence: u32::from_be_bytes([frame[8], frame[9], frame[10], frame[11]]),
+        };
+        Ok((header, &frame[HEADER_LEN..]))
+    }
+}
+
+/// Errors produced while decoding a frame
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ProtocolError {
+    TooShort(usize),
+    BadMagic,
+    UnsupportedVersion(u8),
+}
+
+impl fmt::Display for ProtocolError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ProtocolError::TooShort(len) => write!(f, "Frame too short: {} bytes (header: {})", len, HEADER_LEN),
+            ProtocolError::BadMagic => write!(f, "Frame has an invalid magic value"),
+            ProtocolError::UnsupportedVersion(v) => write!(f, "Unsupported protocol version: {}", v),
+        }
+    }
+}
+
+impl std::error::Error for ProtocolError {}
+
+enum CachedResponse {
+    /// The request is still being handled
+    Pending,
+    /// The request was handled; `None` means the handler sent no response
+    Done(Option<Vec<u8>>),
+}
+
+struct ResponseCache {
+    entries: HashMap<(SocketAddr, u32), (CachedResponse, Instant)>,
+    order: VecDeque<((SocketAddr, u32), Instant)>,
+}
+
+impl ResponseCache {
+    fn evict(&mut self, ttl: Duration, capacity: usize) {
+        while let Some(&(key, inserted)) = self.order.front() {
+            if inserted.elapsed() < ttl && self.entries.len() < capacity {
+                break;
+            }
+            self.order.pop_front();
+            // Only remove the entry if it was not re-inserted since
+            if self.entries.get(&key).is_some_and(|(_, at)| *at == inserted) {
+                self.entries.remove(&key);
+            }
+        }
+    }
+}
+
+/// Server side of the framing protocol
+///
+/// Wraps a [`MessageHandler`] that works on payloads. Requests are decoded,
+/// passed to the inner handler and the response is framed with the request's
+/// ID. Responses are cached per peer and request ID so retransmitted requests
+/// are answered from the cache instead of running the handler again.
+pub struct ReliableHandler<H> {
+    inner: H,
+    cache: Mutex<ResponseCache>,
+    cache_ttl: Duration,
+    cache_capacity: usize,
+}
+
+impl<H: MessageHandler> ReliableHandler<H> {
+    /// Wrap a payload handler with the default cache settings
+    pub fn new(inner: H) -> Self {
+        Self {
+            inner,
+            cache: Mutex::new(ResponseCache {
+                entries: HashMap::new(),
+                order: VecDeque::new(),
+            }),
+            cache_ttl: Duration::from_secs(30),
+            cache_capacity: 4096,
+        }
+    }
+
+    /// Set how long responses are kept for duplicate suppression (default: 30s)
+    pub fn cache_ttl(mut self, ttl: Duration) -> Self {
+        self.cache_ttl = ttl;
+        self
+    }
+
+    /// Set the maximum number of cached responses (default: 4096)
+    pub fn cache_capacity(mut self, capacity: usize) -> Self {
+        self.cache_capacity = capacity.max(1);
+        self
+    }
+
+    /// Get a reference to the wrapped handler
+    pub fn inner(&self) -> &H {
+        &self.inner
+    }
+
+    fn frame_response(request: &Header, payload: &[u8], duplicate: bool) -> Vec<u8> {
+        let flags = if duplicate {
+            Flags::RESPONSE | Flags::DUPLICATE
+        } else {
+            Flags::RESPONSE
+        };
+        Header {
+            request_id: request.request_id,
+            sequence: request.sequence,
+            flags,
+        }
+        .encode(payload)
+    }
+}
+
+impl<H: MessageHandler> MessageHandler for ReliableHandler<H> {
+    fn handle_message(&self, message: &[u8], from: SocketAddr) -> Option<Vec<u8>> {
+        let (header, payload) = match Header::decode(message) {
+            Ok(decoded) => decoded,
+            Err(e) => {
+                self.inner.on_error(&format!("Invalid frame: {}", e), Some(from));
+                return None;
+            }
+        };
+
+        if !header.flags.contains(Flags::REQUEST) {
+            return None;
+        }
+
+        let key = (from, header.request_id);
+        {
+            let mut cache = self.cache.lock().unwrap();
+            cache.evict(self.cache_ttl, self.cache_capacity);
+
+            match cache.entries.get(&key) {
+                // A retransmission raced the original; the original will answer
+                Some((CachedResponse::Pending, _)) => return None,
+                Some((CachedResponse::Done(response), _)) => {
+                    return response
+                        .as_ref()
+                        .map(|response| Self::frame_response(&header, response, true));
+                }
+                None => {
+                    let now = Instant::now();
+                    cache.entries.insert(key, (CachedResponse::Pending, now));
+                    cache.order.push_back((key, now));
+                }
+            }
+        }
+
+        let response = self.inner.handle_message(payload, from);
+
+        if let Some((entry, _)) = self.cache.lock().unwrap().entries.get_mut(&key) {
+            *entry = CachedResponse::Done(response.clone());
+        }
+
+        response.map(|response| Self::frame_response(&header, &response, false))
+    }
+
+    fn on_error(&self, error: &str, from: Option<SocketAddr>) {
+        self.inner.on_error(error, from);
+    }
+
+    fn on_rejected(&self, from: SocketAddr, reason: RejectReason) {
+        self.inner.on_rejected(from, reason);
+    }
+}
+
+/// Retransmission settings for [`UdpClient`]
+#[derive(Debug, Clone, PartialEq)]
+pub struct RetryPolicy {
+    /// Time to wait for a response to the first attempt (default: 200ms)
+    pub initial_timeout: Duration,
+    /// Number of retransmissions after the first attempt (default: 5)
+    pub max_retries: u32,
+    /// Factor applied to the timeout after every attempt (default: 2.0)
+    ///
+    /// A factor below 1.0, or one that is not a number, keeps the timeout
+    /// unchanged.
+    pub backoff: f64,
+    /// Upper bound for the timeout of a single attempt (default: 2s)
+    pub max_timeout: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            initial_timeout: Duration::from_millis(200),
+            max_retries: 5,
+            backoff: 2.0,
+            max_timeout: Duration::from_secs(2),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// The timeout of the attempt after one that waited `timeout`
+    pub(crate) fn next_timeout(&self, timeout: Duration) -> Duration {
+        let next = if self.backoff >= 1.0 {
+            // An infinite or overflowing product fails and saturates
+            Duration::try_from_secs_f64(timeout.as_secs_f64() * self.backoff).unwrap_or(Duration::MAX)
+        } else {
+            timeout
+        };
+        next.min(self.max_timeout)
+    }
+}
+
+/// Errors returned by [`UdpClient`]
+#[derive(Debug)]
+pub enum ClientError {
+    Io(io::Error),
+    /// No response arrived before the retries or the deadline ran out
+    Timeout { attempts: u32 },
+}
+
+impl fmt::Display for ClientError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ClientError::Io(e) => write!(f, "IO error: {}", e),
+            ClientError::Timeout { attempts } => write!(f, "Request timed out after {} attempts", attempts),
+        }
+    }
+}
+
+impl std::error::Error for ClientError {}
+
+impl From<io::Error> for ClientError {
+    fn from(error: io::Error) -> Self {
+        ClientError::Io(error)
+    }
+}
+
+/// Client side of the framing protocol
+///
+/// Every request gets a new request ID and is retransmitted with exponential
+/// backoff until a matching response arrives. Responses to earlier requests
+/// and malformed datagrams are ignored.
+pub struct UdpClient {
+    socket: UdpSocket,
+    next_request_id: u32,
+    retry: RetryPolicy,
+    buffer_size: usize,
+}
+
+impl UdpClient {
+    /// Create a client talking to the given server
+    pub fn connect<A: ToSocketAddrs>(server: A) -> io::Result<Self> {
+        let server = server.to_socket_addrs()?.next().ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidInput, "No server address given")
+        })?;
+        let bind_addr = if server.is_ipv4() { "0.0.0.0:0" } else { "[::]:0" };
+
+        let socket = UdpSocket::bind(bind_addr)?;
+        socket.connect(server)?;
+
+        // Start at a time-derived ID so a restarted client does not hit the
+        // server's duplicate cache with old request IDs
+        let seed = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.subsec_nanos())
+            .unwrap_or(0);
+
+        Ok(Self {
+            socket,
+            next_request_id: seed,
+            retry: RetryPolicy::default(),
+            buffer_size: 8192,
+        })
+    }
+
+    /// Set the retransmission policy
+    pub fn retry_policy(mut self, policy: RetryPolicy) -> Self {
+        self.retry = policy;
+        self
+    }
+
+    /// Set the receive buffer size (default: 8192)
+    pub fn buffer_size(mut self, size: usize) -> Self {
+        self.buffer_size = size;
+        self
+    }
+
+    /// Get the local address of the client socket
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.socket.local_addr()
+    }
+
+    /// Send a request and wait for its response, retrying per the retry policy
+    pub fn request(&mut self, payload: &[u8]) -> Result<Vec<u8>, ClientError> {
+        self.send_request(payload, None)
+    }
+
+    /// Send a request and wait at most `timeout` in total for its response
+    pub fn request_with_timeout(&mut self, payload: &[u8], timeout: Duration) -> Result<Vec<u8>, ClientError> {
+        self.send_request(payload, Some(Instant::now() + timeout))
+    }
+
+    fn send_request(&mut self, payload: &[u8], deadline: Option<Instant>) -> Result<Vec<u8>, ClientError> {
+        let request_id = self.next_request_id;
+        self.next_request_id = self.next_request_id.wrapping_add(1);
+
+        let mut buffer = vec![0; self.buffer_size];
+        let mut attempt_timeout = self.retry.initial_timeout;
+        let mut attempts = 0;
+
+        for sequence in 0..=self.retry.max_retries {
+            let flags = if sequence == 0 {
+                Flags::REQUEST
+            } else {
+                Flags::REQUEST | Flags::RETRANSMIT
+            };
+            let frame = Header { request_id, sequence, flags }.encode(payload);
+            self.socket.send(&frame)?;
+            attempts += 1;
+
+            let mut attempt_deadline = Instant::now() + attempt_timeout;
+            if let Some(deadline) = deadline {
+                attempt_deadline = attempt_deadline.min(deadline);
+            }
+
+            if let Some(response) = self.wait_for(request_id, attempt_deadline, &mut buffer)? {
+                return Ok(response);
+            }
+
+            if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+                break;
+            }
+
+            attempt_timeout = self.retry.next_timeout(attempt_timeout);
+        }
+
+        Err(ClientError::Timeout { attempts })
+    }
+
+    /// Wait until `deadline` for the response to `request_id`
+    fn wait_for(&self, request_id: u32, deadline: Instant, buffer: &mut [u8]) -> Result<Option<Vec<u8>>, ClientError> {
+        loop {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                return Ok(None);
+            }
+            self.socket.set_read_timeout(Some(remaining))?;
+
+            match self.socket.recv(buffer) {
+                Ok(size) => {
+                    if let Ok((header, payload)) = Header::decode(&buffer[..size]) {
+                        if header.flags.contains(Flags::RESPONSE) && header.request_id == request_id {
+                            return Ok(Some(payload.to_vec()));
+                        }
+                    }
+                }
+                Err(e) if matches!(e.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut) => {
+                    return Ok(None);
+                }
+                Err(e) => return Err(e.into()),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ServerConfig, UdpEchoServer};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::thread;
+
+    /// Echo handler that counts how often it runs
+    #[derive(Default)]
+    struct CountingEcho {
+        calls: AtomicUsize,
+    }
+
+    impl MessageHandler for CountingEcho {
+        fn handle_message(&self, message: &[u8], _from: SocketAddr) -> Option<Vec<u8>> {
+            self.calls.fetch_add(1, Ordering::SeqCst);
+            Some(message.to_vec())
+        }
+    }
+
+    /// Forwards datagrams between one client and `server`, dropping the
+    /// first `lose_requests` on the way in and `lose_responses` on the way out
+    fn lossy_proxy(server: SocketAddr, lose_requests: usize, lose_responses: usize) -> SocketAddr {
+        let front = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let back = UdpSocket::bind("127.0.0.1:0").unwrap();
+        back.connect(server).unwrap();
+        let proxy_addr = front.local_addr().unwrap();
+        let client = Arc::new(Mutex::new(None));
+
+        let (front_in, back_out, client_in) = (front.try_clone().unwrap(), back.try_clone().unwrap(), Arc::clone(&client));
+        thread::spawn(move || {
+            let mut buffer = [0; 2048];
+            for seen in 0.. {
+                let Ok((size, from)) = front_in.recv_from(&mut buffer) else { return };
+                *client_in.lock().unwrap() = Some(from);
+                if seen >= lose_requests {
+                    let _ = back_out.send(&buffer[..size]);
+                }
+            }
+        });
+        thread::spawn(move || {
+            let mut buffer = [0; 2048];
+            for seen in 0.. {
+                let Ok(size) = back.recv(&mut buffer) else { return };
+                let client = *client.lock().unwrap();
+                if let Some(client) = client {
+                    if seen >= lose_responses {
+                        let _ = front.send_to(&buffer[..size], client);
+                    }
+                }
+            }
+        });
+        proxy_addr
+    }
+
+    fn start_server(lose_requests: usize, lose_responses: usize) -> (SocketAddr, Arc<ReliableHandler<CountingEcho>>) {
+        let server = UdpEchoServer::new(ServerConfig::new().port(0)).unwrap();
+        let server_addr = server.local_addr().unwrap();
+        let reliable = Arc::new(ReliableHandler::new(CountingEcho::default()));
+
+        let _handle = server.spawn_with_handler(Arc::clone(&reliable));
+        (lossy_proxy(server_addr, lose_requests, lose_responses), reliable)
+    }
+
+    fn fast_retries() -> RetryPolicy {
+        RetryPolicy {
+            initial_timeout: Duration::from_millis(50),
+            max_retries: 4,
+            backoff: 2.0,
+            max_timeout: Duration::from_millis(400),
+        }
+    }
+
+    #[test]
+    fn test_header_round_trip() {
+        let header = Header {
+            request_id: 0xDEADBEEF,
+            sequence: 3,
+            flags: Flags::REQUEST | Flags::RETRANSMIT,
+        };
+        let frame = header.encode(b"payload");
+        assert_eq!(frame.len(), HEADER_LEN + 7);
+
+        let (decoded, payload) = Header::decode(&frame).unwrap();
+        assert_eq!(decoded, header);
+        assert_eq!(payload, b"payload");
+        assert!(decoded.flags.contains(Flags::RETRANSMIT));
+        assert!(!decoded.flags.contains(Flags::RESPONSE));
+
+        assert_eq!(Header::decode(b"UE"), Err(ProtocolError::TooShort(2)));
+        assert_eq!(Header::decode(b"XX\x01\x01\0\0\0\0\0\0\0\0"), Err(ProtocolError::BadMagic));
+        assert_eq!(Header::decode(b"UE\x09\x01\0\0\0\0\0\0\0\0"), Err(ProtocolError::UnsupportedVersion(9)));
+    }
+
+    #[test]
+    fn test_backoff_saturates() {
+        let policy = |backoff| RetryPolicy { backoff, ..RetryPolicy::default() };
+        let start = Duration::from_millis(200);
+        assert_eq!(policy(2.0).next_timeout(start), Duration::from_millis(400));
+        assert_eq!(policy(2.0).next_timeout(Duration::from_secs(5)), Duration::from_secs(2));
+        assert_eq!(policy(1e300).next_timeout(start), Duration::from_secs(2));
+        assert_eq!(policy(f64::INFINITY).next_timeout(start), Duration::from_secs(2));
+        for backoff in [-1.0, 0.5, f64::NAN] {
+            assert_eq!(policy(backoff).next_timeout(start), start);
+        }
+    }
+
+    #[test]
+    fn test_request_survives_lost_requests() {
+        let (server_addr, reliable) = start_server(2, 0);
+        let mut client = UdpClient::connect(server_addr).unwrap().retry_policy(fast_retries());
+
+        assert_eq!(client.request(b"hello").unwrap(), b"hello");
+        assert_eq!(reliable.inner().calls.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn test_lost_responses_are_replayed_from_cache() {
+        let (server_addr, reliable) = start_server(0, 2);
+        let mut client = UdpClient::connect(server_addr).unwrap().retry_policy(fast_retries());
+
+        assert_eq!(client.request(b"exactly once").unwrap(), b"exactly once");
+        assert_eq!(client.request(b"second").unwrap(), b"second");
+
+        // The retransmissions of the first request did not reach the handler
+        assert_eq!(reliable.inner().calls.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn test_request_times_out_when_everything_is_lost() {
+        let (server_addr, _reliable) = start_server(usize::MAX, 0);
+        let mut client = UdpClient::connect(server_addr).unwrap().retry_policy(fast_retries());
+
+        match client.request(b"void") {
+            Err(ClientError::Timeout { attempts }) => assert_eq!(attempts, 5),
+            other => panic!("expected timeout, got {:?}", other),
+        }
+
+        let started = Instant::now();
+        let result = client.request_with_timeout(b"void", Duration::from_millis(120));
+        assert!(matches!(result, Err(ClientError::Timeout { .. })));
+        assert!(started.elapsed() < Duration::from_millis(400));
+    }
+}
@@ -0,0 +1,87 @@
+// reload.rs
+use std::sync::{Arc, PoisonError, RwLock};
+
+use crate::filter::PacketFilter;
+use crate::ServerConfig;
+
+/// Settings a running server reads for every datagram
+pub(crate) struct Runtime {
+    pub(crate) config: ServerConfig,
+    pub(crate) filter: PacketFilter,
+}
+
+impl Runtime {
+    pub(crate) fn new(config: ServerConfig) -> Self {
+        Self {
+            filter: PacketFilter::from_config(&config),
+            config,
+        }
+    }
+}
+
+/// The current runtime, swapped as a whole on reload
+#[derive(Clone)]
+pub(crate) struct SharedRuntime(Arc<RwLock<Arc<Runtime>>>);
+
+impl SharedRuntime {
+    pub(crate) fn new(config: ServerConfig) -> Self {
+        SharedRuntime(Arc::new(RwLock::new(Arc::new(Runtime::new(config)))))
+    }
+
+    pub(crate) fn current(&self) -> Arc<Runtime> {
+        Arc::clone(&self.0.read().unwrap_or_else(PoisonError::into_inner))
+    }
+}
+
+/// Handle for changing the configuration of a running [`UdpEchoServer`](crate::UdpEchoServer)
+///
+/// Logging, message size limits, access lists, rate limits and the
+/// statistics interval take effect with the next datagram; rate limit
+/// buckets start over. Settings that belong to the sockets cannot change
+/// without binding them again, and statistics cannot be turned on or off
+/// while running, so a reload that touches them is refused.
+#[derive(Clone)]
+pub struct ReloadHandle {
+    runtime: SharedRuntime,
+}
+
+impl ReloadHandle {
+    pub(crate) fn new(runtime: SharedRuntime) -> Self {
+        Self { runtime }
+    }
+
+    /// Get a copy of the configuration currently in effect
+    pub fn config(&self) -> ServerConfig {
+        self.runtime.current().config.clone()
+    }
+
+    /// Validate `config` and apply it to the running server
+    pub fn reload(&self, config: ServerConfig) -> Result<(), String> {
+        config.validate()?;
+
+        let mut runtime = self.runtime.0.write().unwrap_or_else(PoisonError::into_inner);
+        let current = &runtime.config;
+
+        let fixed = [
+            ("host", current.host != config.host),
+            ("port", current.port != config.port),
+            ("workers", current.workers != config.workers),
+            ("buffer_size", current.buffer_size != config.buffer_size),
+            ("max_tracked_clients", current.max_tracked_clients != config.max_tracked_clients),
+            ("multicast_v4", current.multicast_v4 != config.multicast_v4),
+            ("multicast_v6", current.multicast_v6 != config.multicast_v6),
+            ("multicast_ttl", current.multicast_ttl != config.multicast_ttl),
+            ("multicast_loop", current.multicast_loop != config.multicast_loop),
+            ("broadcast", current.broadcast != config.broadcast),
+            // The reporting thread is started with the server
+            ("stats", current.stats_enabled != config.stats_enabled),
+        ];
+        let changed: Vec<&str> = fixed.iter().filter(|(_, changed)| *changed).map(|(name, _)| *name).collect();
+        if !changed.is_empty() {
+            return Err(format!("Changing {} requires a restart", changed.join(", ")));
+        }
+
+        *runtime = Arc::new(Runtime::new(config));
+        Ok(())
+    }
+}
@@ -0,0 +1,869 @@
+// secure.rs
+use std::collections::HashMap;
+use std::fmt;
+use std::io;
+use std::net::{SocketAddr, ToSocketAddrs, UdpSocket};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use aes_gcm::aead::rand_core::RngCore;
+use aes_gcm::aead::{Aead, KeyInit, OsRng, Payload};
+use aes_gcm::{Aes256Gcm, Nonce};
+use hkdf::Hkdf;
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+use crate::{ClientError, MessageHandler, RejectReason, RetryPolicy};
+
+type HmacSha256 = Hmac<Sha256>;
+
+const CLIENT_HELLO: u8 = 1;
+const SERVER_HELLO: u8 = 2;
+const DATA: u8 = 3;
+const HELLO_RETRY: u8 = 4;
+const VERSION: u8 = 1;
+
+const RANDOM_LEN: usize = 32;
+const MAC_LEN: usize = 32;
+const TAG_LEN: usize = 16;
+const CLIENT_HELLO_LEN: usize = 2 + RANDOM_LEN + MAC_LEN;
+/// Time the cookie was issued (8) and its MAC
+const COOKIE_LEN: usize = 8 + MAC_LEN;
+const HELLO_RETRY_LEN: usize = 2 + COOKIE_LEN;
+const SERVER_HELLO_LEN: usize = 2 + 8 + RANDOM_LEN + MAC_LEN;
+const DATA_HEADER_LEN: usize = 1 + 8 + 8;
+
+/// Number of sequence numbers the replay window tracks
+pub const REPLAY_WINDOW: u64 = 64;
+
+/// How long a hello retry cookie is accepted
+const COOKIE_LIFETIME: Duration = Duration::from_secs(30);
+
+/// Errors produced while processing secure channel packets
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SecureError {
+    /// The packet is shorter than its type requires
+    TooShort(usize),
+    /// The packet type or protocol version is unknown
+    UnknownPacket(u8),
+    /// A handshake message was not authenticated with the pre-shared key
+    BadHandshake,
+    /// A data packet arrived for a session that does not exist
+    UnknownSession,
+    /// The sequence number was already received or fell out of the replay window
+    Replayed(u64),
+    /// The packet failed AEAD authentication, e.g. because it was modified
+    Decrypt,
+    /// The server refused a new session because it tracks too many
+    TooManySessions,
+}
+
+impl fmt::Display for SecureError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SecureError::TooShort(len) => write!(f, "Packet too short: {} bytes", len),
+            SecureError::UnknownPacket(kind) => write!(f, "Unknown packet type or version: {}", kind),
+            SecureError::BadHandshake => write!(f, "Handshake failed authentication"),
+            SecureError::UnknownSession => write!(f, "No session for this packet"),
+            SecureError::Replayed(seq) => write!(f, "Replayed or stale packet: sequence {}", seq),
+            SecureError::Decrypt => write!(f, "Packet failed authentication"),
+            SecureError::TooManySessions => write!(f, "Too many secure sessions"),
+        }
+    }
+}
+
+impl std::error::Error for SecureError {}
+
+/// Sliding window of received sequence numbers
+///
+/// Accepts every sequence number once, in any order, as long as it is not
+/// more than [`REPLAY_WINDOW`] behind the highest one seen so far.
+#[derive(Debug, Clone, Default)]
+struct ReplayWindow {
+    /// Highest sequence number accepted so far; sequence numbers start at 1
+    highest: u64,
+    /// Bit `i` is set when `highest - i` was received
+    seen: u64,
+}
+
+impl ReplayWindow {
+    fn check(&self, seq: u64) -> Result<(), SecureError> {
+        if seq == 0 {
+            return Err(SecureError::Replayed(seq));
+        }
+        if seq > self.highest {
+            return Ok(());
+        }
+        let offset = self.highest - seq;
+        if offset >= REPLAY_WINDOW || self.seen & (1 << offset) != 0 {
+            return Err(SecureError::Replayed(seq));
+        }
+        Ok(())
+    }
+
+    /// Mark a sequence number as received; only call after `check` and decryption succeeded
+    fn accept(&mut self, seq: u64) {
+        if seq > self.highest {
+            let shift = seq - self.highest;
+            self.seen = if shift >= REPLAY_WINDOW { 0 } else { self.seen << shift };
+            self.seen |= 1;
+            self.highest = seq;
+        } else {
+            self.seen |= 1 << (self.highest - seq);
+        }
+    }
+}
+
+/// Keys and counters of one established session, seen from one side
+struct SessionKeys {
+    id: u64,
+    send: Aes256Gcm,
+    recv: Aes256Gcm,
+    send_seq: u64,
+    window: ReplayWindow,
+}
+
+impl SessionKeys {
+    /// Derive both directions' keys from the pre-shared key and the handshake randoms
+    fn derive(psk: &[u8], id: u64, client_random: &[u8], server_random: &[u8], is_server: bool) -> Self {
+        let mut salt = Vec::with_capacity(2 * RANDOM_LEN + 8);
+        salt.extend_from_slice(client_random);
+        salt.extend_from_slice(server_random);
+        salt.extend_from_slice(&id.to_be_bytes());
+
+        let hkdf = Hkdf::<Sha256>::new(Some(&salt), psk);
+        let mut client_key = [0u8; 32];
+        let mut server_key = [0u8; 32];
+        hkdf.expand(b"udp-echo secure v1 client write", &mut client_key)
+            .expect("32 bytes is a valid HKDF output length");
+        hkdf.expand(b"udp-echo secure v1 server write", &mut server_key)
+            .expect("32 bytes is a valid HKDF output length");
+
+        let client = Aes256Gcm::new_from_slice(&client_key).expect("AES-256 key is 32 bytes");
+        let server = Aes256Gcm::new_from_slice(&server_key).expect("AES-256 key is 32 bytes");
+        let (send, recv) = if is_server { (server, client) } else { (client, server) };
+
+        Self {
+            id,
+            send,
+            recv,
+            send_seq: 0,
+            window: ReplayWindow::default(),
+        }
+    }
+
+    /// Encrypt a payload into a data packet with the next sequence number
+    fn seal(&mut self, payload: &[u8]) -> Vec<u8> {
+        self.send_seq += 1;
+
+        let mut packet = Vec::with_capacity(DATA_HEADER_LEN + payload.len() + TAG_LEN);
+        packet.push(DATA);
+        packet.extend_from_slice(&self.id.to_be_bytes());
+        packet.extend_from_slice(&self.send_seq.to_be_bytes());
+
+        let ciphertext = self.send
+            .encrypt(&nonce(self.send_seq), Payload { msg: payload, aad: &packet })
+            .expect("AES-GCM encryption does not fail for in-memory buffers");
+        packet.extend_from_slice(&ciphertext);
+        packet
+    }
+
+    /// Authenticate and decrypt a data packet, rejecting replays
+    fn open(&mut self, packet: &[u8]) -> Result<Vec<u8>, SecureError> {
+        let (id, seq) = data_header(packet)?;
+        if id != self.id {
+            return Err(SecureError::UnknownSession);
+        }
+        self.window.check(seq)?;
+
+        let (header, ciphertext) = packet.split_at(DATA_HEADER_LEN);
+        let plaintext = self.recv
+            .decrypt(&nonce(seq), Payload { msg: ciphertext, aad: header })
+            .map_err(|_| SecureError::Decrypt)?;
+
+        // Only authenticated packets move the window
+        self.window.accept(seq);
+        Ok(plaintext)
+    }
+}
+
+/// Build the GCM nonce for a sequence number; every key has its own direction
+fn nonce(seq: u64) -> Nonce<aes_gcm::aead::consts::U12> {
+    let mut nonce = [0u8; 12];
+    nonce[4..].copy_from_slice(&seq.to_be_bytes());
+    Nonce::from(nonce)
+}
+
+/// Read session ID and sequence number from a data packet
+fn data_header(packet: &[u8]) -> Result<(u64, u64), SecureError> {
+    if packet.len() < DATA_HEADER_LEN + TAG_LEN {
+        return Err(SecureError::TooShort(packet.len()));
+    }
+    if packet[0] != DATA {
+        return Err(SecureError::UnknownPacket(packet[0]));
+    }
+    let id = u64::from_be_bytes(packet[1..9].try_into().unwrap());
+    let seq = u64::from_be_bytes(packet[9..17].try_into().unwrap());
+    Ok((id, seq))
+}
+
+fn handshake_mac(psk: &[u8], label: &[u8], parts: &[&[u8]]) -> HmacSha256 {
+    let mut mac = <HmacSha256 as Mac>::new_from_slice(psk).expect("HMAC accepts keys of any length");
+    mac.update(label);
+    for part in parts {
+        mac.update(part);
+    }
+    mac
+}
+
+fn random<const N: usize>() -> [u8; N] {
+    let mut bytes = [0u8; N];
+    OsRng.fill_bytes(&mut bytes);
+    bytes
+}
+
+/// `CLIENT_HELLO | version | client random (32) | [cookie (40)] | HMAC-SHA256(psk) (32)`
+///
+/// The first hello carries no cookie; the second echoes the server's.
+fn client_hello(psk: &[u8], client_random: &[u8; RANDOM_LEN], cookie: Option<&[u8]>) -> Vec<u8> {
+    let mut hello = vec![CLIENT_HELLO, VERSION];
+    hello.extend_from_slice(client_random);
+    hello.extend_from_slice(cookie.unwrap_or_default());
+    let mac = handshake_mac(psk, b"client hello", &[&hello]).finalize().into_bytes();
+    hello.extend_from_slice(&mac);
+    hello
+}
+
+/// `SERVER_HELLO | version | session id (8) | server random (32) | HMAC-SHA256(psk) (32)`
+///
+/// The MAC also covers the client random, binding the reply to its hello.
+fn server_hello(psk: &[u8], client_random: &[u8], id: u64, server_random: &[u8; RANDOM_LEN]) -> Vec<u8> {
+    let mut hello = vec![SERVER_HELLO, VERSION];
+    hello.extend_from_slice(&id.to_be_bytes());
+    hello.extend_from_slice(server_random);
+    let mac = handshake_mac(psk, b"server hello", &[client_random, &hello]).finalize().into_bytes();
+    hello.extend_from_slice(&mac);
+    hello
+}
+
+/// `HELLO_RETRY | version | cookie (40)`, the cookie to echo in the next hello
+fn hello_retry_cookie(packet: &[u8]) -> Option<&[u8]> {
+    if packet.len() != HELLO_RETRY_LEN || packet[0] != HELLO_RETRY || packet[1] != VERSION {
+        return None;
+    }
+    Some(&packet[2..])
+}
+
+struct ServerSession {
+    keys: SessionKeys,
+    client_random: [u8; RANDOM_LEN],
+    /// Sent again when the client retransmits its hello
+    hello: Vec<u8>,
+    last_seen: Instant,
+    /// A newer handshake from the same address, which replaces this session
+    /// once a data packet proves the client holds its keys
+    pending: Option<Box<ServerSession>>,
+}
+
+/// Server side of the secure channel
+///
+/// Wraps a [`MessageHandler`] that works on plaintext payloads. A client
+/// proves knowledge of the pre-shared key in its hello; the server answers
+/// with a session ID and both sides derive per-direction AES-256-GCM keys with
+/// HKDF-SHA256. Data packets carry the session ID and a sequence number that
+/// is authenticated as associated data and checked against a sliding replay
+/// window, so reordered packets are accepted once and replays are dropped.
+///
+/// Before it keeps any state for a hello, the server makes the client echo a
+/// cookie: a MAC over the client's address, the time and its random, under a
+/// key that never leaves the server. A hello replayed from spoofed addresses
+/// therefore creates no sessions, and a cookie is only good for 30 seconds.
+///
+/// Sessions are tracked per peer address. A new hello from an address that
+/// already has a session does not replace it right away: the old session
+/// stays in use until a data packet authenticates under the new keys, so a
+/// replayed hello cannot end a live session. Packets that fail any check are
+/// reported through `on_error` and dropped without a response.
+pub struct SecureHandler<H> {
+    inner: H,
+    psk: Vec<u8>,
+    cookie_key: [u8; 32],
+    /// Cookies carry their issue time in seconds since this instant
+    started: Instant,
+    sessions: Mutex<HashMap<SocketAddr, ServerSession>>,
+    session_timeout: Duration,
+    max_sessions: usize,
+}
+
+impl<H: MessageHandler> SecureHandler<H> {
+    /// Wrap a plaintext handler, authenticating clients with a pre-shared key
+    pub fn new<K: Into<Vec<u8>>>(inner: H, psk: K) -> Self {
+        Self {
+            inner,
+            psk: psk.into(),
+            cookie_key: random(),
+            started: Instant::now(),
+            sessions: Mutex::new(HashMap::new()),
+            session_timeout: Duration::from_secs(300),
+            max_sessions: 1024,
+        }
+    }
+
+    /// Set how long an idle session is kept (default: 300s)
+    pub fn session_timeout(mut self, timeout: Duration) -> Self {
+        self.session_timeout = timeout;
+        self
+    }
+
+    /// Set the maximum number of concurrent sessions (default: 1024)
+    pub fn max_sessions(mut self, count: usize) -> Self {
+        self.max_sessions = count.max(1);
+        self
+    }
+
+    /// Get a reference to the wrapped handler
+    pub fn inner(&self) -> &H {
+        &self.inner
+    }
+
+    /// Get the number of established sessions
+    pub fn session_count(&self) -> usize {
+        self.sessions.lock().unwrap().len()
+    }
+
+    /// MAC binding a cookie to the client's address, its issue time and the client random
+    fn cookie_mac(&self, from: SocketAddr, issued: u64, client_random: &[u8]) -> HmacSha256 {
+        handshake_mac(&self.cookie_key, b"cookie", &[from.to_string().as_bytes(), &issued.to_be_bytes(), client_random])
+    }
+
+    fn hello_retry(&self, from: SocketAddr, client_random: &[u8]) -> Vec<u8> {
+        let issued = self.started.elapsed().as_secs();
+        let mut retry = vec![HELLO_RETRY, VERSION];
+        retry.extend_from_slice(&issued.to_be_bytes());
+        retry.extend_from_slice(&self.cookie_mac(from, issued, client_random).finalize().into_bytes());
+        retry
+    }
+
+    fn cookie_is_valid(&self, cookie: &[u8], from: SocketAddr, client_random: &[u8]) -> bool {
+        if cookie.len() != COOKIE_LEN {
+            return false;
+        }
+        let (issued, mac) = cookie.split_at(8);
+        let issued = u64::from_be_bytes(issued.try_into().unwrap());
+        let age = self.started.elapsed().as_secs().checked_sub(issued);
+        age.is_some_and(|age| age < COOKIE_LIFETIME.as_secs())
+            && self.cookie_mac(from, issued, client_random).verify_slice(mac).is_ok()
+    }
+
+    fn handle_hello(&self, message: &[u8], from: SocketAddr) -> Result<Vec<u8>, SecureError> {
+        if message.len() != CLIENT_HELLO_LEN && message.len() != CLIENT_HELLO_LEN + COOKIE_LEN {
+            return Err(SecureError::TooShort(message.len()));
+        }
+        if message[1] != VERSION {
+            return Err(SecureError::UnknownPacket(message[1]));
+        }
+        let (body, mac) = message.split_at(message.len() - MAC_LEN);
+        handshake_mac(&self.psk, b"client hello", &[body])
+            .verify_slice(mac)
+            .map_err(|_| SecureError::BadHandshake)?;
+
+        let (client_random, cookie) = body[2..].split_at(RANDOM_LEN);
+        let client_random: [u8; RANDOM_LEN] = client_random.try_into().unwrap();
+        let mut sessions = self.sessions.lock().unwrap();
+
+        // A retransmitted hello gets the same answer so the session survives a lost reply
+        if let Some(session) = sessions.get_mut(&from) {
+            if session.client_random == client_random {
+                session.last_seen = Instant::now();
+                return Ok(session.hello.clone());
+            }
+            if let Some(pending) = session.pending.as_ref().filter(|pending| pending.client_random == client_random) {
+                return Ok(pending.hello.clone());
+            }
+        }
+
+        // Nothing is kept until the client proves it receives at its address
+        if !self.cookie_is_valid(cookie, from, &client_random) {
+            return Ok(self.hello_retry(from, &client_random));
+        }
+
+        if !sessions.contains_key(&from) && sessions.len() >= self.max_sessions {
+            let timeout = self.session_timeout;
+            sessions.retain(|_, session| session.last_seen.elapsed() < timeout);
+            if sessions.len() >= self.max_sessions {
+                return Err(SecureError::TooManySessions);
+            }
+        }
+
+        let id = u64::from_be_bytes(random());
+        let server_random = random();
+        let hello = server_hello(&self.psk, &client_random, id, &server_random);
+        let session = ServerSession {
+            keys: SessionKeys::derive(&self.psk, id, &client_random, &server_random, true),
+            client_random,
+            hello: hello.clone(),
+            last_seen: Instant::now(),
+            pending: None,
+        };
+        match sessions.get_mut(&from) {
+            Some(current) => current.pending = Some(Box::new(session)),
+            None => {
+                sessions.insert(from, session);
+            }
+        }
+        Ok(hello)
+    }
+
+    fn handle_data(&self, message: &[u8], from: SocketAddr) -> Result<Option<Vec<u8>>, SecureError> {
+        let (id, _) = data_header(message)?;
+        let plaintext = {
+            let mut sessions = self.sessions.lock().unwrap();
+            let session = sessions.get_mut(&from).ok_or(SecureError::UnknownSession)?;
+
+            match session.pending.as_mut() {
+                Some(pending) if pending.keys.id == id => {
+                    let plaintext = pending.keys.open(message)?;
+                    // The client holds the new keys, so its new session replaces the old one
+                    let mut pending = session.pending.take().expect("matched above");
+                    pending.last_seen = Instant::now();
+                    *session = *pending;
+                    plaintext
+                }
+                _ if session.last_seen.elapsed() >= self.session_timeout => {
+                    match session.pending.take() {
+                        Some(pending) => *session = *pending,
+                        None => {
+                            sessions.remove(&from);
+                        }
+                    }
+                    return Err(SecureError::UnknownSession);
+                }
+                _ => {
+                    let plaintext = session.keys.open(message)?;
+                    session.last_seen = Instant::now();
+                    plaintext
+                }
+            }
+        };
+
+        let response = match self.inner.handle_message(&plaintext, from) {
+            Some(response) => response,
+            None => return Ok(None),
+        };
+
+        // The client may have started a new session while the handler ran
+        let mut sessions = self.sessions.lock().unwrap();
+        match sessions.get_mut(&from) {
+            Some(session) if session.keys.id == id => Ok(Some(session.keys.seal(&response))),
+            _ => Ok(None),
+        }
+    }
+}
+
+impl<H: MessageHandler> MessageHandler for SecureHandler<H> {
+    fn handle_message(&self, message: &[u8], from: SocketAddr) -> Option<Vec<u8>> {
+        let result = match message.first() {
+            Some(&CLIENT_HELLO) => self.handle_hello(message, from).map(Some),
+            Some(&DATA) => self.handle_data(message, from),
+            Some(&kind) => Err(SecureError::UnknownPacket(kind)),
+            None => Err(SecureError::TooShort(0)),
+        };
+
+        result.unwrap_or_else(|e| {
+            self.inner.on_error(&format!("Secure channel: {}", e), Some(from));
+            None
+        })
+    }
+
+    fn on_error(&self, error: &str, from: Option<SocketAddr>) {
+        self.inner.on_error(error, from);
+    }
+
+    fn on_rejected(&self, from: SocketAddr, reason: RejectReason) {
+        self.inner.on_rejected(from, reason);
+    }
+}
+
+/// Client side of the secure channel
+///
+/// `connect` runs the handshake, retransmitting the hello per the retry
+/// policy. Afterwards payloads are encrypted with the session keys; responses
+/// that fail authentication or replay checks are ignored.
+pub struct SecureClient {
+    socket: UdpSocket,
+    keys: SessionKeys,
+    timeout: Duration,
+    buffer_size: usize,
+}
+
+impl SecureClient {
+    /// Connect to a server and establish a session with the pre-shared key
+    pub fn connect<A: ToSocketAddrs>(server: A, psk: &[u8]) -> Result<Self, ClientError> {
+        Self::connect_with_policy(server, psk, &RetryPolicy::default())
+    }
+
+    /// Connect with a custom retransmission policy for the handshake
+    pub fn connect_with_policy<A: ToSocketAddrs>(
+        server: A,
+        psk: &[u8],
+        policy: &RetryPolicy,
+    ) -> Result<Self, ClientError> {
+        let server = server.to_socket_addrs()?.next().ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidInput, "No server address given")
+        })?;
+        let bind_addr = if server.is_ipv4() { "0.0.0.0:0" } else { "[::]:0" };
+        let socket = UdpSocket::bind(bind_addr)?;
+        socket.connect(server)?;
+
+        let client_random = random();
+        let mut hello = client_hello(psk, &client_random, None);
+        let mut buffer = [0u8; 512];
+        let mut attempt_timeout = policy.initial_timeout;
+
+        for attempt in 0..=policy.max_retries {
+            socket.send(&hello)?;
+            let deadline = Instant::now() + attempt_timeout;
+
+            while let Some(size) = recv_until(&socket, &mut buffer, deadline)? {
+                if let Some(cookie) = hello_retry_cookie(&buffer[..size]) {
+                    // Echo the cookie right away; retransmissions carry it too
+                    hello = client_hello(psk, &client_random, Some(cookie));
+                    socket.send(&hello)?;
+                } else if let Some((id, server_random)) = verify_server_hello(psk, &client_random, &buffer[..size]) {
+                    return Ok(Self {
+                        socket,
+                        keys: SessionKeys::derive(psk, id, &client_random, &server_random, false),
+                        timeout: Duration::from_secs(1),
+                        buffer_size: 8192,
+                    });
+                }
+            }
+
+            if attempt == policy.max_retries {
+                break;
+            }
+            attempt_timeout = policy.next_timeout(attempt_timeout);
+        }
+
+        Err(ClientError::Timeout { attempts: policy.max_retries + 1 })
+    }
+
+    /// Set how long `recv` and `request` wait for a response (default: 1s)
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Set the receive buffer size (default: 8192)
+    pub fn buffer_size(mut self, size: usize) -> Self {
+        self.buffer_size = size;
+        self
+    }
+
+    /// Get the local address of the client socket
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.socket.local_addr()
+    }
+
+    /// Encrypt and send a payload
+    pub fn send(&mut self, payload: &[u8]) -> Result<(), ClientError> {
+        let packet = self.keys.seal(payload);
+        self.socket.send(&packet)?;
+        Ok(())
+    }
+
+    /// Wait for the next authentic packet from the server and decrypt it
+    pub fn recv(&mut self) -> Result<Vec<u8>, ClientError> {
+        let deadline = Instant::now() + self.timeout;
+        let mut buffer = vec![0; self.buffer_size];
+
+        while let Some(size) = recv_until(&self.socket, &mut buffer, deadline)? {
+            if let Ok(plaintext) = self.keys.open(&buffer[..size]) {
+                return Ok(plaintext);
+            }
+        }
+        Err(ClientError::Timeout { attempts: 1 })
+    }
+
+    /// Send a payload and wait for the response
+    pub fn request(&mut self, payload: &[u8]) -> Result<Vec<u8>, ClientError> {
+        self.send(payload)?;
+        self.recv()
+    }
+}
+
+/// Receive one datagram, or `None` once `deadline` has passed
+fn recv_until(socket: &UdpSocket, buffer: &mut [u8], deadline: Instant) -> io::Result<Option<usize>> {
+    let remaining = deadline.saturating_duration_since(Instant::now());
+    if remaining.is_zero() {
+        return Ok(None);
+    }
+    socket.set_read_timeout(Some(remaining))?;
+
+    match socket.recv(buffer) {
+        Ok(size) => Ok(Some(size)),
+        Err(e) if matches!(e.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut) => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+fn verify_server_hello(psk: &[u8], client_random: &[u8], packet: &[u8]) -> Option<(u64, [u8; RANDOM_LEN])> {
+    if packet.len() != SERVER_HELLO_LEN || packet[0] != SERVER_HELLO || packet[1] != VERSION {
+        return None;
+    }
+    let (body, mac) = packet.split_at(SERVER_HELLO_LEN - MAC_LEN);
+    handshake_mac(psk, b"server hello", &[client_random, body])
+        .verify_slice(mac)
+        .ok()?;
+
+    let id = u64::from_be_bytes(body[2..10].try_into().unwrap());
+    let server_random = body[10..].try_into().unwrap();
+    Some((id, server_random))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ServerConfig, UdpEchoServer};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    const PSK: &[u8] = b"correct horse battery staple";
+
+    /// Echo handler that counts handler calls and reported errors
+    #[derive(Default)]
+    struct CountingEcho {
+        calls: AtomicUsize,
+        errors: AtomicUsize,
+    }
+
+    impl MessageHandler for CountingEcho {
+        fn handle_message(&self, message: &[u8], _from: SocketAddr) -> Option<Vec<u8>> {
+            self.calls.fetch_add(1, Ordering::SeqCst);
+            Some(message.to_vec())
+        }
+
+        fn on_error(&self, _error: &str, _from: Option<SocketAddr>) {
+            self.errors.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    fn start_server() -> (SocketAddr, Arc<SecureHandler<CountingEcho>>) {
+        let server = UdpEchoServer::new(ServerConfig::new().port(0)).unwrap();
+        let server_addr = server.local_addr().unwrap();
+        let handler = Arc::new(SecureHandler::new(CountingEcho::default(), PSK));
+        let _handle = server.spawn_with_handler(Arc::clone(&handler));
+        (server_addr, handler)
+    }
+
+    fn connect(server_addr: SocketAddr) -> SecureClient {
+        SecureClient::connect(server_addr, PSK)
+            .unwrap()
+            .timeout(Duration::from_millis(200))
+    }
+
+    #[test]
+    fn test_replay_window() {
+        let mut window = ReplayWindow::default();
+        for seq in [3, 1, 2, 10] {
+            assert_eq!(window.check(seq), Ok(()));
+            window.accept(seq);
+        }
+        assert_eq!(window.check(2), Err(SecureError::Replayed(2)));
+        assert_eq!(window.check(0), Err(SecureError::Replayed(0)));
+        assert_eq!(window.check(5), Ok(()));
+
+        window.accept(100);
+        assert_eq!(window.check(36), Err(SecureError::Replayed(36)));
+        assert_eq!(window.check(37), Ok(()));
+        assert_eq!(window.check(100), Err(SecureError::Replayed(100)));
+    }
+
+    #[test]
+    fn test_encrypted_round_trip() {
+        let (server_addr, handler) = start_server();
+        let mut client = connect(server_addr);
+
+        assert_eq!(client.request(b"secret").unwrap(), b"secret");
+        assert_eq!(client.request(b"again").unwrap(), b"again");
+        assert_eq!(handler.session_count(), 1);
+
+        // The payload never travels in plaintext
+        let packet = client.keys.seal(b"plaintext marker");
+        assert!(!packet.windows(16).any(|w| w == b"plaintext marker"));
+    }
+
+    #[test]
+    fn test_wrong_key_fails_handshake() {
+        let (server_addr, handler) = start_server();
+        let policy = RetryPolicy {
+            initial_timeout: Duration::from_millis(50),
+            max_retries: 1,
+            ..RetryPolicy::default()
+        };
+
+        let result = SecureClient::connect_with_policy(server_addr, b"wrong key", &policy);
+        assert!(matches!(result, Err(ClientError::Timeout { attempts: 2 })));
+        assert_eq!(handler.session_count(), 0);
+        assert_eq!(handler.inner().errors.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn test_tampered_packet_is_dropped() {
+        let (server_addr, handler) = start_server();
+        let mut client = connect(server_addr);
+
+        let mut packet = client.keys.seal(b"do not touch");
+        let last = packet.len() - 1;
+        packet[last] ^= 0x01;
+        client.socket.send(&packet).unwrap();
+        assert!(matches!(client.recv(), Err(ClientError::Timeout { .. })));
+
+        // Modifying the authenticated header is detected as well
+        let mut packet = client.keys.seal(b"do not touch");
+        packet[16] ^= 0x01;
+        client.socket.send(&packet).unwrap();
+        assert!(matches!(client.recv(), Err(ClientError::Timeout { .. })));
+
+        assert_eq!(handler.inner().calls.load(Ordering::SeqCst), 0);
+        assert_eq!(handler.inner().errors.load(Ordering::SeqCst), 2);
+
+        // The session is still usable afterwards
+        assert_eq!(client.request(b"intact").unwrap(), b"intact");
+    }
+
+    #[test]
+    fn test_replayed_packet_is_dropped() {
+        let (server_addr, handler) = start_server();
+        let mut client = connect(server_addr);
+
+        let packet = client.keys.seal(b"only once");
+        client.socket.send(&packet).unwrap();
+        assert_eq!(client.recv().unwrap(), b"only once");
+
+        client.socket.send(&packet).unwrap();
+        assert!(matches!(client.recv(), Err(ClientError::Timeout { .. })));
+        assert_eq!(handler.inner().calls.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn test_out_of_order_packets_are_accepted() {
+        let (server_addr, handler) = start_server();
+        let mut client = connect(server_addr);
+
+        let first = client.keys.seal(b"first");
+        let second = client.keys.seal(b"second");
+        let third = client.keys.seal(b"third");
+
+        for packet in [&third, &first, &second] {
+            client.socket.send(packet).unwrap();
+        }
+        let mut responses = vec![client.recv().unwrap(), client.recv().unwrap(), client.recv().unwrap()];
+        responses.sort();
+        assert_eq!(responses, [b"first".to_vec(), b"second".to_vec(), b"third".to_vec()]);
+
+        // Packets older than the replay window are rejected even if never seen
+        let stale = client.keys.seal(b"stale");
+        for _ in 0..REPLAY_WINDOW {
+            client.keys.seal(b"skipped");
+        }
+        client.socket.send(&client.keys.seal(b"newest")).unwrap();
+        assert_eq!(client.recv().unwrap(), b"newest");
+        client.socket.send(&stale).unwrap();
+        assert!(matches!(client.recv(), Err(ClientError::Timeout { .. })));
+        assert_eq!(handler.inner().calls.load(Ordering::SeqCst), 4);
+    }
+
+    #[test]
+    fn test_sessions_are_per_peer() {
+        let (server_addr, handler) = start_server();
+        let mut alice = connect(server_addr);
+        let mut bob = connect(server_addr);
+        assert_eq!(handler.session_count(), 2);
+
+        // A packet sealed for one peer's session is rejected from another address
+        let packet = alice.keys.seal(b"alice");
+        bob.socket.send(&packet).unwrap();
+        assert!(matches!(bob.recv(), Err(ClientError::Timeout { .. })));
+
+        assert_eq!(alice.request(b"alice").unwrap(), b"alice");
+        assert_eq!(bob.request(b"bob").unwrap(), b"bob");
+    }
+
+    /// Send a hello from `socket` and return the reply
+    fn exchange(socket: &UdpSocket, hello: &[u8]) -> Vec<u8> {
+        socket.send(hello).unwrap();
+        let mut buffer = [0u8; 512];
+        let deadline = Instant::now() + Duration::from_secs(1);
+        let size = recv_until(socket, &mut buffer, deadline).unwrap().expect("no reply to the hello");
+        buffer[..size].to_vec()
+    }
+
+    /// Run the handshake from the client's address and return the server's hello
+    fn send_hello(client: &SecureClient, client_random: &[u8; RANDOM_LEN]) -> (u64, [u8; RANDOM_LEN]) {
+        let mut reply = exchange(&client.socket, &client_hello(PSK, client_random, None));
+        // A retransmitted hello is answered straight away
+        if let Some(cookie) = hello_retry_cookie(&reply) {
+            reply = exchange(&client.socket, &client_hello(PSK, client_random, Some(cookie)));
+        }
+        verify_server_hello(PSK, client_random, &reply).expect("server hello")
+    }
+
+    #[test]
+    fn test_replayed_hello_from_other_addresses_keeps_no_state() {
+        let (server_addr, handler) = start_server();
+        let client = connect(server_addr);
+        let client_random = random();
+        let first = client_hello(PSK, &client_random, None);
+        let cookie = hello_retry_cookie(&exchange(&client.socket, &first)).unwrap().to_vec();
+        let second = client_hello(PSK, &client_random, Some(&cookie));
+
+        // Sniffed hellos replayed from other addresses only get a new cookie
+        for _ in 0..3 {
+            let spoofed = UdpSocket::bind("127.0.0.1:0").unwrap();
+            spoofed.connect(server_addr).unwrap();
+            for hello in [&first, &second] {
+                assert!(hello_retry_cookie(&exchange(&spoofed, hello)).is_some());
+            }
+        }
+        assert_eq!(handler.session_count(), 1);
+
+        // A cookie is bound to its address and expires
+        let mut handler = SecureHandler::new(CountingEcho::default(), PSK);
+        handler.started = Instant::now().checked_sub(Duration::from_secs(60)).unwrap();
+        let from = client.local_addr().unwrap();
+        let cookie = &handler.hello_retry(from, &client_random)[2..];
+        assert!(handler.cookie_is_valid(cookie, from, &client_random));
+        assert!(!handler.cookie_is_valid(cookie, server_addr, &client_random));
+        let mut expired = 0u64.to_be_bytes().to_vec();
+        expired.extend_from_slice(&handler.cookie_mac(from, 0, &client_random).finalize().into_bytes());
+        assert!(!handler.cookie_is_valid(&expired, from, &client_random));
+    }
+
+    #[test]
+    fn test_replayed_hello_keeps_session() {
+        let (server_addr, handler) = start_server();
+        let mut client = connect(server_addr);
+        assert_eq!(client.request(b"before").unwrap(), b"before");
+
+        // An old hello of this address, replayed by someone without the keys
+        let old_random = random();
+        send_hello(&client, &old_random);
+        send_hello(&client, &old_random);
+        assert_eq!(client.request(b"after replay").unwrap(), b"after replay");
+        assert_eq!(handler.session_count(), 1);
+
+        // A client that really reconnects switches over with its first data packet
+        let new_random = random();
+        let (id, server_random) = send_hello(&client, &new_random);
+        let old_keys = std::mem::replace(
+            &mut client.keys,
+            SessionKeys::derive(PSK, id, &new_random, &server_random, false),
+        );
+        assert_eq!(client.request(b"new session").unwrap(), b"new session");
+
+        let mut client_with_old_keys = SecureClient { keys: old_keys, ..client };
+        assert!(matches!(client_with_old_keys.request(b"old session"), Err(ClientError::Timeout { .. })));
+        assert_eq!(handler.session_count(), 1);
+    }
+}
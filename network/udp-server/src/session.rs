@@ -0,0 +1,533 @@
+// session.rs
+use std::any::{Any, TypeId};
+use std::collections::HashMap;
+use std::fmt;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError, TryLockError};
+use std::time::{Duration, Instant};
+
+use crate::{MessageHandler, RejectReason};
+
+/// State kept for one peer between datagrams
+///
+/// Besides bookkeeping, a session stores at most one value per type, so
+/// handlers can keep their own typed per-peer state without a peer map.
+pub struct Session {
+    peer: SocketAddr,
+    started_at: Instant,
+    last_seen: Instant,
+    messages: u64,
+    closed: bool,
+    /// Set once the session has left the table and its end callback ran
+    ended: bool,
+    values: HashMap<TypeId, Box<dyn Any + Send>>,
+}
+
+impl Session {
+    fn new(peer: SocketAddr) -> Self {
+        let now = Instant::now();
+        Self {
+            peer,
+            started_at: now,
+            last_seen: now,
+            messages: 0,
+            closed: false,
+            ended: false,
+            values: HashMap::new(),
+        }
+    }
+
+    /// Get the address of the peer
+    pub fn peer(&self) -> SocketAddr {
+        self.peer
+    }
+
+    /// Get the time the session started
+    pub fn started_at(&self) -> Instant {
+        self.started_at
+    }
+
+    /// Get the time the last datagram from the peer arrived
+    pub fn last_seen(&self) -> Instant {
+        self.last_seen
+    }
+
+    /// Get the number of datagrams received from the peer, including the current one
+    pub fn message_count(&self) -> u64 {
+        self.messages
+    }
+
+    /// End the session once the current datagram has been handled
+    pub fn close(&mut self) {
+        self.closed = true;
+    }
+
+    /// Store a value, returning the previous value of the same type
+    pub fn insert<T: Any + Send>(&mut self, value: T) -> Option<T> {
+        self.values
+            .insert(TypeId::of::<T>(), Box::new(value))
+            .and_then(|old| old.downcast().ok().map(|old| *old))
+    }
+
+    /// Get the stored value of type `T`
+    pub fn get<T: Any + Send>(&self) -> Option<&T> {
+        self.values.get(&TypeId::of::<T>()).and_then(|value| value.downcast_ref())
+    }
+
+    /// Get the stored value of type `T` mutably
+    pub fn get_mut<T: Any + Send>(&mut self) -> Option<&mut T> {
+        self.values.get_mut(&TypeId::of::<T>()).and_then(|value| value.downcast_mut())
+    }
+
+    /// Get the stored value of type `T`, inserting the result of `init` if there is none
+    pub fn get_or_insert_with<T: Any + Send>(&mut self, init: impl FnOnce() -> T) -> &mut T {
+        self.values
+            .entry(TypeId::of::<T>())
+            .or_insert_with(|| Box::new(init()))
+            .downcast_mut()
+            .expect("session values are keyed by their type")
+    }
+
+    /// Remove and return the stored value of type `T`
+    pub fn remove<T: Any + Send>(&mut self) -> Option<T> {
+        self.values
+            .remove(&TypeId::of::<T>())
+            .and_then(|value| value.downcast().ok().map(|value| *value))
+    }
+}
+
+impl fmt::Debug for Session {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Session")
+            .field("peer", &self.peer)
+            .field("messages", &self.messages)
+            .field("closed", &self.closed)
+            .field("values", &self.values.len())
+            .finish()
+    }
+}
+
+/// Why a session ended
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SessionEndReason {
+    /// No datagram arrived from the peer within the idle timeout
+    IdleTimeout,
+    /// The handler called [`Session::close`]
+    Closed,
+    /// A handler call for the session panicked, so its state may be inconsistent
+    HandlerPanicked,
+}
+
+/// Message handler with per-peer sessions
+///
+/// Used through a [`SessionLayer`], which creates a session for every new peer
+/// and passes it to each call. Calls for the same peer never run concurrently.
+pub trait SessionHandler: Send + Sync {
+    /// Process an incoming message and return the response
+    /// If None is returned, no response is sent
+    fn handle_message(&self, session: &mut Session, message: &[u8]) -> Option<Vec<u8>>;
+
+    /// Called before the first message of a new peer is handled
+    fn on_session_start(&self, _session: &mut Session) {}
+
+    /// Called when a session ends; its stored values are dropped afterwards
+    fn on_session_end(&self, _session: &mut Session, _reason: SessionEndReason) {}
+
+    /// Called when an error occurs
+    fn on_error(&self, error: &str, from: Option<SocketAddr>) {
+        eprintln!("Error from {:?}: {}", from, error);
+    }
+
+    /// Called when a datagram is rejected by the access lists or the rate limit
+    /// Rejected datagrams never get a response and do not start a session
+    fn on_rejected(&self, _from: SocketAddr, _reason: RejectReason) {}
+}
+
+/// Shortest time between sweeps of a full table for a new peer
+const FULL_TABLE_SWEEP_INTERVAL: Duration = Duration::from_millis(100);
+
+struct SessionTable {
+    sessions: HashMap<SocketAddr, Arc<Mutex<Session>>>,
+    last_sweep: Instant,
+}
+
+/// Tracks peers by address and runs a [`SessionHandler`] as a [`MessageHandler`]
+///
+/// Sessions idle for longer than the idle timeout are ended the next time the
+/// table is swept, which happens while handling datagrams or through
+/// [`expire_idle`](SessionLayer::expire_idle). Datagrams from new peers are
+/// dropped while `max_sessions` sessions are active; a full table is swept
+/// for them at most every 100ms.
+pub struct SessionLayer<H> {
+    handler: H,
+    table: Mutex<SessionTable>,
+    idle_timeout: Duration,
+    max_sessions: usize,
+}
+
+impl<H: SessionHandler> SessionLayer<H> {
+    /// Wrap a session handler with the default limits
+    pub fn new(handler: H) -> Self {
+        Self {
+            handler,
+            table: Mutex::new(SessionTable {
+                sessions: HashMap::new(),
+                last_sweep: Instant::now(),
+            }),
+            idle_timeout: Duration::from_secs(60),
+            max_sessions: 1024,
+        }
+    }
+
+    /// Set how long a session may be idle before it ends (default: 60s)
+    pub fn idle_timeout(mut self, timeout: Duration) -> Self {
+        self.idle_timeout = timeout;
+        self
+    }
+
+    /// Set the maximum number of concurrent sessions (default: 1024)
+    pub fn max_sessions(mut self, count: usize) -> Self {
+        self.max_sessions = count.max(1);
+        self
+    }
+
+    /// Get a reference to the wrapped handler
+    pub fn handler(&self) -> &H {
+        &self.handler
+    }
+
+    /// Get the number of active sessions
+    pub fn session_count(&self) -> usize {
+        self.table.lock().unwrap().sessions.len()
+    }
+
+    /// End all sessions that have been idle for longer than the idle timeout
+    pub fn expire_idle(&self) {
+        let expired = {
+            let mut table = self.table.lock().unwrap();
+            self.sweep(&mut table)
+        };
+        self.end_sessions(expired, SessionEndReason::IdleTimeout);
+    }
+
+    /// Remove idle sessions from the table; their end callbacks run after the table is unlocked
+    fn sweep(&self, table: &mut SessionTable) -> Vec<Arc<Mutex<Session>>> {
+        table.last_sweep = Instant::now();
+
+        let idle: Vec<SocketAddr> = table.sessions.iter()
+            .filter(|(_, session)| {
+                // A locked session is being handled right now, so it is not idle
+                match session.try_lock() {
+                    Ok(session) => session.last_seen.elapsed() >= self.idle_timeout,
+                    Err(TryLockError::Poisoned(poisoned)) => {
+                        poisoned.into_inner().last_seen.elapsed() >= self.idle_timeout
+                    }
+                    Err(TryLockError::WouldBlock) => false,
+                }
+            })
+            .map(|(peer, _)| *peer)
+            .collect();
+
+        idle.iter().filter_map(|peer| table.sessions.remove(peer)).collect()
+    }
+
+    fn end_sessions(&self, sessions: Vec<Arc<Mutex<Session>>>, reason: SessionEndReason) {
+        for session in sessions {
+            let mut session = lock(&session);
+            session.ended = true;
+            self.handler.on_session_end(&mut session, reason);
+        }
+    }
+
+    /// Get the session of `from`, starting one if the table has room
+    fn session_for(&self, from: SocketAddr) -> Option<Arc<Mutex<Session>>> {
+        let (session, expired) = {
+            let mut table = self.table.lock().unwrap();
+
+            // Sweep a few times per timeout so idle sessions do not linger, and
+            // sooner when a new peer finds the table full
+            let mut sweep_interval = (self.idle_timeout / 4).min(Duration::from_secs(1));
+            if table.sessions.len() >= self.max_sessions && !table.sessions.contains_key(&from) {
+                sweep_interval = sweep_interval.min(FULL_TABLE_SWEEP_INTERVAL);
+            }
+            let expired = if table.last_sweep.elapsed() >= sweep_interval {
+                self.sweep(&mut table)
+            } else {
+                Vec::new()
+            };
+
+            let session = match table.sessions.get(&from) {
+                Some(session) => Some(Arc::clone(session)),
+                None if table.sessions.len() < self.max_sessions => {
+                    let session = Arc::new(Mutex::new(Session::new(from)));
+                    table.sessions.insert(from, Arc::clone(&session));
+                    Some(session)
+                }
+                None => None,
+            };
+            (session, expired)
+        };
+        self.end_sessions(expired, SessionEndReason::IdleTimeout);
+
+        if session.is_none() {
+            self.handler.on_error(
+                &format!("Session limit of {} reached, dropping datagram", self.max_sessions),
+                Some(from),
+            );
+        }
+        session
+    }
+
+    /// Whether the table still maps `from` to `session`
+    fn is_current(&self, from: SocketAddr, session: &Arc<Mutex<Session>>) -> bool {
+        let table = self.table.lock().unwrap();
+        table.sessions.get(&from).is_some_and(|current| Arc::ptr_eq(current, session))
+    }
+
+    /// Remove `session` from the table if it is still the session of `from`
+    fn remove_current(&self, from: SocketAddr, session: &Arc<Mutex<Session>>) -> bool {
+        let mut table = self.table.lock().unwrap();
+        let current = table.sessions.get(&from).is_some_and(|current| Arc::ptr_eq(current, session));
+        if current {
+            table.sessions.remove(&from);
+        }
+        current
+    }
+}
+
+/// Lock a session, even one whose handler panicked while holding it
+fn lock(session: &Mutex<Session>) -> MutexGuard<'_, Session> {
+    session.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+impl<H: SessionHandler> MessageHandler for SessionLayer<H> {
+    fn handle_message(&self, message: &[u8], from: SocketAddr) -> Option<Vec<u8>> {
+        loop {
+            let session = self.session_for(from)?;
+            let mut guard = match session.lock() {
+                Ok(guard) => guard,
+                Err(poisoned) => {
+                    // A handler call panicked halfway through, so end the
+                    // session rather than hand its state to the next call
+                    let mut guard = poisoned.into_inner();
+                    if !guard.ended && self.remove_current(from, &session) {
+                        guard.ended = true;
+                        self.handler.on_session_end(&mut guard, SessionEndReason::HandlerPanicked);
+                    }
+                    continue;
+                }
+            };
+
+            // The session may have been swept or closed while this call
+            // waited for it; the datagram then belongs to a new one
+            if guard.ended || !self.is_current(from, &session) {
+                continue;
+            }
+
+            if guard.messages == 0 {
+                self.handler.on_session_start(&mut guard);
+            }
+            guard.messages += 1;
+            guard.last_seen = Instant::now();
+
+            let response = self.handler.handle_message(&mut guard, message);
+
+            if guard.closed {
+                self.table.lock().unwrap().sessions.remove(&from);
+                guard.ended = true;
+                self.handler.on_session_end(&mut guard, SessionEndReason::Closed);
+            }
+
+            return response;
+        }
+    }
+
+    fn on_error(&self, error: &str, from: Option<SocketAddr>) {
+        self.handler.on_error(error, from);
+    }
+
+    fn on_rejected(&self, from: SocketAddr, reason: RejectReason) {
+        self.handler.on_rejected(from, reason);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ServerConfig, UdpEchoServer};
+    use std::net::UdpSocket;
+    use std::thread;
+
+    fn addr(port: u16) -> SocketAddr {
+        SocketAddr::from(([127, 0, 0, 1], port))
+    }
+
+    /// Numbers the messages of every peer and logs session events
+    #[derive(Default)]
+    struct Counter {
+        events: Mutex<Vec<String>>,
+    }
+
+    struct Nickname(String);
+
+    impl SessionHandler for Counter {
+        fn handle_message(&self, session: &mut Session, message: &[u8]) -> Option<Vec<u8>> {
+            if let Some(name) = message.strip_prefix(b"nick ") {
+                session.insert(Nickname(String::from_utf8_lossy(name).into_owned()));
+            }
+            if message == b"bye" {
+                session.close();
+            }
+            if message == b"panic" {
+                panic!("handler failed");
+            }
+
+            let count = session.get_or_insert_with(|| 0u32);
+            *count += 1;
+            let name = session.get::<Nickname>().map_or("anonymous", |n| n.0.as_str());
+            Some(format!("{} #{}", name, session.get::<u32>().unwrap()).into_bytes())
+        }
+
+        fn on_session_start(&self, session: &mut Session) {
+            self.events.lock().unwrap().push(format!("start {}", session.peer().port()));
+        }
+
+        fn on_session_end(&self, session: &mut Session, reason: SessionEndReason) {
+            self.events.lock().unwrap().push(format!("end {} {:?}", session.peer().port(), reason));
+        }
+
+        fn on_error(&self, _error: &str, _from: Option<SocketAddr>) {}
+    }
+
+    #[test]
+    fn test_typed_session_storage() {
+        let mut session = Session::new(addr(1));
+        assert_eq!(session.insert(5u32), None);
+        assert_eq!(session.insert(7u32), Some(5));
+        session.insert(String::from("state"));
+
+        *session.get_mut::<u32>().unwrap() += 1;
+        assert_eq!(session.get::<u32>(), Some(&8));
+        assert_eq!(session.get::<String>().map(String::as_str), Some("state"));
+        assert_eq!(session.remove::<String>(), Some(String::from("state")));
+        assert_eq!(session.get::<String>(), None);
+        assert_eq!(session.get::<u64>(), None);
+    }
+
+    #[test]
+    fn test_sessions_keep_per_peer_state() {
+        let layer = SessionLayer::new(Counter::default());
+
+        assert_eq!(layer.handle_message(b"hi", addr(1)).unwrap(), b"anonymous #1");
+        assert_eq!(layer.handle_message(b"nick ann", addr(1)).unwrap(), b"ann #2");
+        assert_eq!(layer.handle_message(b"hi", addr(2)).unwrap(), b"anonymous #1");
+        assert_eq!(layer.handle_message(b"hi", addr(1)).unwrap(), b"ann #3");
+        assert_eq!(layer.session_count(), 2);
+
+        assert_eq!(layer.handle_message(b"bye", addr(1)).unwrap(), b"ann #4");
+        assert_eq!(layer.session_count(), 1);
+        assert_eq!(layer.handle_message(b"back", addr(1)).unwrap(), b"anonymous #1");
+
+        let events = layer.handler().events.lock().unwrap().clone();
+        assert_eq!(events, ["start 1", "start 2", "end 1 Closed", "start 1"]);
+    }
+
+    #[test]
+    fn test_idle_sessions_expire() {
+        let layer = SessionLayer::new(Counter::default()).idle_timeout(Duration::from_millis(50));
+        layer.handle_message(b"hi", addr(1));
+        layer.handle_message(b"hi", addr(2));
+
+        thread::sleep(Duration::from_millis(80));
+        layer.handle_message(b"hi", addr(2));
+        layer.expire_idle();
+
+        // Peer 2 was idle as well, so its next message starts a new session
+        assert_eq!(layer.session_count(), 1);
+        let events = layer.handler().events.lock().unwrap().clone();
+        assert!(events.contains(&"end 1 IdleTimeout".to_string()), "{:?}", events);
+        assert!(events.contains(&"end 2 IdleTimeout".to_string()), "{:?}", events);
+        assert_eq!(events.iter().filter(|e| *e == "start 2").count(), 2);
+    }
+
+    #[test]
+    fn test_datagram_waiting_for_an_ended_session_starts_a_new_one() {
+        let layer = Arc::new(SessionLayer::new(Counter::default()));
+        layer.handle_message(b"hi", addr(1));
+
+        // Hold the session while a datagram for it arrives, then end it the way a sweep does
+        let session = Arc::clone(&layer.table.lock().unwrap().sessions[&addr(1)]);
+        let mut guard = session.lock().unwrap();
+        let waiting = {
+            let layer = Arc::clone(&layer);
+            thread::spawn(move || layer.handle_message(b"hi", addr(1)))
+        };
+        thread::sleep(Duration::from_millis(50));
+        layer.table.lock().unwrap().sessions.remove(&addr(1));
+        guard.ended = true;
+        layer.handler().on_session_end(&mut guard, SessionEndReason::IdleTimeout);
+        drop(guard);
+
+        assert_eq!(waiting.join().unwrap().unwrap(), b"anonymous #1");
+        assert_eq!(layer.session_count(), 1);
+        let events = layer.handler().events.lock().unwrap().clone();
+        assert_eq!(events, ["start 1", "end 1 IdleTimeout", "start 1"]);
+    }
+
+    #[test]
+    fn test_sessions_poisoned_by_a_panicking_handler_are_ended() {
+        let layer = SessionLayer::new(Counter::default()).idle_timeout(Duration::from_millis(50));
+        layer.handle_message(b"hi", addr(1));
+        layer.handle_message(b"hi", addr(2));
+        for peer in [addr(1), addr(2)] {
+            let call = std::panic::AssertUnwindSafe(|| layer.handle_message(b"panic", peer));
+            assert!(std::panic::catch_unwind(call).is_err());
+        }
+
+        // The next datagram ends the poisoned session and starts a new one
+        assert_eq!(layer.handle_message(b"hi", addr(1)).unwrap(), b"anonymous #1");
+
+        // A poisoned session that hears nothing more still expires
+        thread::sleep(Duration::from_millis(80));
+        layer.expire_idle();
+        assert_eq!(layer.session_count(), 0);
+        let events = layer.handler().events.lock().unwrap().clone();
+        assert_eq!(events[..4], ["start 1", "start 2", "end 1 HandlerPanicked", "start 1"]);
+        assert!(events.contains(&"end 2 IdleTimeout".to_string()), "{:?}", events);
+    }
+
+    #[test]
+    fn test_session_limit_over_udp() {
+        let server = UdpEchoServer::new(ServerConfig::new().port(0)).unwrap();
+        let server_addr = server.local_addr().unwrap();
+        let layer = Arc::new(SessionLayer::new(Counter::default()).max_sessions(2));
+        let _handle = server.spawn_with_handler(Arc::clone(&layer));
+
+        let clients: Vec<_> = (0..3)
+            .map(|_| {
+                let client = UdpSocket::bind("127.0.0.1:0").unwrap();
+                client.set_read_timeout(Some(Duration::from_millis(300))).unwrap();
+                client.connect(server_addr).unwrap();
+                client
+            })
+            .collect();
+
+        let mut buffer = [0; 64];
+        for client in &clients[..2] {
+            client.send(b"hello").unwrap();
+            let size = client.recv(&mut buffer).unwrap();
+            assert_eq!(&buffer[..size], b"anonymous #1");
+        }
+
+        // The third peer does not get a session while two are active
+        clients[2].send(b"hello").unwrap();
+        assert!(clients[2].recv(&mut buffer).is_err());
+        assert_eq!(layer.session_count(), 2);
+
+        clients[0].send(b"bye").unwrap();
+        clients[0].recv(&mut buffer).unwrap();
+        clients[2].send(b"hello").unwrap();
+        let size = clients[2].recv(&mut buffer).unwrap();
+        assert_eq!(&buffer[..size], b"anonymous #1");
+    }
+}
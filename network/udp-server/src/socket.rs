@@ -0,0 +1,86 @@
+// socket.rs
+use std::io;
+use std::net::{SocketAddr, ToSocketAddrs, UdpSocket};
+
+use socket2::SockRef;
+
+use crate::ServerConfig;
+
+/// Bind the sockets of a server
+///
+/// A single worker binds a plain socket. Several workers bind one socket each
+/// with `SO_REUSEPORT` to the same address so the kernel spreads datagrams over
+/// them. With port 0 the first socket picks the port and the others follow it.
+pub(crate) fn bind_sockets(config: &ServerConfig) -> io::Result<Vec<UdpSocket>> {
+    let bind_addr = format!("{}:{}", config.host, config.port);
+
+    if config.workers == 1 {
+        let socket = UdpSocket::bind(&bind_addr)?;
+        configure(SockRef::from(&socket), config)?;
+        return Ok(vec![socket]);
+    }
+
+    let addr = bind_addr.to_socket_addrs()?.next().ok_or_else(|| {
+        io::Error::new(io::ErrorKind::InvalidInput, format!("Could not resolve {}", bind_addr))
+    })?;
+
+    let first = bind_reuse_port(addr)?;
+    let addr = first.local_addr()?;
+
+    let mut sockets = vec![first];
+    for _ in 1..config.workers {
+        sockets.push(bind_reuse_port(addr)?);
+    }
+    for socket in &sockets {
+        configure(SockRef::from(socket), config)?;
+    }
+    Ok(sockets)
+}
+
+/// Join the configured multicast groups and apply the multicast and broadcast options
+pub(crate) fn configure(socket: SockRef<'_>, config: &ServerConfig) -> io::Result<()> {
+    for (group, interface) in &config.multicast_v4 {
+        socket.join_multicast_v4(group, interface)?;
+    }
+    for (group, interface) in &config.multicast_v6 {
+        socket.join_multicast_v6(group, *interface)?;
+    }
+
+    let is_ipv6 = socket.local_addr()?.is_ipv6();
+    if let Some(ttl) = config.multicast_ttl {
+        if is_ipv6 {
+            socket.set_multicast_hops_v6(ttl)?;
+        } else {
+            socket.set_multicast_ttl_v4(ttl)?;
+        }
+    }
+    if let Some(enabled) = config.multicast_loop {
+        if is_ipv6 {
+            socket.set_multicast_loop_v6(enabled)?;
+        } else {
+            socket.set_multicast_loop_v4(enabled)?;
+        }
+    }
+    if config.broadcast {
+        socket.set_broadcast(true)?;
+    }
+    Ok(())
+}
+
+#[cfg(all(unix, not(any(target_os = "solaris", target_os = "illumos"))))]
+fn bind_reuse_port(addr: SocketAddr) -> io::Result<UdpSocket> {
+    use socket2::{Domain, Protocol, Socket, Type};
+
+    let socket = Socket::new(Domain::for_address(addr), Type::DGRAM, Some(Protocol::UDP))?;
+    socket.set_reuse_port(true)?;
+    socket.bind(&addr.into())?;
+    Ok(socket.into())
+}
+
+#[cfg(not(all(unix, not(any(target_os = "solaris", target_os = "illumos")))))]
+fn bind_reuse_port(_addr: SocketAddr) -> io::Result<UdpSocket> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "Multiple workers need SO_REUSEPORT, which this platform does not support",
+    ))
+}
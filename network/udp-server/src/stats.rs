@@ -0,0 +1,228 @@
+// stats.rs
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{PoisonError, RwLock};
+use std::time::{Duration, Instant};
+
+use crate::metrics::LATENCY_BUCKETS;
+use crate::{ClientStats, LatencyHistogram, RejectReason, ServerStats};
+
+/// Number of shards the per-client counters are spread over
+const CLIENT_SHARDS: usize = 16;
+
+#[derive(Default)]
+struct ClientCounters {
+    messages_received: AtomicU64,
+    messages_sent: AtomicU64,
+    bytes_received: AtomicU64,
+    bytes_sent: AtomicU64,
+}
+
+/// Lock-free statistics counters updated on the receive and send paths
+///
+/// Server-wide counters and the latency histogram are plain atomics. Client
+/// counters live in sharded maps that are only write-locked when a new client
+/// shows up. [`snapshot`](StatsCollector::snapshot) merges everything into a
+/// [`ServerStats`].
+pub struct StatsCollector {
+    start_time: Instant,
+    messages_received: AtomicU64,
+    messages_sent: AtomicU64,
+    messages_dropped: AtomicU64,
+    rate_limited: AtomicU64,
+    denied: AtomicU64,
+    errors: AtomicU64,
+    bytes_received: AtomicU64,
+    bytes_sent: AtomicU64,
+    latency_buckets: [AtomicU64; LATENCY_BUCKETS.len() + 1],
+    latency_sum_nanos: AtomicU64,
+    clients: [RwLock<HashMap<SocketAddr, ClientCounters>>; CLIENT_SHARDS],
+    tracked_clients: AtomicUsize,
+    max_tracked_clients: usize,
+}
+
+impl StatsCollector {
+    /// Create a collector keeping counters for at most `max_tracked_clients` clients
+    pub fn new(max_tracked_clients: usize) -> Self {
+        Self {
+            start_time: Instant::now(),
+            messages_received: AtomicU64::new(0),
+            messages_sent: AtomicU64::new(0),
+            messages_dropped: AtomicU64::new(0),
+            rate_limited: AtomicU64::new(0),
+            denied: AtomicU64::new(0),
+            errors: AtomicU64::new(0),
+            bytes_received: AtomicU64::new(0),
+            bytes_sent: AtomicU64::new(0),
+            latency_buckets: std::array::from_fn(|_| AtomicU64::new(0)),
+            latency_sum_nanos: AtomicU64::new(0),
+            clients: std::array::from_fn(|_| RwLock::new(HashMap::new())),
+            tracked_clients: AtomicUsize::new(0),
+            max_tracked_clients,
+        }
+    }
+
+    /// Count a received datagram
+    pub fn record_received(&self, from: SocketAddr, size: usize) {
+        self.messages_received.fetch_add(1, Ordering::Relaxed);
+        self.bytes_received.fetch_add(size as u64, Ordering::Relaxed);
+
+        self.with_client(from, |client| {
+            client.messages_received.fetch_add(1, Ordering::Relaxed);
+            client.bytes_received.fetch_add(size as u64, Ordering::Relaxed);
+        });
+    }
+
+    /// Count a sent datagram
+    pub fn record_sent(&self, to: SocketAddr, size: usize) {
+        self.messages_sent.fetch_add(1, Ordering::Relaxed);
+        self.bytes_sent.fetch_add(size as u64, Ordering::Relaxed);
+
+        self.with_client(to, |client| {
+            client.messages_sent.fetch_add(1, Ordering::Relaxed);
+            client.bytes_sent.fetch_add(size as u64, Ordering::Relaxed);
+        });
+    }
+
+    /// Record the execution time of a handler call
+    pub fn record_handler(&self, elapsed: Duration) {
+        let bucket = LatencyHistogram::bucket_index(elapsed);
+        self.latency_buckets[bucket].fetch_add(1, Ordering::Relaxed);
+        self.latency_sum_nanos.fetch_add(elapsed.as_nanos() as u64, Ordering::Relaxed);
+    }
+
+    /// Count a datagram dropped because the handler queue was full
+    pub fn record_dropped(&self) {
+        self.messages_dropped.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Count a datagram rejected by the packet filter
+    pub fn record_rejection(&self, reason: RejectReason) {
+        match reason {
+            RejectReason::Denied => self.denied.fetch_add(1, Ordering::Relaxed),
+            RejectReason::RateLimited => self.rate_limited.fetch_add(1, Ordering::Relaxed),
+        };
+    }
+
+    /// Count a receive, send or handler error
+    pub fn record_error(&self) {
+        self.errors.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Take a consistent-enough copy of all counters
+    ///
+    /// Counters are read one by one, so a snapshot taken under load may be
+    /// off by the datagrams processed while it was being taken.
+    pub fn snapshot(&self) -> ServerStats {
+        let counts = std::array::from_fn(|i| self.latency_buckets[i].load(Ordering::Relaxed));
+        let sum = Duration::from_nanos(self.latency_sum_nanos.load(Ordering::Relaxed));
+
+        let mut clients = HashMap::new();
+        for shard in &self.clients {
+            let shard = shard.read().unwrap_or_else(PoisonError::into_inner);
+            clients.extend(shard.iter().map(|(addr, client)| {
+                (*addr, ClientStats {
+                    messages_received: client.messages_received.load(Ordering::Relaxed),
+                    messages_sent: client.messages_sent.load(Ordering::Relaxed),
+                    bytes_received: client.bytes_received.load(Ordering::Relaxed),
+                    bytes_sent: client.bytes_sent.load(Ordering::Relaxed),
+                })
+            }));
+        }
+
+        ServerStats {
+            messages_received: self.messages_received.load(Ordering::Relaxed),
+            messages_sent: self.messages_sent.load(Ordering::Relaxed),
+            messages_dropped: self.messages_dropped.load(Ordering::Relaxed),
+            rate_limited: self.rate_limited.load(Ordering::Relaxed),
+            denied: self.denied.load(Ordering::Relaxed),
+            errors: self.errors.load(Ordering::Relaxed),
+            bytes_received: self.bytes_received.load(Ordering::Relaxed),
+            bytes_sent: self.bytes_sent.load(Ordering::Relaxed),
+            start_time: self.start_time,
+            handler_latency: LatencyHistogram::from_parts(counts, sum),
+            clients,
+        }
+    }
+
+    fn with_client(&self, addr: SocketAddr, update: impl Fn(&ClientCounters)) {
+        let shard = &self.clients[shard_index(&addr)];
+
+        if let Some(client) = shard.read().unwrap_or_else(PoisonError::into_inner).get(&addr) {
+            update(client);
+            return;
+        }
+
+        // Reserve a slot before inserting so the limit holds across shards
+        if self.tracked_clients.fetch_add(1, Ordering::Relaxed) >= self.max_tracked_clients {
+            self.tracked_clients.fetch_sub(1, Ordering::Relaxed);
+            return;
+        }
+
+        let mut shard = shard.write().unwrap_or_else(PoisonError::into_inner);
+        if shard.contains_key(&addr) {
+            // Another thread added the client in the meantime
+            self.tracked_clients.fetch_sub(1, Ordering::Relaxed);
+        }
+        update(shard.entry(addr).or_default());
+    }
+}
+
+/// Pick a client shard from the low bits of the address and the port
+fn shard_index(addr: &SocketAddr) -> usize {
+    let ip_bits = match addr.ip() {
+        IpAddr::V4(ip) => u32::from(ip) as usize,
+        IpAddr::V6(ip) => u128::from(ip) as usize,
+    };
+    (ip_bits ^ addr.port() as usize ^ (addr.port() as usize >> 4)) % CLIENT_SHARDS
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use std::thread;
+
+    #[test]
+    fn test_concurrent_updates_are_not_lost() {
+        let collector = Arc::new(StatsCollector::new(64));
+
+        let threads: Vec<_> = (0..8)
+            .map(|i| {
+                let collector = Arc::clone(&collector);
+                thread::spawn(move || {
+                    let addr: SocketAddr = format!("10.0.0.{}:4000", i % 4).parse().unwrap();
+                    for _ in 0..1000 {
+                        collector.record_received(addr, 10);
+                        collector.record_handler(Duration::from_micros(200));
+                        collector.record_sent(addr, 5);
+                    }
+                })
+            })
+            .collect();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+
+        let stats = collector.snapshot();
+        assert_eq!(stats.messages_received, 8000);
+        assert_eq!(stats.bytes_sent, 40000);
+        assert_eq!(stats.handler_latency.count(), 8000);
+        assert_eq!(stats.clients.len(), 4);
+        assert_eq!(stats.clients.values().map(|c| c.messages_sent).sum::<u64>(), 8000);
+    }
+
+    #[test]
+    fn test_client_limit() {
+        let collector = StatsCollector::new(2);
+        for port in 1..=5 {
+            collector.record_received(SocketAddr::from(([127, 0, 0, 1], port)), 1);
+        }
+        collector.record_received(SocketAddr::from(([127, 0, 0, 1], 1)), 1);
+
+        let stats = collector.snapshot();
+        assert_eq!(stats.messages_received, 6);
+        assert_eq!(stats.clients.len(), 2);
+    }
+}